@@ -23,7 +23,7 @@ fn main() {
 }
 
 fn smoke_test() -> Result<(), Box<dyn std::error::Error>> {
-    let url = env::args().skip(1).next().expect("No URL provided");
+    let url = env::args().nth(1).expect("No URL provided");
     println!("Fetching {}", url);
 
     let response = Request::builder()
@@ -8,7 +8,7 @@ use std::time::Duration;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let file = env::args().skip(1).next().expect("No file provided");
+    let file = env::args().nth(1).expect("No file provided");
 
     let mut file = io::BufReader::new(File::open(file)?);
     let mut buf = String::new();
@@ -5,23 +5,34 @@ use crate::Stream;
 use crate::{AsyncRead, AsyncReadSeek, AsyncSeek, AsyncWrite};
 use futures_util::future::poll_fn;
 use once_cell::sync::Lazy;
+#[cfg(feature = "tokio")]
+use std::cell::RefCell;
 use std::future::Future;
 use std::io;
 use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::Path;
 use std::pin::Pin;
 use std::sync::Mutex;
 use std::task::Context;
 use std::task::Poll;
 use std::time::Duration;
 
+#[cfg(feature = "tokio")]
+use tokio::runtime::Handle as TokioHandle;
+#[cfg(feature = "tokio")]
 use tokio::runtime::Runtime as TokioRuntime;
 
+#[cfg(not(any(feature = "tokio", feature = "async-std", feature = "smol")))]
+compile_error!("hreq needs a runtime, one of the features tokio, async-std or smol");
+
 #[allow(clippy::needless_doctest_main)]
 /// Switches between different async runtimes.
 ///
-/// This is a global singleton.
+/// This is a global singleton. Agents and servers can use a tokio runtime of
+/// their own instead, see [`Agent::runtime`] and [`Server::runtime`].
 ///
-/// hreq supports different ways of using tokio.
+/// hreq supports different ways of using tokio, feature `tokio`, which is on by default.
 ///
 ///   * `TokioSingle`. The default option. A minimal tokio `rt-core`
 ///     which executes calls in one single thread. It does nothing
@@ -33,12 +44,27 @@ use tokio::runtime::Runtime as TokioRuntime;
 ///   * `TokioOwned`. Uses a preconfigured tokio [`Runtime`] that is
 ///     "handed over" to hreq.
 ///
+/// Other runtimes are available behind cargo features.
+///
+///   * `AsyncStd`. Uses the [async-std] runtime, feature `async-std`.
+///   * `Smol`. Uses the [smol] runtime, feature `smol`.
+///
+/// These use the runtime's own reactor, executor and timers, which means
+/// hreq doesn't start a tokio runtime next to it. Both support `.block()`.
+/// Turn off the default features to not depend on the tokio runtime at all.
+/// Without the `tokio` feature, the default is `AsyncStd` or `Smol`.
+///
+/// [`Agent::runtime`]: struct.Agent.html#method.runtime
+/// [`Server::runtime`]: server/struct.Server.html#method.runtime
 /// [`Handle`]: https://docs.rs/tokio/latest/tokio/runtime/struct.Handle.html
 /// [`Runtime`]: https://docs.rs/tokio/latest/tokio/runtime/struct.Runtime.html
+/// [async-std]: https://docs.rs/async-std
+/// [smol]: https://docs.rs/smol
 #[derive(Debug)]
 #[allow(clippy::large_enum_variant)]
 pub enum AsyncRuntime {
     /// Use a tokio `rt-core` single threaded runtime. This is the default.
+    #[cfg(feature = "tokio")]
     TokioSingle,
     /// Pick up on a tokio shared runtime.
     ///
@@ -53,6 +79,7 @@ pub enum AsyncRuntime {
     ///
     /// AsyncRuntime::TokioShared.make_default();
     /// ```
+    #[cfg(feature = "tokio")]
     TokioShared,
     /// Use a tokio runtime owned by hreq.
     ///
@@ -71,45 +98,141 @@ pub enum AsyncRuntime {
     ///
     /// AsyncRuntime::TokioOwned(runtime).make_default();
     /// ```
+    #[cfg(feature = "tokio")]
     TokioOwned(TokioRuntime),
+    /// Use the async-std runtime.
+    ///
+    /// # Example using async-std.
+    ///
+    /// ```no_run
+    /// use hreq::AsyncRuntime;
+    ///
+    /// AsyncRuntime::AsyncStd.make_default();
+    /// ```
+    #[cfg(feature = "async-std")]
+    AsyncStd,
+    /// Use the smol runtime.
+    ///
+    /// Tasks are spawned on the smol global executor.
+    ///
+    /// # Example using smol.
+    ///
+    /// ```no_run
+    /// use hreq::AsyncRuntime;
+    ///
+    /// AsyncRuntime::Smol.make_default();
+    /// ```
+    #[cfg(feature = "smol")]
+    Smol,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[allow(unused)]
 #[allow(clippy::enum_variant_names)]
 enum Inner {
+    #[cfg(feature = "tokio")]
     TokioSingle,
+    #[cfg(feature = "tokio")]
     TokioShared,
+    #[cfg(feature = "tokio")]
     TokioOwned,
+    #[cfg(feature = "async-std")]
+    AsyncStd,
+    #[cfg(feature = "smol")]
+    Smol,
 }
 
 #[cfg(feature = "server")]
 #[allow(dead_code)]
 pub(crate) enum Listener {
+    #[cfg(feature = "tokio")]
     Tokio(tokio::net::TcpListener),
+    #[cfg(all(feature = "tokio", unix))]
+    TokioUnix(tokio::net::UnixListener),
+    #[cfg(feature = "async-std")]
+    AsyncStd(async_std::net::TcpListener),
+    #[cfg(all(feature = "async-std", unix))]
+    AsyncStdUnix(async_std::os::unix::net::UnixListener),
+    #[cfg(feature = "smol")]
+    Smol(smol::net::TcpListener),
+    #[cfg(all(feature = "smol", unix))]
+    SmolUnix(smol::net::unix::UnixListener),
+}
+
+/// Unix sockets have no address to tell, we use the loopback address for the listener.
+#[cfg(all(feature = "server", unix))]
+fn unix_addr() -> SocketAddr {
+    ([127, 0, 0, 1], 0).into()
 }
 
 #[cfg(feature = "server")]
 impl Listener {
-    pub async fn accept(&mut self) -> Result<(impl Stream, SocketAddr), Error> {
+    /// Accept the next connection, with the remote address unless it's a Unix socket.
+    pub async fn accept(&mut self) -> Result<(impl Stream, Option<SocketAddr>), Error> {
+        #[cfg(feature = "tokio")]
+        use crate::tokio_conv::from_tokio;
         use Listener::*;
-        Ok(match self {
+        let accepted: (Box<dyn Stream>, Option<SocketAddr>) = match self {
+            #[cfg(feature = "tokio")]
             Tokio(v) => {
                 let (t, a) = v.accept().await?;
-                (crate::tokio_conv::from_tokio(t), a)
+                (Box::new(from_tokio(t)), Some(a))
             }
-        })
+            #[cfg(all(feature = "tokio", unix))]
+            TokioUnix(v) => {
+                let (t, _) = v.accept().await?;
+                (Box::new(from_tokio(t)), None)
+            }
+            #[cfg(feature = "async-std")]
+            AsyncStd(v) => {
+                let (t, a) = v.accept().await?;
+                (Box::new(t), Some(a))
+            }
+            #[cfg(all(feature = "async-std", unix))]
+            AsyncStdUnix(v) => {
+                let (t, _) = v.accept().await?;
+                (Box::new(t), None)
+            }
+            #[cfg(feature = "smol")]
+            Smol(v) => {
+                let (t, a) = v.accept().await?;
+                (Box::new(t), Some(a))
+            }
+            #[cfg(all(feature = "smol", unix))]
+            SmolUnix(v) => {
+                let (t, _) = v.accept().await?;
+                (Box::new(t), None)
+            }
+        };
+        Ok(accepted)
     }
 
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        use Listener::*;
         match self {
-            Listener::Tokio(l) => l.local_addr(),
+            #[cfg(feature = "tokio")]
+            Tokio(l) => l.local_addr(),
+            #[cfg(all(feature = "tokio", unix))]
+            TokioUnix(_) => Ok(unix_addr()),
+            #[cfg(feature = "async-std")]
+            AsyncStd(l) => l.local_addr(),
+            #[cfg(all(feature = "async-std", unix))]
+            AsyncStdUnix(_) => Ok(unix_addr()),
+            #[cfg(feature = "smol")]
+            Smol(l) => l.local_addr(),
+            #[cfg(all(feature = "smol", unix))]
+            SmolUnix(_) => Ok(unix_addr()),
         }
     }
 }
 
-static CURRENT_RUNTIME: Lazy<Mutex<Inner>> = Lazy::new(|| {
-    let rt = if tokio::runtime::Handle::try_current().ok().is_some() {
+/// The runtime in use, `None` until it's first needed or made the default.
+static CURRENT_RUNTIME: Lazy<Mutex<Option<Inner>>> = Lazy::new(|| Mutex::new(None));
+
+/// The runtime to use when none is made the default.
+fn default_runtime() -> Inner {
+    #[cfg(feature = "tokio")]
+    let rt = if TokioHandle::try_current().is_ok() {
         trace!("Shared tokio runtime detected");
         async_tokio::use_shared();
         Inner::TokioShared
@@ -118,30 +241,197 @@ static CURRENT_RUNTIME: Lazy<Mutex<Inner>> = Lazy::new(|| {
         Inner::TokioSingle
     };
 
+    #[cfg(all(not(feature = "tokio"), feature = "async-std"))]
+    let rt = Inner::AsyncStd;
+
+    #[cfg(all(not(feature = "tokio"), not(feature = "async-std"), feature = "smol"))]
+    let rt = Inner::Smol;
+
     trace!("Default runtime: {:?}", rt);
 
-    Mutex::new(rt)
-});
+    rt
+}
+
+#[cfg(feature = "tokio")]
+thread_local! {
+    // Runtime of the agent or server whose future is currently polled, if it has one.
+    static LOCAL: RefCell<Option<RuntimeHandle>> = const { RefCell::new(None) };
+}
 
 fn current() -> Inner {
-    *CURRENT_RUNTIME.lock().unwrap()
+    #[cfg(feature = "tokio")]
+    {
+        if LOCAL.with(|l| l.borrow().is_some()) {
+            // the tokio context is entered by InRuntime.
+            return Inner::TokioShared;
+        }
+    }
+    *CURRENT_RUNTIME
+        .lock()
+        .unwrap()
+        .get_or_insert_with(default_runtime)
+}
+
+/// Tokio runtime of an `Agent` or `Server` used instead of the global `AsyncRuntime`.
+#[cfg(feature = "tokio")]
+#[derive(Clone, Debug)]
+pub(crate) struct RuntimeHandle(TokioHandle);
+
+/// Without tokio, there are no runtimes of agents or servers.
+#[cfg(not(feature = "tokio"))]
+#[derive(Clone, Debug)]
+pub(crate) enum RuntimeHandle {}
+
+#[cfg(not(feature = "tokio"))]
+impl RuntimeHandle {
+    pub(crate) fn local() -> Option<RuntimeHandle> {
+        None
+    }
+
+    pub(crate) fn spawn<T>(&self, _task: T)
+    where
+        T: Future + Send + 'static,
+    {
+        match *self {}
+    }
+
+    async fn spawn_blocking<F, T>(&self, _f: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        match *self {}
+    }
+}
+
+#[cfg(not(feature = "tokio"))]
+pub(crate) fn with_runtime<F: Future>(_rt: Option<&RuntimeHandle>, fut: F) -> F {
+    fut
+}
+
+#[cfg(not(feature = "tokio"))]
+pub(crate) fn enter_runtime<R>(_rt: Option<&RuntimeHandle>, f: impl FnOnce() -> R) -> R {
+    f()
+}
+
+#[cfg(feature = "tokio")]
+impl RuntimeHandle {
+    pub(crate) fn new(handle: TokioHandle) -> Self {
+        RuntimeHandle(handle)
+    }
+
+    /// The runtime of the agent or server whose future is currently polled, if any.
+    pub(crate) fn local() -> Option<RuntimeHandle> {
+        LOCAL.with(|l| l.borrow().clone())
+    }
+
+    pub(crate) fn spawn<T>(&self, task: T)
+    where
+        T: Future + Send + 'static,
+    {
+        let task = with_runtime(Some(self), async move {
+            task.await;
+        });
+        self.0.spawn(task);
+    }
+
+    async fn spawn_blocking<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.0
+            .spawn_blocking(f)
+            .await
+            .expect("Blocking task to complete")
+    }
+}
+
+/// Polls the future in the given runtime, if any, instead of the global one.
+///
+/// Connections, timers and tasks spawned by the future use that runtime.
+#[cfg(feature = "tokio")]
+pub(crate) fn with_runtime<F: Future>(rt: Option<&RuntimeHandle>, fut: F) -> InRuntime<F> {
+    InRuntime {
+        rt: rt.cloned(),
+        fut: Box::pin(fut),
+    }
+}
+
+/// Runs the closure in the given runtime, if any, instead of the global one.
+#[cfg(feature = "tokio")]
+pub(crate) fn enter_runtime<R>(rt: Option<&RuntimeHandle>, f: impl FnOnce() -> R) -> R {
+    let rt = match rt {
+        Some(rt) => rt,
+        None => return f(),
+    };
+
+    let _enter = rt.0.enter();
+    let _restore = Restore(LOCAL.with(|l| l.replace(Some(rt.clone()))));
+
+    f()
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) struct InRuntime<F> {
+    rt: Option<RuntimeHandle>,
+    fut: Pin<Box<F>>,
+}
+
+#[cfg(feature = "tokio")]
+impl<F: Future> Future for InRuntime<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let fut = &mut this.fut;
+
+        enter_runtime(this.rt.as_ref(), || fut.as_mut().poll(cx))
+    }
+}
+
+// Puts back the previous local runtime, also if the poll panics.
+#[cfg(feature = "tokio")]
+struct Restore(Option<RuntimeHandle>);
+
+#[cfg(feature = "tokio")]
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let prev = self.0.take();
+        LOCAL.with(|l| *l.borrow_mut() = prev);
+    }
 }
 
 impl AsyncRuntime {
     fn into_inner(self) -> Inner {
         match self {
+            #[cfg(feature = "tokio")]
             AsyncRuntime::TokioSingle => {
                 async_tokio::use_default();
                 Inner::TokioSingle
             }
+            #[cfg(feature = "tokio")]
             AsyncRuntime::TokioShared => {
                 async_tokio::use_shared();
                 Inner::TokioShared
             }
+            #[cfg(feature = "tokio")]
             AsyncRuntime::TokioOwned(rt) => {
                 async_tokio::use_owned(rt);
                 Inner::TokioOwned
             }
+            #[cfg(feature = "async-std")]
+            AsyncRuntime::AsyncStd => {
+                #[cfg(feature = "tokio")]
+                async_tokio::unset_singletons();
+                Inner::AsyncStd
+            }
+            #[cfg(feature = "smol")]
+            AsyncRuntime::Smol => {
+                #[cfg(feature = "tokio")]
+                async_tokio::unset_singletons();
+                Inner::Smol
+            }
         }
     }
 
@@ -152,35 +442,99 @@ impl AsyncRuntime {
         trace!("Set runtime: {:?}", self);
 
         let inner = self.into_inner();
-        *current = inner;
+        *current = Some(inner);
     }
 
     pub(crate) async fn connect_tcp(addr: &str) -> Result<impl Stream, Error> {
         use Inner::*;
-        Ok(match current() {
-            TokioSingle | TokioShared | TokioOwned => async_tokio::connect_tcp(addr).await?,
-        })
+        let stream: Box<dyn Stream> = match current() {
+            #[cfg(feature = "tokio")]
+            TokioSingle | TokioShared | TokioOwned => {
+                Box::new(async_tokio::connect_tcp(addr).await?)
+            }
+            #[cfg(feature = "async-std")]
+            AsyncStd => Box::new(async_std_rt::connect_tcp(addr).await?),
+            #[cfg(feature = "smol")]
+            Smol => Box::new(async_smol::connect_tcp(addr).await?),
+        };
+        Ok(stream)
+    }
+
+    #[cfg(unix)]
+    pub(crate) async fn connect_unix(path: &Path) -> Result<impl Stream, Error> {
+        use Inner::*;
+        let stream: Box<dyn Stream> = match current() {
+            #[cfg(feature = "tokio")]
+            TokioSingle | TokioShared | TokioOwned => {
+                Box::new(async_tokio::connect_unix(path).await?)
+            }
+            #[cfg(feature = "async-std")]
+            AsyncStd => Box::new(async_std_rt::connect_unix(path).await?),
+            #[cfg(feature = "smol")]
+            Smol => Box::new(async_smol::connect_unix(path).await?),
+        };
+        Ok(stream)
     }
 
     pub(crate) async fn timeout(duration: Duration) {
         use Inner::*;
         match current() {
+            #[cfg(feature = "tokio")]
             TokioSingle | TokioShared | TokioOwned => async_tokio::timeout(duration).await,
+            #[cfg(feature = "async-std")]
+            AsyncStd => async_std_rt::timeout(duration).await,
+            #[cfg(feature = "smol")]
+            Smol => async_smol::timeout(duration).await,
         }
     }
 
     #[doc(hidden)]
     pub fn spawn<T: Future + Send + 'static>(task: T) {
+        if let Some(rt) = RuntimeHandle::local() {
+            rt.spawn(task);
+            return;
+        }
         use Inner::*;
         match current() {
+            #[cfg(feature = "tokio")]
             TokioSingle | TokioShared | TokioOwned => async_tokio::spawn(task),
+            #[cfg(feature = "async-std")]
+            AsyncStd => async_std_rt::spawn(task),
+            #[cfg(feature = "smol")]
+            Smol => async_smol::spawn(task),
+        }
+    }
+
+    /// Run blocking code, such as file system access, on a thread where it doesn't
+    /// hold up the async tasks.
+    pub(crate) async fn spawn_blocking<F, T>(f: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        if let Some(rt) = RuntimeHandle::local() {
+            return rt.spawn_blocking(f).await;
+        }
+        use Inner::*;
+        match current() {
+            #[cfg(feature = "tokio")]
+            TokioSingle | TokioShared | TokioOwned => async_tokio::spawn_blocking(f).await,
+            #[cfg(feature = "async-std")]
+            AsyncStd => async_std_rt::spawn_blocking(f).await,
+            #[cfg(feature = "smol")]
+            Smol => async_smol::spawn_blocking(f).await,
         }
     }
 
     pub(crate) fn block_on<F: Future>(task: F) -> F::Output {
         use Inner::*;
         match current() {
+            #[cfg(feature = "tokio")]
             TokioSingle | TokioShared | TokioOwned => async_tokio::block_on(task),
+            #[cfg(feature = "async-std")]
+            AsyncStd => async_std_rt::block_on(task),
+            #[cfg(feature = "smol")]
+            Smol => async_smol::block_on(task),
         }
     }
 
@@ -188,18 +542,43 @@ impl AsyncRuntime {
     pub(crate) async fn listen(addr: SocketAddr) -> Result<Listener, Error> {
         use Inner::*;
         match current() {
+            #[cfg(feature = "tokio")]
             TokioSingle | TokioShared | TokioOwned => async_tokio::listen(addr).await,
+            #[cfg(feature = "async-std")]
+            AsyncStd => async_std_rt::listen(addr).await,
+            #[cfg(feature = "smol")]
+            Smol => async_smol::listen(addr).await,
         }
     }
 
-    pub(crate) fn file_to_reader(file: std::fs::File) -> impl AsyncReadSeek {
+    #[cfg(all(feature = "server", unix))]
+    pub(crate) async fn listen_unix(path: &Path) -> Result<Listener, Error> {
         use Inner::*;
         match current() {
-            TokioSingle | TokioShared | TokioOwned => async_tokio::file_to_reader(file),
+            #[cfg(feature = "tokio")]
+            TokioSingle | TokioShared | TokioOwned => async_tokio::listen_unix(path).await,
+            #[cfg(feature = "async-std")]
+            AsyncStd => async_std_rt::listen_unix(path).await,
+            #[cfg(feature = "smol")]
+            Smol => async_smol::listen_unix(path).await,
         }
     }
+
+    pub(crate) fn file_to_reader(file: std::fs::File) -> impl AsyncReadSeek + Unpin + Send + Sync {
+        use Inner::*;
+        let reader: Box<dyn AsyncReadSeek + Unpin + Send + Sync> = match current() {
+            #[cfg(feature = "tokio")]
+            TokioSingle | TokioShared | TokioOwned => Box::new(async_tokio::file_to_reader(file)),
+            #[cfg(feature = "async-std")]
+            AsyncStd => Box::new(async_std_rt::file_to_reader(file)),
+            #[cfg(feature = "smol")]
+            Smol => Box::new(async_smol::file_to_reader(file)),
+        };
+        reader
+    }
 }
 
+#[cfg(feature = "tokio")]
 pub(crate) mod async_tokio {
     use super::*;
     use crate::tokio_conv::from_tokio;
@@ -218,7 +597,7 @@ pub(crate) mod async_tokio {
         *rt_singleton = rt;
     }
 
-    fn unset_singletons() {
+    pub(crate) fn unset_singletons() {
         let unset = || {
             let rt = RUNTIME.lock().unwrap().take();
             {
@@ -268,6 +647,10 @@ pub(crate) mod async_tokio {
     pub(crate) async fn connect_tcp(addr: &str) -> Result<impl Stream, Error> {
         Ok(from_tokio(TcpStream::connect(addr).await?))
     }
+    #[cfg(unix)]
+    pub(crate) async fn connect_unix(path: &Path) -> Result<impl Stream, Error> {
+        Ok(from_tokio(tokio::net::UnixStream::connect(path).await?))
+    }
     pub(crate) async fn timeout(duration: Duration) {
         tokio::time::sleep(duration).await;
     }
@@ -280,6 +663,17 @@ pub(crate) mod async_tokio {
             task.await;
         });
     }
+    pub(crate) async fn spawn_blocking<F, T>(f: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let join = {
+            let handle = HANDLE.lock().unwrap();
+            handle.as_ref().unwrap().spawn_blocking(f)
+        };
+        join.await.expect("Blocking task to complete")
+    }
     pub(crate) fn block_on<F: Future>(task: F) -> F::Output {
         let mut rt = RUNTIME.lock().unwrap();
         if let Some(rt) = rt.as_mut() {
@@ -296,12 +690,122 @@ pub(crate) mod async_tokio {
         Ok(Listener::Tokio(listener))
     }
 
+    #[cfg(all(feature = "server", unix))]
+    pub(crate) async fn listen_unix(path: &Path) -> Result<Listener, Error> {
+        let listener = tokio::net::UnixListener::bind(path)?;
+        Ok(Listener::TokioUnix(listener))
+    }
+
     pub(crate) fn file_to_reader(file: std::fs::File) -> impl AsyncReadSeek {
         let file = tokio::fs::File::from_std(file);
         from_tokio(file)
     }
 }
 
+#[cfg(feature = "async-std")]
+pub(crate) mod async_std_rt {
+    use super::*;
+    use async_std::net::TcpStream;
+    use async_std::task;
+
+    pub(crate) async fn connect_tcp(addr: &str) -> Result<impl Stream, Error> {
+        Ok(TcpStream::connect(addr).await?)
+    }
+    #[cfg(unix)]
+    pub(crate) async fn connect_unix(path: &Path) -> Result<impl Stream, Error> {
+        Ok(async_std::os::unix::net::UnixStream::connect(path).await?)
+    }
+    pub(crate) async fn timeout(duration: Duration) {
+        task::sleep(duration).await;
+    }
+    pub(crate) fn spawn<T>(task: T)
+    where
+        T: Future + Send + 'static,
+    {
+        task::spawn(async move {
+            task.await;
+        });
+    }
+    pub(crate) async fn spawn_blocking<F, T>(f: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        task::spawn_blocking(f).await
+    }
+    pub(crate) fn block_on<F: Future>(task: F) -> F::Output {
+        task::block_on(task)
+    }
+
+    #[cfg(feature = "server")]
+    pub(crate) async fn listen(addr: SocketAddr) -> Result<Listener, Error> {
+        let listener = async_std::net::TcpListener::bind(addr).await?;
+        Ok(Listener::AsyncStd(listener))
+    }
+
+    #[cfg(all(feature = "server", unix))]
+    pub(crate) async fn listen_unix(path: &Path) -> Result<Listener, Error> {
+        let listener = async_std::os::unix::net::UnixListener::bind(path).await?;
+        Ok(Listener::AsyncStdUnix(listener))
+    }
+
+    pub(crate) fn file_to_reader(file: std::fs::File) -> impl AsyncReadSeek {
+        async_std::fs::File::from(file)
+    }
+}
+
+#[cfg(feature = "smol")]
+pub(crate) mod async_smol {
+    use super::*;
+    use smol::net::TcpStream;
+
+    pub(crate) async fn connect_tcp(addr: &str) -> Result<impl Stream, Error> {
+        Ok(TcpStream::connect(addr).await?)
+    }
+    #[cfg(unix)]
+    pub(crate) async fn connect_unix(path: &Path) -> Result<impl Stream, Error> {
+        Ok(smol::net::unix::UnixStream::connect(path).await?)
+    }
+    pub(crate) async fn timeout(duration: Duration) {
+        smol::Timer::after(duration).await;
+    }
+    pub(crate) fn spawn<T>(task: T)
+    where
+        T: Future + Send + 'static,
+    {
+        smol::spawn(async move {
+            task.await;
+        })
+        .detach();
+    }
+    pub(crate) async fn spawn_blocking<F, T>(f: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        smol::unblock(f).await
+    }
+    pub(crate) fn block_on<F: Future>(task: F) -> F::Output {
+        smol::block_on(task)
+    }
+
+    #[cfg(feature = "server")]
+    pub(crate) async fn listen(addr: SocketAddr) -> Result<Listener, Error> {
+        let listener = smol::net::TcpListener::bind(addr).await?;
+        Ok(Listener::Smol(listener))
+    }
+
+    #[cfg(all(feature = "server", unix))]
+    pub(crate) async fn listen_unix(path: &Path) -> Result<Listener, Error> {
+        let listener = smol::net::unix::UnixListener::bind(path)?;
+        Ok(Listener::SmolUnix(listener))
+    }
+
+    pub(crate) fn file_to_reader(file: std::fs::File) -> impl AsyncReadSeek {
+        smol::fs::File::from(file)
+    }
+}
+
 // TODO does this cause memory leaks?
 pub async fn never() {
     poll_fn::<(), _>(|_| Poll::Pending).await;
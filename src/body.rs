@@ -1,19 +1,26 @@
 //! Request and response body. content-encoding, charset etc.
 
+use crate::async_impl::{enter_runtime, RuntimeHandle};
 use crate::body_codec::{BodyCodec, BodyImpl};
 use crate::bw::BandwidthMonitor;
 use crate::charset::CharCodec;
+use crate::format::{mime_of, BodyFormat, Json};
 use crate::from_utf8::from_utf8_lossy_replace;
 use crate::head_ext::HeaderMapExt;
+use crate::json_lines::{JsonLines, JsonLinesReader, CT_NDJSON};
+use crate::observe::BodyObserver;
 use crate::params::HReqParams;
+use crate::progress::Direction;
 use crate::uninit::UninitBuf;
 use crate::AsyncRead;
 use crate::AsyncRuntime;
 use crate::Error;
+use bytes::Bytes;
 use encoding_rs::Encoding;
 use futures_util::future::poll_fn;
 use futures_util::io::AsyncReadExt;
 use futures_util::ready;
+use futures_util::stream::{self, Stream};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::fmt;
@@ -27,7 +34,6 @@ use std::task::{Context, Poll};
 
 const CT_TEXT: &str = "text/plain; charset=utf-8";
 const CT_BIN: &str = "application/octet-stream";
-const CT_JSON: &str = "application/json; charset=utf-8";
 const MAX_STRING_SIZE: usize = 10 * 1024 * 1024;
 
 /// Body of an http request or response.
@@ -69,6 +75,7 @@ const MAX_STRING_SIZE: usize = 10 * 1024 * 1024;
 /// | `Body::from_file(file)`                | `file`               |
 /// | `Body::from_async_read(reader, None)`  | -                    |
 /// | `Body::from_sync_read(reader, None)`   | -                    |
+/// | `Body::from_json_lines(values)`        | -                    |
 ///
 /// ## Readers and performance
 ///
@@ -140,6 +147,10 @@ const MAX_STRING_SIZE: usize = 10 * 1024 * 1024;
 ///
 ///   * `content-type: text/html charset=iso8859-1`
 ///
+/// Responses of `text/*` without a charset in the header are sniffed. A byte order
+/// mark, `<meta charset>` in `text/html` or the declaration in `text/xml` decides,
+/// and failing those the charset is guessed from the first 1024 bytes.
+///
 /// The wanted charset is assumed to be `utf-8` unless changed by [`charset_decode_target`].
 ///
 /// The function can be disabled by using [`charset_decode`].
@@ -167,13 +178,18 @@ pub struct Body {
     codec: BodyCodec,
     length: Option<u64>, // incoming length if given with reader
     content_typ: Option<&'static str>,
+    incoming_typ: Option<String>,
     override_source_enc: Option<&'static Encoding>,
     has_read: bool,
     char_codec: Option<CharCodec>,
     deadline_fut: Option<Pin<Box<dyn Future<Output = io::Error> + Send + Sync>>>,
+    runtime: Option<RuntimeHandle>,
     unfinished_recs: Option<Arc<()>>,
+    observers: Vec<Box<dyn BodyObserver>>,
     prebuffered: Option<Cursor<Vec<u8>>>,
+    buffered: Option<Bytes>,
     bw: Option<BandwidthMonitor>,
+    trailers: Option<http::HeaderMap>,
 }
 
 impl Body {
@@ -317,8 +333,11 @@ impl Body {
     ///     .send(vec![0x42, 0x43]).block().unwrap();
     /// ```
     pub fn from_vec(bytes: Vec<u8>) -> Self {
+        let bytes = Bytes::from(bytes);
         let len = bytes.len() as u64;
-        Self::from_sync_read(io::Cursor::new(bytes), Some(len)).ctype(CT_BIN)
+        let mut new = Self::from_sync_read(io::Cursor::new(bytes.clone()), Some(len)).ctype(CT_BIN);
+        new.buffered = Some(bytes);
+        new
     }
 
     /// Creates a body from a `std::fs::File`.
@@ -380,8 +399,80 @@ impl Body {
     /// let body = Body::from_json(&json);
     /// ```
     pub fn from_json<B: Serialize + ?Sized>(json: &B) -> Self {
-        let vec = serde_json::to_vec(json).expect("Failed to encode JSON");
-        Self::from_vec(vec).ctype(CT_JSON)
+        Self::from_serde::<Json, B>(json).expect("Failed to encode JSON")
+    }
+
+    /// Creates a body from a type serializable in the given [`BodyFormat`].
+    ///
+    /// This also sets the `content-type` of the format and `content-length` headers.
+    /// Fails if the value can't be serialized in the format.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hreq::{Body, Json};
+    /// use serde_derive::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// struct MyThing {
+    ///   name: String,
+    ///   age: u8,
+    /// }
+    ///
+    /// let thing = MyThing {
+    ///   name: "Karl Kajal".to_string(),
+    ///   age: 32,
+    /// };
+    ///
+    /// let body = Body::from_serde::<Json, _>(&thing)?;
+    /// # Ok::<(), hreq::Error>(())
+    /// ```
+    ///
+    /// [`BodyFormat`]: trait.BodyFormat.html
+    pub fn from_serde<F: BodyFormat, B: Serialize + ?Sized>(value: &B) -> Result<Self, Error> {
+        let vec = F::to_vec(value)?;
+        Ok(Self::from_vec(vec).ctype(F::CONTENT_TYPE))
+    }
+
+    /// Creates a streaming newline-delimited JSON (NDJSON) body from an iterator.
+    ///
+    /// Each value is serialized to one line when the body is sent, the values are not
+    /// held in memory. Sets `content-type: application/x-ndjson`. The length is not
+    /// known up front, which means HTTP/1.1 uses chunked encoding.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    /// use hreq::Body;
+    ///
+    /// let records = (0..1_000_000).map(|n| vec![n, n * 2]);
+    ///
+    /// Request::post("https://post-to-here")
+    ///     .send(Body::from_json_lines(records)).block().unwrap();
+    /// ```
+    pub fn from_json_lines<I>(values: I) -> Self
+    where
+        I: IntoIterator,
+        I::IntoIter: Send + 'static,
+        I::Item: Serialize,
+    {
+        Self::from_json_lines_stream(stream::iter(values))
+    }
+
+    /// Creates a streaming newline-delimited JSON (NDJSON) body from a `Stream`.
+    ///
+    /// Like [`from_json_lines`], but the values are produced asynchronously. Values are
+    /// pulled from the stream as the body is sent.
+    ///
+    /// [`from_json_lines`]: struct.Body.html#method.from_json_lines
+    pub fn from_json_lines_stream<S>(values: S) -> Self
+    where
+        S: Stream + Send + 'static,
+        S::Item: Serialize,
+    {
+        let reader = JsonLinesReader::new(values);
+        Body::from_async_read(reader, None).ctype(CT_NDJSON)
     }
 
     /// Creates a body from anything implementing the `AsyncRead` trait.
@@ -426,16 +517,53 @@ impl Body {
             codec,
             length,
             content_typ: None,
+            incoming_typ: None,
             override_source_enc: None,
             has_read: false,
             char_codec: None,
             deadline_fut: None,
+            runtime: None,
             unfinished_recs: None,
+            observers: vec![],
             prebuffered: None,
+            buffered: None,
             bw: None,
+            trailers: None,
         }
     }
 
+    /// Set trailing headers to send after the body data.
+    ///
+    /// Trailers are sent for HTTP/2 as a trailing `HEADERS` frame. hreq's HTTP/1.1
+    /// implementation does not support trailers, and they are dropped with a warning.
+    ///
+    /// ```
+    /// use hreq::prelude::*;
+    ///
+    /// let mut trailers = http::HeaderMap::new();
+    /// trailers.insert("x-checksum", "abc123".parse().unwrap());
+    ///
+    /// let body = Body::from_str("Hello").with_trailers(trailers);
+    ///
+    /// assert_eq!(body.trailers().unwrap()["x-checksum"], "abc123");
+    /// ```
+    pub fn with_trailers(mut self, trailers: http::HeaderMap) -> Self {
+        self.trailers = Some(trailers);
+        self
+    }
+
+    /// Trailing headers of the body.
+    ///
+    /// For a body we send, these are the trailers set using [`with_trailers`].
+    ///
+    /// For a received body, the trailers are only available once the body is
+    /// fully read. Received trailers are only supported for HTTP/2.
+    ///
+    /// [`with_trailers`]: struct.Body.html#method.with_trailers
+    pub fn trailers(&self) -> Option<&http::HeaderMap> {
+        self.trailers.as_ref().or_else(|| self.codec.trailers())
+    }
+
     fn ctype(mut self, c: &'static str) -> Self {
         self.content_typ = Some(c);
         self
@@ -445,6 +573,10 @@ impl Body {
         self.unfinished_recs = Some(unfin);
     }
 
+    pub(crate) fn add_observer(&mut self, observer: impl BodyObserver + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
     pub(crate) fn set_bw_monitor(&mut self, bw: Option<BandwidthMonitor>) {
         self.bw = bw;
     }
@@ -477,6 +609,11 @@ impl Body {
         self.content_typ
     }
 
+    /// The entire contents, if the body was created from memory and is not yet read.
+    pub(crate) fn buffered(&self) -> Option<&[u8]> {
+        self.buffered.as_deref().filter(|_| !self.has_read)
+    }
+
     pub(crate) fn is_configurable(&self) -> bool {
         !self.has_read
     }
@@ -507,12 +644,37 @@ impl Body {
 
         self.deadline_fut = Some(params.deadline().delay_fut());
 
+        // the timers of the body keep using the runtime of the agent or server,
+        // also when read after the request or handler is done.
+        self.runtime = RuntimeHandle::local();
+
+        if is_incoming {
+            self.incoming_typ = headers.get_str("content-type").map(|s| s.to_string());
+        }
+
         let mut new_codec = None;
         if let BodyCodec::Deferred(reader) = &mut self.codec {
             if let Some(mut reader) = reader.take() {
                 // pass bw monitor on to reader where the counting actually happens.
                 reader.set_bw_monitor(self.bw.clone());
 
+                // progress is also counted on the bytes as received.
+                if is_incoming {
+                    if let Some(throttle) = &params.throttle {
+                        reader.set_throttle(throttle.for_body());
+                    }
+                    if let Some(limit) = params.body_idle_timeout {
+                        reader.set_idle_timeout(limit);
+                    }
+                    if let Some(cancel) = &params.cancel {
+                        reader.set_cancel(cancel);
+                    }
+                    if let Some(progress) = &params.progress {
+                        let total = headers.get_as::<u64>("content-length");
+                        reader.set_progress(progress.track(Direction::Download, total));
+                    }
+                }
+
                 let use_enc =
                     !is_incoming && params.content_encode || is_incoming && params.content_decode;
 
@@ -536,13 +698,17 @@ impl Body {
             &params.charset_tx
         };
 
-        // TODO sniff charset from html pages like
-        // <meta content="text/html; charset=UTF-8" http-equiv="Content-Type">
         if let Some((from, to)) =
             charset_config.resolve(is_incoming, headers, self.override_source_enc)
         {
-            // don't use a codec if this is pass-thru
-            if from == to {
+            let sniff = charset_config.sniff_markup(is_incoming, headers, self.override_source_enc);
+
+            if let Some(markup) = sniff {
+                // without a charset in the headers, the body tells us.
+                self.char_codec = Some(CharCodec::sniffing(markup, to));
+                trace!("Charset codec (incoming): sniff {:?}", markup);
+            } else if from == to {
+                // don't use a codec if this is pass-thru
                 trace!("Charset codec pass through: {:?}", from);
             } else {
                 self.char_codec = Some(CharCodec::new(from, to));
@@ -741,6 +907,96 @@ impl Body {
         Ok(serde_json::from_str(&s)?)
     }
 
+    /// Reads the body to end and deserializes it in the given [`BodyFormat`]. Body is
+    /// limited to 10MB.
+    ///
+    /// Incoming bodies with a `content-type` that isn't of the format are an error. Bodies
+    /// without `content-type` are read regardless.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    /// use hreq::Json;
+    /// use serde_derive::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct MyThing {
+    ///   name: String,
+    ///   age: String,
+    /// }
+    ///
+    /// let mut res = Request::get("http://foo")
+    ///   .call().block().unwrap();
+    ///
+    /// let thing: MyThing = res.body_mut().read_as::<Json, _>().block().unwrap();
+    /// ```
+    ///
+    /// [`BodyFormat`]: trait.BodyFormat.html
+    pub async fn read_as<F: BodyFormat, T: DeserializeOwned>(&mut self) -> Result<T, Error> {
+        if let Some(ctype) = &self.incoming_typ {
+            let mime = mime_of(ctype);
+            if !F::is_mime(&mime) {
+                return Err(Error::Proto(format!(
+                    "Unexpected content-type for {}: {}",
+                    F::CONTENT_TYPE,
+                    ctype
+                )));
+            }
+        }
+
+        // Like read_to_string, textual formats such as JSON expect utf-8.
+        if let Some(char_codec) = &mut self.char_codec {
+            char_codec.remove_encoder();
+        }
+
+        let vec = self.read_to_vec(MAX_STRING_SIZE).await?;
+
+        F::from_slice(&vec)
+    }
+
+    /// Reads the body as newline-delimited JSON (NDJSON), one value per line.
+    ///
+    /// Lines are parsed as they are read, which means the body is never held in memory
+    /// in its entirety. Lines are limited to 1MB, which can be changed using
+    /// [`max_line_size`]. See [`JsonLines`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    /// use futures_util::stream::StreamExt;
+    /// use serde_derive::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Record {
+    ///   id: u64,
+    /// }
+    ///
+    /// async fn import() -> Result<(), hreq::Error> {
+    ///     let mut res = Request::get("https://export-from-here").call().await?;
+    ///
+    ///     let mut records = res.body_mut().json_lines::<Record>();
+    ///
+    ///     while let Some(record) = records.next().await {
+    ///         println!("{}", record?.id);
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// [`max_line_size`]: struct.JsonLines.html#method.max_line_size
+    /// [`JsonLines`]: struct.JsonLines.html
+    pub fn json_lines<T: DeserializeOwned>(&mut self) -> JsonLines<'_, T> {
+        // Remove any user set char encoder since JSON is utf-8.
+        if let Some(char_codec) = &mut self.char_codec {
+            char_codec.remove_encoder();
+        }
+
+        JsonLines::new(self)
+    }
+
     /// Reads to body to end and discards it.
     ///
     /// HTTP/1.1 has no "multiplexing" of several concurrent request over the same socket;
@@ -833,33 +1089,47 @@ impl AsyncRead for Body {
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
         let this = self.get_mut();
-        if !this.has_read {
-            this.has_read = true;
+        let rt = this.runtime.clone();
+
+        enter_runtime(rt.as_ref(), || this.poll_body(cx, buf))
+    }
+}
+
+impl Body {
+    fn poll_body(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        if !self.has_read {
+            self.has_read = true;
         }
 
         // use deadline if it's present
-        let deadl = this.deadline_fut.as_mut();
+        let deadl = self.deadline_fut.as_mut();
         if let Some(deadl) = deadl {
             if let Poll::Ready(err) = deadl.as_mut().poll(cx) {
                 return Poll::Ready(Err(err));
             }
         }
 
-        let amount = if let Some(prebuf) = &mut this.prebuffered {
+        let amount = if let Some(prebuf) = &mut self.prebuffered {
             // entire contents is prebuffered
             prebuf.read(buf)?
         } else {
             // read from underlying
-            ready!(if let Some(char_codec) = &mut this.char_codec {
-                char_codec.poll_codec(cx, &mut this.codec, buf)
+            ready!(if let Some(char_codec) = &mut self.char_codec {
+                char_codec.poll_codec(cx, &mut self.codec, buf)
             } else {
-                Pin::new(&mut this.codec).poll_read(cx, buf)
+                Pin::new(&mut self.codec).poll_read(cx, buf)
             })?
         };
 
+        for observer in &self.observers {
+            observer.received(&buf[..amount]);
+        }
+
         if amount == 0 {
             // by removing this arc, we reduce the unfinished recs count.
-            this.unfinished_recs.take();
+            self.unfinished_recs.take();
+            // and the observers are done, such as the rate limit permit.
+            self.observers.clear();
         }
 
         Ok(amount).into()
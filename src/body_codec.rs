@@ -1,6 +1,11 @@
 use crate::bw::BandwidthMonitor;
+use crate::cancel::{cancelled_io, Cancel, Listener};
+use crate::deadline::IdleTimer;
+use crate::progress::ProgressTracker;
+use crate::throttle::Throttle;
 use crate::uninit::UninitBuf;
 use crate::AsyncRead;
+use crate::AsyncRuntime;
 use bytes::Bytes;
 use futures_io::AsyncBufRead;
 use futures_util::future::poll_fn;
@@ -8,10 +13,12 @@ use futures_util::ready;
 use h2::RecvStream as H2RecvStream;
 use hreq_h1::RecvStream as H1RecvStream;
 use std::fmt;
+use std::future::Future;
 use std::io;
 use std::io::Read;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 #[cfg(feature = "gzip")]
 use async_compression::futures::bufread::{GzipDecoder, GzipEncoder};
@@ -76,6 +83,22 @@ impl BodyCodec {
         }
     }
 
+    fn reader(&self) -> Option<&BodyReader> {
+        match self {
+            BodyCodec::Deferred(r) => r.as_ref(),
+            BodyCodec::Pass(r) => Some(r),
+            #[cfg(feature = "gzip")]
+            BodyCodec::GzipDecoder(r) => Some(r.get_ref().get_ref()),
+            #[cfg(feature = "gzip")]
+            BodyCodec::GzipEncoder(r) => Some(r.get_ref().get_ref()),
+        }
+    }
+
+    /// Trailers received from the underlying stream, once fully read.
+    pub fn trailers(&self) -> Option<&http::HeaderMap> {
+        self.reader().and_then(|r| r.trailers.as_ref())
+    }
+
     fn reader_mut(&mut self) -> Option<&mut BodyReader> {
         match self {
             BodyCodec::Deferred(r) => r.as_mut(),
@@ -120,6 +143,12 @@ pub struct BodyReader {
     h2_leftover_bytes: Option<H2BytesReader>,
     is_finished: bool,
     bw: Option<BandwidthMonitor>,
+    progress: Option<ProgressTracker>,
+    throttle: Option<Throttle>,
+    pause: Option<Pin<Box<dyn Future<Output = ()> + Send + Sync>>>,
+    idle: Option<IdleTimer>,
+    cancel: Option<Listener>,
+    trailers: Option<http::HeaderMap>,
 }
 
 pub(crate) enum BodyImpl {
@@ -140,6 +169,12 @@ impl BodyReader {
             consumed: 0,
             is_finished: false,
             bw: None,
+            progress: None,
+            throttle: None,
+            pause: None,
+            idle: None,
+            cancel: None,
+            trailers: None,
         }
     }
 
@@ -147,6 +182,22 @@ impl BodyReader {
         self.bw = bw;
     }
 
+    pub(crate) fn set_progress(&mut self, progress: ProgressTracker) {
+        self.progress = Some(progress);
+    }
+
+    pub(crate) fn set_throttle(&mut self, throttle: Throttle) {
+        self.throttle = Some(throttle);
+    }
+
+    pub(crate) fn set_idle_timeout(&mut self, limit: Duration) {
+        self.idle = Some(IdleTimer::new(limit));
+    }
+
+    pub(crate) fn set_cancel(&mut self, cancel: &Cancel) {
+        self.cancel = Some(cancel.listen());
+    }
+
     /// Fills the internal buffer from the underlying reader. If prebuffer_to is > 0 will
     /// try to fill to that level.
     ///
@@ -171,6 +222,21 @@ impl BodyReader {
             return Ok(0).into();
         }
 
+        if let Some(cancel) = &mut self.cancel {
+            if Pin::new(cancel).poll(cx).is_ready() {
+                // dropping the stream resets it (h2) or closes the connection (h1).
+                self.imp = BodyImpl::RequestEmpty;
+                self.h2_leftover_bytes = None;
+                return Err(cancelled_io()).into();
+            }
+        }
+
+        // a throttle pause after the previous read.
+        if let Some(pause) = &mut self.pause {
+            ready!(pause.as_mut().poll(cx));
+            self.pause = None;
+        }
+
         // h2 streams might have leftovers to use up before reading any more.
         if let Some(br) = &mut self.h2_leftover_bytes {
             let amt = br.read(buf)?;
@@ -182,6 +248,44 @@ impl BodyReader {
             return Ok(amt).into();
         }
 
+        let amount = match self.poll_read_imp(cx, buf) {
+            Poll::Ready(amount) => {
+                if let Some(idle) = &mut self.idle {
+                    idle.reset();
+                }
+                amount?
+            }
+            Poll::Pending => {
+                // waiting on the other side, which is what the idle timer is for.
+                if let Some(idle) = &mut self.idle {
+                    idle.poll_idle(cx)?;
+                }
+                return Poll::Pending;
+            }
+        };
+
+        if amount == 0 {
+            self.is_finished = true;
+        }
+
+        if let Some(bw) = &self.bw {
+            bw.append_read_bytes(amount);
+        }
+
+        if let Some(progress) = &mut self.progress {
+            progress.add(amount);
+        }
+
+        if let Some(throttle) = &self.throttle {
+            if let Some(pause) = throttle.consume(amount) {
+                self.pause = Some(Box::pin(AsyncRuntime::timeout(pause)));
+            }
+        }
+
+        Ok(amount).into()
+    }
+
+    fn poll_read_imp(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
         let amount = match &mut self.imp {
             BodyImpl::RequestEmpty => 0,
             BodyImpl::RequestAsyncRead(reader) => ready!(Pin::new(reader).poll_read(cx, buf))?,
@@ -221,19 +325,18 @@ impl BodyReader {
 
                     amt
                 } else {
+                    // end of data, there might be trailers.
+                    self.trailers = ready!(recv.poll_trailers(cx)).map_err(|e| {
+                        let other = format!("Other h2 error (poll_trailers): {}", e);
+                        e.into_io()
+                            .unwrap_or_else(|| io::Error::new(io::ErrorKind::Other, other))
+                    })?;
+
                     0
                 }
             }
         };
 
-        if amount == 0 {
-            self.is_finished = true;
-        }
-
-        if let Some(bw) = &self.bw {
-            bw.append_read_bytes(amount);
-        }
-
         Ok(amount).into()
     }
 
@@ -253,7 +356,7 @@ impl BodyReader {
                 // when prebuffering, we are reading until the buffer len() is as much as allowed.
                 self.prebuffer_to > 0 && buffer_len == self.prebuffer_to
                 // when not prebuffering, any content is enough.
-                || self.prebuffer_to == 0 && self.buffer.len() > 0;
+                || self.prebuffer_to == 0 && !self.buffer.is_empty();
 
             if self.is_finished || read_enough {
                 // only first poll_fill_buf is prebuffering.
@@ -285,7 +388,7 @@ impl AsyncBufRead for BodyReader {
 
         ready!(this.poll_refill_buf(cx))?;
 
-        return Ok(this.unconsumed()).into();
+        Ok(this.unconsumed()).into()
     }
 
     fn consume(self: Pin<&mut Self>, amt: usize) {
@@ -1,3 +1,4 @@
+use crate::bw::BandwidthMonitor;
 use crate::Error;
 use bytes::Bytes;
 use futures_util::future::poll_fn;
@@ -6,7 +7,8 @@ use hreq_h1 as h1;
 /// Generalisation over sending body data.
 pub(crate) enum BodySender {
     H1(h1::SendStream),
-    H2(h2::SendStream<Bytes>),
+    /// The monitor of the connection, if any, to record the activity of sending.
+    H2(h2::SendStream<Bytes>, Option<BandwidthMonitor>),
 }
 
 impl BodySender {
@@ -17,7 +19,7 @@ impl BodySender {
 
         match self {
             BodySender::H1(s) => Ok(s.send_data(buf, false).await?),
-            BodySender::H2(s) => {
+            BodySender::H2(s, bw) => {
                 loop {
                     if buf.is_empty() {
                         break;
@@ -42,10 +44,14 @@ impl BodySender {
                         }
                     };
 
-                    let data = (&buf[..actual_capacity]).to_vec().into();
+                    let data = buf[..actual_capacity].to_vec().into();
 
                     s.send_data(data, false)?;
 
+                    if let Some(bw) = bw {
+                        bw.record_activity();
+                    }
+
                     buf = &buf[actual_capacity..];
                 }
 
@@ -54,10 +60,21 @@ impl BodySender {
         }
     }
 
-    pub async fn send_end(&mut self) -> Result<(), Error> {
+    pub async fn send_end(&mut self, trailers: Option<&http::HeaderMap>) -> Result<(), Error> {
         match self {
-            BodySender::H1(s) => Ok(s.send_data(&[], true).await?),
-            BodySender::H2(s) => Ok(s.send_data(Bytes::new(), true)?),
+            BodySender::H1(s) => {
+                if trailers.is_some() {
+                    warn!("Trailers are not supported for http/1.1 and are dropped");
+                }
+                Ok(s.send_data(&[], true).await?)
+            }
+            BodySender::H2(s, _) => {
+                if let Some(trailers) = trailers {
+                    Ok(s.send_trailers(trailers.clone())?)
+                } else {
+                    Ok(s.send_data(Bytes::new(), true)?)
+                }
+            }
         }
     }
 }
@@ -1,25 +1,35 @@
-//! Calculates h2 window size given a current bandwidth estimation.
+//! Calculates h2 window size given a current bandwidth estimation, and keeps idle
+//! h2 connections alive.
 //!
 //! Ideas from here: https://github.com/hyperium/hyper/blob/aafeeb7638c42a2f2e79a42a06625d4d2cfc76e3/src/proto/h2/ping.rs
 //!
 //! # BDP Algorithm
 //!
 //! 1. When receiving a DATA frame, if a BDP ping isn't outstanding:
-//!   1a. Record current time.
-//!   1b. Send a BDP ping.
+//!    1a. Record current time.
+//!    1b. Send a BDP ping.
 //! 2. Increment the number of received bytes.
 //! 3. When the BDP ping ack is received:
-//!   3a. Record duration from sent time.
-//!   3b. Merge RTT with a running average.
-//!   3c. Calculate bdp as bytes/rtt.
-//!   3d. If bdp is over 2/3 max, set new max to bdp and update windows.
+//!    3a. Record duration from sent time.
+//!    3b. Merge RTT with a running average.
+//!    3c. Calculate bdp as bytes/rtt.
+//!    3d. If bdp is over 2/3 max, set new max to bdp and update windows.
 
-use futures_util::ready;
+use crate::h2_config::Http2Config;
+use crate::AsyncRuntime;
 use h2::{Ping, PingPong};
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
 
+/// Monitors an h2 connection using the connection's single `PingPong`.
+///
+/// The pings serve two purposes:
+///
+///   * Bandwidth estimation (BDP), to update the h2 window sizes.
+///   * Keep-alive, to detect a dead peer on an idle connection.
 #[derive(Clone)]
 pub(crate) struct BandwidthMonitor {
     inner: Arc<Mutex<Inner>>,
@@ -27,91 +37,214 @@ pub(crate) struct BandwidthMonitor {
 
 type WindowSize = u32;
 
+/// Outcome of polling the pings of the monitor.
+pub(crate) enum PingEvent {
+    /// The BDP estimation wants a new window size.
+    WindowUpdate(WindowSize),
+    /// The peer didn't answer a keep-alive ping in time.
+    KeepAliveTimeout,
+    /// Sending a ping or receiving the pong failed, the connection is dead.
+    Failed(h2::Error),
+}
+
 impl BandwidthMonitor {
-    pub fn new(pinger: PingPong) -> Self {
-        BandwidthMonitor {
+    /// Creates a monitor if the config asks for any pinging.
+    ///
+    /// `window` is the configured window size, which the BDP never goes below.
+    pub fn from_config(
+        config: &Http2Config,
+        window: WindowSize,
+        pinger: impl FnOnce() -> PingPong,
+    ) -> Option<Self> {
+        let bdp = if config.is_bandwidth_auto_tune() {
+            Some(Bdp::new(window))
+        } else {
+            None
+        };
+
+        let keep_alive = config
+            .keep_alive()
+            .map(|(interval, timeout)| KeepAlive::new(interval, timeout));
+
+        if bdp.is_none() && keep_alive.is_none() {
+            return None;
+        }
+
+        Some(BandwidthMonitor {
             inner: Arc::new(Mutex::new(Inner {
-                pinger,
+                pinger: pinger(),
                 ping_sent: None,
+                last_active: Instant::now(),
                 bytes: 0,
-                bdp: Bdp::new(),
+                bdp,
+                keep_alive,
             })),
-        }
+        })
     }
 
     pub fn append_read_bytes(&self, bytes: usize) {
         let mut lock = self.inner.lock().unwrap();
         lock.bytes += bytes;
+        lock.last_active = Instant::now();
+    }
+
+    /// Records traffic on the connection, which makes keep-alive pings unnecessary.
+    pub fn record_activity(&self) {
+        let mut lock = self.inner.lock().unwrap();
+        lock.last_active = Instant::now();
     }
 
-    /// Poll for ping/pong to drive banwidth monitoring resulting in h2 window updates.
+    /// Poll for ping/pong to drive banwidth monitoring and keep-alive.
     ///
     /// This poll() is special, it's just piggy-backed on polling the underlying connection.
-    /// Hence it doesn't register any waker unless it's Pending on an outstanding pong.
-    pub fn poll_window_update(&mut self, cx: &mut Context<'_>) -> Poll<WindowSize> {
+    /// Bandwidth monitoring doesn't register any waker unless it's Pending on an
+    /// outstanding pong. Keep-alive registers timers to wake up an idle connection.
+    pub fn poll_ping(&mut self, cx: &mut Context<'_>) -> Poll<PingEvent> {
         let mut lock = self.inner.lock().unwrap();
-
-        if lock.ping_sent.is_none() {
-            // send ping if we haven't done so.
-
-            match lock.pinger.send_ping(Ping::opaque()) {
-                Ok(_) => {
-                    lock.ping_sent = Some(Instant::now());
+        let inner = &mut *lock;
+
+        // bdp pings are sent when the connection is polled, but not straight after a pong.
+        let mut bdp_ping = inner.bdp.is_some();
+
+        loop {
+            if let Some(ping_sent) = inner.ping_sent {
+                match inner.pinger.poll_pong(cx) {
+                    Poll::Ready(Ok(_pong)) => {
+                        let rtt = ping_sent.elapsed();
+
+                        inner.ping_sent = None;
+                        inner.last_active = Instant::now();
+
+                        let bytes = inner.bytes;
+
+                        // reset back for next ping
+                        inner.bytes = 0;
+
+                        trace!("Received pong; bytes = {}, rtt = {:?}", bytes, rtt);
+
+                        if let Some(bdp) = &mut inner.bdp {
+                            if let Some(window_update) = bdp.update(bytes, rtt) {
+                                return Poll::Ready(PingEvent::WindowUpdate(window_update));
+                            }
+                        }
+
+                        bdp_ping = false;
+                    }
+
+                    Poll::Ready(Err(e)) => {
+                        debug!("Pong error: {}", e);
+                        return Poll::Ready(PingEvent::Failed(e));
+                    }
+
+                    Poll::Pending => {
+                        if let Some(ka) = &mut inner.keep_alive {
+                            let deadline = ping_sent + ka.timeout;
+                            if ka.poll_timer(cx, deadline).is_ready() {
+                                debug!("Keep-alive ping timed out after {:?}", ka.timeout);
+                                return Poll::Ready(PingEvent::KeepAliveTimeout);
+                            }
+                        }
+                        return Poll::Pending;
+                    }
                 }
-                Err(e) => {
-                    debug!("Error sending ping: {}", e);
+            } else {
+                let keep_alive_ping = match &mut inner.keep_alive {
+                    Some(ka) => {
+                        let next = inner.last_active + ka.interval;
+                        ka.poll_timer(cx, next).is_ready()
+                    }
+                    None => false,
+                };
+
+                if !bdp_ping && !keep_alive_ping {
+                    return Poll::Pending;
                 }
-            }
 
-            // no need to progress, since we must have a pong first.
-            return Poll::Pending;
+                match inner.pinger.send_ping(Ping::opaque()) {
+                    Ok(_) => {
+                        inner.ping_sent = Some(Instant::now());
+                    }
+                    Err(e) => {
+                        debug!("Error sending ping: {}", e);
+                        return Poll::Ready(PingEvent::Failed(e));
+                    }
+                }
+            }
         }
+    }
+}
 
-        let (bytes, rtt) = match ready!(lock.pinger.poll_pong(cx)) {
-            Ok(_pong) => {
-                let rtt = lock.ping_sent.expect("Pong implies ping_sent").elapsed();
+struct Inner {
+    /// Sender of pings, from connection.
+    pinger: PingPong,
 
-                lock.ping_sent = None;
+    /// Time we sent last ping. None if not sent.
+    ping_sent: Option<Instant>,
 
-                let bytes = lock.bytes;
+    /// Time of the last frames read or written by us, or a pong, or the start of
+    /// the connection.
+    last_active: Instant,
 
-                // reset back for next ping
-                lock.bytes = 0;
+    /// Accumulated bytes received since `ping_sent`.
+    bytes: usize,
 
-                trace!("Received BDP pong; bytes = {}, rtt = {:?}", bytes, rtt);
+    /// BDP impl, if bandwidth auto tuning is on.
+    bdp: Option<Bdp>,
 
-                (bytes, rtt)
-            }
+    /// Keep-alive, if enabled.
+    keep_alive: Option<KeepAlive>,
+}
 
-            Err(e) => {
-                debug!("Pong error: {}", e);
+type Timer = Pin<Box<dyn Future<Output = ()> + Send>>;
 
-                return Poll::Pending;
-            }
-        };
+struct KeepAlive {
+    /// Time without activity before sending a ping.
+    interval: Duration,
 
-        let window_update = lock.bdp.update(bytes, rtt);
+    /// Time to wait for a pong before considering the connection dead.
+    timeout: Duration,
 
-        if let Some(window_update) = window_update {
-            Poll::Ready(window_update)
-        } else {
-            Poll::Pending
+    /// Timer to wake us up at the instant.
+    timer: Option<(Instant, Timer)>,
+}
+
+impl KeepAlive {
+    fn new(interval: Duration, timeout: Duration) -> Self {
+        KeepAlive {
+            interval,
+            timeout,
+            timer: None,
         }
     }
-}
 
-struct Inner {
-    /// Sender of pings, from connection.
-    pinger: PingPong,
+    /// Ready when the `at` instant has passed, otherwise registers a timer to wake us.
+    fn poll_timer(&mut self, cx: &mut Context<'_>, at: Instant) -> Poll<()> {
+        loop {
+            let now = Instant::now();
 
-    /// Time we sent last ping. None if not sent.
-    ping_sent: Option<Instant>,
+            if now >= at {
+                self.timer = None;
+                return Poll::Ready(());
+            }
 
-    /// Accumulated bytes received since `ping_sent`.
-    bytes: usize,
+            // activity moves `at` forward all the time, an earlier timer wakes us to
+            // check again rather than making a new timer each time.
+            let reuse = matches!(&self.timer, Some((timer_at, _)) if *timer_at <= at);
+
+            if !reuse {
+                let delay = AsyncRuntime::timeout(at - now);
+                self.timer = Some((at, Box::pin(delay)));
+            }
+
+            let (_, timer) = self.timer.as_mut().unwrap();
 
-    /// BDP impl
-    bdp: Bdp,
+            if timer.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+
+            self.timer = None;
+        }
+    }
 }
 
 /// Any higher than this likely will be hitting the TCP flow control.
@@ -129,9 +262,10 @@ struct Bdp {
 }
 
 impl Bdp {
-    fn new() -> Self {
+    /// Starts from the configured window, the bdp only ever increases from there.
+    fn new(window: WindowSize) -> Self {
         Bdp {
-            bdp: 0,
+            bdp: window,
             largest_bandwidth: 0.0,
             rtt: 0.0,
         }
@@ -139,7 +273,7 @@ impl Bdp {
 
     fn update(&mut self, bytes: usize, rtt: Duration) -> Option<WindowSize> {
         // Stop counting if we're at limit.
-        if self.bdp as usize == BDP_LIMIT {
+        if self.bdp as usize >= BDP_LIMIT {
             return None;
         }
 
@@ -177,3 +311,21 @@ impl Bdp {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bdp_starts_at_window() {
+        let mut bdp = Bdp::new(1024 * 1024);
+
+        // a small sample never shrinks the configured window.
+        assert_eq!(bdp.update(10_000, Duration::from_millis(10)), None);
+        assert_eq!(bdp.bdp, 1024 * 1024);
+
+        // a sample near the window grows it.
+        let window = bdp.update(800_000, Duration::from_millis(10));
+        assert_eq!(window, Some(1_600_000));
+    }
+}
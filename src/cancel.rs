@@ -0,0 +1,193 @@
+//! Cancelling in-flight requests from another task.
+
+use crate::Error;
+use futures_util::future::FutureExt;
+use futures_util::select;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// Handle to cancel requests, also after the response headers have arrived.
+///
+/// Attach a token to a request with [`cancel_token`], or to every request of an agent
+/// with [`Agent::cancel_token`]. The token is cheap to clone, and clones share the same
+/// state. Calling [`cancel`] on any clone aborts all requests using the token, whether
+/// they are connecting, sending the request or reading the response body.
+///
+/// Cancelled requests fail with [`Error::Cancelled`]. An http2 stream is reset, an
+/// http/1.1 connection is closed.
+///
+/// Once cancelled, a token stays cancelled. New requests using it fail straight away.
+///
+/// ```no_run
+/// use hreq::prelude::*;
+/// use hreq::{AsyncRuntime, CancelToken};
+///
+/// let token = CancelToken::new();
+///
+/// let mut res = Request::get("https://example.com/huge-file.iso")
+///     .cancel_token(&token)
+///     .call().block().unwrap();
+///
+/// // such as when the user navigates away.
+/// let token2 = token.clone();
+/// AsyncRuntime::spawn(async move {
+///     token2.cancel();
+/// });
+///
+/// let err = res.body_mut().read_to_vec(1024 * 1024 * 1024).block().unwrap_err();
+/// assert!(err.is_cancelled());
+/// ```
+///
+/// [`cancel_token`]: trait.RequestBuilderExt.html#tymethod.cancel_token
+/// [`Agent::cancel_token`]: struct.Agent.html#method.cancel_token
+/// [`cancel`]: struct.CancelToken.html#method.cancel
+/// [`Error::Cancelled`]: enum.Error.html#variant.Cancelled
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    /// Wakers of the tasks waiting on this token, by listener id.
+    wakers: Mutex<HashMap<usize, Waker>>,
+}
+
+static LISTENER_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl CancelToken {
+    /// Creates a new token that is not cancelled.
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    /// Cancels all requests using this token.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+
+        let wakers: Vec<_> = {
+            let mut lock = self.inner.wakers.lock().unwrap();
+            lock.drain().map(|(_, w)| w).collect()
+        };
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Tells whether [`cancel`] has been called.
+    ///
+    /// [`cancel`]: struct.CancelToken.html#method.cancel
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CancelToken {{ cancelled: {} }}", self.is_cancelled())
+    }
+}
+
+/// The tokens of a request, such as its own and the agent's. Any one cancels.
+#[derive(Clone, Debug)]
+pub(crate) struct Cancel {
+    tokens: Vec<CancelToken>,
+}
+
+impl Cancel {
+    pub fn new(token: &CancelToken) -> Self {
+        Cancel {
+            tokens: vec![token.clone()],
+        }
+    }
+
+    /// Combine with the tokens of another cancel.
+    pub fn and(mut self, other: &Cancel) -> Self {
+        self.tokens.extend(other.tokens.iter().cloned());
+        self
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.tokens.iter().any(|t| t.is_cancelled())
+    }
+
+    /// Future that completes when any token is cancelled.
+    pub fn listen(&self) -> Listener {
+        Listener {
+            tokens: self.tokens.clone(),
+            id: LISTENER_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    pub async fn race<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+    {
+        if self.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        select! {
+            a = f.fuse() => a,
+            _ = self.listen().fuse() => Err(Error::Cancelled),
+        }
+    }
+}
+
+pub(crate) struct Listener {
+    tokens: Vec<CancelToken>,
+    id: usize,
+}
+
+impl Future for Listener {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        for token in &this.tokens {
+            let mut lock = token.inner.wakers.lock().unwrap();
+
+            // checked under lock to not miss a cancel() between check and register.
+            if token.is_cancelled() {
+                return Poll::Ready(());
+            }
+
+            lock.insert(this.id, cx.waker().clone());
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        for token in &self.tokens {
+            token.inner.wakers.lock().unwrap().remove(&self.id);
+        }
+    }
+}
+
+/// Marks an `io::Error` from reading a cancelled body, which becomes `Error::Cancelled`.
+#[derive(Debug)]
+pub(crate) struct CancelledIo;
+
+pub(crate) fn cancelled_io() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, CancelledIo)
+}
+
+impl fmt::Display for CancelledIo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl std::error::Error for CancelledIo {}
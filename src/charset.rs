@@ -1,10 +1,14 @@
-use crate::AsyncBufRead;
-use encoding_rs::{Decoder, Encoder, Encoding};
+use crate::sniff::{sniff_encoding, Markup, SNIFF_LEN};
+use crate::{AsyncBufRead, AsyncRead, AsyncRuntime};
+use encoding_rs::{Decoder, DecoderResult, Encoder, EncoderResult, Encoding};
+use futures_util::io::BufReader;
 use futures_util::ready;
 use std::fmt;
+use std::future::Future;
 use std::io;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 /// Charset transcoder
 pub struct CharCodec {
@@ -12,6 +16,32 @@ pub struct CharCodec {
     enc: Option<Encoder>,
     decoded: String,
     is_end: bool,
+    sniff: Option<Sniff>,
+    malformed: Malformed,
+    // input bytes consumed so far.
+    offset: u64,
+    // input offset of the first char in decoded, when checking for unmappable chars.
+    char_start: u64,
+    // encoder of the source charset, to know the input length of decoded chars.
+    src_enc: Option<Encoder>,
+    // output that didn't fit in a small read.
+    pending: Vec<u8>,
+    // error to return once the output before it is read.
+    error: Option<CharsetError>,
+}
+
+/// How long to wait for more input to sniff, when a stream sends less than `SNIFF_LEN`.
+const SNIFF_WAIT: Duration = Duration::from_millis(100);
+
+/// Room for any one char in the output, also as an HTML numeric character reference.
+const MIN_OUTPUT: usize = 16;
+
+/// Start of the input held back until the source encoding is known.
+struct Sniff {
+    markup: Markup,
+    buf: Vec<u8>,
+    // started when the source stalls with some input held back.
+    wait: Option<Pin<Box<dyn Future<Output = ()> + Send + Sync>>>,
 }
 
 // CharCodec should be pub(crate), but we expose it to the fuzz testing, so
@@ -28,6 +58,69 @@ impl CharCodec {
             },
             decoded: String::new(),
             is_end: false,
+            sniff: None,
+            malformed: Malformed::Replace,
+            offset: 0,
+            char_start: 0,
+            src_enc: None,
+            pending: Vec::new(),
+            error: None,
+        }
+    }
+
+    pub(crate) fn set_malformed(&mut self, malformed: Malformed) {
+        self.malformed = malformed;
+    }
+
+    /// Transcoder that sniffs the source encoding from the start of the input.
+    pub(crate) fn sniffing(markup: Markup, to: &'static Encoding) -> CharCodec {
+        let mut codec = CharCodec::new(encoding_rs::UTF_8, to);
+        codec.sniff = Some(Sniff {
+            markup,
+            buf: Vec::with_capacity(SNIFF_LEN),
+            wait: None,
+        });
+        codec
+    }
+
+    /// Collects input for sniffing. Returns the amount consumed from `src`.
+    fn sniff_feed(&mut self, src: &[u8]) -> usize {
+        let sniff = match &mut self.sniff {
+            Some(v) => v,
+            None => return 0,
+        };
+
+        let amount = (SNIFF_LEN - sniff.buf.len()).min(src.len());
+        sniff.buf.extend_from_slice(&src[..amount]);
+
+        // empty src means the input ended before we got enough to sniff.
+        let is_end = src.is_empty();
+
+        if is_end || sniff.buf.len() == SNIFF_LEN {
+            self.sniff_done(is_end);
+        }
+
+        amount
+    }
+
+    /// Settles the source encoding from the input held back so far.
+    fn sniff_done(&mut self, is_end: bool) {
+        if let Some(sniff) = self.sniff.take() {
+            let from = sniff_encoding(&sniff.buf, sniff.markup, is_end);
+            trace!("Charset sniffed ({:?}): {}", sniff.markup, from.name());
+
+            // decode what we held back, the rest continues in decode_from_buf.
+            self.dec = from.new_decoder();
+            let mut decoded = String::with_capacity(
+                self.dec
+                    .max_utf8_buffer_length(sniff.buf.len())
+                    .unwrap_or(sniff.buf.len() * 3),
+            );
+            let (_, _, had_errors) = self.dec.decode_to_string(&sniff.buf, &mut decoded, false);
+            if had_errors {
+                debug!("Character decoder had errors");
+            }
+            self.decoded.push_str(&decoded);
         }
     }
 
@@ -41,15 +134,52 @@ impl CharCodec {
         from: &mut R,
         dst: &mut [u8],
     ) -> Poll<Result<usize, io::Error>> {
-        // get some incoming bytes from source
-        let src = ready!(Pin::new(&mut *from).poll_fill_buf(cx))?;
+        // hold back the start of the input until we know the source encoding.
+        while let Some(sniff) = &mut self.sniff {
+            let src = match Pin::new(&mut *from).poll_fill_buf(cx) {
+                Poll::Pending if !sniff.buf.is_empty() => {
+                    // a stream might not send more for a long time, go with what
+                    // we got after a short while.
+                    let wait = sniff
+                        .wait
+                        .get_or_insert_with(|| Box::pin(AsyncRuntime::timeout(SNIFF_WAIT)));
+                    ready!(wait.as_mut().poll(cx));
+                    trace!("Charset sniff on {} bytes after waiting", sniff.buf.len());
+                    self.sniff_done(false);
+                    break;
+                }
+                r => ready!(r)?,
+            };
+            let consumed = self.sniff_feed(src);
+            Pin::new(&mut *from).consume(consumed);
+        }
+
+        loop {
+            // get some incoming bytes from source
+            let src = match Pin::new(&mut *from).poll_fill_buf(cx) {
+                Poll::Pending if self.has_output() => {
+                    // a stream might not send more for a long time, hand over
+                    // what is already decoded.
+                    let amount = self.output_decoded(dst);
+                    if amount > 0 {
+                        return Ok(amount).into();
+                    }
+                    return Poll::Pending;
+                }
+                r => ready!(r)?,
+            };
 
-        let mut consumed = 0;
-        let ret = self.decode_from_buf(src, dst, &mut consumed);
+            let mut consumed = 0;
+            let ret = self.decode_from_buf(src, dst, &mut consumed);
 
-        Pin::new(&mut *from).consume(consumed);
+            Pin::new(&mut *from).consume(consumed);
 
-        Poll::Ready(ret)
+            // input that doesn't make any output yet, like the first half of a
+            // multi byte char, must not be mistaken for the end.
+            if ret.as_ref().ok() != Some(&0) || consumed == 0 {
+                return Poll::Ready(ret);
+            }
+        }
     }
 
     pub fn decode_from_buf(
@@ -58,46 +188,136 @@ impl CharCodec {
         dst: &mut [u8],
         consumed: &mut usize,
     ) -> Result<usize, io::Error> {
+        if let Some(err) = self.error {
+            // the output before the error is read first.
+            if !self.has_output() {
+                return Err(err.into_io());
+            }
+        }
+
         // true once when we reach EOF first time
         let mut became_end = false;
 
-        if !self.is_end && src.is_empty() {
+        if !self.is_end && src.is_empty() && self.error.is_none() {
             became_end = true;
             self.is_end = true;
         }
 
         // decode when there's not many chars left in the decoded
-        if (!self.is_end && self.decoded.len() < 128) || became_end {
+        if (!self.is_end && self.error.is_none() && self.decoded.len() < 128) || became_end {
             let mut decode_to = [0_u8; 8_192];
 
-            let (_, decode_read, decode_written, decode_had_errors) =
-                self.dec.decode_to_utf8(src, &mut decode_to[..], became_end);
-
-            if decode_had_errors {
-                debug!("Character decoder had errors");
+            if self.offset == 0 {
+                // the decoder removes a BOM, which is not part of any char.
+                if let Some((_, bom_len)) = Encoding::for_bom(src) {
+                    self.char_start = bom_len as u64;
+                }
             }
 
+            let (decode_read, decode_written) = match self.malformed {
+                Malformed::Replace => {
+                    let (_, decode_read, decode_written, decode_had_errors) =
+                        self.dec.decode_to_utf8(src, &mut decode_to[..], became_end);
+
+                    if decode_had_errors {
+                        debug!("Character decoder had errors");
+                    }
+
+                    (decode_read, decode_written)
+                }
+                Malformed::Error => {
+                    let (res, decode_read, decode_written) = self
+                        .dec
+                        .decode_to_utf8_without_replacement(src, &mut decode_to[..], became_end);
+
+                    if let DecoderResult::Malformed(bad, after) = res {
+                        let pos = self.offset + decode_read as u64;
+                        let offset = pos.saturating_sub(bad as u64 + after as u64);
+                        self.error = Some(CharsetError::new(offset, None));
+                    }
+
+                    (decode_read, decode_written)
+                }
+            };
+
             *consumed = decode_read;
+            self.offset += decode_read as u64;
 
             // this unsafe is ok because we trust encoding_rs produces legit utf8.
             let decoded = unsafe { std::str::from_utf8_unchecked(&decode_to[0..decode_written]) };
             self.decoded.push_str(decoded);
         }
 
+        Ok(self.output_decoded(dst))
+    }
+
+    fn has_output(&self) -> bool {
+        !self.decoded.is_empty() || !self.pending.is_empty()
+    }
+
+    /// Moves decoded chars to `dst`, transcoding them if there's a target encoding.
+    fn output_decoded(&mut self, dst: &mut [u8]) -> usize {
+        if self.pending.is_empty() && dst.len() < MIN_OUTPUT && !self.decoded.is_empty() {
+            // the next char might not fit, go via a buffer that surely fits it.
+            let mut buf = [0_u8; MIN_OUTPUT];
+            let amount = self.transcode_decoded(&mut buf);
+            self.pending.extend_from_slice(&buf[..amount]);
+        }
+
+        if !self.pending.is_empty() {
+            let amount = self.pending.len().min(dst.len());
+            dst[..amount].copy_from_slice(&self.pending[..amount]);
+            self.pending.drain(..amount);
+            return amount;
+        }
+
+        self.transcode_decoded(dst)
+    }
+
+    fn transcode_decoded(&mut self, dst: &mut [u8]) -> usize {
         if let Some(enc) = &mut self.enc {
             // transcode to the output encoding
-            let (_, encode_read, encode_written, encode_had_errors) =
-                enc.encode_from_utf8(&self.decoded[..], dst, self.is_end);
-            if encode_had_errors {
-                debug!("Character encoder had errors");
-            }
+            let (encode_read, encode_written) = match self.malformed {
+                Malformed::Replace => {
+                    let (_, encode_read, encode_written, encode_had_errors) =
+                        enc.encode_from_utf8(&self.decoded[..], dst, self.is_end);
+                    if encode_had_errors {
+                        debug!("Character encoder had errors");
+                    }
+                    (encode_read, encode_written)
+                }
+                Malformed::Error => {
+                    let (res, encode_read, encode_written) = enc
+                        .encode_from_utf8_without_replacement(&self.decoded[..], dst, self.is_end);
+
+                    let ok_read = match res {
+                        EncoderResult::Unmappable(c) => encode_read - c.len_utf8(),
+                        _ => encode_read,
+                    };
+
+                    // the input offset of the chars is only needed for this error, but
+                    // we keep count since the decoded chars are moved on.
+                    let from = self.dec.encoding();
+                    self.char_start +=
+                        source_len(from, &mut self.src_enc, &self.decoded[..ok_read]);
+
+                    if let EncoderResult::Unmappable(c) = res {
+                        // this error is before any malformed input found when decoding.
+                        self.error = Some(CharsetError::new(self.char_start, Some(c)));
+                        self.decoded.clear();
+                        return encode_written;
+                    }
+
+                    (encode_read, encode_written)
+                }
+            };
 
             // encode_read is a char offset into the string. we don't need to
             // split this on a byte offset.
             let rest = self.decoded.split_off(encode_read);
             self.decoded = rest;
 
-            Ok(encode_written)
+            encode_written
         } else {
             // the output is utf8, and that's what we already have,
             // don't do any additional encoding.
@@ -109,14 +329,49 @@ impl CharCodec {
                 max -= 1;
             }
 
-            (&mut dst[0..max]).copy_from_slice(&decoded_bytes[0..max]);
+            dst[0..max].copy_from_slice(&decoded_bytes[0..max]);
 
             // this unsafe is ok, since we moved max to be on a char boundary.
             let vec = unsafe { self.decoded.as_mut_vec() };
             let rest = vec.split_off(max);
             *vec = rest;
 
-            Ok(max)
+            max
+        }
+    }
+}
+
+/// The length of decoded chars in the encoding they were decoded from.
+fn source_len(from: &'static Encoding, src_enc: &mut Option<Encoder>, s: &str) -> u64 {
+    if from == encoding_rs::UTF_8 {
+        return s.len() as u64;
+    }
+
+    if from == encoding_rs::UTF_16LE || from == encoding_rs::UTF_16BE {
+        return s.encode_utf16().count() as u64 * 2;
+    }
+
+    if from.is_single_byte() {
+        return s.chars().count() as u64;
+    }
+
+    // encode them back, the encoder keeps any state, such as ISO-2022-JP modes.
+    let enc = src_enc.get_or_insert_with(|| from.new_encoder());
+    let mut buf = [0_u8; 1_024];
+    let mut rest = s;
+    let mut len = 0;
+
+    loop {
+        let (res, read, written) = enc.encode_from_utf8_without_replacement(rest, &mut buf, false);
+        len += written as u64;
+        rest = &rest[read..];
+
+        match res {
+            EncoderResult::InputEmpty => return len,
+            EncoderResult::OutputFull => {}
+            // decodable but not encodable chars, such as JIS X 0212 in EUC-JP, take
+            // three bytes like in UTF-8.
+            EncoderResult::Unmappable(c) => len += c.len_utf8() as u64,
         }
     }
 }
@@ -135,3 +390,155 @@ impl fmt::Debug for CharCodec {
         )
     }
 }
+
+/// How a [`CharsetReader`] handles malformed input.
+///
+/// [`CharsetReader`]: struct.CharsetReader.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Malformed {
+    /// Replace malformed input with U+FFFD (the replacement character). Chars the
+    /// target charset can't represent become HTML numeric character references, such
+    /// as `&#12354;`. This is what bodies do.
+    Replace,
+    /// Fail the read with a [`CharsetError`], after the output up to the error.
+    ///
+    /// [`CharsetError`]: struct.CharsetError.html
+    Error,
+}
+
+/// Malformed input, or a char the target charset can't represent.
+///
+/// Failed reads of a [`CharsetReader`] with [`Malformed::Error`] are `std::io::Error`
+/// of kind `InvalidData` that wrap this error, see [`from_io`].
+///
+/// [`CharsetReader`]: struct.CharsetReader.html
+/// [`Malformed::Error`]: enum.Malformed.html#variant.Error
+/// [`from_io`]: struct.CharsetError.html#method.from_io
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CharsetError {
+    offset: u64,
+    unmappable: Option<char>,
+}
+
+impl CharsetError {
+    fn new(offset: u64, unmappable: Option<char>) -> Self {
+        CharsetError { offset, unmappable }
+    }
+
+    /// Byte offset in the input of the malformed sequence, or of the char that
+    /// can't be represented.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The char the target charset can't represent, or `None` for malformed input.
+    pub fn unmappable(&self) -> Option<char> {
+        self.unmappable
+    }
+
+    /// The charset error of a read error, if it is one.
+    pub fn from_io(err: &io::Error) -> Option<CharsetError> {
+        err.get_ref()?.downcast_ref::<CharsetError>().copied()
+    }
+
+    pub(crate) fn into_io(self) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, self)
+    }
+}
+
+impl fmt::Display for CharsetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(c) = self.unmappable {
+            write!(f, "unmappable char {:?} at byte {}", c, self.offset)
+        } else {
+            write!(f, "malformed input at byte {}", self.offset)
+        }
+    }
+}
+
+impl std::error::Error for CharsetError {}
+
+/// Streaming transcoder of text from one charset to another.
+///
+/// Wraps an `AsyncRead` or `AsyncBufRead`, and reads the text in the target charset.
+/// This is the same transcoding bodies do for `text/*` content types, for text that
+/// doesn't come through a body, such as files or sockets.
+///
+/// A byte order mark in the input overrides the source charset, and is removed.
+///
+/// ```
+/// use hreq::prelude::*;
+/// use hreq::{CharsetError, CharsetReader, Malformed};
+/// use futures_util::io::AsyncReadExt;
+///
+/// // "おはよう" in Shift_JIS
+/// let input: &[u8] = &[0x82, 0xa8, 0x82, 0xcd, 0x82, 0xe6, 0x82, 0xa4];
+///
+/// let mut reader = CharsetReader::new(input, encoding_rs::SHIFT_JIS, encoding_rs::UTF_8);
+/// let mut s = String::new();
+/// reader.read_to_string(&mut s).block().unwrap();
+///
+/// assert_eq!(s, "おはよう");
+///
+/// // 0xa0 is not valid Shift_JIS
+/// let input: &[u8] = &[0x82, 0xa8, 0xa0];
+///
+/// let mut reader = CharsetReader::new(input, encoding_rs::SHIFT_JIS, encoding_rs::UTF_8)
+///     .malformed(Malformed::Error);
+/// let mut s = String::new();
+/// let err = reader.read_to_string(&mut s).block().unwrap_err();
+///
+/// assert_eq!(CharsetError::from_io(&err).unwrap().offset(), 2);
+/// ```
+pub struct CharsetReader<R> {
+    inner: R,
+    codec: CharCodec,
+}
+
+impl<R: AsyncRead + Unpin> CharsetReader<BufReader<R>> {
+    /// Creates a reader transcoding `from` one charset `to` another.
+    pub fn new(reader: R, from: &'static Encoding, to: &'static Encoding) -> Self {
+        CharsetReader::from_buf_read(BufReader::new(reader), from, to)
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> CharsetReader<R> {
+    /// Creates a reader transcoding `from` one charset `to` another, using the buffer
+    /// of the underlying reader.
+    pub fn from_buf_read(reader: R, from: &'static Encoding, to: &'static Encoding) -> Self {
+        CharsetReader {
+            inner: reader,
+            codec: CharCodec::new(from, to),
+        }
+    }
+
+    /// Changes how malformed input is handled. Defaults to [`Malformed::Replace`].
+    ///
+    /// [`Malformed::Replace`]: enum.Malformed.html#variant.Replace
+    pub fn malformed(mut self, malformed: Malformed) -> Self {
+        self.codec.set_malformed(malformed);
+        self
+    }
+
+    /// Unwraps the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for CharsetReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.codec.poll_codec(cx, &mut this.inner, buf)
+    }
+}
+
+impl<R> fmt::Debug for CharsetReader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CharsetReader {{ codec: {:?} }}", self.codec)
+    }
+}
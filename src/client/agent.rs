@@ -1,23 +1,40 @@
 //! Connection pooling, redirects, cookies etc.
 
-use super::conn::BodyBuf;
-use super::connect;
+use super::cache::{Cache, Lookup};
+use super::conn::{send_upgrade, BodyBuf};
+#[cfg(unix)]
+use super::connect_unix;
 use super::cookies::Cookies;
+use super::download::{download, download_segmented};
+use super::fixtures::{Fixtures, Lookup as FixtureLookup};
+use super::limit::RateLimit;
 use super::Connection;
-use crate::async_impl::AsyncRuntime;
+use super::{connect, connect_stream, connect_with, Connector};
+use crate::async_impl::{with_runtime, AsyncRuntime, RuntimeHandle};
+use crate::cancel::{Cancel, CancelToken};
+use crate::deadline::limit;
+use crate::h2_config::Http2Config;
+use crate::har::Har;
 use crate::params::resolve_hreq_params;
 use crate::params::HReqParams;
 use crate::params::QueryParams;
+use crate::progress::{Progress, ProgressFn};
+use crate::throttle::Throttle;
+use crate::upgrade::is_upgrade_request;
 use crate::uri_ext::UriExt;
 use crate::Body;
 use crate::Error;
 use crate::ResponseExt;
+use crate::TimeoutKind;
 use cookie::Cookie;
+use std::convert::TryFrom;
 use std::fmt;
 use std::future::Future;
+use std::path::Path;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// Agents provide redirects, connection pooling, cookies and retries.
 ///
@@ -58,6 +75,16 @@ pub struct Agent {
     retries: i8,
     pooling: bool,
     use_cookies: bool,
+    h2_config: Http2Config,
+    cache: Option<Cache>,
+    rate_limit: Option<RateLimit>,
+    progress: Option<ProgressFn>,
+    throttle: Option<Throttle>,
+    cancel: Option<Cancel>,
+    fixtures: Option<Fixtures>,
+    har: Option<Har>,
+    connector: Option<Arc<dyn Connector>>,
+    runtime: Option<RuntimeHandle>,
 }
 
 impl Agent {
@@ -76,6 +103,16 @@ impl Agent {
             retries: 5,
             pooling: true,
             use_cookies: true,
+            h2_config: Http2Config::new(),
+            cache: None,
+            rate_limit: None,
+            progress: None,
+            throttle: None,
+            cancel: None,
+            fixtures: None,
+            har: None,
+            connector: None,
+            runtime: None,
         }
     }
 
@@ -153,6 +190,377 @@ impl Agent {
         }
     }
 
+    /// Changes the settings used for new HTTP/2 connections.
+    ///
+    /// Connections already in the pool keep their settings.
+    ///
+    /// ```
+    /// use hreq::Agent;
+    /// use hreq::Http2Config;
+    ///
+    /// let mut agent = Agent::new();
+    /// agent.http2_config(Http2Config::new().max_frame_size(64 * 1024));
+    /// ```
+    pub fn http2_config(&mut self, config: Http2Config) {
+        self.h2_config = config;
+    }
+
+    /// Sets a cache for responses.
+    ///
+    /// Defaults to no cache. Responses allowed to be cached are stored, and later
+    /// requests for the same uri are served from the cache while fresh. See [`Cache`].
+    ///
+    /// The cache will be used for the next call to `.send()`.
+    ///
+    /// ```
+    /// use hreq::{Agent, Cache};
+    ///
+    /// let mut agent = Agent::new();
+    /// agent.cache(Cache::memory());
+    /// ```
+    ///
+    /// [`Cache`]: struct.Cache.html
+    pub fn cache(&mut self, cache: Cache) {
+        self.cache = Some(cache);
+    }
+
+    /// Sets limits for the requests sent to each host.
+    ///
+    /// Defaults to no limits. Requests over the limits are queued rather than failed.
+    /// See [`RateLimit`].
+    ///
+    /// The limits will be used for the next call to `.send()`.
+    ///
+    /// ```
+    /// use hreq::{Agent, RateLimit};
+    /// use std::time::Duration;
+    ///
+    /// let mut agent = Agent::new();
+    /// agent.rate_limit(RateLimit::new().requests_per(100, Duration::from_secs(60)));
+    /// ```
+    ///
+    /// [`RateLimit`]: struct.RateLimit.html
+    pub fn rate_limit(&mut self, limit: RateLimit) {
+        self.rate_limit = Some(limit);
+    }
+
+    /// Sets a callback for the progress of request and response bodies.
+    ///
+    /// Used for all requests sent by this agent, unless the request has a callback of its
+    /// own set with [`on_progress`]. See [`Progress`].
+    ///
+    /// ```
+    /// use hreq::Agent;
+    ///
+    /// let mut agent = Agent::new();
+    /// agent.on_progress(|p| {
+    ///     println!("{:?} {} of {:?}", p.direction, p.transferred, p.total);
+    /// });
+    /// ```
+    ///
+    /// [`on_progress`]: trait.RequestBuilderExt.html#tymethod.on_progress
+    /// [`Progress`]: struct.Progress.html
+    pub fn on_progress<F>(&mut self, f: F)
+    where
+        F: Fn(Progress) + Send + Sync + 'static,
+    {
+        self.progress = Some(ProgressFn::new(f));
+    }
+
+    /// Limits the rate of body data for all requests sent by this agent, in bytes per
+    /// second.
+    ///
+    /// The limit is a budget shared by the request and response bodies of all requests,
+    /// also those running concurrently in agents created for [`download_segmented`].
+    /// A request can have a limit of its own in addition, using [`throttle`].
+    ///
+    /// The limit will be used for the next call to `.send()`.
+    ///
+    /// ```
+    /// use hreq::Agent;
+    ///
+    /// let mut agent = Agent::new();
+    /// // at most 1MB/s in total
+    /// agent.throttle(1024 * 1024);
+    /// ```
+    ///
+    /// [`download_segmented`]: struct.Agent.html#method.download_segmented
+    /// [`throttle`]: trait.RequestBuilderExt.html#tymethod.throttle
+    pub fn throttle(&mut self, bytes_per_second: u64) {
+        self.throttle = Some(Throttle::new(bytes_per_second));
+    }
+
+    /// Makes all requests sent by this agent cancellable using a [`CancelToken`].
+    ///
+    /// Cancelling the token aborts every request in flight, including downloads and
+    /// response bodies still being read. A request can have a token of its own in
+    /// addition, using [`cancel_token`].
+    ///
+    /// The token will be used for the next call to `.send()`. Since a cancelled token
+    /// stays cancelled, set a new one to continue using the agent.
+    ///
+    /// ```
+    /// use hreq::{Agent, CancelToken};
+    ///
+    /// let mut agent = Agent::new();
+    ///
+    /// let token = CancelToken::new();
+    /// agent.cancel_token(&token);
+    ///
+    /// // later, from anywhere: cancel everything.
+    /// token.cancel();
+    /// ```
+    ///
+    /// [`CancelToken`]: struct.CancelToken.html
+    /// [`cancel_token`]: trait.RequestBuilderExt.html#tymethod.cancel_token
+    pub fn cancel_token(&mut self, token: &CancelToken) {
+        self.cancel = Some(Cancel::new(token));
+    }
+
+    /// Records requests and responses to a fixture file, or replays them.
+    ///
+    /// See [`Fixtures`] for how requests are matched against recorded ones.
+    ///
+    /// ```
+    /// use hreq::{Agent, Fixtures};
+    ///
+    /// let mut agent = Agent::new();
+    /// agent.fixtures(Fixtures::record("tests/fixtures/example.json"));
+    /// ```
+    ///
+    /// [`Fixtures`]: struct.Fixtures.html
+    pub fn fixtures(&mut self, fixtures: Fixtures) {
+        self.fixtures = Some(fixtures);
+    }
+
+    /// Records all traffic of this agent for export as a HAR (HTTP Archive) file.
+    ///
+    /// See [`Har`] for what is recorded.
+    ///
+    /// ```
+    /// use hreq::{Agent, Har};
+    ///
+    /// let har = Har::new();
+    ///
+    /// let mut agent = Agent::new();
+    /// agent.har(har.clone());
+    ///
+    /// // ... send requests, then export with har.to_json()
+    /// ```
+    ///
+    /// [`Har`]: struct.Har.html
+    pub fn har(&mut self, har: Har) {
+        self.har = Some(har);
+    }
+
+    /// Opens connections using a custom [`Connector`] instead of TCP and TLS.
+    ///
+    /// Connections already pooled in the agent are closed.
+    ///
+    /// ```
+    /// use hreq::prelude::*;
+    /// use hreq::{Agent, HostPort, Protocol};
+    /// use tokio_util::compat::TokioAsyncReadCompatExt;
+    ///
+    /// let mut agent = Agent::new();
+    ///
+    /// agent.connector(|host_port: HostPort| async move {
+    ///     let tcp = tokio::net::TcpStream::connect(host_port.to_string()).await?;
+    ///     Ok::<_, hreq::Error>((tcp.compat(), Protocol::Unknown))
+    /// });
+    /// ```
+    ///
+    /// [`Connector`]: trait.Connector.html
+    pub fn connector(&mut self, connector: impl Connector) {
+        self.connections.clear();
+        self.connector = Some(Arc::new(connector));
+    }
+
+    /// Uses the given tokio runtime for this agent instead of the global [`AsyncRuntime`].
+    ///
+    /// Connections are opened and driven, and timeouts run, in this runtime. Agents
+    /// without a runtime of their own use the global default. This makes it possible to
+    /// use hreq in several tokio runtimes in the same process.
+    ///
+    /// Connections already pooled in the agent are closed.
+    ///
+    /// The runtime must be driven for as long as the agent and its response bodies
+    /// are used. A multi threaded runtime drives itself, a current thread runtime only
+    /// runs inside its `block_on`. Awaiting the agent with `.block()` drives the global
+    /// runtime, not this one, and would wait forever.
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    /// use hreq::Agent;
+    ///
+    /// let rt = tokio::runtime::Builder::new_current_thread()
+    ///     .enable_all()
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let mut agent = Agent::new();
+    /// agent.runtime(rt.handle().clone());
+    ///
+    /// let body = rt.block_on(async {
+    ///     let req = Request::get("https://httpbin.org/get").with_body(())?;
+    ///     agent.send(req).await?.into_body().read_to_string().await
+    /// }).unwrap();
+    /// ```
+    ///
+    /// [`AsyncRuntime`]: enum.AsyncRuntime.html
+    #[cfg(feature = "tokio")]
+    pub fn runtime(&mut self, handle: tokio::runtime::Handle) {
+        self.connections.clear();
+        self.runtime = Some(RuntimeHandle::new(handle));
+    }
+
+    /// Sends all requests straight into a server in the same process, without any sockets.
+    ///
+    /// Requests go through the same client code as over the network: cookies, redirects,
+    /// retries, charset and content encoding all work as usual. The host in the uri is
+    /// only used for the `host` header, cookies and such, every connection goes to the
+    /// given server. Handlers get `None` for the [`remote_addr`].
+    ///
+    /// Like for `listen`, the server's routes are cloned on this call. The server keeps
+    /// serving as long as this agent, or any agent cloned from it, is around.
+    ///
+    /// This sets the agent's [`connector`], replacing any previous one.
+    ///
+    /// ```
+    /// use hreq::prelude::*;
+    /// use hreq::Agent;
+    ///
+    /// let mut server = Server::new();
+    /// server.at("/hello").get(|_req| async { "Hello there" });
+    ///
+    /// let mut agent = Agent::new();
+    /// agent.in_process(&server);
+    ///
+    /// let req = Request::get("http://my-service/hello").with_body(()).unwrap();
+    /// let mut res = agent.send(req).block().unwrap();
+    ///
+    /// assert_eq!(res.body_mut().read_to_string().block().unwrap(), "Hello there");
+    /// ```
+    ///
+    /// [`connector`]: struct.Agent.html#method.connector
+    /// [`remote_addr`]: server/trait.ServerRequestExt.html#tymethod.remote_addr
+    #[cfg(feature = "server")]
+    pub fn in_process<State>(&mut self, server: &crate::server::Server<State>)
+    where
+        State: Clone + Unpin + Send + Sync + 'static,
+    {
+        self.connector(server.in_process());
+    }
+
+    /// Downloads the uri to a file, resuming a previous partial download if possible.
+    ///
+    /// The data is first written to `<path>.part`, which is renamed to `path` once the
+    /// download is complete. If the download is interrupted, the partial file is kept,
+    /// and calling `download` again continues where it left off.
+    ///
+    /// A download is resumed with a `Range` request carrying the `ETag` or `Last-Modified`
+    /// of the original response in `If-Range`. If the server ignores the range, or the
+    /// resource changed, the download restarts from the beginning.
+    ///
+    /// Returns the size of the downloaded file.
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    /// use hreq::Agent;
+    ///
+    /// let mut agent = Agent::new();
+    ///
+    /// let size = agent
+    ///     .download("https://example.com/large-dataset.csv", "dataset.csv")
+    ///     .block()
+    ///     .unwrap();
+    /// ```
+    pub async fn download<U>(&mut self, uri: U, path: impl AsRef<Path>) -> Result<u64, Error>
+    where
+        http::Uri: TryFrom<U>,
+        <http::Uri as TryFrom<U>>::Error: Into<http::Error>,
+    {
+        let uri = http::Uri::try_from(uri).map_err(|e| Error::Http(e.into()))?;
+        download(self, uri, path.as_ref()).await
+    }
+
+    /// Downloads the uri to a file using several connections in parallel.
+    ///
+    /// If the server supports range requests (`accept-ranges: bytes`), the file is
+    /// split into (at most) `segments` byte ranges, which are fetched concurrently over
+    /// separate connections. Each range is written straight into its place in a file
+    /// preallocated to the full size. A failing range is retried on its own, continuing
+    /// from where it stopped, using the [retries] setting of this agent.
+    ///
+    /// Servers not supporting ranges, or small files, fall back on a regular
+    /// [`download`].
+    ///
+    /// Returns the size of the downloaded file.
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    /// use hreq::Agent;
+    ///
+    /// let mut agent = Agent::new();
+    ///
+    /// let size = agent
+    ///     .download_segmented("https://example.com/artifact.tar.gz", "artifact.tar.gz", 8)
+    ///     .block()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// [retries]: struct.Agent.html#method.retries
+    /// [`download`]: struct.Agent.html#method.download
+    pub async fn download_segmented<U>(
+        &mut self,
+        uri: U,
+        path: impl AsRef<Path>,
+        segments: usize,
+    ) -> Result<u64, Error>
+    where
+        http::Uri: TryFrom<U>,
+        <http::Uri as TryFrom<U>>::Error: Into<http::Error>,
+    {
+        let uri = http::Uri::try_from(uri).map_err(|e| Error::Http(e.into()))?;
+        let retries = self.retries.max(0) as usize;
+        download_segmented(self, uri, path.as_ref(), segments, retries).await
+    }
+
+    /// New agent with the same settings and cookies, but without any pooled connections.
+    pub(crate) fn clone_settings(&self) -> Agent {
+        Agent {
+            connections: vec![],
+            cookies: self.cookies.clone(),
+            redirects: self.redirects,
+            retries: self.retries,
+            pooling: self.pooling,
+            use_cookies: self.use_cookies,
+            h2_config: self.h2_config.clone(),
+            cache: self.cache.clone(),
+            rate_limit: self.rate_limit.clone(),
+            progress: self.progress.clone(),
+            throttle: self.throttle.clone(),
+            cancel: self.cancel.clone(),
+            fixtures: self.fixtures.clone(),
+            har: self.har.clone(),
+            connector: self.connector.clone(),
+            runtime: self.runtime.clone(),
+        }
+    }
+
+    /// Keep the cookies collected by an agent made with `clone_settings`.
+    pub(crate) fn merge_cookies(&mut self, other: Agent) {
+        if !self.use_cookies {
+            return;
+        }
+        if let Some(other) = other.cookies {
+            match &mut self.cookies {
+                Some(cookies) => cookies.merge(other),
+                None => self.cookies = Some(other),
+            }
+        }
+    }
+
     /// Get all cookies held in this agent matching the given uri.
     pub fn get_cookies(&self, uri: &http::Uri) -> Vec<&Cookie<'static>> {
         if let Some(cookies) = &self.cookies {
@@ -167,6 +575,15 @@ impl Agent {
             return Ok(None);
         }
         let host_port = uri.host_port()?;
+
+        // evict connections that ended, such as failing keep-alive.
+        self.connections.retain(|c| {
+            if c.is_closed() {
+                debug!("Evict closed connection: {}", c.host_port());
+            }
+            !c.is_closed()
+        });
+
         let ret = self
             .connections
             .iter_mut()
@@ -218,9 +635,32 @@ impl Agent {
         let body = body.into();
 
         // apply the parameters, query params affect the request uri.
-        let parts = resolve_hreq_params(parts);
+        let mut parts = resolve_hreq_params(parts);
+
+        // the agent progress callback is used unless the request has one.
+        let params = parts.extensions.get_mut::<HReqParams>().unwrap();
+        if params.progress.is_none() {
+            params.progress = self.progress.clone();
+        }
+
+        params.har = self.har.clone();
+
+        // the agent budget applies in addition to any request throttle.
+        if let Some(throttle) = &self.throttle {
+            params.throttle = Some(match params.throttle.take() {
+                Some(t) => t.and(throttle),
+                None => throttle.clone(),
+            });
+        }
+
+        if let Some(cancel) = &self.cancel {
+            params.cancel = Some(match params.cancel.take() {
+                Some(c) => c.and(cancel),
+                None => cancel.clone(),
+            });
+        }
 
-        let params = parts.extensions.get::<HReqParams>().unwrap().clone();
+        let params = params.clone();
 
         // Buffer of body data so we can handle resending the body on 307/308 redirects.
         let mut body_buffer = BodyBuf::new(params.redirect_body_buffer);
@@ -232,9 +672,26 @@ impl Agent {
         // for lifetime reasons it's easier to handle the cookie storage separately
         let mut cookies = self.cookies.take();
 
-        let ret = deadline
-            .race(self.do_send(parts, body, params, &mut cookies, &mut body_buffer))
-            .await;
+        let cancel = params.cancel.clone();
+
+        let runtime = self.runtime.clone();
+
+        // boxed since the future is big, and would be held twice in this one.
+        let send = Box::pin(deadline.race(self.do_send(
+            parts,
+            body,
+            params,
+            &mut cookies,
+            &mut body_buffer,
+        )));
+
+        let ret = with_runtime(runtime.as_ref(), async move {
+            match cancel {
+                Some(cancel) => cancel.race(send).await,
+                None => send.await,
+            }
+        })
+        .await;
 
         self.cookies = cookies;
 
@@ -253,10 +710,16 @@ impl Agent {
 
         let mut retries = self.retries;
         let mut backoff_millis: u64 = 125;
-        let mut redirects = self.redirects;
+        let mut redirects = params
+            .redirect_max
+            .map(|n| n as i8)
+            .unwrap_or(self.redirects);
         let pooling = self.pooling;
         let mut unpooled: Option<Connection> = None;
         let use_cookies = self.use_cookies;
+        let cache = self.cache.clone();
+        let rate_limit = self.rate_limit.clone();
+        let fixtures = self.fixtures.clone();
 
         // if we have a param.with_override, whenever we are to open a connection,
         // we check whether the current uri has an equal hostport to this, that
@@ -286,62 +749,229 @@ impl Agent {
                 }
             }
 
+            // requests taking over the connection get a connection of their own,
+            // which is never pooled, redirected or retried.
+            if is_upgrade_request(req.method(), req.headers()) {
+                if params.force_http2 {
+                    return Err(Error::User(
+                        "Upgrade/CONNECT is not possible with force_http2".into(),
+                    ));
+                }
+
+                if fixtures.as_ref().map(|f| f.is_replay()) == Some(true) {
+                    return Err(Error::User(
+                        "Upgrade/CONNECT can't be replayed from fixtures".into(),
+                    ));
+                }
+
+                let hostport_uri = uri.host_port()?;
+
+                #[cfg(unix)]
+                {
+                    if let (Some(path), true) = (&params.unix_socket, orig_hostport == hostport_uri)
+                    {
+                        debug!("Connect unix socket for upgrade: {}", path.display());
+                        let stream = limit(
+                            params.connect_timeout,
+                            TimeoutKind::Connect,
+                            AsyncRuntime::connect_unix(path),
+                        )
+                        .await?;
+                        break send_upgrade(hostport_uri, stream, req, &self.h2_config).await;
+                    }
+                }
+
+                // same override logic as for regular connections.
+                let hostport = match &params.with_override {
+                    Some(arc) if orig_hostport == hostport_uri => (**arc).clone(),
+                    _ => hostport_uri,
+                };
+
+                if let Some(connector) = &self.connector {
+                    debug!("Connect with connector for upgrade: {}", hostport);
+                    let (stream, _) = limit(
+                        params.connect_timeout,
+                        TimeoutKind::Connect,
+                        connector.connect(hostport.clone()),
+                    )
+                    .await?;
+                    break send_upgrade(hostport, stream, req, &self.h2_config).await;
+                }
+
+                debug!("Connect new for upgrade: {}", hostport);
+                let (stream, _) = connect_stream(&hostport, false, &params).await?;
+
+                break send_upgrade(hostport, stream, req, &self.h2_config).await;
+            }
+
             // remember whether request is idempotent in case we are to retry
             let is_idempotent = req.method().is_idempotent();
+            let is_safe = req.method().is_safe();
 
             // next_req holds our (potential) next request in case of redirects.
             next_req = clone_to_empty_body(&req);
 
+            // a stored response might spare us the request.
+            let mut pending = None;
+            if let Some(cache) = &cache {
+                match cache.lookup(&mut req, &params).await {
+                    Lookup::Bypass => {}
+                    Lookup::Fresh(entry) => {
+                        debug!("Cache hit: {}", uri);
+                        break Ok(entry.response(&params));
+                    }
+                    Lookup::StaleWhileRevalidate(entry) => {
+                        debug!("Cache hit (stale while revalidate): {}", uri);
+
+                        // the cookies are already in the request.
+                        let mut agent = self.clone_settings();
+                        agent.cookies(false);
+
+                        let mut revalidate = clone_to_empty_body(&req);
+                        revalidate.headers_mut().insert(
+                            "cache-control",
+                            http::header::HeaderValue::from_static("no-cache"),
+                        );
+
+                        let fut = agent.send_future(revalidate);
+                        AsyncRuntime::spawn(async move {
+                            // the response is stored once its body is read to the end.
+                            let res = match fut.await {
+                                Ok(mut res) => res.body_mut().read_and_discard().await,
+                                Err(e) => Err(e),
+                            };
+                            if let Err(e) = res {
+                                debug!("Revalidate in background failed: {}", e);
+                            }
+                        });
+
+                        break Ok(entry.response(&params));
+                    }
+                    Lookup::Forward(p) => pending = Some(p),
+                }
+            }
+
+            // recorded responses stand in for the server when replaying.
+            let mut replayed = None;
+            let mut recording = None;
+            if let Some(fixtures) = &fixtures {
+                match fixtures.lookup(&mut req, &params).await? {
+                    FixtureLookup::Replay(exchange) => {
+                        replayed = Some(exchange.response(&params));
+                    }
+                    FixtureLookup::Record(p) => recording = Some(p),
+                }
+            }
+
+            // wait our turn if the host is rate limited. requests served from the
+            // cache or fixtures don't count.
+            let mut permit = match (&rate_limit, &replayed) {
+                (Some(limit), None) => Some(limit.acquire(&uri).await?),
+                _ => None,
+            };
+
             // grab connection for the current request
-            let conn = match self.reuse_from_pool(&uri)? {
-                Some(conn) => conn,
+            let mut conn = match self.reuse_from_pool(&uri)? {
+                // replayed responses need no connection.
+                _ if replayed.is_some() => None,
+                Some(conn) => Some(conn),
                 None => {
                     let hostport_uri = uri.host_port()?;
                     let mut conn: Option<Connection> = None;
-
-                    let HReqParams {
-                        force_http2,
-                        tls_disable_verify,
-                        ..
-                    } = params;
+                    let connect_start = Instant::now();
 
                     // if the current request is for the same uri (hostport part) as
-                    // the original uri, we will use the override.
-                    if orig_hostport == hostport_uri {
+                    // the original uri, we will use the unix socket or override.
+                    #[cfg(unix)]
+                    {
+                        if conn.is_none() && orig_hostport == hostport_uri {
+                            if let Some(path) = &params.unix_socket {
+                                debug!("Connect new: {} over: {}", uri, path.display());
+                                conn = Some(
+                                    connect_unix(&hostport_uri, path, &params, &self.h2_config)
+                                        .await?,
+                                );
+                            }
+                        }
+                    }
+
+                    if conn.is_none() {
+                        if let Some(connector) = &self.connector {
+                            let hostport = match &params.with_override {
+                                Some(arc) if orig_hostport == hostport_uri => (**arc).clone(),
+                                _ => hostport_uri.clone(),
+                            };
+                            debug!("Connect new: {} with connector: {}", uri, hostport);
+                            conn = Some(
+                                connect_with(&**connector, hostport, &params, &self.h2_config)
+                                    .await?,
+                            );
+                        }
+                    }
+
+                    if conn.is_none() && orig_hostport == hostport_uri {
                         if let Some(arc) = params.with_override.clone() {
                             let hostport = &*arc;
                             debug!("Connect new: {} with override: {}", uri, hostport);
-                            conn = Some(connect(hostport, force_http2, tls_disable_verify).await?);
+                            conn = Some(connect(hostport, &params, &self.h2_config).await?);
                         }
                     }
 
-                    let conn = match conn {
+                    let mut conn = match conn {
                         Some(conn) => conn,
                         // no override for this connection.
                         None => {
                             debug!("Connect new: {}", hostport_uri);
-                            connect(&hostport_uri, force_http2, tls_disable_verify).await?
+                            connect(&hostport_uri, &params, &self.h2_config).await?
                         }
                     };
 
+                    conn.set_connect_time(connect_start.elapsed());
+
                     if pooling {
                         self.connections.push(conn);
                         let idx = self.connections.len() - 1;
-                        self.connections.get_mut(idx).unwrap()
+                        self.connections.get_mut(idx)
                     } else {
                         unpooled.replace(conn);
-                        unpooled.as_mut().unwrap()
+                        unpooled.as_mut()
                     }
                 }
             };
 
             debug!("{} {}", req.method(), req.uri());
 
-            match conn.send_request(req, body_buffer).await {
+            let result = match &mut conn {
+                Some(conn) => conn.send_request(req, body_buffer).await,
+                None => Ok(replayed.take().expect("Replayed response")),
+            };
+
+            // record what the server responded.
+            let result = match (result, recording, &fixtures) {
+                (Ok(res), Some(pending), Some(fixtures)) => {
+                    fixtures.store(pending, res, &params).await
+                }
+                (result, _, _) => result,
+            };
+
+            match result {
                 Ok(mut res) => {
                     // whether we are to retain this connection in the pool.
                     let mut retain = true;
 
+                    // in flight until the body is read.
+                    if let Some(permit) = permit.take() {
+                        res.body_mut().add_observer(permit);
+                    }
+
+                    // unsafe methods invalidate stored responses for the uri.
+                    if let Some(cache) = &cache {
+                        let status = res.status();
+                        if !is_safe && !status.is_client_error() && !status.is_server_error() {
+                            cache.invalidate(&uri).await;
+                        }
+                    }
+
                     // squirrel away cookies (also in redirects)
                     if use_cookies {
                         for cookie_head in res.headers().get_all("set-cookie") {
@@ -395,10 +1025,15 @@ impl Agent {
                             next_req = http::Request::from_parts(parts, body);
                         }
 
-                        if is_307ish
-                            && !conn.is_http2()
-                            && conn.host_port() == &next_req.uri().host_port()?
-                        {
+                        let same_h1_host = match &conn {
+                            Some(conn) => {
+                                !conn.is_http2()
+                                    && conn.host_port() == &next_req.uri().host_port()?
+                            }
+                            None => false,
+                        };
+
+                        if is_307ish && same_h1_host {
                             // there's a big chance we started sending the body to the
                             // current host before we received the 307/308. for http1
                             // that means the upstream is "clogged" with a half body.
@@ -414,7 +1049,7 @@ impl Agent {
                         }
 
                         // drop connection from pool if need be.
-                        if !retain {
+                        if let (false, Some(conn)) = (retain, &conn) {
                             let conn_id = conn.id();
                             debug!("Remove from pool: {}", conn.host_port());
                             self.connections.retain(|c| c.id() != conn_id);
@@ -425,12 +1060,17 @@ impl Agent {
                     }
 
                     // a non-redirect is a ready response returned to the user
+                    if let (Some(cache), Some(pending)) = (&cache, pending) {
+                        break cache.store(pending, res, &params).await;
+                    }
                     break Ok(res);
                 }
                 Err(err) => {
                     // remove this (failed) connection from the pool.
-                    let conn_id = conn.id();
-                    self.connections.retain(|c| c.id() != conn_id);
+                    if let Some(conn) = &conn {
+                        let conn_id = conn.id();
+                        self.connections.retain(|c| c.id() != conn_id);
+                    }
 
                     // retry?
                     retries -= 1;
@@ -0,0 +1,889 @@
+//! HTTP response cache (RFC 9111).
+//!
+//! Responses to `GET` requests are stored together with the request headers named in
+//! their `Vary`. How long a stored response is fresh comes from `Cache-Control`,
+//! `Expires` and `Age`, or a heuristic based on `Last-Modified` when neither is given.
+//! Stale responses are revalidated using `If-None-Match` and `If-Modified-Since`.
+//!
+//! Bodies are stored after content and charset decoding, and are served without
+//! decoding them again. The stored headers are adjusted to the decoded body. A body
+//! is stored as the caller reads it, and the response is stored once read to the end.
+
+use crate::body_codec::BodyImpl;
+use crate::head_ext::HeaderMapExt;
+use crate::params::HReqParams;
+use crate::AsyncRead;
+use crate::AsyncRuntime;
+use crate::Body;
+use crate::Error;
+use futures_util::io::Cursor;
+use futures_util::ready;
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use http::StatusCode;
+use httpdate::parse_http_date;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+
+/// Status codes that can be stored without explicit freshness.
+const HEURISTIC_STATUS: &[u16] = &[200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+/// Headers of a `304` that must not replace those of the stored response.
+const KEEP_ON_UPDATE: &[&str] = &["content-length", "content-encoding", "transfer-encoding"];
+
+/// Headers describing the body as sent, which don't apply to the decoded body.
+const DROP_ON_STORE: &[&str] = &["content-encoding", "content-length", "transfer-encoding"];
+
+/// First line of a cache file, followed by the key.
+const DISK_MAGIC: &str = "hreq-cache 1 ";
+
+/// A cache of HTTP responses used by an [`Agent`].
+///
+/// Follows the rules for a private cache in [RFC 9111]. Responses to `GET` requests are
+/// stored when `Cache-Control` and `Expires` allow it, taking `Vary` into account.
+///
+///   * Fresh responses are served without contacting the server.
+///   * Stale responses are revalidated using `If-None-Match` and `If-Modified-Since`.
+///     A `304 Not Modified` serves the stored body with updated headers.
+///   * Responses with `stale-while-revalidate` are served stale for that long, while
+///     being revalidated in the background.
+///   * `POST`, `PUT`, `DELETE` etc. remove the stored responses for the uri.
+///
+/// Requests with `Range` or conditional headers, `Cache-Control: no-store`, or with
+/// content or charset decoding turned off, bypass the cache.
+///
+/// Clones of a cache share the stored responses.
+///
+/// ```no_run
+/// use hreq::prelude::*;
+/// use hreq::{Agent, Cache};
+///
+/// let mut agent = Agent::new();
+/// agent.cache(Cache::memory());
+///
+/// // the second request is served from the cache, if the response allows it.
+/// for _ in 0..2 {
+///     let req = Request::get("https://example.com/").with_body(()).unwrap();
+///     agent.send(req).block().unwrap();
+/// }
+/// ```
+///
+/// [`Agent`]: struct.Agent.html
+/// [RFC 9111]: https://www.rfc-editor.org/rfc/rfc9111
+#[derive(Clone)]
+pub struct Cache {
+    store: Arc<Store>,
+    max_body_size: usize,
+}
+
+enum Store {
+    Memory(Mutex<HashMap<String, Vec<Entry>>>),
+    Disk(PathBuf),
+}
+
+impl Cache {
+    /// Creates a cache keeping the responses in memory.
+    ///
+    /// ```
+    /// use hreq::Cache;
+    ///
+    /// let cache = Cache::memory();
+    /// ```
+    pub fn memory() -> Self {
+        Cache::with_store(Store::Memory(Mutex::new(HashMap::new())))
+    }
+
+    /// Creates a cache keeping the responses in files in a directory.
+    ///
+    /// The directory is created when the first response is stored. Responses stored
+    /// earlier in the same directory, also by other processes, are reused.
+    ///
+    /// ```
+    /// use hreq::Cache;
+    ///
+    /// let cache = Cache::disk("/tmp/hreq-cache");
+    /// ```
+    pub fn disk(dir: impl Into<PathBuf>) -> Self {
+        Cache::with_store(Store::Disk(dir.into()))
+    }
+
+    fn with_store(store: Store) -> Self {
+        Cache {
+            store: Arc::new(store),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    /// Largest response body to store, in bytes.
+    ///
+    /// Defaults to 10MiB. Larger responses are passed on without being stored.
+    ///
+    /// Up to this much of each cacheable response is held in memory while it is read.
+    pub fn max_body_size(mut self, size: usize) -> Self {
+        self.max_body_size = size;
+        self
+    }
+
+    /// Look for a stored response to the request.
+    ///
+    /// If the request is to be sent, a stale response is revalidated by adding
+    /// validators to the request.
+    pub(crate) async fn lookup(
+        &self,
+        req: &mut http::Request<Body>,
+        params: &HReqParams,
+    ) -> Lookup {
+        if !is_cacheable_request(req, params) {
+            return Lookup::Bypass;
+        }
+
+        let req_cc = Directives::parse(req.headers());
+
+        if req_cc.has("no-store") {
+            return Lookup::Bypass;
+        }
+
+        let key = req.uri().to_string();
+        let stored = self.get(&key, req.headers()).await;
+
+        let mut pending = Pending {
+            key,
+            req_headers: req.headers().clone(),
+            request_time: SystemTime::now(),
+            stale: None,
+        };
+
+        let entry = match stored {
+            Some(entry) => entry,
+            None => return Lookup::Forward(pending),
+        };
+
+        let res_cc = Directives::parse(&entry.headers);
+
+        let age = entry.age(SystemTime::now());
+        let lifetime = entry.lifetime();
+
+        let pragma_no_cache = req_cc.is_empty()
+            && req
+                .headers()
+                .get_str("pragma")
+                .map(|v| v.contains("no-cache"))
+                .unwrap_or(false);
+        let no_cache = req_cc.has("no-cache") || res_cc.has("no-cache") || pragma_no_cache;
+        let too_old = req_cc.seconds("max-age").map(|max| age > max) == Some(true);
+
+        if !no_cache && !too_old {
+            if age < lifetime {
+                trace!("Cache fresh ({:?} < {:?}): {}", age, lifetime, pending.key);
+                return Lookup::Fresh(entry);
+            }
+
+            if let Some(swr) = res_cc.seconds("stale-while-revalidate") {
+                if !res_cc.has("must-revalidate") && age < lifetime + swr {
+                    return Lookup::StaleWhileRevalidate(entry);
+                }
+            }
+        }
+
+        if entry.add_validators(req.headers_mut()) {
+            pending.stale = Some(entry);
+        }
+
+        Lookup::Forward(pending)
+    }
+
+    /// Store the response to a forwarded request, if allowed. A `304` for a revalidated
+    /// request is turned into the stored response.
+    pub(crate) async fn store(
+        &self,
+        pending: Pending,
+        res: http::Response<Body>,
+        params: &HReqParams,
+    ) -> Result<http::Response<Body>, Error> {
+        let response_time = SystemTime::now();
+
+        if res.status() == StatusCode::NOT_MODIFIED {
+            if let Some(mut entry) = pending.stale {
+                debug!("Cache revalidated: {}", pending.key);
+                entry.update(res.headers(), pending.request_time, response_time);
+                self.put(&pending.key, entry.clone()).await;
+                return Ok(entry.response(params));
+            }
+            return Ok(res);
+        }
+
+        // the response body reports progress as the tee reads it, not again when served.
+        let params = &HReqParams {
+            progress: None,
+            ..params.clone()
+        };
+
+        let (mut parts, body) = res.into_parts();
+
+        let entry = Entry {
+            status: parts.status,
+            headers: parts.headers.clone(),
+            vary: vary_values(&parts.headers, &pending.req_headers),
+            request_time: pending.request_time,
+            response_time,
+            body: Arc::from(&[][..]),
+        };
+
+        if !entry.is_storable() {
+            return Ok(http::Response::from_parts(parts, body));
+        }
+
+        // the length of the body as read, if decoding doesn't change it.
+        let length = body.content_encoded_length();
+        decoded_headers(&mut parts.headers, length.map(|l| l as usize));
+
+        let tee = Tee {
+            body,
+            cache: self.clone(),
+            key: pending.key,
+            entry: Some(entry),
+            data: vec![],
+            commit: None,
+        };
+
+        // no prebuffering, the caller gets the data as it arrives.
+        let mut body = Body::new(BodyImpl::RequestAsyncRead(Box::new(tee)), length, false);
+        body.configure(&decoded_params(params), &parts.headers, true);
+
+        Ok(http::Response::from_parts(parts, body))
+    }
+
+    /// Remove all stored responses for the uri.
+    pub(crate) async fn invalidate(&self, uri: &http::Uri) {
+        let key = uri.to_string();
+
+        match &*self.store {
+            Store::Memory(map) => {
+                map.lock().unwrap().remove(&key);
+            }
+            Store::Disk(dir) => {
+                let path = disk_path(dir, &key);
+                AsyncRuntime::spawn_blocking(move || {
+                    if let Err(e) = fs::remove_file(path) {
+                        if e.kind() != io::ErrorKind::NotFound {
+                            warn!("Failed to remove cache file: {}", e);
+                        }
+                    }
+                })
+                .await;
+            }
+        }
+    }
+
+    /// Most recent stored response matching the request headers.
+    async fn get(&self, key: &str, req_headers: &HeaderMap) -> Option<Entry> {
+        let entries = match &*self.store {
+            Store::Memory(map) => map.lock().unwrap().get(key).cloned().unwrap_or_default(),
+            Store::Disk(dir) => {
+                let dir = dir.clone();
+                let key = key.to_string();
+                AsyncRuntime::spawn_blocking(move || read_entries(&dir, &key)).await
+            }
+        };
+
+        entries
+            .into_iter()
+            .filter(|e| e.matches(req_headers))
+            .max_by_key(|e| e.response_time)
+    }
+
+    async fn put(&self, key: &str, entry: Entry) {
+        // a response replaces any stored one for the same variant.
+        fn replace(entries: &mut Vec<Entry>, entry: Entry) {
+            entries.retain(|e| e.vary != entry.vary);
+            entries.push(entry);
+        }
+
+        match &*self.store {
+            Store::Memory(map) => {
+                let mut map = map.lock().unwrap();
+                replace(map.entry(key.to_string()).or_default(), entry);
+            }
+            Store::Disk(dir) => {
+                let dir = dir.clone();
+                let key = key.to_string();
+
+                AsyncRuntime::spawn_blocking(move || {
+                    let mut entries = read_entries(&dir, &key);
+                    replace(&mut entries, entry);
+
+                    // the cache is a best effort, failing to store is not an error.
+                    if let Err(e) = write_entries(&dir, &key, &entries) {
+                        warn!("Failed to write cache file: {}", e);
+                    }
+                })
+                .await;
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cache")
+    }
+}
+
+pub(crate) enum Lookup {
+    /// The request doesn't use the cache.
+    Bypass,
+    /// A stored response fresh enough to serve.
+    Fresh(Entry),
+    /// A stale response to serve while it is revalidated in the background.
+    StaleWhileRevalidate(Entry),
+    /// The request is to be sent, the response might be stored.
+    Forward(Pending),
+}
+
+/// A request sent on to the server.
+pub(crate) struct Pending {
+    key: String,
+    req_headers: HeaderMap,
+    request_time: SystemTime,
+    /// Stale response being revalidated.
+    stale: Option<Entry>,
+}
+
+/// Body of a response to store, which is stored while the caller reads it.
+///
+/// The entry is stored when the body is read to the end, before the caller sees the end.
+/// A body failing to read, dropped early, or larger than the max body size is not stored.
+struct Tee {
+    body: Body,
+    cache: Cache,
+    key: String,
+    /// The entry to store, `None` once the body is too big.
+    entry: Option<Entry>,
+    data: Vec<u8>,
+    /// Storing the entry. In a `Mutex` to make the reader `Sync`, it's never locked.
+    commit: Option<Mutex<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+}
+
+impl AsyncRead for Tee {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if let Some(commit) = &mut this.commit {
+            ready!(commit.get_mut().unwrap().as_mut().poll(cx));
+            this.commit = None;
+            return Ok(0).into();
+        }
+
+        let amount = ready!(Pin::new(&mut this.body).poll_read(cx, buf))?;
+
+        if amount > 0 {
+            if this.entry.is_some() {
+                this.data.extend_from_slice(&buf[..amount]);
+
+                if this.data.len() > this.cache.max_body_size {
+                    trace!("Too big to cache: {}", this.key);
+                    this.entry = None;
+                    this.data = vec![];
+                }
+            }
+
+            return Ok(amount).into();
+        }
+
+        if let Some(mut entry) = this.entry.take() {
+            debug!("Cache store: {}", this.key);
+
+            let data = mem::take(&mut this.data);
+            decoded_headers(&mut entry.headers, Some(data.len()));
+            entry.body = data.into();
+
+            let cache = this.cache.clone();
+            let key = mem::take(&mut this.key);
+            let commit = async move { cache.put(&key, entry).await };
+            this.commit = Some(Mutex::new(Box::pin(commit)));
+
+            return Pin::new(this).poll_read(cx, buf);
+        }
+
+        Ok(0).into()
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Entry {
+    status: StatusCode,
+    headers: HeaderMap,
+    /// Values of the request headers named in `Vary`.
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    request_time: SystemTime,
+    response_time: SystemTime,
+    body: Arc<[u8]>,
+}
+
+impl Entry {
+    /// Response to serve from this entry.
+    pub(crate) fn response(&self, params: &HReqParams) -> http::Response<Body> {
+        let mut headers = self.headers.clone();
+
+        let age = self.age(SystemTime::now()).as_secs();
+        headers.insert("age", HeaderValue::from(age));
+
+        let body = self.body(params, &headers);
+
+        let mut res = http::Response::new(body);
+        *res.status_mut() = self.status;
+        *res.headers_mut() = headers;
+        res.extensions_mut().insert(params.clone());
+
+        res
+    }
+
+    fn body(&self, params: &HReqParams, headers: &HeaderMap) -> Body {
+        let len = self.body.len() as u64;
+        let mut body = Body::from_async_read(Cursor::new(self.body.clone()), Some(len));
+        body.configure(&decoded_params(params), headers, true);
+        body
+    }
+
+    fn matches(&self, req_headers: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| combined(req_headers, name) == *value)
+    }
+
+    fn is_storable(&self) -> bool {
+        let cc = Directives::parse(&self.headers);
+
+        let explicit = cc.has("max-age") || self.headers.contains_key("expires");
+        let status = self.status.as_u16();
+
+        let status_ok = status != 206
+            && !self.status.is_informational()
+            && (explicit || HEURISTIC_STATUS.contains(&status));
+
+        let vary_all = self
+            .headers
+            .get_all("vary")
+            .iter()
+            .any(|v| v.to_str().map(|v| v.contains('*')).unwrap_or(true));
+
+        status_ok
+            && !cc.has("no-store")
+            && !vary_all
+            && (self.lifetime() > Duration::ZERO || self.has_validator())
+    }
+
+    /// How long the response is fresh after being generated by the server.
+    fn lifetime(&self) -> Duration {
+        let cc = Directives::parse(&self.headers);
+
+        if let Some(max_age) = cc.seconds("max-age") {
+            return max_age;
+        }
+
+        let date = self.date();
+
+        if let Some(expires) = self.headers.get_str("expires") {
+            // invalid dates, such as "0", means already expired.
+            return parse_http_date(expires)
+                .map(|expires| since(expires, date))
+                .unwrap_or_default();
+        }
+
+        if HEURISTIC_STATUS.contains(&self.status.as_u16()) {
+            let last_modified = self
+                .headers
+                .get_str("last-modified")
+                .and_then(|v| parse_http_date(v).ok());
+
+            if let Some(last_modified) = last_modified {
+                return since(date, last_modified) / 10;
+            }
+        }
+
+        Duration::ZERO
+    }
+
+    /// Current age of the response, including time spent in other caches.
+    fn age(&self, now: SystemTime) -> Duration {
+        let age_value = self
+            .headers
+            .get_as::<u64>("age")
+            .map(Duration::from_secs)
+            .unwrap_or_default();
+
+        let apparent_age = since(self.response_time, self.date());
+        let response_delay = since(self.response_time, self.request_time);
+        let initial_age = apparent_age.max(age_value + response_delay);
+
+        initial_age + since(now, self.response_time)
+    }
+
+    fn date(&self) -> SystemTime {
+        self.headers
+            .get_str("date")
+            .and_then(|v| parse_http_date(v).ok())
+            .unwrap_or(self.response_time)
+    }
+
+    fn has_validator(&self) -> bool {
+        self.headers.contains_key("etag") || self.headers.contains_key("last-modified")
+    }
+
+    /// Add conditional headers for revalidation. Returns false if there are no validators.
+    fn add_validators(&self, headers: &mut HeaderMap) -> bool {
+        if let Some(etag) = self.headers.get("etag") {
+            headers.insert("if-none-match", etag.clone());
+        }
+        if let Some(last_modified) = self.headers.get("last-modified") {
+            headers.insert("if-modified-since", last_modified.clone());
+        }
+        self.has_validator()
+    }
+
+    /// Update with the headers of a `304 Not Modified`.
+    fn update(&mut self, headers: &HeaderMap, request_time: SystemTime, response_time: SystemTime) {
+        for name in headers.keys() {
+            if KEEP_ON_UPDATE.contains(&name.as_str()) {
+                continue;
+            }
+            self.headers.remove(name);
+            for value in headers.get_all(name) {
+                self.headers.append(name.clone(), value.clone());
+            }
+        }
+        self.request_time = request_time;
+        self.response_time = response_time;
+
+        // a content-type in the 304 describes the body as sent.
+        strip_charset(&mut self.headers);
+    }
+}
+
+/// Parsed `Cache-Control` directives.
+struct Directives(Vec<(String, Option<String>)>);
+
+impl Directives {
+    fn parse(headers: &HeaderMap) -> Self {
+        let mut directives = vec![];
+
+        for value in headers.get_all("cache-control") {
+            let value = match value.to_str() {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+
+            for d in value.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+                let (name, arg) = match d.find('=') {
+                    Some(idx) => (&d[..idx], Some(d[idx + 1..].trim().trim_matches('"'))),
+                    None => (d, None),
+                };
+                directives.push((name.trim().to_ascii_lowercase(), arg.map(String::from)));
+            }
+        }
+
+        Directives(directives)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.0.iter().any(|(n, _)| n == name)
+    }
+
+    fn seconds(&self, name: &str) -> Option<Duration> {
+        self.0
+            .iter()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, arg)| arg.as_ref()?.parse::<u64>().ok())
+            .map(Duration::from_secs)
+    }
+}
+
+fn is_cacheable_request(req: &http::Request<Body>, params: &HReqParams) -> bool {
+    const BYPASS_HEADERS: &[&str] = &[
+        "range",
+        "if-match",
+        "if-none-match",
+        "if-modified-since",
+        "if-unmodified-since",
+        "if-range",
+    ];
+
+    req.method() == http::Method::GET
+        && params.content_decode
+        && params.charset_rx.source.is_on()
+        && !BYPASS_HEADERS
+            .iter()
+            .any(|h| req.headers().contains_key(*h))
+}
+
+/// Adjust the headers to a body that is content and charset decoded.
+///
+/// `len` is the length of the decoded body, if known.
+pub(crate) fn decoded_headers(headers: &mut HeaderMap, len: Option<usize>) {
+    for name in DROP_ON_STORE {
+        headers.remove(*name);
+    }
+    if let Some(len) = len {
+        headers.insert("content-length", len.into());
+    }
+    strip_charset(headers);
+}
+
+/// Remove the charset parameter of the content-type.
+fn strip_charset(headers: &mut HeaderMap) {
+    let ctype = match headers.get_str("content-type") {
+        Some(v) if v.to_ascii_lowercase().contains("charset") => v,
+        _ => return,
+    };
+
+    let stripped = ctype
+        .split(';')
+        .filter(|p| !p.trim().to_ascii_lowercase().starts_with("charset"))
+        .collect::<Vec<_>>()
+        .join(";");
+
+    if let Ok(value) = HeaderValue::from_str(&stripped) {
+        headers.insert("content-type", value);
+    }
+}
+
+/// Params for a body that is already decoded.
+pub(crate) fn decoded_params(params: &HReqParams) -> HReqParams {
+    let mut params = params.clone();
+    params.content_decode = false;
+    params.charset_rx.toggle_source(false);
+    params
+}
+
+fn vary_values(
+    res_headers: &HeaderMap,
+    req_headers: &HeaderMap,
+) -> Vec<(HeaderName, Option<HeaderValue>)> {
+    let mut vary = vec![];
+
+    for value in res_headers.get_all("vary") {
+        let value = match value.to_str() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+                let value = combined(req_headers, &name);
+                vary.push((name, value));
+            }
+        }
+    }
+
+    vary
+}
+
+/// All values of a header as one.
+fn combined(headers: &HeaderMap, name: &HeaderName) -> Option<HeaderValue> {
+    let mut values = headers.get_all(name).iter();
+    let first = values.next()?;
+
+    let mut all = first.as_bytes().to_vec();
+    for value in values {
+        all.extend_from_slice(b", ");
+        all.extend_from_slice(value.as_bytes());
+    }
+
+    HeaderValue::from_bytes(&all).ok()
+}
+
+fn since(later: SystemTime, earlier: SystemTime) -> Duration {
+    later.duration_since(earlier).unwrap_or_default()
+}
+
+fn disk_path(dir: &Path, key: &str) -> PathBuf {
+    // FNV-1a, which unlike the std hasher is stable between builds.
+    let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    dir.join(format!("{:016x}", hash))
+}
+
+fn read_entries(dir: &Path, key: &str) -> Vec<Entry> {
+    let path = disk_path(dir, key);
+
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(_) => return vec![],
+    };
+
+    decode_entries(&data, key).unwrap_or_else(|| {
+        debug!("Ignore unreadable cache file: {:?}", path);
+        vec![]
+    })
+}
+
+fn write_entries(dir: &Path, key: &str, entries: &[Entry]) -> io::Result<()> {
+    static TMP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    fs::create_dir_all(dir)?;
+
+    let path = disk_path(dir, key);
+
+    // write and rename, so readers never see a half written file.
+    let tmp = path.with_extension(format!(
+        "tmp-{}-{}",
+        std::process::id(),
+        TMP_COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    fs::write(&tmp, encode_entries(key, entries))?;
+    fs::rename(&tmp, &path)
+}
+
+// The file format is a line with the key, followed by the entries. Each entry is
+//
+//   entry <status> <request time> <response time> <body length>
+//   h <response header>: <value>
+//   v <vary request header>: <value>
+//   v <vary request header missing in the request>
+//   <empty line>
+//   <body>
+//
+// Header values can't contain newlines, which makes them safe to write as lines.
+fn encode_entries(key: &str, entries: &[Entry]) -> Vec<u8> {
+    let mut out = format!("{}{}\n", DISK_MAGIC, key).into_bytes();
+
+    fn line(out: &mut Vec<u8>, kind: &str, name: &HeaderName, value: Option<&HeaderValue>) {
+        out.extend_from_slice(kind.as_bytes());
+        out.extend_from_slice(name.as_str().as_bytes());
+        if let Some(value) = value {
+            out.extend_from_slice(b": ");
+            out.extend_from_slice(value.as_bytes());
+        }
+        out.push(b'\n');
+    }
+
+    for entry in entries {
+        out.extend_from_slice(
+            format!(
+                "entry {} {} {} {}\n",
+                entry.status.as_u16(),
+                to_millis(entry.request_time),
+                to_millis(entry.response_time),
+                entry.body.len()
+            )
+            .as_bytes(),
+        );
+
+        for (name, value) in &entry.headers {
+            line(&mut out, "h ", name, Some(value));
+        }
+        for (name, value) in &entry.vary {
+            line(&mut out, "v ", name, value.as_ref());
+        }
+
+        out.push(b'\n');
+        out.extend_from_slice(&entry.body);
+    }
+
+    out
+}
+
+fn decode_entries(data: &[u8], key: &str) -> Option<Vec<Entry>> {
+    let mut rest = data;
+
+    // another key with the same hash, or another format.
+    if next_line(&mut rest)? != format!("{}{}", DISK_MAGIC, key).as_bytes() {
+        return None;
+    }
+
+    let mut entries = vec![];
+
+    while !rest.is_empty() {
+        let head = std::str::from_utf8(next_line(&mut rest)?).ok()?;
+        let mut head = head.strip_prefix("entry ")?.split(' ');
+
+        let status = StatusCode::from_u16(head.next()?.parse().ok()?).ok()?;
+        let request_time = from_millis(head.next()?.parse().ok()?);
+        let response_time = from_millis(head.next()?.parse().ok()?);
+        let len: usize = head.next()?.parse().ok()?;
+
+        let mut headers = HeaderMap::new();
+        let mut vary = vec![];
+
+        loop {
+            let line = next_line(&mut rest)?;
+
+            if line.is_empty() {
+                break;
+            }
+
+            let (kind, line) = line.split_at(2.min(line.len()));
+
+            let (name, value) = match line.windows(2).position(|w| w == b": ") {
+                Some(idx) => (
+                    &line[..idx],
+                    Some(HeaderValue::from_bytes(&line[idx + 2..]).ok()?),
+                ),
+                None => (line, None),
+            };
+            let name = HeaderName::from_bytes(name).ok()?;
+
+            match kind {
+                b"h " => {
+                    headers.append(name, value?);
+                }
+                b"v " => vary.push((name, value)),
+                _ => return None,
+            }
+        }
+
+        if rest.len() < len {
+            return None;
+        }
+
+        let (body, tail) = rest.split_at(len);
+        rest = tail;
+
+        entries.push(Entry {
+            status,
+            headers,
+            vary,
+            request_time,
+            response_time,
+            body: body.into(),
+        });
+    }
+
+    Some(entries)
+}
+
+fn next_line<'a>(rest: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let idx = rest.iter().position(|b| *b == b'\n')?;
+    let line = &rest[..idx];
+    *rest = &rest[idx + 1..];
+    Some(line)
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    since(time, UNIX_EPOCH).as_millis() as u64
+}
+
+fn from_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
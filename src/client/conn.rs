@@ -1,15 +1,25 @@
 use crate::body_codec::BodyImpl;
 use crate::body_send::BodySender;
 use crate::bw::BandwidthMonitor;
+use crate::deadline::limit;
+use crate::h2_config::Http2Config;
+use crate::h2c;
+use crate::har::{HarBody, HarEntry};
 use crate::head_ext::HeaderMapExt;
 use crate::params::HReqParams;
+use crate::progress::Direction;
+use crate::proto::Protocol;
 use crate::uninit::UninitBuf;
+use crate::upgrade::{self, RefusedBody, Takeover, Upgraded};
 use crate::uri_ext::HostPort;
 use crate::uri_ext::MethodExt;
 use crate::Body;
 use crate::Error;
+use crate::Stream;
+use crate::TimeoutKind;
 use crate::AGENT_IDENT;
 use bytes::Bytes;
+use futures_util::io::AsyncWriteExt;
 use futures_util::ready;
 use h2::client::SendRequest as H2SendRequest;
 use hreq_h1 as h1;
@@ -19,10 +29,11 @@ use std::fmt;
 use std::future::Future;
 use std::io;
 use std::pin::Pin;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Duration;
 
 static ID_COUNTER: Lazy<AtomicUsize> = Lazy::new(|| AtomicUsize::new(0));
 const START_BUF_SIZE: usize = 16_384;
@@ -35,6 +46,11 @@ pub struct Connection {
     inner: Inner,
     unfinished_reqs: Arc<()>,
     bw: Option<BandwidthMonitor>,
+    closed: Arc<AtomicBool>,
+    /// Time it took to connect, until used by the first request.
+    connect_time: Option<Duration>,
+    /// Pending h2c upgrade, asked for by the first request.
+    h2c: Option<H2cUpgrade>,
 }
 
 enum Inner {
@@ -43,28 +59,51 @@ enum Inner {
 }
 
 impl Connection {
-    pub(crate) fn new_h1(host_port: HostPort, conn: H1SendRequest) -> Self {
-        Self::new(host_port, Inner::H1(conn), None)
+    /// `closed` is set by the task driving the connection when it ends.
+    pub(crate) fn new_h1(
+        host_port: HostPort,
+        conn: H1SendRequest,
+        closed: Arc<AtomicBool>,
+    ) -> Self {
+        Self::new(host_port, Inner::H1(conn), None, closed)
     }
 
     pub(crate) fn new_h2(
         host_port: HostPort,
         conn: H2SendRequest<Bytes>,
-        bw: BandwidthMonitor,
+        bw: Option<BandwidthMonitor>,
+        closed: Arc<AtomicBool>,
     ) -> Self {
-        Self::new(host_port, Inner::H2(conn), Some(bw))
+        Self::new(host_port, Inner::H2(conn), bw, closed)
     }
 
-    fn new(host_port: HostPort, inner: Inner, bw: Option<BandwidthMonitor>) -> Self {
+    fn new(
+        host_port: HostPort,
+        inner: Inner,
+        bw: Option<BandwidthMonitor>,
+        closed: Arc<AtomicBool>,
+    ) -> Self {
         Connection {
             id: ID_COUNTER.fetch_add(1, Ordering::Relaxed),
             host_port,
             inner,
             unfinished_reqs: Arc::new(()),
             bw,
+            closed,
+            connect_time: None,
+            h2c: None,
         }
     }
 
+    /// Make the first request ask for an upgrade to http2 (h2c).
+    pub(crate) fn set_h2c_upgrade(&mut self, upgrade: H2cUpgrade) {
+        self.h2c = Some(upgrade);
+    }
+
+    pub(crate) fn set_connect_time(&mut self, time: Duration) {
+        self.connect_time = Some(time);
+    }
+
     pub(crate) fn id(&self) -> usize {
         self.id
     }
@@ -80,6 +119,11 @@ impl Connection {
         }
     }
 
+    /// Tells whether the underlying connection has ended, and can't be used anymore.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
     pub(crate) fn unfinished_requests(&self) -> usize {
         Arc::strong_count(&self.unfinished_reqs) - 1 // -1 for self
     }
@@ -96,10 +140,11 @@ impl Connection {
 
         let params = parts.extensions.get::<HReqParams>().unwrap();
         let deadline = params.deadline();
+        let har = params.har.clone();
 
         // resolve deferred body codecs because content-encoding and content-type are settled.
         if body.is_configurable() {
-            body.configure(&params, &parts.headers, false);
+            body.configure(params, &parts.headers, false);
 
             // for small request bodies we try to fully buffer the incoming data.
             if params.prebuffer {
@@ -109,6 +154,19 @@ impl Connection {
 
         configure_request(&mut parts, &body, self.is_http2());
 
+        // only the first request asks for the upgrade.
+        let mut h2c = self.h2c.take();
+        if h2c.is_some() {
+            h2c::set_upgrade_headers(&mut parts.headers);
+        }
+
+        // the entry follows the request into send_req, and then the response body.
+        let har =
+            har.map(|har| har.start(&parts, self.id, self.connect_time.take(), self.is_http2()));
+        if let Some(har) = &har {
+            parts.extensions.insert(har.clone());
+        }
+
         let req = http::Request::from_parts(parts, body);
 
         debug!(
@@ -125,10 +183,33 @@ impl Connection {
 
         // send request against a deadline
         let response = deadline
-            .race(send_req(req, body_buffer, &self.inner, unfin, bw))
-            .await?;
+            .race(send_req(
+                req,
+                body_buffer,
+                &self.inner,
+                unfin,
+                bw,
+                h2c.as_mut(),
+            ))
+            .await;
+
+        if let (Some(har), Err(err)) = (&har, &response) {
+            har.failed(err);
+        }
 
-        Ok(response)
+        if let Some(h2c) = h2c {
+            if let Some(conn) = h2c.switched {
+                debug!("h2c upgrade accepted: {}", self.host_port);
+                self.inner = conn.inner;
+                self.bw = conn.bw;
+                self.closed = conn.closed;
+            } else {
+                // refused (or failed), the connection goes on as http/1.1.
+                h2c.takeover.release();
+            }
+        }
+
+        response
     }
 }
 
@@ -167,26 +248,172 @@ pub(crate) fn configure_request(parts: &mut http::request::Parts, body: &Body, i
     }
 }
 
-async fn send_req(
+/// Send a request that asks to take over the connection (`Upgrade` or `CONNECT`).
+///
+/// The request gets a connection of its own, which stops reading after the response
+/// head. If the server agrees, the stream is taken from the http/1.1 layer and available
+/// via `OnUpgrade` in the response extensions.
+pub(crate) async fn send_upgrade(
+    host_port: HostPort,
+    stream: impl Stream,
+    req: http::Request<Body>,
+    h2_config: &Http2Config,
+) -> Result<http::Response<Body>, Error> {
+    if req.method() == http::Method::CONNECT {
+        return send_connect(stream, req).await;
+    }
+
+    let takeover = Takeover::new(stream, 0);
+
+    let mut conn =
+        super::open_stream(host_port, takeover.clone(), Protocol::Http11, h2_config).await?;
+
+    let method = req.method().clone();
+
+    debug!("Upgrade {} {} {:?}", req.method(), req.uri(), req.headers());
+
+    let res = conn.send_request(req, &mut BodyBuf::new(0)).await?;
+    let (mut parts, mut body) = res.into_parts();
+
+    // the OnUpgrade fails if the sender is dropped without sending.
+    let (tx, on_upgrade) = upgrade::on_upgrade_pair();
+    parts.extensions.insert(on_upgrade);
+
+    if upgrade::is_upgrade_response(&method, parts.status) {
+        let upgraded = takeover
+            .take_stream()
+            .ok_or_else(|| Error::Proto("Failed to take over upgraded stream".into()))?;
+
+        tx.send(upgraded);
+
+        // whatever follows the head is no longer http.
+        body = Body::empty();
+
+        if let Some(params) = parts.extensions.get::<HReqParams>() {
+            body.configure(params, &parts.headers, true);
+        }
+    } else {
+        trace!("Upgrade refused: {}", parts.status);
+        takeover.release();
+    }
+
+    Ok(http::Response::from_parts(parts, body))
+}
+
+/// Send a `CONNECT` request, which is written straight to the stream since hreq-h1 can't
+/// send it.
+async fn send_connect(
+    mut stream: impl Stream,
     req: http::Request<Body>,
+) -> Result<http::Response<Body>, Error> {
+    let (mut parts, mut body) = req.into_parts();
+
+    let params = parts.extensions.get::<HReqParams>().unwrap().clone();
+    let deadline = params.deadline();
+
+    if body.is_configurable() {
+        body.configure(&params, &parts.headers, false);
+
+        // we write the body ourselves, which requires a known length.
+        body.attempt_prebuffer().await?;
+    }
+
+    if body.is_definitely_a_body() && body.content_encoded_length().is_none() {
+        return Err(Error::User(
+            "CONNECT request body must have a known length".into(),
+        ));
+    }
+
+    configure_request(&mut parts, &body, false);
+
+    let req = http::Request::from_parts(parts, ());
+
+    debug!("CONNECT {} {:?}", req.uri(), req.headers());
+
+    let send_and_recv = async {
+        upgrade::write_all(&mut stream, &upgrade::write_connect_head(&req)?).await?;
+
+        if !body.is_definitely_no_body() {
+            futures_util::io::copy(&mut body, &mut stream).await?;
+            stream.flush().await?;
+        }
+
+        limit(
+            params.response_header_timeout,
+            TimeoutKind::ResponseHeaders,
+            upgrade::read_head(&mut stream),
+        )
+        .await
+    };
+
+    let (head, leftover) = deadline.race(send_and_recv).await?;
+
+    let (mut parts, _) = upgrade::parse_res_head(&head)?.into_parts();
+
+    debug!("{:?} {} {:?}", parts.version, parts.status, parts.headers);
+
+    parts.extensions.insert(params.clone());
+
+    let upgraded = Upgraded::new(stream, leftover);
+
+    // the OnUpgrade fails if the sender is dropped without sending.
+    let (tx, on_upgrade) = upgrade::on_upgrade_pair();
+    parts.extensions.insert(on_upgrade);
+
+    let mut res_body = if parts.status.is_success() {
+        tx.send(upgraded);
+
+        Body::empty()
+    } else {
+        trace!("CONNECT refused: {}", parts.status);
+        drop(tx);
+
+        let no_body = parts.status.is_informational()
+            || parts.status == http::StatusCode::NO_CONTENT
+            || parts.status == http::StatusCode::NOT_MODIFIED;
+
+        let (reader, len) = RefusedBody::new(&parts.headers, no_body, upgraded);
+
+        Body::from_async_read(reader, len)
+    };
+
+    res_body.configure(&params, &parts.headers, true);
+
+    Ok(http::Response::from_parts(parts, res_body))
+}
+
+async fn send_req(
+    mut req: http::Request<Body>,
     body_buffer: &mut BodyBuf,
     proto: &Inner,
     unfin: Arc<()>,
-    bw: Option<BandwidthMonitor>,
+    mut bw: Option<BandwidthMonitor>,
+    h2c: Option<&mut H2cUpgrade>,
 ) -> Result<http::Response<Body>, Error> {
     let params = req.extensions().get::<HReqParams>().unwrap().clone();
+    let har = req.extensions_mut().remove::<HarEntry>();
 
     let (parts, mut body_read) = req.into_parts();
     let req = http::Request::from_parts(parts, ());
 
-    let no_body = body_read.is_definitely_no_body() && body_buffer.len() == 0;
+    // trailers require the stream to be open after the head.
+    let no_body = body_read.is_definitely_no_body()
+        && body_buffer.len() == 0
+        && body_read.trailers().is_none();
 
-    let (mut res_fut, mut body_send) = proto.do_send(req, no_body).await?;
+    let (mut res_fut, mut body_send) = proto.do_send(req, no_body, bw.clone()).await?;
     let mut early_response = None;
 
     // this buffer should probably be less than h2 window size
     let mut buf = UninitBuf::with_capacity(START_BUF_SIZE, MAX_BUF_SIZE);
 
+    let mut progress = params.progress.as_ref().map(|p| {
+        let total = body_read.content_encoded_length();
+        p.track(Direction::Upload, total)
+    });
+
+    let throttle = params.throttle.as_ref().map(|t| t.for_body());
+
     if !no_body {
         let mut use_body_buf = true;
 
@@ -237,22 +464,68 @@ async fn send_req(
                 break;
             }
 
+            // Pay off any excess before these bytes go out.
+            if let Some(throttle) = &throttle {
+                throttle.transferred(amount_read).await;
+            }
+
             // Ship it to they underlying http1.1/http2 layer.
             body_send.send_data(&buf[0..amount_read]).await?;
+
+            if let Some(progress) = &mut progress {
+                progress.add(amount_read);
+            }
+
+            if let Some(har) = &har {
+                har.sent_data(&buf[0..amount_read]);
+            }
         }
 
+        body_send.send_end(body_read.trailers()).await?;
+
         // pass the body back with the buffer
         body_buffer.return_body = Some(body_read);
+    }
 
-        body_send.send_end().await?;
+    if let Some(har) = &har {
+        har.sent();
     }
 
     let (mut parts, mut res_body) = if let Some(res) = early_response {
         res?
     } else {
-        res_fut.await?
+        // the clock starts once the request is sent in full.
+        limit(
+            params.response_header_timeout,
+            TimeoutKind::ResponseHeaders,
+            res_fut,
+        )
+        .await?
     };
 
+    // the server accepted the h2c upgrade, and answers the request over http2.
+    if let Some(h2c) = h2c {
+        if parts.status == http::StatusCode::SWITCHING_PROTOCOLS {
+            let stream1 = h2c.switch().await?;
+            bw = h2c.switched.as_ref().and_then(|c| c.bw.clone());
+
+            let res = limit(
+                params.response_header_timeout,
+                TimeoutKind::ResponseHeaders,
+                ResponseFuture::H2(stream1),
+            )
+            .await?;
+
+            parts = res.0;
+            res_body = res.1;
+        }
+    }
+
+    if let Some(har) = har {
+        har.response(&parts);
+        res_body.add_observer(HarBody(har));
+    }
+
     debug!("{:?} {} {:?}", parts.version, parts.status, parts.headers);
 
     parts.extensions.insert(params.clone());
@@ -269,6 +542,7 @@ impl Inner {
         &self,
         req: http::Request<()>,
         no_body: bool,
+        bw: Option<BandwidthMonitor>,
     ) -> Result<(ResponseFuture, BodySender), Error> {
         Ok(match self {
             Inner::H1(h1) => {
@@ -279,7 +553,10 @@ impl Inner {
             Inner::H2(h2) => {
                 let mut h2 = h2.clone().ready().await?;
                 let (fut, send_body) = h2.send_request(req, no_body)?;
-                (ResponseFuture::H2(fut), BodySender::H2(send_body))
+                if let Some(bw) = &bw {
+                    bw.record_activity();
+                }
+                (ResponseFuture::H2(fut), BodySender::H2(send_body, bw))
             }
         })
     }
@@ -422,6 +699,46 @@ impl io::Read for BodyBuf {
     }
 }
 
+/// A pending h2c upgrade of a plain http/1.1 connection.
+///
+/// The first request asks for the upgrade. If the server accepts, the stream is taken
+/// from the http/1.1 layer, and the request is answered over http2 as stream 1.
+pub(crate) struct H2cUpgrade {
+    host_port: HostPort,
+    takeover: Takeover,
+    h2_config: Http2Config,
+    /// The http2 connection once switched.
+    switched: Option<Box<Connection>>,
+}
+
+impl H2cUpgrade {
+    pub(crate) fn new(host_port: HostPort, takeover: Takeover, h2_config: Http2Config) -> Self {
+        H2cUpgrade {
+            host_port,
+            takeover,
+            h2_config,
+            switched: None,
+        }
+    }
+
+    /// Switch to http2 after a `101 Switching Protocols`.
+    ///
+    /// Returns the response future of stream 1, which is the upgrade request.
+    async fn switch(&mut self) -> Result<h2::client::ResponseFuture, Error> {
+        let upgraded = self
+            .takeover
+            .take_stream()
+            .ok_or_else(|| Error::Proto("Failed to take over h2c upgraded stream".into()))?;
+
+        let (conn, stream1) =
+            super::open_h2c(self.host_port.clone(), upgraded, &self.h2_config).await?;
+
+        self.switched = Some(Box::new(conn));
+
+        Ok(stream1)
+    }
+}
+
 impl fmt::Display for Inner {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
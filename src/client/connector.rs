@@ -0,0 +1,76 @@
+//! Pluggable transports for the agent.
+
+use crate::proto::Protocol;
+use crate::uri_ext::HostPort;
+use crate::Error;
+use crate::Stream;
+use std::future::Future;
+use std::pin::Pin;
+
+/// Future returned by [`Connector::connect`].
+///
+/// [`Connector::connect`]: trait.Connector.html#tymethod.connect
+pub type ConnectFuture =
+    Pin<Box<dyn Future<Output = Result<(Box<dyn Stream>, Protocol), Error>> + Send>>;
+
+/// Opens the streams an [`Agent`] talks http over.
+///
+/// By default, the agent connects with TCP, wrapped in TLS for `https`. A connector
+/// replaces that with any [`Stream`]: in-memory pipes in tests, vsock, tunnels or a
+/// different TLS stack. The agent hands the stream to its http/1.1 or http/2 layer,
+/// which means pooling, cookies and redirects work as usual.
+///
+/// The connector is given the host and port of the uri, or the one set by
+/// [`with_override`]. When [`HostPort::is_tls`] is true, it's up to the connector to
+/// do TLS, hreq doesn't wrap the returned stream.
+///
+/// The returned [`Protocol`] tells which http version to use, such as negotiated by TLS
+/// ALPN. `Protocol::Unknown` means http/1.1, unless [`force_http2`] is set. The
+/// [`h2c_upgrade`] setting is not used for connectors.
+///
+/// Any closure `Fn(HostPort) -> impl Future<Output = Result<(impl Stream, Protocol), Error>>`
+/// is a connector.
+///
+/// ```no_run
+/// use hreq::prelude::*;
+/// use hreq::{Agent, HostPort, Protocol};
+/// use tokio_util::compat::TokioAsyncReadCompatExt;
+///
+/// let mut agent = Agent::new();
+///
+/// // route every connection through a local tunnel.
+/// agent.connector(|_host_port: HostPort| async move {
+///     let tcp = tokio::net::TcpStream::connect("127.0.0.1:9000").await?;
+///     Ok::<_, hreq::Error>((tcp.compat(), Protocol::Unknown))
+/// });
+///
+/// let req = Request::get("http://my-service/").with_body(()).unwrap();
+/// agent.send(req).block().unwrap();
+/// ```
+///
+/// [`Agent`]: struct.Agent.html
+/// [`Stream`]: trait.Stream.html
+/// [`Protocol`]: enum.Protocol.html
+/// [`HostPort::is_tls`]: struct.HostPort.html#method.is_tls
+/// [`with_override`]: trait.RequestBuilderExt.html#tymethod.with_override
+/// [`force_http2`]: trait.RequestBuilderExt.html#tymethod.force_http2
+/// [`h2c_upgrade`]: trait.RequestBuilderExt.html#tymethod.h2c_upgrade
+pub trait Connector: Send + Sync + 'static {
+    /// Open a new stream to the host and port.
+    fn connect(&self, host_port: HostPort) -> ConnectFuture;
+}
+
+impl<F, Fut, S> Connector for F
+where
+    F: Fn(HostPort) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(S, Protocol), Error>> + Send + 'static,
+    S: Stream,
+{
+    fn connect(&self, host_port: HostPort) -> ConnectFuture {
+        let fut = self(host_port);
+        Box::pin(async move {
+            let (stream, proto) = fut.await?;
+            Ok((Box::new(stream) as Box<dyn Stream>, proto))
+        })
+    }
+}
@@ -11,7 +11,7 @@ use time::{Duration, OffsetDateTime};
 /// just offset sessions cookies indefinitely.
 const DEFAULT_COOKIE_MAX_AGES_DAYS: Duration = Duration::days(9999);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct Cookies {
     domains: HashMap<String, CookieJar>,
 }
@@ -42,10 +42,20 @@ impl Cookies {
             let exp = OffsetDateTime::now_utc() + max;
             cookie.set_expires(Some(exp))
         }
-        let jar = self.domains.entry(domain).or_insert_with(CookieJar::new);
+        let jar = self.domains.entry(domain).or_default();
         jar.add(cookie);
     }
 
+    /// Add all cookies of another jar, replacing those with the same name.
+    pub fn merge(&mut self, other: Cookies) {
+        for (domain, jar) in other.domains {
+            let ours = self.domains.entry(domain).or_default();
+            for cookie in jar.iter() {
+                ours.add(cookie.clone());
+            }
+        }
+    }
+
     pub fn get(&self, uri: &http::Uri) -> Vec<&Cookie<'static>> {
         let mut ret = vec![];
 
@@ -0,0 +1,817 @@
+//! Converting requests to and from curl command lines.
+
+use crate::client::RequestBuilderExt;
+use crate::params::{HReqParams, QueryParams};
+use crate::uri_ext::HostPort;
+use crate::Body;
+use crate::Error;
+use http::{Method, Request, Uri};
+use std::fs;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Converts requests to and from [curl] command lines.
+///
+/// `to_curl` renders the method, uri, headers and body along with the hreq settings that
+/// have a curl equivalent. The body is only included when it's in memory, i.e. created
+/// from a `&str`, `String`, `Vec<u8>`, JSON etc. Bodies from readers and files are left out.
+///
+/// | hreq                              | curl                                   |
+/// |-----------------------------------|----------------------------------------|
+/// | [`timeout`]                       | `--max-time`                           |
+/// | [`connect_timeout`]               | `--connect-timeout`                    |
+/// | [`with_override`]                 | `--resolve` or `--connect-to`          |
+/// | [`tls_disable_server_cert_verify`]| `--insecure`                           |
+/// | [`force_http2`]                   | `--http2-prior-knowledge` or `--http2` |
+/// | [`h2c_upgrade`]                   | `--http2`                              |
+/// | [`unix_socket`]                   | `--unix-socket`                        |
+/// | [`redirect_max`]                  | `--location --max-redirs`              |
+///
+/// `from_curl` does the reverse for a pasted command line. Like curl, redirects are only
+/// followed with `-L`, otherwise the request gets a [`redirect_max`] of `0`. With `-L`,
+/// `--max-redirs` sets the [`redirect_max`]. Flags that
+/// only affect curl's output, such as `-v` or `-s`, are ignored. Flags hreq can't honor, such as `-F`,
+/// result in an error rather than a request that's subtly different.
+///
+/// ```
+/// use hreq::prelude::*;
+/// use hreq::CurlExt;
+///
+/// let req = Request::post("https://my-api/ingest")
+///     .header("x-api-key", "secret")
+///     .timeout_millis(5_000)
+///     .with_body("Hello").unwrap();
+///
+/// assert_eq!(
+///     req.to_curl(),
+///     "curl -X POST https://my-api/ingest -H 'x-api-key: secret' \
+///      -H 'content-type: text/plain; charset=utf-8' --data-binary Hello --max-time 5"
+/// );
+///
+/// let req = Request::from_curl(r#"
+///     curl -s 'https://my-api/ingest?verbose=1' \
+///       -H 'content-type: application/json' \
+///       -d '{"name":"Martin"}'
+/// "#).unwrap();
+///
+/// assert_eq!(req.method(), "POST");
+/// assert_eq!(req.header("content-type"), Some("application/json"));
+/// ```
+///
+/// [curl]: https://curl.se/
+/// [`timeout`]: trait.RequestBuilderExt.html#tymethod.timeout
+/// [`connect_timeout`]: trait.RequestBuilderExt.html#tymethod.connect_timeout
+/// [`with_override`]: trait.RequestBuilderExt.html#tymethod.with_override
+/// [`tls_disable_server_cert_verify`]: trait.RequestBuilderExt.html#tymethod.tls_disable_server_cert_verify
+/// [`force_http2`]: trait.RequestBuilderExt.html#tymethod.force_http2
+/// [`h2c_upgrade`]: trait.RequestBuilderExt.html#tymethod.h2c_upgrade
+/// [`unix_socket`]: trait.RequestBuilderExt.html#tymethod.unix_socket
+/// [`redirect_max`]: trait.RequestBuilderExt.html#tymethod.redirect_max
+pub trait CurlExt: Sized {
+    /// Render this request as a curl command line.
+    ///
+    /// Query parameters added with [`query`] are part of the rendered uri. If the body
+    /// sets a content type, and there's no `content-type` header, the header is added.
+    ///
+    /// [`query`]: trait.RequestBuilderExt.html#tymethod.query
+    fn to_curl(&self) -> String;
+
+    /// Parse a curl command line into a request.
+    ///
+    /// The command is split like a POSIX shell would, including line continuations and
+    /// `$'...'` quoting. A leading `curl` is optional.
+    fn from_curl(command: &str) -> Result<Self, Error>;
+}
+
+impl CurlExt for Request<Body> {
+    fn to_curl(&self) -> String {
+        let params = self.extensions().get::<HReqParams>();
+        let uri = match self.extensions().get::<QueryParams>() {
+            Some(query) => query.apply_to_uri(self.uri()),
+            None => self.uri().clone(),
+        };
+        let body = self.body().buffered().filter(|b| !b.is_empty());
+
+        let mut args = vec!["curl".to_string()];
+
+        if self.method() == Method::HEAD {
+            args.push("--head".into());
+        } else if self.method() != Method::GET || body.is_some() {
+            args.push("-X".into());
+            args.push(quote(self.method().as_str().as_bytes()));
+        }
+
+        args.push(quote(uri.to_string().as_bytes()));
+
+        for (name, value) in self.headers() {
+            let header = if value.is_empty() {
+                format!("{};", name)
+            } else {
+                format!("{}: {}", name, String::from_utf8_lossy(value.as_bytes()))
+            };
+            args.push("-H".into());
+            args.push(quote(header.as_bytes()));
+        }
+
+        if let Some(body) = body {
+            if !self.headers().contains_key("content-type") {
+                if let Some(ctype) = self.body().content_type() {
+                    args.push("-H".into());
+                    args.push(quote(format!("content-type: {}", ctype).as_bytes()));
+                }
+            }
+            args.push("--data-binary".into());
+            args.push(quote(body));
+        }
+
+        if let Some(params) = params {
+            if let Some(timeout) = params.timeout {
+                args.push("--max-time".into());
+                args.push(timeout.as_secs_f64().to_string());
+            }
+
+            if let Some(timeout) = params.connect_timeout {
+                args.push("--connect-timeout".into());
+                args.push(timeout.as_secs_f64().to_string());
+            }
+
+            if let (Some(over), Ok(host_port)) = (&params.with_override, HostPort::from_uri(&uri)) {
+                let ip = over.host().trim_matches(|c| c == '[' || c == ']');
+                if over.port() == host_port.port() && ip.parse::<IpAddr>().is_ok() {
+                    args.push("--resolve".into());
+                    args.push(quote(format!("{}:{}", host_port, over.host()).as_bytes()));
+                } else {
+                    args.push("--connect-to".into());
+                    args.push(quote(format!("{}:{}", host_port, over).as_bytes()));
+                }
+            }
+
+            if let Some(path) = &params.unix_socket {
+                args.push("--unix-socket".into());
+                args.push(quote(path.to_string_lossy().as_bytes()));
+            }
+
+            if params.tls_disable_verify {
+                args.push("--insecure".into());
+            }
+
+            if let Some(max) = params.redirect_max.filter(|m| *m > 0) {
+                args.push("--location".into());
+                args.push("--max-redirs".into());
+                args.push(max.to_string());
+            }
+
+            let is_tls = uri.scheme_str() == Some("https");
+            if params.force_http2 && !is_tls {
+                args.push("--http2-prior-knowledge".into());
+            } else if params.force_http2 || params.h2c_upgrade && !is_tls {
+                args.push("--http2".into());
+            }
+        }
+
+        args.join(" ")
+    }
+
+    fn from_curl(command: &str) -> Result<Self, Error> {
+        let mut args = split_args(command)?.into_iter().peekable();
+
+        if args.peek().map(|a| a == b"curl").unwrap_or(false) {
+            args.next();
+        }
+
+        let mut cmd = Command::default();
+
+        while let Some(arg) = args.next() {
+            let arg = text(arg)?;
+
+            if let Some(name) = arg.strip_prefix("--") {
+                let opt = Opt::lookup(name)
+                    .ok_or_else(|| Error::User(format!("Unsupported curl option: {}", arg)))?;
+                let value = if opt.takes_arg() {
+                    Some(next_value(&mut args, &arg)?)
+                } else {
+                    None
+                };
+                cmd.apply(opt, value)?;
+            } else if arg.len() > 1 && arg.starts_with('-') {
+                // clustered short options, like -sSL or -XPOST
+                let mut rest = &arg[1..];
+                while let Some(c) = rest.chars().next() {
+                    rest = &rest[c.len_utf8()..];
+                    let opt = Opt::lookup(&c.to_string())
+                        .ok_or_else(|| Error::User(format!("Unsupported curl option: -{}", c)))?;
+                    if opt.takes_arg() {
+                        let value = if rest.is_empty() {
+                            next_value(&mut args, &arg)?
+                        } else {
+                            rest.as_bytes().to_vec()
+                        };
+                        cmd.apply(opt, Some(value))?;
+                        break;
+                    }
+                    cmd.apply(opt, None)?;
+                }
+            } else {
+                cmd.apply(Opt::Url, Some(arg.into_bytes()))?;
+            }
+        }
+
+        cmd.into_request()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Opt {
+    Request,
+    Header,
+    Data,
+    DataBinary,
+    DataRaw,
+    Json,
+    Get,
+    Head,
+    Url,
+    User,
+    UserAgent,
+    Referer,
+    Cookie,
+    MaxTime,
+    ConnectTimeout,
+    Insecure,
+    Resolve,
+    ConnectTo,
+    UnixSocket,
+    Http2,
+    Http2PriorKnowledge,
+    Location,
+    MaxRedirs,
+    /// Options only affecting curl's own behavior or output.
+    Ignore,
+    IgnoreArg,
+}
+
+impl Opt {
+    fn lookup(name: &str) -> Option<Opt> {
+        use Opt::*;
+        Some(match name {
+            "X" | "request" => Request,
+            "H" | "header" => Header,
+            "d" | "data" | "data-ascii" => Data,
+            "data-binary" => DataBinary,
+            "data-raw" => DataRaw,
+            "json" => Json,
+            "G" | "get" => Get,
+            "I" | "head" => Head,
+            "url" => Url,
+            "u" | "user" => User,
+            "A" | "user-agent" => UserAgent,
+            "e" | "referer" => Referer,
+            "b" | "cookie" => Cookie,
+            "m" | "max-time" => MaxTime,
+            "connect-timeout" => ConnectTimeout,
+            "k" | "insecure" => Insecure,
+            "resolve" => Resolve,
+            "connect-to" => ConnectTo,
+            "unix-socket" => UnixSocket,
+            "http2" => Http2,
+            "http2-prior-knowledge" => Http2PriorKnowledge,
+            "L" | "location" | "location-trusted" => Location,
+            "max-redirs" => MaxRedirs,
+            "v" | "verbose" | "s" | "silent" | "S" | "show-error" | "i" | "include"
+            | "compressed" | "f" | "fail" | "fail-with-body" | "g" | "globoff" | "N"
+            | "no-buffer" | "#" | "progress-bar" | "http1.1" => Ignore,
+            "o" | "output" | "w" | "write-out" | "D" | "dump-header" | "retry" | "trace"
+            | "trace-ascii" => IgnoreArg,
+            _ => return None,
+        })
+    }
+
+    fn takes_arg(self) -> bool {
+        use Opt::*;
+        !matches!(
+            self,
+            Get | Head | Insecure | Http2 | Http2PriorKnowledge | Location | Ignore
+        )
+    }
+}
+
+#[derive(Default)]
+struct Command {
+    method: Option<String>,
+    url: Option<String>,
+    headers: Vec<(String, String)>,
+    data: Option<Vec<u8>>,
+    json: bool,
+    get: bool,
+    head: bool,
+    user: Option<String>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    insecure: bool,
+    resolve: Vec<String>,
+    connect_to: Vec<String>,
+    unix_socket: Option<String>,
+    http2: bool,
+    http2_prior_knowledge: bool,
+    location: bool,
+    max_redirs: Option<u8>,
+}
+
+impl Command {
+    fn apply(&mut self, opt: Opt, value: Option<Vec<u8>>) -> Result<(), Error> {
+        let value = value.unwrap_or_default();
+
+        match opt {
+            Opt::Request => self.method = Some(text(value)?),
+            Opt::Header => {
+                let header = text(value)?;
+                if let Some(name) = header.strip_suffix(';') {
+                    // "name;" is curl's way of sending an empty header.
+                    self.headers.push((name.trim().into(), String::new()));
+                } else if let Some((name, value)) = header.split_once(':') {
+                    // "name:" without a value removes one of curl's default headers.
+                    if !value.trim().is_empty() {
+                        self.headers.push((name.trim().into(), value.trim().into()));
+                    }
+                } else {
+                    return Err(Error::User(format!("Bad curl header: {}", header)));
+                }
+            }
+            Opt::Data | Opt::DataBinary | Opt::DataRaw | Opt::Json => {
+                let part = if opt != Opt::DataRaw && value.starts_with(b"@") {
+                    let mut part = read_data_file(&value[1..])?;
+                    if opt == Opt::Data {
+                        // like curl, -d @file strips newlines.
+                        part.retain(|b| *b != b'\r' && *b != b'\n');
+                    }
+                    part
+                } else {
+                    value
+                };
+                let data = self.data.get_or_insert_with(Vec::new);
+                if !data.is_empty() {
+                    data.push(b'&');
+                }
+                data.extend_from_slice(&part);
+                self.json |= opt == Opt::Json;
+            }
+            Opt::Get => self.get = true,
+            Opt::Head => self.head = true,
+            Opt::Url => {
+                if self.url.is_some() {
+                    return Err(Error::User("More than one URL in curl command".into()));
+                }
+                self.url = Some(text(value)?);
+            }
+            Opt::User => self.user = Some(text(value)?),
+            Opt::UserAgent => self.headers.push(("user-agent".into(), text(value)?)),
+            Opt::Referer => self.headers.push(("referer".into(), text(value)?)),
+            Opt::Cookie => {
+                let cookie = text(value)?;
+                if !cookie.contains('=') {
+                    return Err(Error::User(format!(
+                        "Reading cookies from a file is not supported: {}",
+                        cookie
+                    )));
+                }
+                self.headers.push(("cookie".into(), cookie));
+            }
+            Opt::MaxTime => self.timeout = Some(seconds("--max-time", value)?),
+            Opt::ConnectTimeout => {
+                self.connect_timeout = Some(seconds("--connect-timeout", value)?)
+            }
+            Opt::Insecure => self.insecure = true,
+            Opt::Resolve => self.resolve.push(text(value)?),
+            Opt::ConnectTo => self.connect_to.push(text(value)?),
+            Opt::UnixSocket => self.unix_socket = Some(text(value)?),
+            Opt::Http2 => self.http2 = true,
+            Opt::Http2PriorKnowledge => self.http2_prior_knowledge = true,
+            Opt::Location => self.location = true,
+            Opt::MaxRedirs => self.max_redirs = Some(max_redirs(value)?),
+            Opt::Ignore | Opt::IgnoreArg => {}
+        }
+
+        Ok(())
+    }
+
+    fn into_request(mut self) -> Result<Request<Body>, Error> {
+        let url = self
+            .url
+            .take()
+            .ok_or_else(|| Error::User("No URL in curl command".into()))?;
+
+        // curl defaults to http when there is no scheme.
+        let mut url = if url.contains("://") {
+            url
+        } else {
+            format!("http://{}", url)
+        };
+
+        let mut data = self.data.take();
+
+        if self.get {
+            if let Some(query) = data.take() {
+                url.push(if url.contains('?') { '&' } else { '?' });
+                url.push_str(&text(query)?);
+            }
+        }
+
+        let uri = url.parse::<Uri>().map_err(http::Error::from)?;
+        let host_port = HostPort::from_uri(&uri)?;
+
+        let method = match self.method.take() {
+            Some(method) => method,
+            None if self.head => "HEAD".into(),
+            None if data.is_some() => "POST".into(),
+            None => "GET".into(),
+        };
+
+        let mut builder = Request::builder().method(method.as_str()).uri(uri);
+
+        let has_header = |name: &str| {
+            self.headers
+                .iter()
+                .any(|(n, _)| n.eq_ignore_ascii_case(name))
+        };
+
+        if data.is_some() && !has_header("content-type") {
+            let ctype = if self.json {
+                "application/json"
+            } else {
+                "application/x-www-form-urlencoded"
+            };
+            builder = builder.header("content-type", ctype);
+        }
+
+        if self.json && !has_header("accept") {
+            builder = builder.header("accept", "application/json");
+        }
+
+        if let Some(user) = &self.user {
+            if !has_header("authorization") {
+                let auth = format!("Basic {}", base64(user.as_bytes()));
+                builder = builder.header("authorization", auth);
+            }
+        }
+
+        for (name, value) in &self.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+
+        if !self.location {
+            builder = builder.redirect_max(0);
+        } else if let Some(max) = self.max_redirs {
+            builder = builder.redirect_max(max);
+        }
+
+        #[cfg(feature = "tls")]
+        {
+            builder = builder.tls_disable_server_cert_verify(self.insecure);
+        }
+
+        if self.http2_prior_knowledge {
+            builder = builder.force_http2(true);
+        } else if self.http2 && !host_port.is_tls() {
+            builder = builder.h2c_upgrade(true);
+        }
+
+        if let Some(path) = &self.unix_socket {
+            #[cfg(unix)]
+            {
+                builder = builder.unix_socket(path);
+            }
+            #[cfg(not(unix))]
+            {
+                return Err(Error::User(format!(
+                    "Unix sockets are not supported on this platform: {}",
+                    path
+                )));
+            }
+        }
+
+        if let Some((host, port)) = find_override(&self.resolve, &self.connect_to, &host_port)? {
+            builder = builder.with_override(&host, port, host_port.is_tls());
+        }
+
+        let body = match data {
+            Some(data) => Body::from_vec(data),
+            None => Body::empty(),
+        };
+
+        Ok(builder.body(body)?)
+    }
+}
+
+/// Find the `--resolve` or `--connect-to` entry that applies to the host and port.
+fn find_override(
+    resolve: &[String],
+    connect_to: &[String],
+    host_port: &HostPort,
+) -> Result<Option<(String, u16)>, Error> {
+    let bad = |opt: &str, v: &str| Error::User(format!("Bad curl {}: {}", opt, v));
+
+    // HOST:PORT:ADDR[,ADDR]...
+    for entry in resolve {
+        let (host, rest) = split_host(entry).ok_or_else(|| bad("--resolve", entry))?;
+        let (port, addr) = rest
+            .split_once(':')
+            .ok_or_else(|| bad("--resolve", entry))?;
+        let port = port.parse::<u16>().map_err(|_| bad("--resolve", entry))?;
+        let addr = addr.split(',').next().unwrap_or("");
+        let addr = addr.trim_matches(|c| c == '[' || c == ']');
+
+        if host == host_port.host() && port == host_port.port() && !addr.is_empty() {
+            return Ok(Some((addr.to_string(), port)));
+        }
+    }
+
+    // HOST1:PORT1:HOST2:PORT2 where empty parts match/keep anything.
+    for entry in connect_to {
+        let (host1, rest) = split_host(entry).ok_or_else(|| bad("--connect-to", entry))?;
+        let (port1, rest) = rest
+            .split_once(':')
+            .ok_or_else(|| bad("--connect-to", entry))?;
+        let (host2, port2) = split_host(rest).ok_or_else(|| bad("--connect-to", entry))?;
+
+        let port_matches = port1.is_empty() || port1.parse() == Ok(host_port.port());
+        if (host1.is_empty() || host1 == host_port.host()) && port_matches {
+            let host = if host2.is_empty() {
+                host_port.host()
+            } else {
+                host2
+            };
+            let port = if port2.is_empty() {
+                host_port.port()
+            } else {
+                port2.parse().map_err(|_| bad("--connect-to", entry))?
+            };
+            return Ok(Some((host.to_string(), port)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Split "host:rest" where host might be a bracketed IPv6 address.
+fn split_host(s: &str) -> Option<(&str, &str)> {
+    if let Some(s) = s.strip_prefix('[') {
+        let end = s.find(']')?;
+        let rest = s[end + 1..].strip_prefix(':')?;
+        Some((&s[..end], rest))
+    } else {
+        s.split_once(':')
+    }
+}
+
+fn next_value(args: &mut impl Iterator<Item = Vec<u8>>, opt: &str) -> Result<Vec<u8>, Error> {
+    args.next()
+        .ok_or_else(|| Error::User(format!("Missing value for curl option: {}", opt)))
+}
+
+fn text(arg: Vec<u8>) -> Result<String, Error> {
+    String::from_utf8(arg).map_err(|e| Error::User(format!("Bad UTF-8 in curl command: {}", e)))
+}
+
+fn seconds(opt: &str, arg: Vec<u8>) -> Result<Duration, Error> {
+    let secs = text(arg)?;
+    let parsed = secs
+        .parse::<f64>()
+        .ok()
+        .filter(|s| s.is_finite() && *s >= 0.0)
+        .ok_or_else(|| Error::User(format!("Bad curl {}: {}", opt, secs)))?;
+    Ok(Duration::from_secs_f64(parsed))
+}
+
+fn max_redirs(arg: Vec<u8>) -> Result<u8, Error> {
+    let max = text(arg)?;
+    let parsed = max
+        .parse::<i64>()
+        .ok()
+        .filter(|m| *m >= -1)
+        .ok_or_else(|| Error::User(format!("Bad curl --max-redirs: {}", max)))?;
+    // -1 is unlimited in curl, the agent counts redirects in an i8.
+    let limit = i8::MAX as i64;
+    Ok(if parsed == -1 {
+        limit
+    } else {
+        parsed.min(limit)
+    } as u8)
+}
+
+fn read_data_file(path: &[u8]) -> Result<Vec<u8>, Error> {
+    if path == b"-" {
+        return Err(Error::User(
+            "Reading curl data from stdin is not supported".into(),
+        ));
+    }
+    Ok(fs::read(String::from_utf8_lossy(path).as_ref())?)
+}
+
+/// Quote an argument for a POSIX shell, leaving it bare when that's safe.
+fn quote(arg: &[u8]) -> String {
+    let is_safe = |b: &u8| b.is_ascii_alphanumeric() || b"-_./:=@,%+".contains(b);
+
+    if !arg.is_empty() && arg.iter().all(is_safe) {
+        return String::from_utf8_lossy(arg).into();
+    }
+
+    match std::str::from_utf8(arg) {
+        Ok(s) if !s.chars().any(|c| c.is_control() && c != '\n' && c != '\t') => {
+            format!("'{}'", s.replace('\'', r"'\''"))
+        }
+        // binary or control characters use $'...' with escapes.
+        _ => {
+            let mut out = String::from("$'");
+            for b in arg {
+                match b {
+                    b'\\' => out.push_str(r"\\"),
+                    b'\'' => out.push_str(r"\'"),
+                    b'\n' => out.push_str(r"\n"),
+                    b'\t' => out.push_str(r"\t"),
+                    b'\r' => out.push_str(r"\r"),
+                    0x20..=0x7e => out.push(*b as char),
+                    _ => out.push_str(&format!("\\x{:02x}", b)),
+                }
+            }
+            out.push('\'');
+            out
+        }
+    }
+}
+
+/// Split a command line into arguments the way a POSIX shell does.
+fn split_args(command: &str) -> Result<Vec<Vec<u8>>, Error> {
+    let unterminated = || Error::User("Unterminated quote in curl command".into());
+
+    let mut args = vec![];
+    let mut cur: Option<Vec<u8>> = None;
+    let mut chars = command.chars().peekable();
+
+    fn push(arg: &mut Vec<u8>, c: char) {
+        let mut buf = [0; 4];
+        arg.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(arg) = cur.take() {
+                    args.push(arg);
+                }
+            }
+            '\\' => match chars.next() {
+                // line continuation
+                Some('\n') => {}
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                }
+                Some(c) => push(cur.get_or_insert_with(Vec::new), c),
+                None => {}
+            },
+            '\'' => {
+                let arg = cur.get_or_insert_with(Vec::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '\'' => break,
+                        c => push(arg, c),
+                    }
+                }
+            }
+            '"' => {
+                let arg = cur.get_or_insert_with(Vec::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '"' => break,
+                        '\\' => match chars.next().ok_or_else(unterminated)? {
+                            '\n' => {}
+                            c @ '"' | c @ '\\' | c @ '$' | c @ '`' => push(arg, c),
+                            c => {
+                                push(arg, '\\');
+                                push(arg, c);
+                            }
+                        },
+                        c => push(arg, c),
+                    }
+                }
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                let arg = cur.get_or_insert_with(Vec::new);
+                loop {
+                    match chars.next().ok_or_else(unterminated)? {
+                        '\'' => break,
+                        '\\' => {
+                            let c = chars.next().ok_or_else(unterminated)?;
+                            match c {
+                                'n' => arg.push(b'\n'),
+                                't' => arg.push(b'\t'),
+                                'r' => arg.push(b'\r'),
+                                'a' => arg.push(0x07),
+                                'b' => arg.push(0x08),
+                                'e' | 'E' => arg.push(0x1b),
+                                'f' => arg.push(0x0c),
+                                'v' => arg.push(0x0b),
+                                'x' => arg.push(escaped_byte(&mut chars, 16, 2, 0)),
+                                '0'..='7' => {
+                                    let first = c.to_digit(8).unwrap();
+                                    arg.push(escaped_byte(&mut chars, 8, 2, first));
+                                }
+                                c => push(arg, c),
+                            }
+                        }
+                        c => push(arg, c),
+                    }
+                }
+            }
+            c => push(cur.get_or_insert_with(Vec::new), c),
+        }
+    }
+
+    if let Some(arg) = cur.take() {
+        args.push(arg);
+    }
+
+    Ok(args)
+}
+
+/// Read up to `max` more digits in `radix` into a byte value.
+fn escaped_byte(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    radix: u32,
+    max: usize,
+    mut value: u32,
+) -> u8 {
+    for _ in 0..max {
+        match chars.peek().and_then(|c| c.to_digit(radix)) {
+            Some(d) => {
+                value = value * radix + d;
+                chars.next();
+            }
+            None => break,
+        }
+    }
+    value as u8
+}
+
+fn base64(input: &[u8]) -> String {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity((input.len() + 2) / 3 * 4);
+
+    for chunk in input.chunks(3) {
+        let n = chunk.iter().fold(0_u32, |n, b| n << 8 | *b as u32) << (8 * (3 - chunk.len()));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(CHARS[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_args_quoting() {
+        let args =
+            split_args("curl 'a b' \"c \\\"d\\\"\" e\\ f $'\\x00\\n' g'h'\"i\" \\\n  -k").unwrap();
+        let expected: Vec<&[u8]> = vec![
+            b"curl", b"a b", b"c \"d\"", b"e f", b"\x00\n", b"ghi", b"-k",
+        ];
+        assert_eq!(args, expected);
+    }
+
+    #[test]
+    fn quote_round_trip() {
+        for arg in &[
+            &b"plain"[..],
+            b"it's",
+            b"",
+            b"two\nlines",
+            b"\x00\xff\x1b'\\",
+        ] {
+            let quoted = quote(arg);
+            assert_eq!(split_args(&quoted).unwrap(), vec![arg.to_vec()]);
+        }
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b"user:pass"), "dXNlcjpwYXNz");
+        assert_eq!(base64(b"a"), "YQ==");
+        assert_eq!(base64(b"ab"), "YWI=");
+    }
+}
@@ -0,0 +1,453 @@
+//! Resumable downloads to a file.
+//!
+//! The data is downloaded to a `<path>.part` file, which is renamed to `<path>` once
+//! complete. The validator (ETag or Last-Modified) of the response is kept in a
+//! `<path>.part.validator` file, to be able to resume a partial download using a
+//! `Range` request with `If-Range`.
+//!
+//! Segmented downloads fetch several byte ranges of the resource concurrently, each
+//! with an agent (and connection) of its own, writing into a preallocated `.part` file.
+
+use super::Agent;
+use super::RequestBuilderExt;
+use crate::head_ext::HeaderMapExt;
+use crate::AsyncRuntime;
+use crate::Error;
+use futures_util::future::try_join_all;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const BUF_SIZE: usize = 16_384;
+
+/// Smallest range worth its own connection in a segmented download.
+const MIN_SEGMENT_SIZE: u64 = 64 * 1024;
+
+pub(crate) async fn download(agent: &mut Agent, uri: http::Uri, path: &Path) -> Result<u64, Error> {
+    let part = with_suffix(path, ".part");
+    let validator_path = with_suffix(path, ".part.validator");
+
+    // a partial download can only be resumed if we know which version of the resource it is.
+    let validator = {
+        let validator_path = validator_path.clone();
+        blocking(move || fs::read_to_string(validator_path))
+            .await
+            .ok()
+    };
+
+    let mut offset = match (&validator, file_len(&part).await) {
+        (Some(_), Ok(len)) => len,
+        _ => 0,
+    };
+
+    loop {
+        let mut req = http::Request::get(uri.clone())
+            // ranges are for the bytes as sent, we can't decode them.
+            .content_decode(false)
+            .charset_decode(false);
+
+        if offset > 0 {
+            debug!("Resume download at {}: {}", offset, uri);
+            req = req
+                .header("range", format!("bytes={}-", offset))
+                .header("if-range", validator.as_deref().unwrap());
+        }
+
+        let mut res = agent.send(req.with_body(())?).await?;
+
+        let status = res.status();
+
+        let (file, total) = match status.as_u16() {
+            // server agreed to continue where we left off.
+            206 => {
+                let range = res
+                    .headers()
+                    .get_str("content-range")
+                    .and_then(content_range);
+
+                match range {
+                    Some((Some(start), total)) if start == offset => {
+                        let part = part.clone();
+                        let file = blocking(move || fs::OpenOptions::new().append(true).open(part))
+                            .await?;
+                        (file, total)
+                    }
+                    _ => {
+                        debug!("Unexpected content-range, restart download: {}", uri);
+                        offset = 0;
+                        continue;
+                    }
+                }
+            }
+
+            // range not satisfiable, which is expected if we got the entire file already.
+            416 if offset > 0 => {
+                let range = res
+                    .headers()
+                    .get_str("content-range")
+                    .and_then(content_range);
+
+                if let Some((None, Some(total))) = range {
+                    if total == offset {
+                        break;
+                    }
+                }
+
+                debug!("Range not satisfiable, restart download: {}", uri);
+                offset = 0;
+                continue;
+            }
+
+            // the server ignored the range, or the resource changed.
+            200 => {
+                if offset > 0 {
+                    debug!("Range ignored, restart download: {}", uri);
+                }
+
+                // only strong etags are allowed in if-range.
+                let etag = res
+                    .headers()
+                    .get_str("etag")
+                    .filter(|v| !v.starts_with("W/"));
+                let last_modified = res.headers().get_str("last-modified");
+
+                let validator = etag.or(last_modified).map(|v| v.to_string());
+                let validator_path = validator_path.clone();
+                let part = part.clone();
+
+                let file = blocking(move || {
+                    match validator {
+                        Some(v) => fs::write(&validator_path, v)?,
+                        None => remove_if_exists(&validator_path)?,
+                    }
+                    fs::File::create(part)
+                })
+                .await?;
+
+                let total = res.headers().get_as::<u64>("content-length");
+
+                (file, total)
+            }
+
+            _ => {
+                return Err(Error::Proto(format!(
+                    "Download failed with status {}: {}",
+                    status, uri
+                )));
+            }
+        };
+
+        write_body(res.body_mut(), file).await?;
+
+        let len = file_len(&part).await?;
+
+        if let Some(total) = total {
+            if len != total {
+                return Err(Error::Proto(format!(
+                    "Download incomplete {} of {} bytes: {}",
+                    len, total, uri
+                )));
+            }
+        }
+
+        break;
+    }
+
+    let len = file_len(&part).await?;
+
+    let path = path.to_owned();
+    blocking(move || {
+        fs::rename(&part, path)?;
+        remove_if_exists(&validator_path)
+    })
+    .await?;
+
+    Ok(len)
+}
+
+pub(crate) async fn download_segmented(
+    agent: &mut Agent,
+    uri: http::Uri,
+    path: &Path,
+    segments: usize,
+    retries: usize,
+) -> Result<u64, Error> {
+    let part = with_suffix(path, ".part");
+    let validator_path = with_suffix(path, ".part.validator");
+
+    // probe for range support and the total length with a single byte range.
+    let req = http::Request::get(uri.clone())
+        .content_decode(false)
+        .charset_decode(false)
+        .header("range", "bytes=0-0")
+        .with_body(())?;
+
+    let mut res = agent.send(req).await?;
+
+    let range = res
+        .headers()
+        .get_str("content-range")
+        .and_then(content_range);
+
+    let length = match range {
+        Some((Some(0), Some(length))) if res.status() == http::StatusCode::PARTIAL_CONTENT => {
+            length
+        }
+        _ => {
+            debug!("No range support, download in one go: {}", uri);
+            // the body might be the entire resource, which we don't want to read twice.
+            drop(res);
+            return download(agent, uri, path).await;
+        }
+    };
+
+    // the single byte, to finish the request.
+    res.body_mut().read_and_discard().await?;
+
+    let segments = (segments as u64).min(length / MIN_SEGMENT_SIZE);
+
+    if segments <= 1 {
+        return download(agent, uri, path).await;
+    }
+
+    // ensures all segments are from the same version of the resource.
+    let etag = res
+        .headers()
+        .get_str("etag")
+        .filter(|v| !v.starts_with("W/"));
+    let validator = etag
+        .or_else(|| res.headers().get_str("last-modified"))
+        .map(|v| v.to_string());
+
+    {
+        let part = part.clone();
+        blocking(move || {
+            // a segmented download can't be resumed by a plain download.
+            remove_if_exists(&validator_path)?;
+            fs::File::create(part)?.set_len(length)
+        })
+        .await?;
+    }
+
+    let size = length / segments;
+
+    let fetches = (0..segments).map(|i| {
+        let start = i * size;
+        let end = if i == segments - 1 {
+            length
+        } else {
+            start + size
+        };
+
+        fetch_segment(
+            agent.clone_settings(),
+            &uri,
+            &part,
+            validator.as_deref(),
+            start,
+            end,
+            retries,
+        )
+    });
+
+    debug!("Download {} in {} segments: {}", length, segments, uri);
+
+    // the first failing segment fails the download, and drops the others.
+    let segment_agents = try_join_all(fetches).await?;
+
+    // cookies set on any segment are kept, as if sent through the agent itself.
+    for segment_agent in segment_agents {
+        agent.merge_cookies(segment_agent);
+    }
+
+    let path = path.to_owned();
+    blocking(move || fs::rename(part, path)).await?;
+
+    Ok(length)
+}
+
+/// Fetch the range `start..end` into the same range of the file, retrying on failure.
+///
+/// Returns the agent, with the cookies it collected.
+async fn fetch_segment(
+    mut agent: Agent,
+    uri: &http::Uri,
+    part: &Path,
+    validator: Option<&str>,
+    start: u64,
+    end: u64,
+    retries: usize,
+) -> Result<Agent, Error> {
+    // position advances with each write, a retry continues from there.
+    let mut pos = start;
+    let mut retry = 0;
+
+    loop {
+        match fetch_range(&mut agent, uri, part, validator, &mut pos, end).await {
+            Ok(()) => return Ok(agent),
+            Err(e) => {
+                if retry >= retries {
+                    return Err(e);
+                }
+                retry += 1;
+                debug!(
+                    "Retry segment {}-{} at {} ({}): {}",
+                    start, end, pos, retry, e
+                );
+            }
+        }
+    }
+}
+
+async fn fetch_range(
+    agent: &mut Agent,
+    uri: &http::Uri,
+    part: &Path,
+    validator: Option<&str>,
+    pos: &mut u64,
+    end: u64,
+) -> Result<(), Error> {
+    let mut req = http::Request::get(uri.clone())
+        .content_decode(false)
+        .charset_decode(false)
+        // range is end inclusive.
+        .header("range", format!("bytes={}-{}", *pos, end - 1));
+
+    if let Some(validator) = validator {
+        req = req.header("if-range", validator);
+    }
+
+    let mut res = agent.send(req.with_body(())?).await?;
+
+    let range = res
+        .headers()
+        .get_str("content-range")
+        .and_then(content_range);
+
+    if res.status() != http::StatusCode::PARTIAL_CONTENT || range.map(|r| r.0) != Some(Some(*pos)) {
+        return Err(Error::Proto(format!(
+            "Expected partial content at {}, got {}: {}",
+            *pos,
+            res.status(),
+            uri
+        )));
+    }
+
+    let mut file = {
+        let part = part.to_owned();
+        let pos = *pos;
+        blocking(move || {
+            let mut file = fs::OpenOptions::new().write(true).open(part)?;
+            file.seek(SeekFrom::Start(pos))?;
+            Ok(file)
+        })
+        .await?
+    };
+
+    let body = res.body_mut();
+    let mut buf = vec![0; BUF_SIZE];
+
+    while *pos < end {
+        let max = (BUF_SIZE as u64).min(end - *pos) as usize;
+        let amount = body.read(&mut buf[..max]).await?;
+
+        if amount == 0 {
+            break;
+        }
+
+        let (f, b) = write_chunk(file, buf, amount).await?;
+        file = f;
+        buf = b;
+
+        *pos += amount as u64;
+    }
+
+    if *pos < end {
+        return Err(Error::Proto(format!(
+            "Segment ended at {} before {}: {}",
+            *pos, end, uri
+        )));
+    }
+
+    blocking(move || file.sync_all()).await?;
+
+    Ok(())
+}
+
+async fn write_body(body: &mut crate::Body, mut file: fs::File) -> Result<(), Error> {
+    let mut buf = vec![0; BUF_SIZE];
+
+    loop {
+        let amount = body.read(&mut buf).await?;
+
+        if amount == 0 {
+            break;
+        }
+
+        // everything written so far is kept if the download is interrupted.
+        let (f, b) = write_chunk(file, buf, amount).await?;
+        file = f;
+        buf = b;
+    }
+
+    blocking(move || file.sync_all()).await?;
+
+    Ok(())
+}
+
+/// Write `buf[..amount]`, handing back the file and buffer for the next write.
+async fn write_chunk(
+    mut file: fs::File,
+    buf: Vec<u8>,
+    amount: usize,
+) -> io::Result<(fs::File, Vec<u8>)> {
+    blocking(move || {
+        file.write_all(&buf[..amount])?;
+        Ok((file, buf))
+    })
+    .await
+}
+
+/// Run file system operations without blocking the async runtime.
+async fn blocking<F, T>(f: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    AsyncRuntime::spawn_blocking(f).await
+}
+
+async fn file_len(path: &Path) -> io::Result<u64> {
+    let path = path.to_owned();
+    blocking(move || fs::metadata(path).map(|m| m.len())).await
+}
+
+/// Parse `bytes 0-499/1234` or `bytes */1234` into (start, total).
+fn content_range(v: &str) -> Option<(Option<u64>, Option<u64>)> {
+    let v = v.strip_prefix("bytes ")?;
+    let (range, total) = v.split_at(v.find('/')?);
+
+    let total = total[1..].parse::<u64>().ok();
+
+    let start = if range == "*" {
+        None
+    } else {
+        Some(range[..range.find('-')?].parse::<u64>().ok()?)
+    };
+
+    Some((start, total))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut s: OsString = path.as_os_str().to_owned();
+    s.push(suffix);
+    s.into()
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        r => r,
+    }
+}
@@ -0,0 +1,554 @@
+//! Recording and replaying requests to fixture files.
+//!
+//! Every request sent over the network, including each redirect, is one exchange in the
+//! fixture file. Request bodies are recorded as given by the user, and response bodies
+//! after content and charset decoding. Replayed bodies are served without decoding them
+//! again.
+
+use super::cache::{decoded_headers, decoded_params};
+use crate::params::HReqParams;
+use crate::Body;
+use crate::Error;
+use futures_util::io::Cursor;
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use http::{Method, StatusCode};
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const BUF_SIZE: usize = 16_384;
+
+/// Records requests and responses of an [`Agent`] to a fixture file, or replays them.
+///
+/// In record mode, requests go to the server as usual, and every request/response pair
+/// is written to the file. In replay mode, responses are served from the file without
+/// any network access. A request that doesn't match any recorded one fails with an error.
+///
+/// Redirects, cookies and retries are handled by the agent in both modes, which means
+/// each redirect is a separate exchange in the file.
+///
+/// Requests match recorded ones on method and uri. Use [`match_headers`] and
+/// [`match_body`] to also compare headers or bodies. When the same request was recorded
+/// several times, the recorded responses are replayed in order, repeating the last.
+///
+/// The file is JSON, which is easy to inspect and edit. Be aware that all request headers
+/// are recorded, including secrets such as `authorization`.
+///
+/// Clones share the recorded exchanges. When recording, the file is written by [`save`],
+/// or when the last clone is dropped.
+///
+/// ```no_run
+/// use hreq::prelude::*;
+/// use hreq::{Agent, Fixtures};
+///
+/// // record once with network access.
+/// let mut agent = Agent::new();
+/// let fixtures = Fixtures::record("tests/fixtures/example.json");
+/// agent.fixtures(fixtures.clone());
+///
+/// let req = Request::get("https://example.com/").with_body(()).unwrap();
+/// agent.send(req).block().unwrap();
+///
+/// fixtures.save().unwrap();
+///
+/// // then replay without.
+/// let mut agent = Agent::new();
+/// agent.fixtures(Fixtures::replay("tests/fixtures/example.json").unwrap());
+///
+/// let req = Request::get("https://example.com/").with_body(()).unwrap();
+/// agent.send(req).block().unwrap();
+/// ```
+///
+/// [`Agent`]: struct.Agent.html
+/// [`match_headers`]: struct.Fixtures.html#method.match_headers
+/// [`match_body`]: struct.Fixtures.html#method.match_body
+/// [`save`]: struct.Fixtures.html#method.save
+#[derive(Clone)]
+pub struct Fixtures {
+    path: PathBuf,
+    mode: Mode,
+    matching: Matching,
+    store: Arc<Mutex<Store>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Record,
+    Replay,
+}
+
+#[derive(Clone)]
+struct Matching {
+    method: bool,
+    uri: bool,
+    headers: Vec<HeaderName>,
+    body: bool,
+}
+
+struct Store {
+    path: PathBuf,
+    exchanges: Vec<Exchange>,
+    /// Whether each exchange has been replayed.
+    replayed: Vec<bool>,
+    /// Whether there are recorded exchanges not written to the file.
+    unsaved: bool,
+}
+
+#[derive(Clone)]
+pub(crate) struct Exchange {
+    method: Method,
+    uri: String,
+    req_headers: HeaderMap,
+    req_body: Vec<u8>,
+    status: StatusCode,
+    res_headers: HeaderMap,
+    res_body: Arc<[u8]>,
+}
+
+impl Fixtures {
+    /// Records to the file, replacing it when the exchanges are saved.
+    ///
+    /// The directory of the file is created if needed.
+    ///
+    /// ```
+    /// use hreq::Fixtures;
+    ///
+    /// let fixtures = Fixtures::record("tests/fixtures/example.json");
+    /// ```
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let store = Store::new(path.clone(), vec![]);
+        Fixtures::with_mode(path, Mode::Record, store)
+    }
+
+    /// Replays from a previously recorded file.
+    ///
+    /// Fails if the file can't be read or parsed.
+    ///
+    /// ```no_run
+    /// use hreq::Fixtures;
+    ///
+    /// let fixtures = Fixtures::replay("tests/fixtures/example.json").unwrap();
+    /// ```
+    pub fn replay(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+
+        let data = fs::read(&path)?;
+        let exchanges = decode_exchanges(&data).ok_or_else(|| {
+            Error::User(format!("Failed to parse fixture file: {}", path.display()))
+        })?;
+
+        let store = Store::new(path.clone(), exchanges);
+
+        Ok(Fixtures::with_mode(path, Mode::Replay, store))
+    }
+
+    fn with_mode(path: PathBuf, mode: Mode, store: Store) -> Self {
+        Fixtures {
+            path,
+            mode,
+            matching: Matching {
+                method: true,
+                uri: true,
+                headers: vec![],
+                body: false,
+            },
+            store: Arc::new(Mutex::new(store)),
+        }
+    }
+
+    /// Whether requests must have the recorded method to match.
+    ///
+    /// Defaults to `true`.
+    pub fn match_method(mut self, enabled: bool) -> Self {
+        self.matching.method = enabled;
+        self
+    }
+
+    /// Whether requests must have the recorded uri, including the query, to match.
+    ///
+    /// Defaults to `true`.
+    pub fn match_uri(mut self, enabled: bool) -> Self {
+        self.matching.uri = enabled;
+        self
+    }
+
+    /// Request headers that must have the recorded values to match.
+    ///
+    /// Defaults to none. A header missing in both the request and the recording matches.
+    /// Fails if a name isn't a valid header name.
+    ///
+    /// ```
+    /// use hreq::Fixtures;
+    ///
+    /// let fixtures = Fixtures::record("tests/fixtures/example.json")
+    ///     .match_headers(&["accept", "x-api-version"])
+    ///     .unwrap();
+    /// ```
+    pub fn match_headers(mut self, names: &[&str]) -> Result<Self, Error> {
+        self.matching.headers = names
+            .iter()
+            .map(|n| HeaderName::from_bytes(n.as_bytes()).map_err(http::Error::from))
+            .collect::<Result<_, _>>()?;
+        Ok(self)
+    }
+
+    /// Whether requests must have the recorded body to match.
+    ///
+    /// Defaults to `false`.
+    pub fn match_body(mut self, enabled: bool) -> Self {
+        self.matching.body = enabled;
+        self
+    }
+
+    /// Writes the recorded exchanges to the file.
+    ///
+    /// Does nothing when replaying, or when nothing was recorded since the last save.
+    /// This is otherwise done when the last clone is dropped, where errors can only
+    /// be logged.
+    ///
+    /// ```no_run
+    /// use hreq::Fixtures;
+    ///
+    /// let fixtures = Fixtures::record("tests/fixtures/example.json");
+    /// // ... send requests with an agent using a clone of the fixtures.
+    /// fixtures.save().unwrap();
+    /// ```
+    pub fn save(&self) -> Result<(), Error> {
+        let mut store = self.store.lock().unwrap();
+        store.save()?;
+        Ok(())
+    }
+
+    /// Take the request body to record or match it. When recording, the request
+    /// gets a body with the same data to be sent.
+    pub(crate) async fn lookup(
+        &self,
+        req: &mut http::Request<Body>,
+        params: &HReqParams,
+    ) -> Result<Lookup, Error> {
+        let mut body = std::mem::replace(req.body_mut(), Body::empty());
+
+        // the header would otherwise be set from the body we are about to replace.
+        if let Some(ctype) = body.content_type() {
+            if !req.headers().contains_key("content-type") {
+                let value = HeaderValue::from_str(ctype).expect("Valid content-type");
+                req.headers_mut().insert("content-type", value);
+            }
+        }
+
+        if body.is_configurable() {
+            body.configure(&plain_params(params), req.headers(), false);
+        }
+
+        let req_body = read_all(&mut body).await?;
+
+        let pending = Pending {
+            method: req.method().clone(),
+            uri: req.uri().to_string(),
+            req_headers: req.headers().clone(),
+            req_body,
+        };
+
+        if self.mode == Mode::Record {
+            *req.body_mut() = Body::from_vec(pending.req_body.clone());
+            return Ok(Lookup::Record(pending));
+        }
+
+        let exchange = self.find(&pending).ok_or_else(|| {
+            Error::User(format!(
+                "No recorded response in {} for: {} {}",
+                self.path.display(),
+                pending.method,
+                pending.uri
+            ))
+        })?;
+
+        debug!("Replay fixture: {} {}", pending.method, pending.uri);
+
+        Ok(Lookup::Replay(exchange))
+    }
+
+    /// Record the response to a sent request.
+    pub(crate) async fn store(
+        &self,
+        pending: Pending,
+        res: http::Response<Body>,
+        params: &HReqParams,
+    ) -> Result<http::Response<Body>, Error> {
+        let (mut parts, mut body) = res.into_parts();
+
+        let res_body: Arc<[u8]> = read_all(&mut body).await?.into();
+
+        decoded_headers(&mut parts.headers, Some(res_body.len()));
+
+        let exchange = Exchange {
+            method: pending.method,
+            uri: pending.uri,
+            req_headers: pending.req_headers,
+            req_body: pending.req_body,
+            status: parts.status,
+            res_headers: parts.headers.clone(),
+            res_body,
+        };
+
+        debug!("Record fixture: {} {}", exchange.method, exchange.uri);
+
+        let body = exchange.body(params);
+
+        {
+            let mut store = self.store.lock().unwrap();
+            store.exchanges.push(exchange);
+            store.replayed.push(false);
+            store.unsaved = true;
+        }
+
+        Ok(http::Response::from_parts(parts, body))
+    }
+
+    pub(crate) fn is_replay(&self) -> bool {
+        self.mode == Mode::Replay
+    }
+
+    /// First matching exchange not replayed yet, or the last matching one.
+    fn find(&self, pending: &Pending) -> Option<Exchange> {
+        let mut store = self.store.lock().unwrap();
+
+        let matching: Vec<usize> = (0..store.exchanges.len())
+            .filter(|idx| self.matches(&store.exchanges[*idx], pending))
+            .collect();
+
+        let idx = matching
+            .iter()
+            .find(|idx| !store.replayed[**idx])
+            .or_else(|| matching.last())
+            .copied()?;
+
+        store.replayed[idx] = true;
+
+        Some(store.exchanges[idx].clone())
+    }
+
+    fn matches(&self, exchange: &Exchange, pending: &Pending) -> bool {
+        let m = &self.matching;
+
+        (!m.method || exchange.method == pending.method)
+            && (!m.uri || exchange.uri == pending.uri)
+            && (!m.body || exchange.req_body == pending.req_body)
+            && m.headers.iter().all(|name| {
+                let a: Vec<_> = exchange.req_headers.get_all(name).iter().collect();
+                let b: Vec<_> = pending.req_headers.get_all(name).iter().collect();
+                a == b
+            })
+    }
+}
+
+impl fmt::Debug for Fixtures {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Fixtures")
+    }
+}
+
+impl Store {
+    fn new(path: PathBuf, exchanges: Vec<Exchange>) -> Self {
+        Store {
+            path,
+            replayed: vec![false; exchanges.len()],
+            exchanges,
+            unsaved: false,
+        }
+    }
+
+    fn save(&mut self) -> io::Result<()> {
+        if self.unsaved {
+            write_exchanges(&self.path, &self.exchanges)?;
+            self.unsaved = false;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Store {
+    fn drop(&mut self) {
+        if let Err(e) = self.save() {
+            warn!(
+                "Failed to write fixture file {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+pub(crate) enum Lookup {
+    /// The recorded exchange to serve the response of.
+    Replay(Exchange),
+    /// The request is to be sent, and the response recorded.
+    Record(Pending),
+}
+
+/// A request being recorded.
+pub(crate) struct Pending {
+    method: Method,
+    uri: String,
+    req_headers: HeaderMap,
+    req_body: Vec<u8>,
+}
+
+impl Exchange {
+    /// Response to serve from this exchange.
+    pub(crate) fn response(&self, params: &HReqParams) -> http::Response<Body> {
+        let mut res = http::Response::new(self.body(params));
+        *res.status_mut() = self.status;
+        *res.headers_mut() = self.res_headers.clone();
+        res.extensions_mut().insert(params.clone());
+        res
+    }
+
+    fn body(&self, params: &HReqParams) -> Body {
+        let len = self.res_body.len() as u64;
+        let mut body = Body::from_async_read(Cursor::new(self.res_body.clone()), Some(len));
+        body.configure(&decoded_params(params), &self.res_headers, true);
+        body
+    }
+}
+
+/// Params to read an outgoing body as given by the user.
+fn plain_params(params: &HReqParams) -> HReqParams {
+    let mut params = params.clone();
+    params.content_encode = false;
+    params.charset_tx.toggle_target(false);
+    params.progress = None;
+    params.throttle = None;
+    params
+}
+
+async fn read_all(body: &mut Body) -> Result<Vec<u8>, Error> {
+    let mut data = vec![];
+    let mut buf = vec![0; BUF_SIZE];
+
+    loop {
+        let amount = body.read(&mut buf).await?;
+        if amount == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..amount]);
+    }
+
+    Ok(data)
+}
+
+// The file format is JSON:
+//
+//   {
+//     "exchanges": [
+//       {
+//         "request": { "method": "GET", "uri": "...", "headers": [["name", "value"]], "body": "" },
+//         "response": { "status": 200, "headers": [["name", "value"]], "body": "..." }
+//       }
+//     ]
+//   }
+//
+// Bodies that aren't UTF-8 are written as hex in "body_hex" instead of "body".
+fn write_exchanges(path: &Path, exchanges: &[Exchange]) -> io::Result<()> {
+    fn headers(headers: &HeaderMap) -> Value {
+        headers
+            .iter()
+            .map(|(name, value)| json!([name.as_str(), String::from_utf8_lossy(value.as_bytes())]))
+            .collect()
+    }
+
+    fn with_body(mut obj: Map<String, Value>, body: &[u8]) -> Value {
+        match std::str::from_utf8(body) {
+            Ok(s) => obj.insert("body".into(), s.into()),
+            Err(_) => obj.insert("body_hex".into(), to_hex(body).into()),
+        };
+        obj.into()
+    }
+
+    let exchanges: Vec<Value> = exchanges
+        .iter()
+        .map(|e| {
+            let mut request = Map::new();
+            request.insert("method".into(), e.method.as_str().into());
+            request.insert("uri".into(), e.uri.as_str().into());
+            request.insert("headers".into(), headers(&e.req_headers));
+
+            let mut response = Map::new();
+            response.insert("status".into(), e.status.as_u16().into());
+            response.insert("headers".into(), headers(&e.res_headers));
+
+            json!({
+                "request": with_body(request, &e.req_body),
+                "response": with_body(response, &e.res_body),
+            })
+        })
+        .collect();
+
+    let json = serde_json::to_vec_pretty(&json!({ "exchanges": exchanges }))?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    fs::write(path, json)
+}
+
+fn decode_exchanges(data: &[u8]) -> Option<Vec<Exchange>> {
+    fn headers(value: &Value) -> Option<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        for pair in value.as_array()? {
+            let name = HeaderName::from_bytes(pair.get(0)?.as_str()?.as_bytes()).ok()?;
+            let value = HeaderValue::from_str(pair.get(1)?.as_str()?).ok()?;
+            headers.append(name, value);
+        }
+        Some(headers)
+    }
+
+    fn body(value: &Value) -> Option<Vec<u8>> {
+        if let Some(hex) = value.get("body_hex") {
+            return from_hex(hex.as_str()?);
+        }
+        Some(value.get("body")?.as_str()?.as_bytes().to_vec())
+    }
+
+    let json: Value = serde_json::from_slice(data).ok()?;
+
+    let mut exchanges = vec![];
+
+    for e in json.get("exchanges")?.as_array()? {
+        let req = e.get("request")?;
+        let res = e.get("response")?;
+
+        let status = res.get("status")?.as_u64()?;
+
+        exchanges.push(Exchange {
+            method: Method::from_bytes(req.get("method")?.as_str()?.as_bytes()).ok()?,
+            uri: req.get("uri")?.as_str()?.to_string(),
+            req_headers: headers(req.get("headers")?)?,
+            req_body: body(req)?,
+            status: StatusCode::from_u16(status as u16).ok()?,
+            res_headers: headers(res.get("headers")?)?,
+            res_body: body(res)?.into(),
+        });
+    }
+
+    Some(exchanges)
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,286 @@
+//! Per host rate limits and concurrency caps.
+
+use crate::deadline;
+use crate::observe::BodyObserver;
+use crate::uri_ext::UriExt;
+use crate::AsyncRuntime;
+use crate::Error;
+use crate::TimeoutKind;
+use futures_util::future::poll_fn;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant};
+
+type Timer = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+type Hosts = Arc<Mutex<HashMap<String, HostState>>>;
+
+/// Id of each request waiting for a free slot.
+static NEXT_WAITER: AtomicUsize = AtomicUsize::new(0);
+
+/// Limits on the requests an [`Agent`] sends to each host.
+///
+/// Two kinds of limits can be combined:
+///
+///   * A rate, as a token bucket of `n` requests per interval. Up to `n` requests can
+///     be sent in a burst, after which requests are spread out over the interval.
+///   * A maximum number of requests in flight. A request is in flight from when it is
+///     sent until its response body is read to the end, or dropped.
+///
+/// Requests over the limits are queued until they can go. The time waiting counts
+/// towards the [`timeout`] of the request. A [`queue_timeout`] additionally limits
+/// how long a request waits, failing it with [`TimeoutKind::Queue`].
+///
+/// The limits are per host and port. Clones of a `RateLimit` share the same limits,
+/// which means a `RateLimit` given to several agents limits their requests together.
+///
+/// ```
+/// use hreq::{Agent, RateLimit};
+/// use std::time::Duration;
+///
+/// let mut agent = Agent::new();
+///
+/// agent.rate_limit(
+///     RateLimit::new()
+///         .requests_per(10, Duration::from_secs(1))
+///         .max_in_flight(2)
+///         .queue_timeout(Duration::from_secs(30)),
+/// );
+/// ```
+///
+/// [`Agent`]: struct.Agent.html
+/// [`timeout`]: trait.RequestBuilderExt.html#tymethod.timeout
+/// [`queue_timeout`]: struct.RateLimit.html#method.queue_timeout
+/// [`TimeoutKind::Queue`]: enum.TimeoutKind.html#variant.Queue
+#[derive(Clone)]
+pub struct RateLimit {
+    requests: Option<(u32, Duration)>,
+    max_in_flight: Option<usize>,
+    queue_timeout: Option<Duration>,
+    hosts: Hosts,
+}
+
+struct HostState {
+    tokens: f64,
+    refilled: Instant,
+    in_flight: usize,
+    /// Requests waiting for another to finish, by id.
+    waiters: Vec<(usize, Waker)>,
+}
+
+impl RateLimit {
+    /// Creates a new rate limit, without any limits set.
+    pub fn new() -> Self {
+        RateLimit {
+            requests: None,
+            max_in_flight: None,
+            queue_timeout: None,
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Allow `n` requests per `interval` to each host.
+    pub fn requests_per(mut self, n: u32, interval: Duration) -> Self {
+        assert!(n > 0, "requests_per with 0 requests");
+        self.requests = Some((n, interval));
+        self
+    }
+
+    /// Allow at most `n` requests in flight to each host.
+    pub fn max_in_flight(mut self, n: usize) -> Self {
+        assert!(n > 0, "max_in_flight of 0");
+        self.max_in_flight = Some(n);
+        self
+    }
+
+    /// Longest time a request waits in the queue before failing with a
+    /// [`TimeoutKind::Queue`] timeout.
+    ///
+    /// Defaults to no limit apart from the timeout of the request itself.
+    ///
+    /// [`TimeoutKind::Queue`]: enum.TimeoutKind.html#variant.Queue
+    pub fn queue_timeout(mut self, timeout: Duration) -> Self {
+        self.queue_timeout = Some(timeout);
+        self
+    }
+
+    /// Wait for a request to the uri to be allowed.
+    pub(crate) async fn acquire(&self, uri: &http::Uri) -> Result<Permit, Error> {
+        let host = uri.host_port()?.to_string();
+
+        let wait = self.wait(host.clone());
+
+        let permit = deadline::limit(self.queue_timeout, TimeoutKind::Queue, async {
+            Ok::<_, Error>(wait.await)
+        })
+        .await?;
+
+        trace!("Rate limit permit: {}", host);
+
+        Ok(permit)
+    }
+
+    async fn wait(&self, host: String) -> Permit {
+        let mut timer: Option<Timer> = None;
+
+        // removes our waker from the queue if this future is dropped.
+        let waiting = Waiting {
+            hosts: &self.hosts,
+            host: &host,
+            id: NEXT_WAITER.fetch_add(1, Ordering::Relaxed),
+        };
+
+        poll_fn(|cx| loop {
+            if let Some(t) = &mut timer {
+                if t.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                timer = None;
+            }
+
+            let mut hosts = self.hosts.lock().unwrap();
+
+            let state = hosts.entry(host.clone()).or_insert_with(|| HostState {
+                tokens: self.requests.map(|(n, _)| n as f64).unwrap_or(0.0),
+                refilled: Instant::now(),
+                in_flight: 0,
+                waiters: vec![],
+            });
+
+            if let Some(max) = self.max_in_flight {
+                if state.in_flight >= max {
+                    state.waiters.retain(|(id, _)| *id != waiting.id);
+                    state.waiters.push((waiting.id, cx.waker().clone()));
+                    return Poll::Pending;
+                }
+            }
+
+            if let Some((n, interval)) = self.requests {
+                let now = Instant::now();
+                let per_token = interval / n;
+
+                // refill with the tokens earned since last time, up to a full bucket.
+                let earned = (now - state.refilled).as_secs_f64() / per_token.as_secs_f64();
+                state.tokens = (state.tokens + earned).min(n as f64);
+                state.refilled = now;
+
+                if state.tokens < 1.0 {
+                    let wait = per_token.mul_f64(1.0 - state.tokens);
+                    trace!("Rate limit wait {:?}: {}", wait, host);
+                    timer = Some(Box::pin(AsyncRuntime::timeout(wait)));
+                    continue;
+                }
+
+                state.tokens -= 1.0;
+            }
+
+            state.in_flight += 1;
+
+            return Poll::Ready(Permit {
+                hosts: self.hosts.clone(),
+                host: host.clone(),
+            });
+        })
+        .await
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit::new()
+    }
+}
+
+impl fmt::Debug for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RateLimit")
+            .field("requests", &self.requests)
+            .field("max_in_flight", &self.max_in_flight)
+            .field("queue_timeout", &self.queue_timeout)
+            .finish()
+    }
+}
+
+/// A request in flight, which ends when this is dropped.
+pub(crate) struct Permit {
+    hosts: Hosts,
+    host: String,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut hosts = self.hosts.lock().unwrap();
+
+        if let Some(state) = hosts.get_mut(&self.host) {
+            state.in_flight -= 1;
+
+            // the waiters race for the free slot, the losers queue up again.
+            for (_, waker) in state.waiters.drain(..) {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl BodyObserver for Permit {}
+
+/// A request waiting in the queue of a host.
+struct Waiting<'a> {
+    hosts: &'a Hosts,
+    host: &'a str,
+    id: usize,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let mut hosts = self.hosts.lock().unwrap();
+
+        if let Some(state) = hosts.get_mut(self.host) {
+            state.waiters.retain(|(id, _)| *id != self.id);
+        }
+    }
+}
+
+impl fmt::Debug for Permit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Permit {}", self.host)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_util::task::noop_waker;
+    use std::task::Context;
+
+    #[test]
+    fn dropped_waiter_leaves_queue() {
+        let limit = RateLimit::new().max_in_flight(1);
+        let uri: http::Uri = "http://example.com/".parse().unwrap();
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut acquire = Box::pin(limit.acquire(&uri));
+        let permit = match acquire.as_mut().poll(&mut cx) {
+            Poll::Ready(permit) => permit.unwrap(),
+            Poll::Pending => panic!("first request must not wait"),
+        };
+
+        let mut queued = Box::pin(limit.acquire(&uri));
+        assert!(queued.as_mut().poll(&mut cx).is_pending());
+
+        let waiters =
+            |limit: &RateLimit| limit.hosts.lock().unwrap()["example.com:80"].waiters.len();
+        assert_eq!(waiters(&limit), 1);
+
+        drop(queued);
+        assert_eq!(waiters(&limit), 0);
+
+        drop(permit);
+    }
+}
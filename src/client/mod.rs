@@ -5,38 +5,134 @@ use hreq_h1 as h1;
 use tokio_util::compat::FuturesAsyncReadCompatExt;
 
 mod agent;
+mod cache;
 mod conn;
+mod connector;
 mod cookies;
+mod curl;
+mod download;
+mod fixtures;
+mod limit;
 mod req_ext;
 mod reqb_ext;
 
 pub use agent::{Agent, ResponseFuture};
+pub use cache::Cache;
+pub use connector::{ConnectFuture, Connector};
+pub use curl::CurlExt;
+pub use fixtures::Fixtures;
+pub use limit::RateLimit;
 pub use req_ext::RequestExt;
 pub use reqb_ext::RequestBuilderExt;
 
 #[cfg(feature = "server")]
 pub(crate) use conn::configure_request;
 
-use crate::bw::BandwidthMonitor;
+use crate::bw::{BandwidthMonitor, PingEvent};
+use crate::deadline::limit;
+use crate::h2_config::Http2Config;
+use crate::h2c::H2cClientStream;
+use crate::params::HReqParams;
 use crate::proto::Protocol;
+use crate::upgrade::{Takeover, Upgraded};
 use crate::uri_ext::HostPort;
-use conn::Connection;
+use crate::TimeoutKind;
+use conn::{Connection, H2cUpgrade};
 use futures_util::future::poll_fn;
 use std::future::Future;
+#[cfg(unix)]
+use std::path::Path;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::task::Poll;
 
 pub(crate) async fn connect(
     host_port: &HostPort,
-    force_http2: bool,
-    #[allow(unused_variables)] tls_disable_verify: bool,
+    params: &HReqParams,
+    h2_config: &Http2Config,
 ) -> Result<Connection, Error> {
+    let (stream, alpn_proto) = connect_stream(host_port, true, params).await?;
+
+    if params.h2c_upgrade && !params.force_http2 && !host_port.is_tls() {
+        return connect_h2c(host_port, stream, h2_config).await;
+    }
+
+    let proto = if params.force_http2 {
+        Protocol::Http2
+    } else {
+        alpn_proto
+    };
+
+    open_stream(host_port.to_owned(), stream, proto, h2_config).await
+}
+
+/// Connect over a Unix domain socket. The connection is always plain http.
+#[cfg(unix)]
+pub(crate) async fn connect_unix(
+    host_port: &HostPort,
+    path: &Path,
+    params: &HReqParams,
+    h2_config: &Http2Config,
+) -> Result<Connection, Error> {
+    let stream = limit(
+        params.connect_timeout,
+        TimeoutKind::Connect,
+        AsyncRuntime::connect_unix(path),
+    )
+    .await?;
+
+    let proto = if params.force_http2 {
+        Protocol::Http2
+    } else {
+        Protocol::Http11
+    };
+
+    open_stream(host_port.to_owned(), stream, proto, h2_config).await
+}
+
+/// Connect using a user provided connector.
+pub(crate) async fn connect_with(
+    connector: &dyn Connector,
+    host_port: HostPort,
+    params: &HReqParams,
+    h2_config: &Http2Config,
+) -> Result<Connection, Error> {
+    let (stream, proto) = limit(
+        params.connect_timeout,
+        TimeoutKind::Connect,
+        connector.connect(host_port.clone()),
+    )
+    .await?;
+
+    let proto = if params.force_http2 {
+        Protocol::Http2
+    } else {
+        proto
+    };
+
+    open_stream(host_port, stream, proto, h2_config).await
+}
+
+/// Connect the (optionally TLS wrapped) stream to the host without starting any http.
+///
+/// `allow_http2` controls whether http2 is offered in the TLS ALPN negotiation.
+pub(crate) async fn connect_stream(
+    host_port: &HostPort,
+    #[allow(unused_variables)] allow_http2: bool,
+    params: &HReqParams,
+) -> Result<(impl Stream, Protocol), Error> {
     // "host:port"
     let addr = host_port.to_string();
 
     let (stream, alpn_proto) = {
         // "raw" tcp
-        let tcp = AsyncRuntime::connect_tcp(&addr).await?;
+        let tcp = limit(
+            params.connect_timeout,
+            TimeoutKind::Connect,
+            AsyncRuntime::connect_tcp(&addr),
+        )
+        .await?;
 
         #[cfg(feature = "tls")]
         {
@@ -45,8 +141,13 @@ pub(crate) async fn connect(
 
             if host_port.is_tls() {
                 // wrap in tls
-                let (tls, proto) =
-                    wrap_tls_client(tcp, host_port.host(), tls_disable_verify).await?;
+                let tls_disable_verify = params.tls_disable_verify;
+                let (tls, proto) = limit(
+                    params.tls_handshake_timeout,
+                    TimeoutKind::TlsHandshake,
+                    wrap_tls_client(tcp, host_port.host(), allow_http2, tls_disable_verify),
+                )
+                .await?;
                 (Either::A(tls), proto)
             } else {
                 // use tcp
@@ -58,69 +159,147 @@ pub(crate) async fn connect(
         (tcp, Protocol::Unknown)
     };
 
-    let proto = if force_http2 {
-        Protocol::Http2
-    } else {
-        alpn_proto
-    };
+    Ok((stream, alpn_proto))
+}
 
-    open_stream(host_port.to_owned(), stream, proto).await
+/// Open a plain http/1.1 connection where the first request asks for an upgrade to
+/// http2 (h2c). If the server refuses the upgrade, we continue with http/1.1.
+async fn connect_h2c(
+    host_port: &HostPort,
+    stream: impl Stream,
+    h2_config: &Http2Config,
+) -> Result<Connection, Error> {
+    // reads stop after the head of the response to the upgrade request.
+    let takeover = Takeover::new(stream, 0);
+
+    let mut conn = open_stream(
+        host_port.to_owned(),
+        takeover.clone(),
+        Protocol::Http11,
+        h2_config,
+    )
+    .await?;
+
+    conn.set_h2c_upgrade(H2cUpgrade::new(
+        host_port.to_owned(),
+        takeover,
+        h2_config.clone(),
+    ));
+
+    Ok(conn)
+}
+
+/// Start http2 over a stream where the server accepted an h2c upgrade.
+///
+/// Returns the response future of stream 1, which is the request that asked for the upgrade.
+pub(crate) async fn open_h2c(
+    host_port: HostPort,
+    stream: Upgraded,
+    h2_config: &Http2Config,
+) -> Result<(Connection, h2::client::ResponseFuture), Error> {
+    let stream = H2cClientStream::new(stream);
+
+    let (conn, stream1) = open_h2(host_port, stream, h2_config, true).await?;
+
+    Ok((conn, stream1.expect("Stream 1 of h2c upgrade")))
 }
 
 pub(crate) async fn open_stream(
     host_port: HostPort,
     stream: impl Stream,
     proto: Protocol,
+    h2_config: &Http2Config,
 ) -> Result<Connection, Error> {
     if proto == Protocol::Http2 {
-        const DEFAULT_STREAM_WINDOW: u32 = 65_535;
-        const DEFAULT_CONN_WINDOW: u32 = 65_535;
-        const DEFAULT_MAX_FRAME_SIZE: u32 = 16_384;
-
-        let mut builder = h2::client::Builder::default();
-        builder
-            .initial_window_size(DEFAULT_STREAM_WINDOW)
-            .initial_connection_window_size(DEFAULT_CONN_WINDOW)
-            .max_frame_size(DEFAULT_MAX_FRAME_SIZE);
-
-        let (h2, mut h2conn) = builder.handshake(stream.compat()).await?;
-
-        let pinger = h2conn.ping_pong().expect("Take ping_pong of h2conn");
-        let bw = BandwidthMonitor::new(pinger);
-
-        let mut bw_conn = bw.clone();
-
-        // piggy-back the bandwidth monitor on polling the connection
-        let conn_and_bw = poll_fn(move |cx| {
-            if let Poll::Ready(window_size) = bw_conn.poll_window_update(cx) {
-                trace!("Update h2 window size: {}", window_size);
-                h2conn.set_target_window_size(window_size);
-                h2conn.set_initial_window_size(window_size)?;
-            };
-            Pin::new(&mut h2conn).poll(cx)
-        });
-
-        // drives the connection independently of the h2 api surface.
-        let conn_task = async {
-            if let Err(err) = conn_and_bw.await {
-                // this is expected to happen when the connection disconnects
-                trace!("Error in connection: {:?}", err);
-            }
-        };
-
-        AsyncRuntime::spawn(conn_task);
-
-        Ok(Connection::new_h2(host_port, h2, bw))
+        let (conn, _) = open_h2(host_port, stream, h2_config, false).await?;
+        Ok(conn)
     } else {
+        // set when the task driving the connection ends.
+        let closed = Arc::new(AtomicBool::new(false));
+        let closed_task = closed.clone();
+
         let (h1, h1conn) = h1::client::handshake(stream);
         // drives the connection independently of the h1 api surface
-        let conn_task = async {
+        let conn_task = async move {
             if let Err(err) = h1conn.await {
                 // this is expected to happen when the connection disconnects
                 trace!("Error in connection: {:?}", err);
             }
+            closed_task.store(true, Ordering::Relaxed);
         };
         AsyncRuntime::spawn(conn_task);
-        Ok(Connection::new_h1(host_port, h1))
+        Ok(Connection::new_h1(host_port, h1, closed))
     }
 }
+
+async fn open_h2(
+    host_port: HostPort,
+    stream: impl Stream,
+    h2_config: &Http2Config,
+    h2c_upgrade: bool,
+) -> Result<(Connection, Option<h2::client::ResponseFuture>), Error> {
+    // set when the task driving the connection ends.
+    let closed = Arc::new(AtomicBool::new(false));
+    let closed_task = closed.clone();
+
+    let builder = h2_config.client_builder();
+
+    let (h2, mut h2conn) = builder.handshake(stream.compat()).await?;
+
+    // The request that asked for an h2c upgrade is stream 1. This placeholder opens
+    // stream 1 on our side to receive the response, but its HEADERS are dropped by
+    // H2cClientStream. The pseudo headers are all in the static HPACK table, which
+    // means the dropped header block leaves no trace in the dynamic table.
+    //
+    // It must be sent before the connection is driven, to be encoded before any
+    // settings from the server are applied.
+    let stream1 = if h2c_upgrade {
+        let req = http::Request::get("/").body(())?;
+        let (fut, _) = h2.clone().ready().await?.send_request(req, true)?;
+        Some(fut)
+    } else {
+        None
+    };
+
+    let window = h2_config.client_window();
+
+    let bw = BandwidthMonitor::from_config(h2_config, window, || {
+        h2conn.ping_pong().expect("Take ping_pong of h2conn")
+    });
+
+    let mut bw_conn = bw.clone();
+
+    // piggy-back the bandwidth monitor and keep-alive on polling the connection
+    let conn_and_bw = poll_fn(move |cx| {
+        if let Some(bw_conn) = &mut bw_conn {
+            match bw_conn.poll_ping(cx) {
+                Poll::Ready(PingEvent::WindowUpdate(window_size)) => {
+                    trace!("Update h2 window size: {}", window_size);
+                    h2conn.set_target_window_size(window_size);
+                    h2conn.set_initial_window_size(window_size)?;
+                }
+                Poll::Ready(PingEvent::KeepAliveTimeout) => {
+                    return Poll::Ready(Err(Error::Proto("h2 keep-alive timeout".into())));
+                }
+                Poll::Ready(PingEvent::Failed(e)) => {
+                    return Poll::Ready(Err(e.into()));
+                }
+                Poll::Pending => {}
+            }
+        }
+        Pin::new(&mut h2conn).poll(cx).map_err(Error::from)
+    });
+
+    // drives the connection independently of the h2 api surface.
+    let conn_task = async move {
+        if let Err(err) = conn_and_bw.await {
+            // this is expected to happen when the connection disconnects
+            trace!("Error in connection: {:?}", err);
+        }
+        closed_task.store(true, Ordering::Relaxed);
+    };
+
+    AsyncRuntime::spawn(conn_task);
+
+    Ok((Connection::new_h2(host_port, h2, bw, closed), stream1))
+}
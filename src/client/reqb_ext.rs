@@ -1,15 +1,22 @@
 //! Extension trait for `http::request::Builder`
 
+use crate::cancel::{Cancel, CancelToken};
 use crate::client::agent::ResponseFuture;
 use crate::client::req_ext::RequestExt;
+use crate::format::BodyFormat;
 use crate::params::QueryParams;
 use crate::params::{AutoCharset, HReqParams};
+use crate::progress::{Progress, ProgressFn};
+use crate::throttle::Throttle;
 use crate::uri_ext::HostPort;
 use crate::Body;
+use crate::Error;
 use encoding_rs::Encoding;
 use http::request;
 use http::Request;
 use serde::Serialize;
+#[cfg(unix)]
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -74,6 +81,104 @@ where
     /// ```
     fn timeout_millis(self, millis: u64) -> Self;
 
+    /// Set a timeout for opening a new connection.
+    ///
+    /// This is the TCP connect (or Unix socket/[`Connector`]) only, TLS has a timeout of
+    /// its own. Connections reused from the pool are not affected. A connection that
+    /// can't be established in time fails with a [`TimeoutKind::Connect`] error.
+    ///
+    /// The limit applies to each connection attempt, also those following redirects.
+    /// It's not a replacement for [`timeout`] for the entire request.
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    /// use hreq::TimeoutKind;
+    /// use std::time::Duration;
+    ///
+    /// let res = Request::get("https://httpbin.org/get")
+    ///     .connect_timeout(Duration::from_secs(2))
+    ///     .call().block();
+    ///
+    /// if let Err(e) = res {
+    ///     if e.timeout_kind() == Some(TimeoutKind::Connect) {
+    ///         println!("server is unreachable");
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// [`Connector`]: trait.Connector.html
+    /// [`TimeoutKind::Connect`]: enum.TimeoutKind.html#variant.Connect
+    /// [`timeout`]: trait.RequestBuilderExt.html#tymethod.timeout
+    fn connect_timeout(self, duration: Duration) -> Self;
+
+    /// Set a timeout for the TLS handshake of a new `https` connection.
+    ///
+    /// The clock starts once the TCP connection is established. Failure to complete the
+    /// handshake in time is a [`TimeoutKind::TlsHandshake`] error.
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// let res = Request::get("https://httpbin.org/get")
+    ///     .tls_handshake_timeout(Duration::from_secs(5))
+    ///     .call().block();
+    /// ```
+    ///
+    /// [`TimeoutKind::TlsHandshake`]: enum.TimeoutKind.html#variant.TlsHandshake
+    fn tls_handshake_timeout(self, duration: Duration) -> Self;
+
+    /// Set a timeout for receiving the response headers.
+    ///
+    /// The clock starts when the request, including the body, is sent in full. A server
+    /// that doesn't respond in time gives a [`TimeoutKind::ResponseHeaders`] error.
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// let res = Request::get("https://httpbin.org/delay/10")
+    ///     .response_header_timeout(Duration::from_secs(5))
+    ///     .call().block();
+    ///
+    /// assert!(res.unwrap_err().is_timeout());
+    /// ```
+    ///
+    /// [`TimeoutKind::ResponseHeaders`]: enum.TimeoutKind.html#variant.ResponseHeaders
+    fn response_header_timeout(self, duration: Duration) -> Self;
+
+    /// Set a max time to wait for more data when reading the response body.
+    ///
+    /// Unlike [`timeout`], this doesn't limit how long the body takes in total, only the
+    /// gap between received chunks. This makes it a good fit for long running streaming
+    /// downloads, that should still fail when the socket stalls. Reading the body fails
+    /// with a [`TimeoutKind::BodyIdle`] error.
+    ///
+    /// Time spent by the reader not reading, or paused by [`throttle`], is not counted.
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    /// use std::time::Duration;
+    ///
+    /// let mut res = Request::get("https://example.com/huge-file.iso")
+    ///     .body_idle_timeout(Duration::from_secs(30))
+    ///     .call().block().unwrap();
+    ///
+    /// let mut buf = vec![0_u8; 16_384];
+    /// loop {
+    ///     // fails if no data arrives for 30 seconds.
+    ///     let amount = res.body_mut().read(&mut buf).block().unwrap();
+    ///     if amount == 0 {
+    ///         break;
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// [`timeout`]: trait.RequestBuilderExt.html#tymethod.timeout
+    /// [`throttle`]: trait.RequestBuilderExt.html#tymethod.throttle
+    /// [`TimeoutKind::BodyIdle`]: enum.TimeoutKind.html#variant.BodyIdle
+    fn body_idle_timeout(self, duration: Duration) -> Self;
+
     /// Force the request to use http2.
     ///
     /// Normally whether to use http2 is negotiated as part of TLS (https). The TLS feature is
@@ -95,6 +200,38 @@ where
     /// ["prior knowledge"]: https://http2.github.io/http2-spec/#known-http
     fn force_http2(self, force: bool) -> Self;
 
+    /// Try to upgrade plain http connections to http2 (h2c).
+    ///
+    /// For `http://` without TLS, there is no ALPN to negotiate http2. Instead the http2
+    /// spec describes an [upgrade mechanism] where the client asks to switch protocol
+    /// using `Upgrade: h2c`.
+    ///
+    /// When enabled, the first request of every new plain http connection asks for the
+    /// upgrade. If the server agrees, the response and the rest of the connection is http2,
+    /// otherwise it continues as http1.1. Connections that already exist in the pool are
+    /// reused as is.
+    ///
+    /// A request body is sent over http1.1 before the switch, and servers may choose to
+    /// not upgrade requests with a body.
+    ///
+    /// This setting has no effect for `https://` (which uses ALPN), or when combined
+    /// with [`force_http2`].
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    ///
+    /// let res = Request::get("http://my-http2-server/")
+    ///     .h2c_upgrade(true)
+    ///     .call().block().unwrap();
+    ///
+    /// // if the server agreed to upgrade
+    /// assert_eq!(res.version(), http::Version::HTTP_2);
+    /// ```
+    ///
+    /// [upgrade mechanism]: https://http2.github.io/http2-spec/#discover-http
+    /// [`force_http2`]: trait.RequestBuilderExt.html#tymethod.force_http2
+    fn h2c_upgrade(self, enabled: bool) -> Self;
+
     /// Toggle automatic request body charset encoding. Defaults to `true`.
     ///
     /// hreq encodes the request body of text MIME types according to the `charset` in
@@ -340,6 +477,24 @@ where
     /// ```
     fn redirect_body_buffer(self, size: usize) -> Self;
 
+    /// Changes number of redirects to follow for this request.
+    ///
+    /// Defaults to the agent's [`redirects`], which is `5` unless changed. Set to `0`
+    /// to disable redirects, in which case the redirect response is returned.
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    ///
+    /// let res = Request::get("https://my-redirect-server/")
+    ///     .redirect_max(0)
+    ///     .call().block().unwrap();
+    ///
+    /// assert_eq!(res.status(), 302);
+    /// ```
+    ///
+    /// [`redirects`]: struct.Agent.html#method.redirects
+    fn redirect_max(self, amount: u8) -> Self;
+
     /// Toggle ability to read the request body into memory.
     ///
     /// When sending a request body, it's usually a good idea to read the entire body
@@ -351,6 +506,81 @@ where
     /// Use this toggle to turn this behavior off.
     fn prebuffer_request_body(self, enable: bool) -> Self;
 
+    /// Set a callback for the progress of sending the request body, and reading the
+    /// response body.
+    ///
+    /// The callback is called each time some bytes are transferred, with the bytes so far,
+    /// and the total if known from the `content-length`. This takes precedence over a
+    /// callback set with [`Agent::on_progress`].
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    /// use hreq::Direction;
+    ///
+    /// let file = std::fs::File::open("my-big-movie.m4v").unwrap();
+    ///
+    /// Request::post("https://my-upload-server/")
+    ///     .on_progress(|p| {
+    ///         if p.direction == Direction::Upload {
+    ///             println!("{} of {:?}", p.transferred, p.total);
+    ///         }
+    ///     })
+    ///     .send(file)
+    ///     .block().unwrap();
+    /// ```
+    ///
+    /// [`Agent::on_progress`]: struct.Agent.html#method.on_progress
+    fn on_progress<F>(self, f: F) -> Self
+    where
+        F: Fn(Progress) + Send + Sync + 'static;
+
+    /// Limit the rate of sending the request body, and reading the response body, in
+    /// bytes per second.
+    ///
+    /// The limit applies to each body separately. The first second's worth of bytes go
+    /// in a burst, after that the body is transferred at the given rate. This is in
+    /// addition to any shared limit set with [`Agent::throttle`].
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    ///
+    /// let mut res = Request::get("https://example.com/large-file")
+    ///     // at most 100kB/s
+    ///     .throttle(100 * 1024)
+    ///     .call()
+    ///     .block()
+    ///     .unwrap();
+    ///
+    /// let data = res.body_mut().read_to_vec(100 * 1024 * 1024).block().unwrap();
+    /// ```
+    ///
+    /// [`Agent::throttle`]: struct.Agent.html#method.throttle
+    fn throttle(self, bytes_per_second: u64) -> Self;
+
+    /// Make the request cancellable using a [`CancelToken`].
+    ///
+    /// Cancelling the token aborts the request at any stage, also while reading the
+    /// response body. This is in addition to any token set with [`Agent::cancel_token`].
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    /// use hreq::CancelToken;
+    ///
+    /// let token = CancelToken::new();
+    ///
+    /// let res = Request::get("https://example.com/")
+    ///     .cancel_token(&token)
+    ///     .call();
+    ///
+    /// token.cancel();
+    ///
+    /// assert!(res.block().unwrap_err().is_cancelled());
+    /// ```
+    ///
+    /// [`CancelToken`]: struct.CancelToken.html
+    /// [`Agent::cancel_token`]: struct.Agent.html#method.cancel_token
+    fn cancel_token(self, token: &CancelToken) -> Self;
+
     /// Override the host, port and TLS setting of where to connect to.
     ///
     /// This is mostly used for testing.
@@ -367,6 +597,30 @@ where
     /// [`Uri`]: https://docs.rs/http/latest/http/uri/struct.Uri.html
     fn with_override(self, host: &str, port: u16, tls: bool) -> Self;
 
+    /// Connect over a Unix domain socket instead of TCP.
+    ///
+    /// The host and port in the [`Uri`] are not used to connect, but are still used for
+    /// the `host` header, cookies etc. The connection is always plain http, regardless of
+    /// the scheme. Like for [`with_override`], the socket is only used for the host/port
+    /// found in the [`Uri`], and not when following redirects to other host/ports.
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    ///
+    /// let mut res = Request::get("http://localhost/v1.41/containers/json")
+    ///     .unix_socket("/var/run/docker.sock")
+    ///     .call()
+    ///     .block()
+    ///     .unwrap();
+    ///
+    /// let containers = res.body_mut().read_to_string().block().unwrap();
+    /// ```
+    ///
+    /// [`Uri`]: https://docs.rs/http/latest/http/uri/struct.Uri.html
+    /// [`with_override`]: trait.RequestBuilderExt.html#tymethod.with_override
+    #[cfg(unix)]
+    fn unix_socket<P: AsRef<Path>>(self, path: P) -> Self;
+
     /// Disables verification of server certificate.
     ///
     /// This is generally a bad idea. With verification turned off, anyone can intercept
@@ -484,6 +738,50 @@ where
     fn send_json<B>(self, body: &B) -> ResponseFuture
     where
         B: Serialize + ?Sized + Send + Sync;
+
+    /// Finish building the request by providing an object serialized in a [`BodyFormat`].
+    ///
+    /// This sets both `content-type` of the format and `content-length`. Fails if
+    /// the object can't be serialized in the format.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use serde_derive::Serialize;
+    /// use hreq::prelude::*;
+    /// use hreq::Json;
+    ///
+    /// #[derive(Serialize)]
+    /// struct MyThing {
+    ///   name: String,
+    ///   age: String,
+    /// }
+    ///
+    /// let thing = MyThing {
+    ///   name: "Karl Kajal".into(),
+    ///   age: "32".into(),
+    /// };
+    ///
+    /// let req = http::Request::post("http://foo")
+    ///   .with_serde::<Json, _>(&thing);
+    /// ```
+    ///
+    /// [`BodyFormat`]: trait.BodyFormat.html
+    fn with_serde<F, B>(self, body: &B) -> Result<Request<Body>, Error>
+    where
+        F: BodyFormat,
+        B: Serialize + ?Sized;
+
+    /// Send the built request with provided object serialized in a [`BodyFormat`].
+    ///
+    /// This is a shortcut to both provide the body and send the request. Serialization
+    /// errors are returned when awaiting the response.
+    ///
+    /// [`BodyFormat`]: trait.BodyFormat.html
+    fn send_serde<F, B>(self, body: &B) -> ResponseFuture
+    where
+        F: BodyFormat,
+        B: Serialize + ?Sized + Send + Sync;
 }
 
 impl RequestBuilderExt for request::Builder {
@@ -507,12 +805,42 @@ impl RequestBuilderExt for request::Builder {
         self.timeout(Duration::from_millis(millis))
     }
 
+    fn connect_timeout(self, duration: Duration) -> Self {
+        with_hreq_params(self, |params| {
+            params.connect_timeout = Some(duration);
+        })
+    }
+
+    fn tls_handshake_timeout(self, duration: Duration) -> Self {
+        with_hreq_params(self, |params| {
+            params.tls_handshake_timeout = Some(duration);
+        })
+    }
+
+    fn response_header_timeout(self, duration: Duration) -> Self {
+        with_hreq_params(self, |params| {
+            params.response_header_timeout = Some(duration);
+        })
+    }
+
+    fn body_idle_timeout(self, duration: Duration) -> Self {
+        with_hreq_params(self, |params| {
+            params.body_idle_timeout = Some(duration);
+        })
+    }
+
     fn force_http2(self, enabled: bool) -> Self {
         with_hreq_params(self, |params| {
             params.force_http2 = enabled;
         })
     }
 
+    fn h2c_upgrade(self, enabled: bool) -> Self {
+        with_hreq_params(self, |params| {
+            params.h2c_upgrade = enabled;
+        })
+    }
+
     fn charset_encode(self, enable: bool) -> Self {
         with_hreq_params(self, |params| {
             params.charset_tx.toggle_target(enable);
@@ -565,18 +893,55 @@ impl RequestBuilderExt for request::Builder {
         })
     }
 
+    fn redirect_max(self, amount: u8) -> Self {
+        with_hreq_params(self, |params| {
+            params.redirect_max = Some(amount);
+        })
+    }
+
     fn prebuffer_request_body(self, enable: bool) -> Self {
         with_hreq_params(self, |params| {
             params.prebuffer = enable;
         })
     }
 
+    fn on_progress<F>(self, f: F) -> Self
+    where
+        F: Fn(Progress) + Send + Sync + 'static,
+    {
+        with_hreq_params(self, |params| {
+            params.progress = Some(ProgressFn::new(f));
+        })
+    }
+
+    fn throttle(self, bytes_per_second: u64) -> Self {
+        with_hreq_params(self, |params| {
+            params.throttle = Some(Throttle::per_body(bytes_per_second));
+        })
+    }
+
+    fn cancel_token(self, token: &CancelToken) -> Self {
+        with_hreq_params(self, |params| {
+            params.cancel = Some(match params.cancel.take() {
+                Some(c) => c.and(&Cancel::new(token)),
+                None => Cancel::new(token),
+            });
+        })
+    }
+
     fn with_override(self, host: &str, port: u16, tls: bool) -> Self {
         with_hreq_params(self, |params| {
             params.with_override = Some(Arc::new(HostPort::new(host, port, tls)));
         })
     }
 
+    #[cfg(unix)]
+    fn unix_socket<P: AsRef<Path>>(self, path: P) -> Self {
+        with_hreq_params(self, |params| {
+            params.unix_socket = Some(Arc::new(path.as_ref().to_path_buf()));
+        })
+    }
+
     #[cfg(feature = "tls")]
     fn tls_disable_server_cert_verify(self, disable: bool) -> Self {
         with_hreq_params(self, |params| {
@@ -618,6 +983,27 @@ impl RequestBuilderExt for request::Builder {
             Err(v) => ResponseFuture::new(async move { Err(v.into()) }),
         }
     }
+
+    fn with_serde<F, B>(self, body: &B) -> Result<Request<Body>, Error>
+    where
+        F: BodyFormat,
+        B: Serialize + ?Sized,
+    {
+        let body = Body::from_serde::<F, B>(body)?;
+        Ok(self.with_body(body)?)
+    }
+
+    fn send_serde<F, B>(self, body: &B) -> ResponseFuture
+    where
+        F: BodyFormat,
+        B: Serialize + ?Sized + Send + Sync,
+    {
+        let req = self.with_serde::<F, B>(body);
+        match req {
+            Ok(v) => v.send(),
+            Err(v) => ResponseFuture::new(async move { Err(v) }),
+        }
+    }
 }
 
 fn get_or_insert<T: Send + Sync + 'static, F: FnOnce() -> T>(
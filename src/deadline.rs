@@ -1,11 +1,12 @@
 use crate::async_impl::never;
 use crate::AsyncRuntime;
-use crate::Error;
+use crate::{Error, TimeoutKind};
 use futures_util::future::FutureExt;
 use futures_util::select;
 use std::future::Future;
 use std::io;
 use std::pin::Pin;
+use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
 
 const ZERO: Duration = Duration::from_millis(0);
@@ -49,7 +50,7 @@ impl Deadline {
                 // never completes
                 never().await;
             }
-            io::Error::new(io::ErrorKind::TimedOut, "timeout")
+            TimeoutKind::Request.into_io()
         };
         Box::pin(fut)
     }
@@ -70,3 +71,54 @@ impl Deadline {
         }
     }
 }
+
+/// Limits a single step of a request, such as connecting, to an optional duration.
+pub(crate) async fn limit<T, F, Err>(
+    duration: Option<Duration>,
+    kind: TimeoutKind,
+    f: F,
+) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Err>>,
+    Err: Into<Error>,
+{
+    let duration = match duration {
+        Some(d) => d,
+        None => return f.await.map_err(|e| e.into()),
+    };
+
+    select! {
+        a = f.fuse() => a.map_err(|e| e.into()),
+        _ = AsyncRuntime::timeout(duration).fuse() => Err(kind.into_io().into()),
+    }
+}
+
+/// Max time to wait between chunks of incoming body data.
+pub(crate) struct IdleTimer {
+    limit: Duration,
+    timer: Option<Pin<Box<dyn Future<Output = ()> + Send + Sync>>>,
+}
+
+impl IdleTimer {
+    pub fn new(limit: Duration) -> Self {
+        IdleTimer { limit, timer: None }
+    }
+
+    /// Data arrived, the next wait starts over.
+    pub fn reset(&mut self) {
+        self.timer = None;
+    }
+
+    /// Called while waiting for data. Errors if we waited too long.
+    pub fn poll_idle(&mut self, cx: &mut Context) -> io::Result<()> {
+        let limit = self.limit;
+        let timer = self
+            .timer
+            .get_or_insert_with(|| Box::pin(AsyncRuntime::timeout(limit)));
+
+        match timer.as_mut().poll(cx) {
+            Poll::Ready(_) => Err(TimeoutKind::BodyIdle.into_io()),
+            Poll::Pending => Ok(()),
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::cancel::CancelledIo;
 use std::fmt;
 use std::io;
 
@@ -25,6 +26,10 @@ pub enum Error {
     Http(http::Error),
     /// JSON deserialization errors.
     Json(serde_json::Error),
+    /// Serialization errors of a [`BodyFormat`] other than JSON.
+    ///
+    /// [`BodyFormat`]: trait.BodyFormat.html
+    Format(Box<dyn std::error::Error + Send + Sync>),
     /// TLS (https) errors.
     #[cfg(feature = "tls")]
     TlsError(TLSError),
@@ -36,6 +41,10 @@ pub enum Error {
     AddrParse(net::AddrParseError),
     /// Failure to convert a string to UTF8.
     Utf8(Utf8Error),
+    /// The request was cancelled using a [`CancelToken`].
+    ///
+    /// [`CancelToken`]: struct.CancelToken.html
+    Cancelled,
 }
 
 impl Error {
@@ -63,6 +72,35 @@ impl Error {
         false
     }
 
+    /// Tells if this error is because the request was cancelled using a [`CancelToken`].
+    ///
+    /// [`CancelToken`]: struct.CancelToken.html
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, Error::Cancelled)
+    }
+
+    /// Tells which timeout caused this error, if it is a timeout from hreq.
+    ///
+    /// ```
+    /// use hreq::prelude::*;
+    /// use hreq::TimeoutKind;
+    /// use std::time::Duration;
+    ///
+    /// let req = Request::get("https://httpbin.org/get")
+    ///     .timeout(Duration::from_nanos(1))
+    ///     .call().block();
+    ///
+    /// assert_eq!(req.unwrap_err().timeout_kind(), Some(TimeoutKind::Request));
+    /// ```
+    pub fn timeout_kind(&self) -> Option<TimeoutKind> {
+        match self {
+            Error::Io(e) if e.kind() == io::ErrorKind::TimedOut => {
+                e.get_ref()?.downcast_ref::<TimeoutKind>().copied()
+            }
+            _ => None,
+        }
+    }
+
     /// Agent retry function depends on this classifying retryable errors.
     pub(crate) fn is_retryable(&self) -> bool {
         if let Error::Io(e) = self {
@@ -79,6 +117,61 @@ impl Error {
     }
 }
 
+/// The limit that was reached for a timeout error.
+///
+/// See [`Error::timeout_kind()`].
+///
+/// [`Error::timeout_kind()`]: enum.Error.html#method.timeout_kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutKind {
+    /// The timeout for the entire request, set by [`timeout`].
+    ///
+    /// [`timeout`]: trait.RequestBuilderExt.html#tymethod.timeout
+    Request,
+    /// Opening the connection, set by [`connect_timeout`].
+    ///
+    /// [`connect_timeout`]: trait.RequestBuilderExt.html#tymethod.connect_timeout
+    Connect,
+    /// The TLS handshake, set by [`tls_handshake_timeout`].
+    ///
+    /// [`tls_handshake_timeout`]: trait.RequestBuilderExt.html#tymethod.tls_handshake_timeout
+    TlsHandshake,
+    /// Waiting for the response headers, set by [`response_header_timeout`].
+    ///
+    /// [`response_header_timeout`]: trait.RequestBuilderExt.html#tymethod.response_header_timeout
+    ResponseHeaders,
+    /// Waiting for more response body data, set by [`body_idle_timeout`].
+    ///
+    /// [`body_idle_timeout`]: trait.RequestBuilderExt.html#tymethod.body_idle_timeout
+    BodyIdle,
+    /// Waiting in the queue of a rate limit, set by [`queue_timeout`].
+    ///
+    /// [`queue_timeout`]: struct.RateLimit.html#method.queue_timeout
+    Queue,
+}
+
+impl TimeoutKind {
+    pub(crate) fn into_io(self) -> io::Error {
+        io::Error::new(io::ErrorKind::TimedOut, self)
+    }
+}
+
+impl fmt::Display for TimeoutKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            TimeoutKind::Request => "timeout",
+            TimeoutKind::Connect => "connect timeout",
+            TimeoutKind::TlsHandshake => "tls handshake timeout",
+            TimeoutKind::ResponseHeaders => "response header timeout",
+            TimeoutKind::BodyIdle => "body idle timeout",
+            TimeoutKind::Queue => "rate limit queue timeout",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl std::error::Error for TimeoutKind {}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -89,6 +182,7 @@ impl fmt::Display for Error {
             Error::H2(v) => write!(f, "http2: {}", v),
             Error::Http(v) => write!(f, "http api: {}", v),
             Error::Json(v) => write!(f, "json: {}", v),
+            Error::Format(v) => write!(f, "format: {}", v),
             #[cfg(feature = "tls")]
             Error::TlsError(v) => write!(f, "tls: {}", v),
             #[cfg(feature = "tls")]
@@ -96,6 +190,7 @@ impl fmt::Display for Error {
             #[cfg(feature = "server")]
             Error::AddrParse(v) => write!(f, "addr parse: {}", v),
             Error::Utf8(v) => write!(f, "utf-8: {}", v),
+            Error::Cancelled => write!(f, "cancelled"),
         }
     }
 }
@@ -110,6 +205,7 @@ impl std::error::Error for Error {
             Error::H2(e) => Some(e),
             Error::Http(e) => Some(e),
             Error::Json(e) => Some(e),
+            Error::Format(e) => Some(e.as_ref()),
             #[cfg(feature = "tls")]
             Error::TlsError(e) => Some(e),
             #[cfg(feature = "tls")]
@@ -117,12 +213,16 @@ impl std::error::Error for Error {
             #[cfg(feature = "server")]
             Error::AddrParse(e) => Some(e),
             Error::Utf8(e) => Some(e),
+            Error::Cancelled => None,
         }
     }
 }
 
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Self {
+        if e.get_ref().map(|r| r.is::<CancelledIo>()) == Some(true) {
+            return Error::Cancelled;
+        }
         Error::Io(e)
     }
 }
@@ -0,0 +1,153 @@
+//! Serialization formats of bodies.
+
+use crate::Error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// A serde format bodies can be serialized to and deserialized from.
+///
+/// Used with [`Body::from_serde`], [`Body::read_as`], [`with_serde`] and
+/// [`send_serde`]. [`Json`] is always available. With cargo features there is also
+///
+///   * [`MsgPack`], feature `msgpack`.
+///   * [`Cbor`], feature `cbor`.
+///
+/// Other serde formats can be used by implementing this trait.
+///
+/// ```
+/// use hreq::{BodyFormat, Error};
+/// use serde::de::DeserializeOwned;
+/// use serde::Serialize;
+///
+/// struct Pretty;
+///
+/// impl BodyFormat for Pretty {
+///     const CONTENT_TYPE: &'static str = "application/json; charset=utf-8";
+///
+///     fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+///         Ok(serde_json::to_vec_pretty(value)?)
+///     }
+///
+///     fn from_slice<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error> {
+///         Ok(serde_json::from_slice(data)?)
+///     }
+/// }
+/// ```
+///
+/// [`Body::from_serde`]: struct.Body.html#method.from_serde
+/// [`Body::read_as`]: struct.Body.html#method.read_as
+/// [`with_serde`]: trait.RequestBuilderExt.html#tymethod.with_serde
+/// [`send_serde`]: trait.RequestBuilderExt.html#tymethod.send_serde
+/// [`Json`]: struct.Json.html
+/// [`MsgPack`]: struct.MsgPack.html
+/// [`Cbor`]: struct.Cbor.html
+pub trait BodyFormat {
+    /// The `content-type` set for bodies in this format.
+    const CONTENT_TYPE: &'static str;
+
+    /// Tells whether an incoming body with the given mime type is in this format.
+    ///
+    /// The mime type is lowercase without parameters, such as `application/json`.
+    /// Defaults to comparing with [`CONTENT_TYPE`].
+    ///
+    /// [`CONTENT_TYPE`]: #associatedconstant.CONTENT_TYPE
+    fn is_mime(mime: &str) -> bool {
+        mime == mime_of(Self::CONTENT_TYPE)
+    }
+
+    /// Serializes a value to bytes.
+    fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error>;
+
+    /// Deserializes a value from bytes.
+    fn from_slice<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error>;
+}
+
+/// JSON using the `serde_json` crate.
+///
+/// Also accepts incoming structured syntax types, such as `application/problem+json`.
+#[derive(Debug, Clone, Copy)]
+pub struct Json;
+
+impl BodyFormat for Json {
+    const CONTENT_TYPE: &'static str = "application/json; charset=utf-8";
+
+    fn is_mime(mime: &str) -> bool {
+        mime == "application/json" || mime.starts_with("application/") && mime.ends_with("+json")
+    }
+
+    fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn from_slice<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+/// MessagePack using the `rmp-serde` crate. Requires feature `msgpack`.
+///
+/// Structs are serialized as maps with the field names, which is what most other
+/// MessagePack implementations expect.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy)]
+pub struct MsgPack;
+
+#[cfg(feature = "msgpack")]
+impl BodyFormat for MsgPack {
+    const CONTENT_TYPE: &'static str = "application/msgpack";
+
+    fn is_mime(mime: &str) -> bool {
+        mime == "application/msgpack" || mime == "application/x-msgpack"
+    }
+
+    fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+        rmp_serde::to_vec_named(value).map_err(|e| Error::Format(Box::new(e)))
+    }
+
+    fn from_slice<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error> {
+        rmp_serde::from_read_ref(data).map_err(|e| Error::Format(Box::new(e)))
+    }
+}
+
+/// CBOR using the `ciborium` crate. Requires feature `cbor`.
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl BodyFormat for Cbor {
+    const CONTENT_TYPE: &'static str = "application/cbor";
+
+    fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+        let mut vec = Vec::new();
+        ciborium::ser::into_writer(value, &mut vec).map_err(|e| Error::Format(Box::new(e)))?;
+        Ok(vec)
+    }
+
+    fn from_slice<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error> {
+        ciborium::de::from_reader(data).map_err(|e| Error::Format(Box::new(e)))
+    }
+}
+
+/// The mime type of a `content-type` value, lowercase without parameters.
+pub(crate) fn mime_of(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_mime() {
+        assert!(Json::is_mime(&mime_of("application/json")));
+        assert!(Json::is_mime(&mime_of("Application/JSON; charset=utf-8")));
+        assert!(Json::is_mime(&mime_of("application/problem+json")));
+        assert!(!Json::is_mime(&mime_of("text/plain")));
+    }
+}
@@ -0,0 +1,250 @@
+//! HTTP/2 connection settings.
+
+use std::time::Duration;
+
+/// Settings for HTTP/2 connections.
+///
+/// Used both for the client, via [`Agent::http2_config`], and the server, via
+/// [`Server::http2_config`]. Settings not set use the defaults of the respective side.
+///
+/// | Setting                        | Client default | Server default |
+/// |--------------------------------|----------------|----------------|
+/// | Initial stream window size     | 64KiB          | 1MiB           |
+/// | Initial connection window size | 64KiB          | 1MiB           |
+/// | Max frame size                 | 16KiB          | 16KiB          |
+/// | Max concurrent streams         | unlimited      | unlimited      |
+/// | Max header list size           | unlimited      | unlimited      |
+/// | Bandwidth auto tuning          | on             | on             |
+/// | Keep-alive interval            | off            | off            |
+/// | Keep-alive timeout             | 20s            | 20s            |
+///
+/// ```
+/// use hreq::Agent;
+/// use hreq::Http2Config;
+///
+/// let mut agent = Agent::new();
+///
+/// agent.http2_config(
+///     Http2Config::new()
+///         .initial_stream_window_size(1024 * 1024)
+///         .initial_connection_window_size(4 * 1024 * 1024)
+///         .bandwidth_auto_tune(false),
+/// );
+/// ```
+///
+/// [`Agent::http2_config`]: struct.Agent.html#method.http2_config
+/// [`Server::http2_config`]: server/struct.Server.html#method.http2_config
+#[derive(Debug, Clone)]
+pub struct Http2Config {
+    initial_stream_window_size: Option<u32>,
+    initial_connection_window_size: Option<u32>,
+    max_frame_size: Option<u32>,
+    max_concurrent_streams: Option<u32>,
+    max_header_list_size: Option<u32>,
+    bandwidth_auto_tune: bool,
+    keep_alive_interval: Option<Duration>,
+    keep_alive_timeout: Duration,
+}
+
+const CLIENT_DEFAULT_WINDOW: u32 = 65_535;
+#[cfg(feature = "server")]
+const SERVER_DEFAULT_WINDOW: u32 = 1024 * 1024;
+const DEFAULT_MAX_FRAME_SIZE: u32 = 16_384;
+const MAX_MAX_FRAME_SIZE: u32 = 16_777_215;
+const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(20);
+
+impl Http2Config {
+    /// Create a new configuration with default settings.
+    pub fn new() -> Self {
+        Http2Config {
+            initial_stream_window_size: None,
+            initial_connection_window_size: None,
+            max_frame_size: None,
+            max_concurrent_streams: None,
+            max_header_list_size: None,
+            bandwidth_auto_tune: true,
+            keep_alive_interval: None,
+            keep_alive_timeout: DEFAULT_KEEP_ALIVE_TIMEOUT,
+        }
+    }
+
+    /// Initial flow control window size for each stream, in bytes.
+    ///
+    /// A larger window lets the peer send more data before waiting for
+    /// acknowledgement, which helps on high latency links.
+    pub fn initial_stream_window_size(mut self, size: u32) -> Self {
+        self.initial_stream_window_size = Some(size);
+        self
+    }
+
+    /// Initial flow control window size for the entire connection, in bytes.
+    pub fn initial_connection_window_size(mut self, size: u32) -> Self {
+        self.initial_connection_window_size = Some(size);
+        self
+    }
+
+    /// Max size of frames we accept, in bytes.
+    ///
+    /// Must be between 16KiB and 16MiB (minus one byte), or this panics.
+    pub fn max_frame_size(mut self, size: u32) -> Self {
+        assert!(
+            (DEFAULT_MAX_FRAME_SIZE..=MAX_MAX_FRAME_SIZE).contains(&size),
+            "max_frame_size must be between 16KiB and 16MiB"
+        );
+        self.max_frame_size = Some(size);
+        self
+    }
+
+    /// Max number of concurrent streams the peer can open to us.
+    pub fn max_concurrent_streams(mut self, max: u32) -> Self {
+        self.max_concurrent_streams = Some(max);
+        self
+    }
+
+    /// Max size of a header list we accept, in bytes.
+    pub fn max_header_list_size(mut self, max: u32) -> Self {
+        self.max_header_list_size = Some(max);
+        self
+    }
+
+    /// Toggle bandwidth-delay product (BDP) auto tuning of the window sizes.
+    ///
+    /// When on, hreq uses pings to estimate the bandwidth of the connection and
+    /// grows the window sizes to match. Set to `false` to keep the configured window
+    /// sizes fixed.
+    pub fn bandwidth_auto_tune(mut self, enabled: bool) -> Self {
+        self.bandwidth_auto_tune = enabled;
+        self
+    }
+
+    /// Send keep-alive pings when the connection has been idle for this long.
+    ///
+    /// A peer that disappears without closing the connection is otherwise not noticed
+    /// until the next write. With keep-alive, a connection where the peer doesn't answer
+    /// the ping within the [`keep_alive_timeout`] is torn down. Pending requests on it
+    /// fail, and the `Agent` opens a new connection for the next request.
+    ///
+    /// Defaults to off.
+    ///
+    /// ```
+    /// use hreq::Http2Config;
+    /// use std::time::Duration;
+    ///
+    /// let config = Http2Config::new()
+    ///     .keep_alive_interval(Duration::from_secs(30))
+    ///     .keep_alive_timeout(Duration::from_secs(10));
+    /// ```
+    ///
+    /// [`keep_alive_timeout`]: struct.Http2Config.html#method.keep_alive_timeout
+    pub fn keep_alive_interval(mut self, interval: Duration) -> Self {
+        self.keep_alive_interval = Some(interval);
+        self
+    }
+
+    /// Time to wait for the answer to a keep-alive ping. Defaults to 20 seconds.
+    ///
+    /// Only used if [`keep_alive_interval`] is set.
+    ///
+    /// [`keep_alive_interval`]: struct.Http2Config.html#method.keep_alive_interval
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.keep_alive_timeout = timeout;
+        self
+    }
+
+    pub(crate) fn is_bandwidth_auto_tune(&self) -> bool {
+        self.bandwidth_auto_tune
+    }
+
+    /// The larger of the configured client windows. Bandwidth auto tuning starts here.
+    pub(crate) fn client_window(&self) -> u32 {
+        let stream = self
+            .initial_stream_window_size
+            .unwrap_or(CLIENT_DEFAULT_WINDOW);
+        let conn = self
+            .initial_connection_window_size
+            .unwrap_or(CLIENT_DEFAULT_WINDOW);
+        stream.max(conn)
+    }
+
+    /// The larger of the configured server windows. Bandwidth auto tuning starts here.
+    #[cfg(feature = "server")]
+    pub(crate) fn server_window(&self) -> u32 {
+        let stream = self
+            .initial_stream_window_size
+            .unwrap_or(SERVER_DEFAULT_WINDOW);
+        let conn = self
+            .initial_connection_window_size
+            .unwrap_or(SERVER_DEFAULT_WINDOW);
+        stream.max(conn)
+    }
+
+    /// The initial server stream window.
+    #[cfg(feature = "server")]
+    pub(crate) fn server_stream_window(&self) -> u32 {
+        self.initial_stream_window_size
+            .unwrap_or(SERVER_DEFAULT_WINDOW)
+    }
+
+    /// Keep-alive interval and timeout, if keep-alive is on.
+    pub(crate) fn keep_alive(&self) -> Option<(Duration, Duration)> {
+        self.keep_alive_interval
+            .map(|interval| (interval, self.keep_alive_timeout))
+    }
+
+    pub(crate) fn client_builder(&self) -> h2::client::Builder {
+        let mut builder = h2::client::Builder::default();
+
+        builder
+            .initial_window_size(
+                self.initial_stream_window_size
+                    .unwrap_or(CLIENT_DEFAULT_WINDOW),
+            )
+            .initial_connection_window_size(
+                self.initial_connection_window_size
+                    .unwrap_or(CLIENT_DEFAULT_WINDOW),
+            )
+            .max_frame_size(self.max_frame_size.unwrap_or(DEFAULT_MAX_FRAME_SIZE));
+
+        if let Some(max) = self.max_concurrent_streams {
+            builder.max_concurrent_streams(max);
+        }
+
+        if let Some(max) = self.max_header_list_size {
+            builder.max_header_list_size(max);
+        }
+
+        builder
+    }
+
+    #[cfg(feature = "server")]
+    pub(crate) fn server_builder(&self) -> h2::server::Builder {
+        let mut builder = h2::server::Builder::default();
+
+        builder
+            .initial_window_size(
+                self.initial_stream_window_size
+                    .unwrap_or(SERVER_DEFAULT_WINDOW),
+            )
+            .initial_connection_window_size(
+                self.initial_connection_window_size
+                    .unwrap_or(SERVER_DEFAULT_WINDOW),
+            )
+            .max_frame_size(self.max_frame_size.unwrap_or(DEFAULT_MAX_FRAME_SIZE));
+
+        if let Some(max) = self.max_concurrent_streams {
+            builder.max_concurrent_streams(max);
+        }
+
+        if let Some(max) = self.max_header_list_size {
+            builder.max_header_list_size(max);
+        }
+
+        builder
+    }
+}
+
+impl Default for Http2Config {
+    fn default() -> Self {
+        Http2Config::new()
+    }
+}
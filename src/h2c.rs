@@ -0,0 +1,726 @@
+//! HTTP/2 cleartext (h2c) via the HTTP/1.1 `Upgrade` mechanism (RFC 7540 section 3.2).
+//!
+//! The `h2` crate has no notion of the upgrade. After the `101 Switching Protocols`,
+//! the request that asked for the upgrade is implicitly stream 1 of the new connection.
+//!
+//! * On the server side, we inject `HEADERS` (and `DATA`) frames for stream 1, with the
+//!   upgrade request, right after the client connection preface. The settings in the
+//!   `HTTP2-Settings` header are merged into the first client `SETTINGS` frame. The
+//!   request then arrives as any other h2 request and the response is sent on stream 1.
+//!
+//! * On the client side, a placeholder request is opened as stream 1 of the h2
+//!   connection to receive the response. Its `HEADERS` are dropped on the way out,
+//!   since the server already has the upgrade request as stream 1. The placeholder
+//!   only uses the static HPACK table, which keeps the HPACK state in sync despite
+//!   the dropped header block.
+
+use crate::head_ext::HeaderMapExt;
+#[cfg(feature = "server")]
+use crate::Error;
+use crate::{AsyncRead, AsyncWrite};
+use futures_util::ready;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// The client connection preface.
+pub(crate) const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The `HTTP2-Settings` header sent by the client.
+///
+/// This is a base64url encoded `SETTINGS` payload of `SETTINGS_ENABLE_PUSH = 0`.
+pub(crate) const H2C_SETTINGS: &str = "AAIAAAAA";
+
+const FRAME_HEADER_LEN: usize = 9;
+const FRAME_DATA: u8 = 0x0;
+const FRAME_HEADERS: u8 = 0x1;
+#[cfg(any(test, feature = "server"))]
+const FRAME_SETTINGS: u8 = 0x4;
+const FRAME_CONTINUATION: u8 = 0x9;
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+#[cfg(feature = "server")]
+const SETTING_LEN: usize = 6;
+/// Smallest max frame size any h2 peer must accept.
+#[cfg(feature = "server")]
+const MIN_MAX_FRAME_SIZE: usize = 16_384;
+
+// Connection specific headers not allowed in h2.
+#[cfg(feature = "server")]
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "host",
+    "http2-settings",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Tells whether the request asks for an upgrade to h2c.
+#[cfg(feature = "server")]
+pub(crate) fn is_h2c_upgrade(headers: &http::HeaderMap) -> bool {
+    let upgrade_h2c = headers
+        .get_all("upgrade")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim().eq_ignore_ascii_case("h2c"));
+
+    upgrade_h2c && headers.get("http2-settings").is_some()
+}
+
+/// Decode the `SETTINGS` payload of the `HTTP2-Settings` header.
+///
+/// The header must occur once, and is the base64url encoded payload, without padding.
+#[cfg(feature = "server")]
+pub(crate) fn decode_settings_header(headers: &http::HeaderMap) -> Option<Vec<u8>> {
+    let mut all = headers.get_all("http2-settings").iter();
+
+    let value = all.next()?;
+
+    if all.next().is_some() {
+        return None;
+    }
+
+    let payload = decode_base64url(value.as_bytes())?;
+
+    if payload.len() % SETTING_LEN != 0 {
+        return None;
+    }
+
+    Some(payload)
+}
+
+#[cfg(feature = "server")]
+fn decode_base64url(input: &[u8]) -> Option<Vec<u8>> {
+    // padding is not expected, but harmless.
+    let input = match input.iter().position(|c| *c == b'=') {
+        Some(idx) => &input[..idx],
+        None => input,
+    };
+
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+
+    for c in input {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None,
+        };
+
+        acc = acc << 6 | v as u32;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+
+    Some(out)
+}
+
+/// Payload length of the `SETTINGS` frame the client must send first after the preface.
+///
+/// The header is checked before reading the payload, which is capped to the default
+/// `SETTINGS_MAX_FRAME_SIZE` since no settings are agreed on yet.
+#[cfg(feature = "server")]
+pub(crate) fn settings_payload_len(header: &[u8]) -> Result<usize, Error> {
+    if header.len() < FRAME_HEADER_LEN || header[3] != FRAME_SETTINGS || stream_id(header) != 0 {
+        return Err(Error::Proto(
+            "Expected SETTINGS frame after h2c preface".into(),
+        ));
+    }
+
+    let len = frame_len(header) - FRAME_HEADER_LEN;
+
+    if len > MIN_MAX_FRAME_SIZE {
+        return Err(Error::Proto(format!(
+            "SETTINGS frame after h2c preface too big: {}",
+            len
+        )));
+    }
+
+    Ok(len)
+}
+
+/// Merge the settings of the `HTTP2-Settings` header into the first `SETTINGS` frame
+/// sent by the client.
+///
+/// Both must be acknowledged, and doing it as one frame means the client gets one ACK.
+/// Settings in the frame come after the header ones, and take precedence.
+#[cfg(feature = "server")]
+pub(crate) fn merge_settings(header_payload: &[u8], frame: &[u8]) -> Result<Vec<u8>, Error> {
+    if frame.len() < FRAME_HEADER_LEN || frame[3] != FRAME_SETTINGS || frame[4] != 0 {
+        return Err(Error::Proto(
+            "Expected SETTINGS frame after h2c preface".into(),
+        ));
+    }
+
+    let payload = &frame[FRAME_HEADER_LEN..];
+
+    let mut out = Vec::with_capacity(FRAME_HEADER_LEN + header_payload.len() + payload.len());
+    write_frame_header(
+        &mut out,
+        header_payload.len() + payload.len(),
+        FRAME_SETTINGS,
+        0,
+        0,
+    );
+    out.extend_from_slice(header_payload);
+    out.extend_from_slice(payload);
+
+    Ok(out)
+}
+
+/// Headers to add to a request to ask for an upgrade to h2c.
+pub(crate) fn set_upgrade_headers(headers: &mut http::HeaderMap) {
+    headers.set("connection", "Upgrade, HTTP2-Settings");
+    headers.set("upgrade", "h2c");
+    headers.set("http2-settings", H2C_SETTINGS);
+}
+
+/// Length of the frame starting the buffer, header included.
+pub(crate) fn frame_len(header: &[u8]) -> usize {
+    let payload = (header[0] as usize) << 16 | (header[1] as usize) << 8 | header[2] as usize;
+    FRAME_HEADER_LEN + payload
+}
+
+fn stream_id(header: &[u8]) -> u32 {
+    u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff
+}
+
+#[cfg(any(test, feature = "server"))]
+fn write_frame_header(out: &mut Vec<u8>, len: usize, typ: u8, flags: u8, stream_id: u32) {
+    out.push((len >> 16) as u8);
+    out.push((len >> 8) as u8);
+    out.push(len as u8);
+    out.push(typ);
+    out.push(flags);
+    out.extend_from_slice(&(stream_id & 0x7fff_ffff).to_be_bytes());
+}
+
+/// Encode the upgrade request as h2 `HEADERS` (and `CONTINUATION`) frames for stream 1,
+/// followed by `DATA` frames for the body, if any.
+///
+/// The header block uses literals "without indexing" to not affect the HPACK
+/// dynamic table of the receiving side.
+#[cfg(feature = "server")]
+pub(crate) fn encode_request_frames(req: &http::Request<()>, body: &[u8]) -> Vec<u8> {
+    let mut block = vec![];
+
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+
+    encode_literal(&mut block, b":method", req.method().as_str().as_bytes());
+    encode_literal(&mut block, b":scheme", b"http");
+    encode_literal(&mut block, b":path", path.as_bytes());
+
+    if let Some(host) = req.headers().get("host") {
+        encode_literal(&mut block, b":authority", host.as_bytes());
+    }
+
+    for (name, value) in req.headers() {
+        if CONNECTION_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        if name == "te" && value != "trailers" {
+            continue;
+        }
+        // the body is read over http/1.1 and sent as DATA frames without framing headers.
+        if name == "content-length" {
+            continue;
+        }
+        encode_literal(&mut block, name.as_str().as_bytes(), value.as_bytes());
+    }
+
+    let mut out = vec![];
+    let mut chunks = block.chunks(MIN_MAX_FRAME_SIZE).peekable();
+    let mut typ = FRAME_HEADERS;
+
+    while let Some(chunk) = chunks.next() {
+        let mut flags = if typ == FRAME_HEADERS && body.is_empty() {
+            FLAG_END_STREAM
+        } else {
+            0
+        };
+        if chunks.peek().is_none() {
+            flags |= FLAG_END_HEADERS;
+        }
+
+        write_frame_header(&mut out, chunk.len(), typ, flags, 1);
+        out.extend_from_slice(chunk);
+
+        typ = FRAME_CONTINUATION;
+    }
+
+    let mut chunks = body.chunks(MIN_MAX_FRAME_SIZE).peekable();
+
+    while let Some(chunk) = chunks.next() {
+        let flags = if chunks.peek().is_none() {
+            FLAG_END_STREAM
+        } else {
+            0
+        };
+
+        write_frame_header(&mut out, chunk.len(), FRAME_DATA, flags, 1);
+        out.extend_from_slice(chunk);
+    }
+
+    out
+}
+
+// Literal Header Field without Indexing, new name, no huffman.
+#[cfg(feature = "server")]
+fn encode_literal(out: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    out.push(0);
+    encode_string(out, name);
+    encode_string(out, value);
+}
+
+#[cfg(feature = "server")]
+fn encode_string(out: &mut Vec<u8>, s: &[u8]) {
+    encode_int(out, s.len(), 7, 0);
+    out.extend_from_slice(s);
+}
+
+#[cfg(feature = "server")]
+fn encode_int(out: &mut Vec<u8>, mut value: usize, prefix_bits: u8, first_byte: u8) {
+    let max = (1 << prefix_bits) - 1;
+
+    if value < max {
+        out.push(first_byte | value as u8);
+        return;
+    }
+
+    out.push(first_byte | max as u8);
+    value -= max;
+
+    while value >= 128 {
+        out.push((value % 128 + 128) as u8);
+        value /= 128;
+    }
+
+    out.push(value as u8);
+}
+
+/// Client side stream used after a successful h2c upgrade.
+///
+/// Drops the placeholder request for stream 1 written by the h2 client. That is the
+/// `HEADERS` frame, any `CONTINUATION` up to `END_HEADERS`, and, without `END_STREAM`
+/// in the header block, `DATA` frames up to `END_STREAM`.
+pub(crate) struct H2cClientStream<S> {
+    inner: S,
+    write: WriteState,
+    stream1: Stream1,
+    // bytes to write to inner before accepting more.
+    out: Vec<u8>,
+}
+
+enum WriteState {
+    Preface(usize),
+    Header(Vec<u8>),
+    Payload { left: usize, drop: bool },
+    Pass,
+}
+
+/// Where we are in dropping the stream 1 request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stream1 {
+    Headers,
+    Continuation { end_stream: bool },
+    Data,
+    Done,
+}
+
+impl Stream1 {
+    /// Tells whether to drop the frame, and advances past it.
+    fn drop_frame(&mut self, typ: u8, flags: u8) -> bool {
+        let end_stream = flags & FLAG_END_STREAM > 0;
+        let end_headers = flags & FLAG_END_HEADERS > 0;
+
+        let end_stream = match (*self, typ) {
+            (Stream1::Headers, FRAME_HEADERS) => end_stream,
+            (Stream1::Continuation { end_stream }, FRAME_CONTINUATION) => end_stream,
+            (Stream1::Data, FRAME_DATA) => {
+                if end_stream {
+                    *self = Stream1::Done;
+                }
+                return true;
+            }
+            _ => return false,
+        };
+
+        *self = match (end_headers, end_stream) {
+            (false, _) => Stream1::Continuation { end_stream },
+            (true, false) => Stream1::Data,
+            (true, true) => Stream1::Done,
+        };
+
+        true
+    }
+}
+
+impl<S> H2cClientStream<S> {
+    pub fn new(inner: S) -> Self {
+        H2cClientStream {
+            inner,
+            write: WriteState::Preface(H2_PREFACE.len()),
+            stream1: Stream1::Headers,
+            out: vec![],
+        }
+    }
+
+    /// Process outgoing bytes into self.out.
+    fn filter_write(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            match &mut self.write {
+                WriteState::Preface(left) => {
+                    let amt = (*left).min(buf.len());
+                    self.out.extend_from_slice(&buf[..amt]);
+                    buf = &buf[amt..];
+                    *left -= amt;
+                    if *left == 0 {
+                        self.write = WriteState::Header(Vec::with_capacity(FRAME_HEADER_LEN));
+                    }
+                }
+                WriteState::Header(header) => {
+                    let amt = (FRAME_HEADER_LEN - header.len()).min(buf.len());
+                    header.extend_from_slice(&buf[..amt]);
+                    buf = &buf[amt..];
+
+                    if header.len() == FRAME_HEADER_LEN {
+                        let typ = header[3];
+                        let drop =
+                            stream_id(header) == 1 && self.stream1.drop_frame(typ, header[4]);
+                        if drop {
+                            trace!("h2c drop frame type {} for stream 1", typ);
+                        } else {
+                            self.out.extend_from_slice(header);
+                        }
+                        let left = frame_len(header) - FRAME_HEADER_LEN;
+                        self.write = WriteState::Payload { left, drop };
+                    }
+                }
+                WriteState::Payload { left, drop } => {
+                    let amt = (*left).min(buf.len());
+                    if !*drop {
+                        self.out.extend_from_slice(&buf[..amt]);
+                    }
+                    buf = &buf[amt..];
+                    *left -= amt;
+                    if *left == 0 {
+                        // once the request on stream 1 is dropped, the rest passes.
+                        self.write = if self.stream1 == Stream1::Done {
+                            WriteState::Pass
+                        } else {
+                            WriteState::Header(Vec::with_capacity(FRAME_HEADER_LEN))
+                        };
+                    }
+                }
+                WriteState::Pass => {
+                    self.out.extend_from_slice(buf);
+                    buf = &[];
+                }
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> H2cClientStream<S> {
+    fn poll_drain_out(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while !self.out.is_empty() {
+            let amt = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.out))?;
+            if amt == 0 {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "h2c write zero")).into();
+            }
+            self.out.drain(..amt);
+        }
+        Ok(()).into()
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for H2cClientStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for H2cClientStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.get_mut();
+
+        ready!(this.poll_drain_out(cx))?;
+
+        if let WriteState::Pass = this.write {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+
+        this.filter_write(buf);
+
+        Ok(buf.len()).into()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain_out(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain_out(cx))?;
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_int_prefix() {
+        let mut out = vec![];
+        encode_int(&mut out, 10, 5, 0);
+        assert_eq!(out, vec![10]);
+
+        // RFC 7541 C.1.2
+        let mut out = vec![];
+        encode_int(&mut out, 1337, 5, 0);
+        assert_eq!(out, vec![31, 154, 10]);
+    }
+
+    #[test]
+    #[cfg(feature = "server")]
+    fn settings_header() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("http2-settings", H2C_SETTINGS.parse().unwrap());
+        assert_eq!(
+            decode_settings_header(&headers),
+            Some(vec![0, 2, 0, 0, 0, 0])
+        );
+
+        // not a whole number of settings
+        headers.insert("http2-settings", "AAIAAA".parse().unwrap());
+        assert_eq!(decode_settings_header(&headers), None);
+
+        headers.insert("http2-settings", "AA*AAAAA".parse().unwrap());
+        assert_eq!(decode_settings_header(&headers), None);
+    }
+
+    #[test]
+    #[cfg(feature = "server")]
+    fn merge_settings_frame() {
+        let mut frame = vec![];
+        write_frame_header(&mut frame, 6, FRAME_SETTINGS, 0, 0);
+        frame.extend_from_slice(&[0, 4, 0, 1, 0, 0]);
+
+        let merged = merge_settings(&[0, 2, 0, 0, 0, 0], &frame).unwrap();
+
+        let mut expected = vec![];
+        write_frame_header(&mut expected, 12, FRAME_SETTINGS, 0, 0);
+        expected.extend_from_slice(&[0, 2, 0, 0, 0, 0, 0, 4, 0, 1, 0, 0]);
+
+        assert_eq!(merged, expected);
+    }
+
+    #[test]
+    fn drop_first_stream1_headers() {
+        let mut s = H2cClientStream::new(());
+
+        let mut input = H2_PREFACE.to_vec();
+        // SETTINGS, empty
+        write_frame_header(&mut input, 0, FRAME_SETTINGS, 0, 0);
+        // HEADERS stream 1
+        write_frame_header(&mut input, 3, FRAME_HEADERS, 0x5, 1);
+        input.extend_from_slice(&[1, 2, 3]);
+        // HEADERS stream 3
+        write_frame_header(&mut input, 2, FRAME_HEADERS, 0x5, 3);
+        input.extend_from_slice(&[4, 5]);
+
+        // feed it byte by byte to exercise the state machine
+        for b in &input {
+            s.filter_write(&[*b]);
+        }
+
+        let mut expected = H2_PREFACE.to_vec();
+        write_frame_header(&mut expected, 0, FRAME_SETTINGS, 0, 0);
+        write_frame_header(&mut expected, 2, FRAME_HEADERS, 0x5, 3);
+        expected.extend_from_slice(&[4, 5]);
+
+        assert_eq!(s.out, expected);
+    }
+
+    /// Split into (type, flags, stream id, payload) per frame.
+    #[cfg(feature = "server")]
+    fn frames(mut buf: &[u8]) -> Vec<(u8, u8, u32, &[u8])> {
+        let mut out = vec![];
+        while !buf.is_empty() {
+            let len = frame_len(buf);
+            out.push((buf[3], buf[4], stream_id(buf), &buf[FRAME_HEADER_LEN..len]));
+            buf = &buf[len..];
+        }
+        out
+    }
+
+    #[test]
+    #[cfg(feature = "server")]
+    fn encode_request_small() {
+        let req = http::Request::get("/path?q=1")
+            .header("host", "localhost")
+            .header("connection", "Upgrade, HTTP2-Settings")
+            .header("x-foo", "bar")
+            .body(())
+            .unwrap();
+
+        let out = encode_request_frames(&req, &[]);
+        let frames = frames(&out);
+
+        assert_eq!(frames.len(), 1);
+        let (typ, flags, id, payload) = frames[0];
+        assert_eq!((typ, flags, id), (FRAME_HEADERS, 0x5, 1));
+
+        let mut expected = vec![];
+        encode_literal(&mut expected, b":method", b"GET");
+        encode_literal(&mut expected, b":scheme", b"http");
+        encode_literal(&mut expected, b":path", b"/path?q=1");
+        encode_literal(&mut expected, b":authority", b"localhost");
+        encode_literal(&mut expected, b"x-foo", b"bar");
+        assert_eq!(payload, &expected[..]);
+    }
+
+    #[test]
+    #[cfg(feature = "server")]
+    fn encode_request_continuation() {
+        let big = "x".repeat(MIN_MAX_FRAME_SIZE * 2);
+        let req = http::Request::get("/")
+            .header("x-big", big.as_str())
+            .body(())
+            .unwrap();
+
+        let out = encode_request_frames(&req, &[]);
+        let frames = frames(&out);
+
+        assert_eq!(frames.len(), 3);
+        assert_eq!((frames[0].0, frames[0].1), (FRAME_HEADERS, FLAG_END_STREAM));
+        assert_eq!((frames[1].0, frames[1].1), (FRAME_CONTINUATION, 0));
+        assert_eq!(
+            (frames[2].0, frames[2].1),
+            (FRAME_CONTINUATION, FLAG_END_HEADERS)
+        );
+
+        let block: Vec<u8> = frames.iter().flat_map(|f| f.3.to_vec()).collect();
+        assert!(frames.iter().all(|f| f.2 == 1));
+        assert!(frames.iter().all(|f| f.3.len() <= MIN_MAX_FRAME_SIZE));
+        assert!(block.ends_with(big.as_bytes()));
+    }
+
+    #[test]
+    #[cfg(feature = "server")]
+    fn encode_request_body_data_frames() {
+        let req = http::Request::post("/")
+            .header("content-length", "40000")
+            .body(())
+            .unwrap();
+        let body = vec![42; 40_000];
+
+        let out = encode_request_frames(&req, &body);
+        let frames = frames(&out);
+
+        assert_eq!(frames.len(), 4);
+        // no END_STREAM on HEADERS when there's a body, and no content-length.
+        assert_eq!(
+            (frames[0].0, frames[0].1),
+            (FRAME_HEADERS, FLAG_END_HEADERS)
+        );
+        assert!(!frames[0].3.windows(14).any(|w| w == b"content-length"));
+
+        let data = &frames[1..];
+        assert!(data.iter().all(|f| f.0 == FRAME_DATA && f.2 == 1));
+        assert_eq!(
+            data.iter().map(|f| f.1).collect::<Vec<_>>(),
+            vec![0, 0, FLAG_END_STREAM]
+        );
+        let sent: Vec<u8> = data.iter().flat_map(|f| f.3.to_vec()).collect();
+        assert_eq!(sent, body);
+    }
+
+    #[test]
+    #[cfg(feature = "server")]
+    fn settings_frame_checked() {
+        let mut frame = vec![];
+        write_frame_header(&mut frame, 6, FRAME_SETTINGS, 0, 0);
+        assert_eq!(settings_payload_len(&frame).unwrap(), 6);
+
+        // not SETTINGS
+        let mut frame = vec![];
+        write_frame_header(&mut frame, 6, FRAME_HEADERS, 0, 0);
+        assert!(settings_payload_len(&frame).is_err());
+
+        // not stream 0
+        let mut frame = vec![];
+        write_frame_header(&mut frame, 6, FRAME_SETTINGS, 0, 1);
+        assert!(settings_payload_len(&frame).is_err());
+
+        // too big
+        let mut frame = vec![];
+        write_frame_header(&mut frame, 0xff_ffff, FRAME_SETTINGS, 0, 0);
+        assert!(settings_payload_len(&frame).is_err());
+    }
+
+    #[test]
+    fn drop_stream1_continuation_and_data() {
+        let mut input = H2_PREFACE.to_vec();
+        // HEADERS stream 1, no END_HEADERS, no END_STREAM
+        write_frame_header(&mut input, 2, FRAME_HEADERS, 0, 1);
+        input.extend_from_slice(&[1, 2]);
+        write_frame_header(&mut input, 1, FRAME_CONTINUATION, 0, 1);
+        input.extend_from_slice(&[3]);
+        write_frame_header(&mut input, 1, FRAME_CONTINUATION, FLAG_END_HEADERS, 1);
+        input.extend_from_slice(&[4]);
+        // body over several DATA frames, with another stream in between
+        write_frame_header(&mut input, 2, FRAME_DATA, 0, 1);
+        input.extend_from_slice(&[5, 6]);
+        write_frame_header(&mut input, 1, FRAME_HEADERS, 0x5, 3);
+        input.extend_from_slice(&[7]);
+        write_frame_header(&mut input, 1, FRAME_DATA, FLAG_END_STREAM, 1);
+        input.extend_from_slice(&[8]);
+        // anything for stream 1 after the request passes
+        write_frame_header(&mut input, 4, 0x8, 0, 1);
+        input.extend_from_slice(&[0, 0, 0, 9]);
+
+        let mut expected = H2_PREFACE.to_vec();
+        write_frame_header(&mut expected, 1, FRAME_HEADERS, 0x5, 3);
+        expected.extend_from_slice(&[7]);
+        write_frame_header(&mut expected, 4, 0x8, 0, 1);
+        expected.extend_from_slice(&[0, 0, 0, 9]);
+
+        let mut s = H2cClientStream::new(());
+        s.filter_write(&input);
+        assert_eq!(s.out, expected);
+
+        let mut s = H2cClientStream::new(());
+        for b in &input {
+            s.filter_write(&[*b]);
+        }
+        assert_eq!(s.out, expected);
+    }
+}
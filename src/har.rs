@@ -0,0 +1,463 @@
+//! HAR (HTTP Archive) recording of agent traffic.
+//!
+//! An entry is started for every request sent over a connection, which includes each
+//! redirect and retry. It's completed when the response body is read to the end or
+//! dropped, or when the request fails.
+
+use crate::head_ext::HeaderMapExt;
+use crate::observe::BodyObserver;
+use crate::Error;
+use cookie::Cookie;
+use http::header::HeaderMap;
+use qstring::QString;
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Records the traffic of an [`Agent`] for export as a [HAR 1.2] file.
+///
+/// Every request sent, including redirects and retries, is recorded with headers,
+/// cookies and timings. Bodies are only recorded when turned on with [`bodies`].
+/// Responses served by a [`Cache`] or [`Fixtures`] are not sent, and not recorded.
+///
+/// The export can be loaded in browser devtools and other HAR viewers.
+///
+/// Clones share the recorded entries.
+///
+/// ```no_run
+/// use hreq::prelude::*;
+/// use hreq::{Agent, Har};
+///
+/// let har = Har::new().bodies(true);
+///
+/// let mut agent = Agent::new();
+/// agent.har(har.clone());
+///
+/// let req = Request::get("https://example.com/").with_body(()).unwrap();
+/// agent.send(req).block().unwrap().body_mut().read_and_discard().block().unwrap();
+///
+/// std::fs::write("session.har", har.to_json()).unwrap();
+/// ```
+///
+/// [`Agent`]: struct.Agent.html
+/// [`Cache`]: struct.Cache.html
+/// [`Fixtures`]: struct.Fixtures.html
+/// [`bodies`]: struct.Har.html#method.bodies
+/// [HAR 1.2]: http://www.softwareishard.com/blog/har-12-spec/
+#[derive(Clone, Default)]
+pub struct Har {
+    entries: Arc<Mutex<Vec<Recorded>>>,
+    bodies: bool,
+}
+
+struct Recorded {
+    started: SystemTime,
+    entry: Value,
+}
+
+impl Har {
+    /// Creates a recorder without any entries.
+    ///
+    /// ```
+    /// use hreq::Har;
+    ///
+    /// let har = Har::new();
+    /// ```
+    pub fn new() -> Self {
+        Har::default()
+    }
+
+    /// Whether to record request and response bodies.
+    ///
+    /// Defaults to `false`. The bodies are kept in memory. Request bodies are recorded
+    /// as sent, response bodies after content and charset decoding.
+    pub fn bodies(mut self, enabled: bool) -> Self {
+        self.bodies = enabled;
+        self
+    }
+
+    /// Number of entries recorded so far.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Tells whether no entries are recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all recorded entries.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Exports the recorded entries as HAR 1.2 JSON.
+    ///
+    /// Entries are ordered by the time the requests started.
+    pub fn to_json(&self) -> String {
+        let mut entries: Vec<_> = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| (r.started, r.entry.clone()))
+            .collect();
+
+        entries.sort_by_key(|(started, _)| *started);
+
+        let har = json!({
+            "log": {
+                "version": "1.2",
+                "creator": {
+                    "name": "hreq",
+                    "version": crate::VERSION,
+                },
+                "pages": [],
+                "entries": entries.into_iter().map(|(_, e)| e).collect::<Vec<_>>(),
+            }
+        });
+
+        serde_json::to_string_pretty(&har).expect("Serialize HAR")
+    }
+
+    /// Start an entry for a request about to be sent. The connect time is given for the
+    /// first request on a new connection.
+    pub(crate) fn start(
+        &self,
+        parts: &http::request::Parts,
+        connection: usize,
+        connect_time: Option<Duration>,
+        is_http2: bool,
+    ) -> HarEntry {
+        let started = SystemTime::now() - connect_time.unwrap_or_default();
+
+        let query: Vec<Value> = QString::from(parts.uri.query().unwrap_or(""))
+            .into_pairs()
+            .into_iter()
+            .map(|(name, value)| json!({ "name": name, "value": value }))
+            .collect();
+
+        let cookies: Vec<Value> = parts
+            .headers
+            .get_all("cookie")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|c| Cookie::parse(c.trim().to_string()).ok())
+            .map(|c| json!({ "name": c.name(), "value": c.value() }))
+            .collect();
+
+        let request = json!({
+            "method": parts.method.as_str(),
+            "url": parts.uri.to_string(),
+            "httpVersion": http_version(is_http2),
+            "cookies": cookies,
+            "headers": headers(&parts.headers),
+            "queryString": query,
+            "headersSize": -1,
+        });
+
+        HarEntry(Arc::new(Mutex::new(EntryState {
+            har: self.clone(),
+            started,
+            connection,
+            connect_time,
+            is_http2,
+            start: Instant::now(),
+            request,
+            req_content_type: parts.headers.get_str("content-type").map(String::from),
+            req_body: vec![],
+            req_size: 0,
+            sent: None,
+            response: None,
+            head: None,
+            res_body: vec![],
+            res_size: 0,
+            error: None,
+            done: false,
+        })))
+    }
+}
+
+impl fmt::Debug for Har {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Har")
+    }
+}
+
+/// An entry being recorded, shared between the request and response body.
+#[derive(Clone)]
+pub(crate) struct HarEntry(Arc<Mutex<EntryState>>);
+
+struct EntryState {
+    har: Har,
+    started: SystemTime,
+    connection: usize,
+    connect_time: Option<Duration>,
+    is_http2: bool,
+    /// When the request started being sent, after connecting.
+    start: Instant,
+    request: Value,
+    req_content_type: Option<String>,
+    req_body: Vec<u8>,
+    req_size: u64,
+    /// When the request was fully sent.
+    sent: Option<Instant>,
+    response: Option<Value>,
+    /// When the response head arrived.
+    head: Option<Instant>,
+    res_body: Vec<u8>,
+    res_size: u64,
+    error: Option<String>,
+    done: bool,
+}
+
+impl HarEntry {
+    /// Request body data sent.
+    pub fn sent_data(&self, data: &[u8]) {
+        let mut state = self.0.lock().unwrap();
+        state.req_size += data.len() as u64;
+        if state.har.bodies {
+            state.req_body.extend_from_slice(data);
+        }
+    }
+
+    /// The request is fully sent.
+    pub fn sent(&self) {
+        let mut state = self.0.lock().unwrap();
+        if state.sent.is_none() {
+            state.sent = Some(Instant::now());
+        }
+    }
+
+    /// The response head arrived.
+    pub fn response(&self, parts: &http::response::Parts) {
+        let mut state = self.0.lock().unwrap();
+
+        let now = Instant::now();
+        state.sent.get_or_insert(now);
+        state.head = Some(now);
+
+        let cookies: Vec<Value> = parts
+            .headers
+            .get_all("set-cookie")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .filter_map(|v| Cookie::parse(v.to_string()).ok())
+            .map(|c| {
+                let mut cookie = Map::new();
+                cookie.insert("name".into(), c.name().into());
+                cookie.insert("value".into(), c.value().into());
+                if let Some(path) = c.path() {
+                    cookie.insert("path".into(), path.into());
+                }
+                if let Some(domain) = c.domain() {
+                    cookie.insert("domain".into(), domain.into());
+                }
+                if let Some(http_only) = c.http_only() {
+                    cookie.insert("httpOnly".into(), http_only.into());
+                }
+                if let Some(secure) = c.secure() {
+                    cookie.insert("secure".into(), secure.into());
+                }
+                cookie.into()
+            })
+            .collect();
+
+        let body_size = parts.headers.get_as::<i64>("content-length").unwrap_or(-1);
+
+        state.response = Some(json!({
+            "status": parts.status.as_u16(),
+            "statusText": parts.status.canonical_reason().unwrap_or(""),
+            "httpVersion": http_version(state.is_http2),
+            "cookies": cookies,
+            "headers": headers(&parts.headers),
+            "content": {
+                "mimeType": parts.headers.get_str("content-type").unwrap_or(""),
+            },
+            "redirectURL": parts.headers.get_str("location").unwrap_or(""),
+            "headersSize": -1,
+            "bodySize": body_size,
+        }));
+    }
+
+    /// Response body data read.
+    pub fn received(&self, data: &[u8]) {
+        let mut state = self.0.lock().unwrap();
+        state.res_size += data.len() as u64;
+        if state.har.bodies {
+            state.res_body.extend_from_slice(data);
+        }
+    }
+
+    /// The request failed.
+    pub fn failed(&self, err: &Error) {
+        self.0.lock().unwrap().error = Some(err.to_string());
+        self.finish();
+    }
+
+    /// Complete the entry. Only the first call has any effect.
+    pub fn finish(&self) {
+        self.0.lock().unwrap().finish();
+    }
+}
+
+impl EntryState {
+    fn finish(&mut self) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+
+        let now = Instant::now();
+
+        let sent = self.sent.unwrap_or(now);
+        let head = self.head.unwrap_or(now);
+
+        let connect = self.connect_time.map(millis);
+        let send = millis(sent - self.start);
+        let wait = millis(head - sent);
+        let receive = millis(now - head);
+
+        let time = connect.unwrap_or(0.0) + send + wait + receive;
+
+        let mut request = self.request.clone();
+        request["bodySize"] = self.req_size.into();
+        if self.har.bodies && self.req_size > 0 {
+            request["postData"] = json!({
+                "mimeType": self.req_content_type.as_deref().unwrap_or(""),
+                "text": String::from_utf8_lossy(&self.req_body),
+            });
+        }
+
+        // a failed request has no response, which HAR viewers show as status 0.
+        let mut response = self.response.clone().unwrap_or_else(|| {
+            json!({
+                "status": 0,
+                "statusText": "",
+                "httpVersion": http_version(self.is_http2),
+                "cookies": [],
+                "headers": [],
+                "content": { "mimeType": "" },
+                "redirectURL": "",
+                "headersSize": -1,
+                "bodySize": -1,
+            })
+        });
+        response["content"]["size"] = self.res_size.into();
+        if self.har.bodies && self.res_size > 0 {
+            response["content"]["text"] = String::from_utf8_lossy(&self.res_body).into();
+        }
+
+        let mut entry = json!({
+            "startedDateTime": iso_8601(self.started),
+            "time": time,
+            "request": request,
+            "response": response,
+            "cache": {},
+            "timings": {
+                "blocked": -1,
+                "dns": -1,
+                "connect": connect.unwrap_or(-1.0),
+                "send": send,
+                "wait": wait,
+                "receive": receive,
+                "ssl": -1,
+            },
+            "connection": self.connection.to_string(),
+        });
+
+        if let Some(error) = &self.error {
+            entry["_error"] = error.as_str().into();
+        }
+
+        let recorded = Recorded {
+            started: self.started,
+            entry,
+        };
+
+        self.har.entries.lock().unwrap().push(recorded);
+    }
+}
+
+impl Drop for EntryState {
+    fn drop(&mut self) {
+        // such as when the request future is dropped by a timeout.
+        if !self.done {
+            self.error
+                .get_or_insert_with(|| "Request dropped before completing".into());
+            self.finish();
+        }
+    }
+}
+
+/// Records the response body, and completes the entry when the body is done.
+pub(crate) struct HarBody(pub HarEntry);
+
+impl BodyObserver for HarBody {
+    fn received(&self, data: &[u8]) {
+        self.0.received(data);
+    }
+}
+
+impl Drop for HarBody {
+    fn drop(&mut self) {
+        self.0.finish();
+    }
+}
+
+fn headers(headers: &HeaderMap) -> Value {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            json!({
+                "name": name.as_str(),
+                "value": String::from_utf8_lossy(value.as_bytes()),
+            })
+        })
+        .collect()
+}
+
+fn http_version(is_http2: bool) -> &'static str {
+    if is_http2 {
+        "HTTP/2.0"
+    } else {
+        "HTTP/1.1"
+    }
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+/// Format as `2021-03-04T05:06:07.089Z`.
+fn iso_8601(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+
+    // civil date from days since epoch, by Howard Hinnant.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        since.subsec_millis()
+    )
+}
@@ -0,0 +1,172 @@
+//! In-process transport between an agent and a server, without any sockets.
+
+use crate::client::{ConnectFuture, Connector};
+use crate::proto::Protocol;
+use crate::uri_ext::HostPort;
+use crate::{AsyncRead, AsyncWrite, Stream};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// Max bytes buffered in one direction before writes wait for the reader.
+const MAX_BUFFER: usize = 64 * 1024;
+
+/// Opens connections into a server running in the same process.
+#[derive(Clone)]
+pub(crate) struct InProcess {
+    accept: Arc<dyn Fn(Pipe) + Send + Sync>,
+}
+
+impl InProcess {
+    /// Create from a function that serves the server end of each new connection.
+    pub fn new<F: Fn(Pipe) + Send + Sync + 'static>(accept: F) -> Self {
+        InProcess {
+            accept: Arc::new(accept),
+        }
+    }
+
+    /// Open a new connection, returning the client end.
+    fn open(&self) -> Pipe {
+        let (client, server) = pipe();
+        (self.accept)(server);
+        client
+    }
+}
+
+/// Every connection goes to the server, regardless of host. It talks http/1.1 unless
+/// `force_http2` is set.
+impl Connector for InProcess {
+    fn connect(&self, _host_port: HostPort) -> ConnectFuture {
+        let pipe = self.open();
+        Box::pin(async move { Ok((Box::new(pipe) as Box<dyn Stream>, Protocol::Unknown)) })
+    }
+}
+
+impl fmt::Debug for InProcess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "InProcess")
+    }
+}
+
+/// Pair of connected in-memory streams.
+fn pipe() -> (Pipe, Pipe) {
+    let a = Arc::new(Mutex::new(Half::default()));
+    let b = Arc::new(Mutex::new(Half::default()));
+
+    (
+        Pipe {
+            read: a.clone(),
+            write: b.clone(),
+        },
+        Pipe { read: b, write: a },
+    )
+}
+
+/// One end of an in-memory duplex stream.
+pub(crate) struct Pipe {
+    read: Arc<Mutex<Half>>,
+    write: Arc<Mutex<Half>>,
+}
+
+/// Bytes going in one direction.
+#[derive(Default)]
+struct Half {
+    buf: VecDeque<u8>,
+    /// No more bytes will be written, the reader gets EOF once the buffer is empty.
+    write_closed: bool,
+    /// The reader is gone, further writes fail.
+    read_dropped: bool,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+impl AsyncRead for Pipe {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut half = self.read.lock().unwrap();
+
+        if half.buf.is_empty() {
+            if half.write_closed {
+                return Ok(0).into();
+            }
+            half.read_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let amount = buf.len().min(half.buf.len());
+        for (to, from) in buf.iter_mut().zip(half.buf.drain(..amount)) {
+            *to = from;
+        }
+
+        if let Some(waker) = half.write_waker.take() {
+            waker.wake();
+        }
+
+        Ok(amount).into()
+    }
+}
+
+impl AsyncWrite for Pipe {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let mut half = self.write.lock().unwrap();
+
+        if half.read_dropped || half.write_closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Pipe closed")).into();
+        }
+
+        let amount = buf.len().min(MAX_BUFFER - half.buf.len());
+
+        if amount == 0 && !buf.is_empty() {
+            half.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        half.buf.extend(&buf[..amount]);
+
+        if let Some(waker) = half.read_waker.take() {
+            waker.wake();
+        }
+
+        Ok(amount).into()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Ok(()).into()
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        let mut half = self.write.lock().unwrap();
+
+        half.write_closed = true;
+
+        if let Some(waker) = half.read_waker.take() {
+            waker.wake();
+        }
+
+        Ok(()).into()
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        {
+            let mut half = self.write.lock().unwrap();
+            half.write_closed = true;
+            if let Some(waker) = half.read_waker.take() {
+                waker.wake();
+            }
+        }
+
+        let mut half = self.read.lock().unwrap();
+        half.read_dropped = true;
+        if let Some(waker) = half.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
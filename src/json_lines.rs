@@ -0,0 +1,223 @@
+//! Newline-delimited JSON (NDJSON) streaming of bodies.
+
+use crate::AsyncRead;
+use crate::Body;
+use crate::Error;
+use futures_util::ready;
+use futures_util::stream::Stream;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+pub(crate) const CT_NDJSON: &str = "application/x-ndjson";
+
+const DEFAULT_MAX_LINE_SIZE: usize = 1024 * 1024;
+const READ_SIZE: usize = 16 * 1024;
+
+/// Stream of values parsed from a newline-delimited JSON body.
+///
+/// Created by [`Body::json_lines`]. Each line of the body is parsed into a `T` as soon
+/// as it is read, without buffering the entire body. Blank lines are skipped.
+///
+/// A line that fails to parse is an error item, and the stream continues with the next
+/// line. Failing to read the body, or a line longer than the [max line size], ends
+/// the stream after the error.
+///
+/// [`Body::json_lines`]: struct.Body.html#method.json_lines
+/// [max line size]: struct.JsonLines.html#method.max_line_size
+pub struct JsonLines<'a, T> {
+    body: &'a mut Body,
+    buf: Vec<u8>,
+    // start of the first unread line in buf. read lines are removed on the next fill.
+    start: usize,
+    // position in buf up to which there is no newline.
+    scanned: usize,
+    max_line_size: usize,
+    eof: bool,
+    ended: bool,
+    _ph: PhantomData<fn() -> T>,
+}
+
+impl<'a, T> JsonLines<'a, T> {
+    pub(crate) fn new(body: &'a mut Body) -> Self {
+        JsonLines {
+            body,
+            buf: Vec::new(),
+            start: 0,
+            scanned: 0,
+            max_line_size: DEFAULT_MAX_LINE_SIZE,
+            eof: false,
+            ended: false,
+            _ph: PhantomData,
+        }
+    }
+
+    /// Changes the max size of a single line in bytes. Defaults to 1MB.
+    ///
+    /// A longer line is an error, and ends the stream.
+    pub fn max_line_size(mut self, size: usize) -> Self {
+        self.max_line_size = size;
+        self
+    }
+}
+
+impl<'a, T: DeserializeOwned> JsonLines<'a, T> {
+    /// Range in the buffer of the next complete line, the last line is complete at eof.
+    fn next_line(&mut self) -> Option<Range<usize>> {
+        if let Some(idx) = self.buf[self.scanned..].iter().position(|c| *c == b'\n') {
+            let end = self.scanned + idx;
+            let line = self.start..end;
+            self.start = end + 1;
+            self.scanned = self.start;
+            Some(line)
+        } else if self.eof && self.start < self.buf.len() {
+            let line = self.start..self.buf.len();
+            self.start = self.buf.len();
+            self.scanned = self.start;
+            Some(line)
+        } else {
+            self.scanned = self.buf.len();
+            None
+        }
+    }
+
+    fn poll_fill(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        // drop the lines already read, once per fill rather than per line.
+        if self.start > 0 {
+            self.buf.drain(..self.start);
+            self.scanned -= self.start;
+            self.start = 0;
+        }
+
+        let len = self.buf.len();
+        self.buf.resize(len + READ_SIZE, 0);
+
+        let ret = Pin::new(&mut *self.body).poll_read(cx, &mut self.buf[len..]);
+
+        let amount = match &ret {
+            Poll::Ready(Ok(amount)) => *amount,
+            _ => 0,
+        };
+        self.buf.truncate(len + amount);
+
+        if let Poll::Ready(Ok(0)) = ret {
+            self.eof = true;
+        }
+
+        ret.map_ok(|_| ())
+    }
+}
+
+impl<'a, T: DeserializeOwned> Stream for JsonLines<'a, T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.ended {
+                return None.into();
+            }
+
+            if let Some(range) = this.next_line() {
+                let line = &this.buf[range];
+                if line.iter().all(|c| c.is_ascii_whitespace()) {
+                    continue;
+                }
+                if line.len() > this.max_line_size {
+                    this.ended = true;
+                    return Some(Err(line_too_long(this.max_line_size))).into();
+                }
+                return Some(serde_json::from_slice(line).map_err(Error::from)).into();
+            }
+
+            if this.eof {
+                this.ended = true;
+                continue;
+            }
+
+            if this.buf.len() - this.start > this.max_line_size {
+                this.ended = true;
+                return Some(Err(line_too_long(this.max_line_size))).into();
+            }
+
+            if let Err(e) = ready!(this.poll_fill(cx)) {
+                this.ended = true;
+                return Some(Err(e.into())).into();
+            }
+        }
+    }
+}
+
+impl<'a, T> fmt::Debug for JsonLines<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "JsonLines {{ max_line_size: {} }}", self.max_line_size)
+    }
+}
+
+fn line_too_long(max: usize) -> Error {
+    Error::User(format!("JSON line is longer than {} bytes", max))
+}
+
+/// Reader producing newline-delimited JSON from a stream of values.
+///
+/// The stream is in a `Mutex` to make the reader `Sync`, it's never locked.
+pub(crate) struct JsonLinesReader<S> {
+    stream: Mutex<Pin<Box<S>>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<S> JsonLinesReader<S>
+where
+    S: Stream,
+    S::Item: Serialize,
+{
+    pub fn new(stream: S) -> Self {
+        JsonLinesReader {
+            stream: Mutex::new(Box::pin(stream)),
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl<S> AsyncRead for JsonLinesReader<S>
+where
+    S: Stream,
+    S::Item: Serialize,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if this.pos == this.buf.len() {
+            let stream = this.stream.get_mut().unwrap();
+
+            let next = match ready!(stream.as_mut().poll_next(cx)) {
+                Some(v) => v,
+                None => return Ok(0).into(),
+            };
+
+            this.buf = serde_json::to_vec(&next)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            this.buf.push(b'\n');
+            this.pos = 0;
+        }
+
+        let amount = buf.len().min(this.buf.len() - this.pos);
+        buf[..amount].copy_from_slice(&this.buf[this.pos..this.pos + amount]);
+        this.pos += amount;
+
+        Ok(amount).into()
+    }
+}
@@ -120,13 +120,18 @@
 //!
 //! How to configure the options is explained in [`AsyncRuntime`].
 //!
-//! ## Tokio only
+//! ## async-std and smol
 //!
-//! This project set out with the ambition to be runtime agnostic, specifically
-//! to also support async-std (and/or smol), however in practice that was not
-//! a viable route due to it taking too much work to maintain. Rust is likely
-//! to eventually provide a pluggable runtime mechanic, in which case this
-//! library will try to be agnostic again.
+//! With the cargo features `async-std` or `smol`, there are the additional
+//! flavors `AsyncStd` and `Smol`. These use the runtime's own reactor,
+//! executor and timers for connections, servers, files and timeouts, so an
+//! application on async-std or smol doesn't need to start tokio next to it.
+//! Turn off the default features to drop the tokio runtime altogether, in
+//! which case `AsyncStd` or `Smol` is the default.
+//!
+//! ```toml
+//! hreq = { version = "0.8", default-features = false, features = ["async-std", "gzip", "tls", "server"] }
+//! ```
 //!
 //! # Agent, redirect and retries
 //!
@@ -253,6 +258,12 @@
 //!
 //! Only content types with a mime type `text/*` will be decoded.
 //!
+//! When the response doesn't declare a charset, it is sniffed from a
+//! byte order mark, a `<meta charset>` tag in HTML, the declaration
+//! in XML, or, failing those, guessed from the first 1024 bytes. A
+//! body sending less than that is sniffed after a short wait for more.
+//! Event streams are always utf-8.
+//!
 //! The charset encoding does not need to work only with utf-8.  It
 //! can transcode between different encodings as appropriate.  See
 //! [`charset_encode_source`] and [`charset_decode_target`].
@@ -294,6 +305,19 @@
 //! let body = Body::from_json(&json);
 //! ```
 //!
+//! Other serde formats are used through the [`BodyFormat`] trait. The
+//! cargo features `msgpack` and `cbor` provide MessagePack and CBOR.
+//!
+//! ```
+//! # // ignore this example if not feature msgpack
+//! # #[cfg(feature = "msgpack")] {
+//! use hreq::{Body, MsgPack};
+//!
+//! let body = Body::from_serde::<MsgPack, _>(&vec![1, 2, 3])?;
+//! # }
+//! # Ok::<(), hreq::Error>(())
+//! ```
+//!
 //! # Server
 //!
 //! hreq started as a client but now also got a simple server mechanism. It
@@ -335,11 +359,27 @@
 //! * Follow redirects
 //! * Retry on connection problems
 //! * HTTP/1.1 transfer-encoding chunked
+//! * HTTP/1.1 Upgrade and CONNECT
+//! * HTTP/2 cleartext (h2c) upgrade
 //! * Gzip encode/decode
 //! * Charset encode/decode
+//! * Streaming charset transcoding of any async reader
 //! * Connection pooling
-//! * JSON serialize/deserialize
+//! * JSON serialize/deserialize, MessagePack and CBOR with features
+//! * Streaming newline-delimited JSON
 //! * Cookies
+//! * Response caching (RFC 9111), in memory or on disk
+//! * Per host rate limits and concurrency caps
+//! * Upload and download progress callbacks
+//! * Bandwidth throttling of request and response bodies
+//! * In-process transport from agent to server, without sockets
+//! * Record and replay of requests to fixture files
+//! * HAR (HTTP Archive) export of agent traffic
+//! * Convert requests to and from curl command lines
+//! * Unix domain sockets for client and server
+//! * Pluggable connectors for custom transports
+//! * Granular connect, TLS handshake, response header and body idle timeouts
+//! * Cancellation tokens for in-flight requests
 //!
 //! [http crate]: https://crates.io/crates/http
 //! [`rt-core`]: https://docs.rs/tokio/latest/tokio/runtime/index.html#basic-scheduler
@@ -354,6 +394,7 @@
 //! [`charset_encode_source`]: https://docs.rs/hreq/latest/hreq/trait.RequestBuilderExt.html#tymethod.charset_encode_source
 //! [`charset_decode_target`]: https://docs.rs/hreq/latest/hreq/trait.RequestBuilderExt.html#tymethod.charset_decode_target
 //! [serde]: https://crates.io/crates/serde
+//! [`BodyFormat`]: https://docs.rs/hreq/latest/hreq/trait.BodyFormat.html
 //! [`server module doc`]: https://docs.rs/hreq/latest/hreq/server/index.html
 #[macro_use]
 extern crate log;
@@ -364,20 +405,35 @@ mod body;
 mod body_codec;
 mod body_send;
 mod bw;
+mod cancel;
 mod charset;
 mod client;
 mod deadline;
 mod either;
 mod error;
+mod format;
 mod from_utf8;
+mod h2_config;
+mod h2c;
+mod har;
 mod head_ext;
+#[cfg(feature = "server")]
+mod inproc;
+mod json_lines;
+mod observe;
 mod params;
+mod progress;
 mod proto;
 mod res_ext;
+mod sniff;
+mod throttle;
 mod uninit;
+mod upgrade;
 mod uri_ext;
 
-pub use client::{Agent, ResponseFuture};
+pub use client::{
+    Agent, Cache, ConnectFuture, Connector, CurlExt, Fixtures, RateLimit, ResponseFuture,
+};
 
 #[cfg(feature = "server")]
 pub mod server;
@@ -385,6 +441,7 @@ pub mod server;
 #[cfg(feature = "tls")]
 mod tls;
 
+#[cfg(feature = "tokio")]
 mod tokio_conv;
 
 #[doc(hidden)]
@@ -401,10 +458,24 @@ pub(crate) use futures_io::{AsyncBufRead, AsyncRead, AsyncSeek, AsyncWrite};
 pub use crate::async_impl::AsyncRuntime;
 pub use crate::block_ext::BlockExt;
 pub use crate::body::Body;
+pub use crate::cancel::CancelToken;
+pub use crate::charset::{CharsetError, CharsetReader, Malformed};
 pub use crate::client::RequestBuilderExt;
 pub use crate::client::RequestExt;
-pub use crate::error::Error;
+pub use crate::error::{Error, TimeoutKind};
+#[cfg(feature = "cbor")]
+pub use crate::format::Cbor;
+#[cfg(feature = "msgpack")]
+pub use crate::format::MsgPack;
+pub use crate::format::{BodyFormat, Json};
+pub use crate::h2_config::Http2Config;
+pub use crate::har::Har;
+pub use crate::json_lines::JsonLines;
+pub use crate::progress::{Direction, Progress};
+pub use crate::proto::Protocol;
 pub use crate::res_ext::ResponseExt;
+pub use crate::upgrade::{OnUpgrade, Upgraded};
+pub use crate::uri_ext::HostPort;
 pub use http;
 
 pub mod cookie {
@@ -429,7 +500,7 @@ pub mod prelude {
     //! ```
 
     #[doc(no_inline)]
-    pub use crate::{BlockExt, Body, RequestBuilderExt, RequestExt, ResponseExt};
+    pub use crate::{BlockExt, Body, CurlExt, RequestBuilderExt, RequestExt, ResponseExt};
 
     #[doc(no_inline)]
     pub use http::{Request, Response};
@@ -439,7 +510,13 @@ pub mod prelude {
     pub use crate::server::{ResponseBuilderExt, Router, Server, ServerRequestExt};
 }
 
-pub(crate) trait Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+/// A bidirectional byte stream, such as a TCP socket, hreq can talk http over.
+///
+/// Implemented for anything that is `AsyncRead + AsyncWrite + Unpin + Send + 'static`.
+/// See [`Connector`].
+///
+/// [`Connector`]: trait.Connector.html
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
 impl<Z: AsyncRead + AsyncWrite + Unpin + Send + 'static> Stream for Z {}
 
 pub(crate) trait AsyncReadSeek: AsyncRead + AsyncSeek {}
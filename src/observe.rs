@@ -0,0 +1,10 @@
+//! Observers of response bodies.
+
+/// Observes a response body being read, such as to record it or to hold on to a
+/// resource until the body is done.
+///
+/// Observers are dropped when the body is read to the end, or when the body is dropped.
+pub(crate) trait BodyObserver: Send + Sync {
+    /// Body data read, after content and charset decoding.
+    fn received(&self, _data: &[u8]) {}
+}
@@ -1,11 +1,18 @@
+use crate::cancel::Cancel;
 use crate::deadline::Deadline;
+use crate::format::mime_of;
+use crate::har::Har;
 use crate::head_ext::HeaderMapExt;
+use crate::progress::ProgressFn;
+use crate::sniff::Markup;
+use crate::throttle::Throttle;
 use crate::uri_ext::HostPort;
 use encoding_rs::Encoding;
 use http::Uri;
 use once_cell::sync::Lazy;
 use qstring::QString;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -13,24 +20,37 @@ use std::time::{Duration, Instant};
 #[allow(unused_variables)]
 pub(crate) struct HReqParams {
     pub local_addr: SocketAddr,
-    pub remote_addr: SocketAddr,
+    pub remote_addr: Option<SocketAddr>,
     pub req_start: Option<Instant>,
     pub timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    pub tls_handshake_timeout: Option<Duration>,
+    pub response_header_timeout: Option<Duration>,
+    pub body_idle_timeout: Option<Duration>,
     pub force_http2: bool,
+    pub h2c_upgrade: bool,
     pub charset_tx: CharsetConfig,
     pub charset_rx: CharsetConfig,
     pub content_encode: bool,
     pub content_decode: bool,
     pub redirect_body_buffer: usize,
+    pub redirect_max: Option<u8>,
     pub with_override: Option<Arc<HostPort>>,
+    pub unix_socket: Option<Arc<PathBuf>>,
     pub tls_disable_verify: bool,
     pub prebuffer: bool,
+    pub progress: Option<ProgressFn>,
+    pub har: Option<Har>,
+    pub throttle: Option<Throttle>,
+    pub cancel: Option<Cancel>,
 }
 
 #[derive(Clone, Debug)]
 pub struct CharsetConfig {
     pub source: AutoCharset,
     pub target: AutoCharset,
+    /// Sniff the source encoding of incoming bodies without a declared charset.
+    pub sniff: bool,
 }
 
 impl CharsetConfig {
@@ -91,6 +111,41 @@ impl CharsetConfig {
 
         Some((s_enc, t_enc))
     }
+
+    /// The kind of text to sniff the source encoding of, if sniffing applies.
+    ///
+    /// Only incoming `text/` bodies without a (known) charset in the headers are sniffed.
+    pub fn sniff_markup(
+        &self,
+        is_incoming: bool,
+        headers: &http::header::HeaderMap,
+        override_source: Option<&'static Encoding>,
+    ) -> Option<Markup> {
+        if !self.sniff || !is_incoming || override_source.is_some() {
+            return None;
+        }
+
+        if !matches!(self.source, AutoCharset::Auto) || self.target.is_off() {
+            return None;
+        }
+
+        let declared = charset_from_headers(headers)
+            .map(|s| s.as_bytes())
+            .and_then(Encoding::for_label);
+
+        if declared.is_some() {
+            return None;
+        }
+
+        let mime = mime_of(headers.get_str("content-type")?);
+
+        // event streams are always UTF-8.
+        if !mime.starts_with("text/") || mime == "text/event-stream" {
+            return None;
+        }
+
+        Some(Markup::from_mime(&mime))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -128,24 +183,37 @@ impl HReqParams {
     pub fn new() -> Self {
         HReqParams {
             local_addr: *DEFAULT_ADDR,
-            remote_addr: *DEFAULT_ADDR,
+            remote_addr: None,
             req_start: None,
             timeout: None,
+            connect_timeout: None,
+            tls_handshake_timeout: None,
+            response_header_timeout: None,
+            body_idle_timeout: None,
             force_http2: false,
+            h2c_upgrade: false,
             charset_tx: CharsetConfig {
                 source: AutoCharset::Auto,
                 target: AutoCharset::Auto,
+                sniff: false,
             },
             charset_rx: CharsetConfig {
                 source: AutoCharset::Auto,
                 target: AutoCharset::Auto,
+                sniff: false,
             },
             content_encode: true,
             content_decode: true,
             redirect_body_buffer: 0,
+            redirect_max: None,
             with_override: None,
+            unix_socket: None,
             tls_disable_verify: false,
             prebuffer: true,
+            progress: None,
+            har: None,
+            throttle: None,
+            cancel: None,
         }
     }
 
@@ -175,12 +243,12 @@ fn charset_from_headers(headers: &http::header::HeaderMap) -> Option<&str> {
 
     // text/html; charset=utf-8
     fn after_semi(s: &str) -> Option<&str> {
-        s.split(';').last()
+        s.split(';').next_back()
     }
 
     // charset=utf-8
     fn after_eq(s: &str) -> Option<&str> {
-        s.split('=').last()
+        s.split('=').next_back()
     }
 
     headers
@@ -201,6 +269,8 @@ pub fn resolve_hreq_params(mut parts: http::request::Parts) -> http::request::Pa
     }
     let hreq_params = parts.extensions.get_mut::<HReqParams>().unwrap();
     hreq_params.mark_request_start();
+    // responses to requests are sniffed when lacking a charset.
+    hreq_params.charset_rx.sniff = true;
     parts
 }
 
@@ -217,7 +287,12 @@ impl QueryParams {
     }
 
     fn apply(self, parts: &mut http::request::Parts) {
-        let mut uri_parts = parts.uri.clone().into_parts();
+        parts.uri = self.apply_to_uri(&parts.uri);
+    }
+
+    /// The uri with these query parameters added.
+    pub fn apply_to_uri(&self, uri: &Uri) -> Uri {
+        let mut uri_parts = uri.clone().into_parts();
 
         // Construct new instance of PathAndQuery with our modified query.
         let new_path_and_query = {
@@ -229,8 +304,8 @@ impl QueryParams {
                 .unwrap_or(("", ""));
 
             let mut qs = QString::from(query);
-            for (key, value) in self.params.into_iter() {
-                qs.add_pair((key, value));
+            for (key, value) in &self.params {
+                qs.add_pair((key.as_str(), value.as_str()));
             }
 
             // PathAndQuery has no API for modifying any fields. This seems to be our only
@@ -243,7 +318,6 @@ impl QueryParams {
         // This is good. We can change the PathAndQuery field.
         uri_parts.path_and_query = Some(new_path_and_query);
 
-        let new_uri = Uri::from_parts(uri_parts).unwrap();
-        parts.uri = new_uri;
+        Uri::from_parts(uri_parts).unwrap()
     }
 }
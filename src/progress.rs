@@ -0,0 +1,79 @@
+//! Progress callbacks for request and response bodies.
+
+use std::fmt;
+use std::sync::Arc;
+
+/// Which body a [`Progress`] is for.
+///
+/// [`Progress`]: struct.Progress.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// The request body being sent.
+    Upload,
+    /// The response body being read.
+    Download,
+}
+
+/// Progress of a body transfer, passed to progress callbacks.
+///
+/// Bytes are counted as sent or received, i.e. before any content or charset
+/// decoding. The total is known when the body has a `content-length`.
+///
+/// See [`on_progress`] for requests and [`Agent::on_progress`].
+///
+/// [`on_progress`]: trait.RequestBuilderExt.html#tymethod.on_progress
+/// [`Agent::on_progress`]: struct.Agent.html#method.on_progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Whether this is the request or response body.
+    pub direction: Direction,
+    /// Bytes transferred so far.
+    pub transferred: u64,
+    /// Total bytes, if known.
+    pub total: Option<u64>,
+}
+
+/// A progress callback shared between requests.
+#[derive(Clone)]
+pub(crate) struct ProgressFn(Arc<dyn Fn(Progress) + Send + Sync>);
+
+impl ProgressFn {
+    pub fn new<F: Fn(Progress) + Send + Sync + 'static>(f: F) -> Self {
+        ProgressFn(Arc::new(f))
+    }
+
+    /// Start tracking a transfer.
+    pub fn track(&self, direction: Direction, total: Option<u64>) -> ProgressTracker {
+        ProgressTracker {
+            f: self.clone(),
+            progress: Progress {
+                direction,
+                transferred: 0,
+                total,
+            },
+        }
+    }
+}
+
+impl fmt::Debug for ProgressFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ProgressFn")
+    }
+}
+
+/// Counts the bytes of one transfer, calling the callback as they come.
+#[derive(Debug)]
+pub(crate) struct ProgressTracker {
+    f: ProgressFn,
+    progress: Progress,
+}
+
+impl ProgressTracker {
+    pub fn add(&mut self, amount: usize) {
+        if amount == 0 {
+            return;
+        }
+        self.progress.transferred += amount as u64;
+        (self.f.0)(self.progress);
+    }
+}
@@ -1,8 +1,16 @@
-#[derive(Debug, PartialEq, Eq)]
+/// The http protocol to talk over a connection.
+///
+/// Used by a [`Connector`] to hint what protocol was negotiated, such as via TLS ALPN.
+///
+/// [`Connector`]: trait.Connector.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(unused)]
 pub enum Protocol {
+    /// HTTP/1.1
     Http11,
+    /// HTTP/2
     Http2,
+    /// Not known. hreq uses HTTP/1.1 unless `force_http2` is set.
     Unknown,
 }
 
@@ -13,7 +21,7 @@ pub const ALPN_H2: &[u8] = b"h2";
 
 impl Protocol {
     #[cfg(feature = "tls")]
-    pub fn from_alpn(alpn: Option<&[u8]>) -> Self {
+    pub(crate) fn from_alpn(alpn: Option<&[u8]>) -> Self {
         if let Some(v) = alpn {
             if v.len() == 8 && v == ALPN_H1 {
                 Protocol::Http11
@@ -1,4 +1,5 @@
 use crate::head_ext::HeaderMapExt;
+use crate::OnUpgrade;
 use http::Response;
 use std::str::FromStr;
 
@@ -61,6 +62,28 @@ pub trait ResponseExt {
     /// assert_eq!(res.status().as_u16(), 200);
     /// ```
     fn status_code(&self) -> u16;
+
+    /// Take the connection of a response to an `Upgrade` or `CONNECT` request.
+    ///
+    /// Returns `None` if the request didn't ask for an upgrade, or if the upgrade
+    /// was already taken. The returned future fails if the server refused the upgrade.
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    /// use futures_util::io::AsyncWriteExt;
+    ///
+    /// let mut res = Request::get("http://my-server/chat")
+    ///     .header("connection", "upgrade")
+    ///     .header("upgrade", "my-chat-protocol")
+    ///     .call().block().unwrap();
+    ///
+    /// assert_eq!(res.status_code(), 101);
+    ///
+    /// let mut upgraded = res.on_upgrade().unwrap().block().unwrap();
+    ///
+    /// upgraded.write_all(b"hello").block().unwrap();
+    /// ```
+    fn on_upgrade(&mut self) -> Option<OnUpgrade>;
 }
 
 impl<B> ResponseExt for Response<B> {
@@ -75,4 +98,8 @@ impl<B> ResponseExt for Response<B> {
     fn status_code(&self) -> u16 {
         self.status().as_u16()
     }
+
+    fn on_upgrade(&mut self) -> Option<OnUpgrade> {
+        self.extensions_mut().remove::<OnUpgrade>()
+    }
 }
@@ -1,10 +1,11 @@
 use crate::body::Body;
 use crate::body_codec::BodyImpl;
 use crate::body_send::BodySender;
-use crate::bw::BandwidthMonitor;
+use crate::bw::{BandwidthMonitor, PingEvent};
 use crate::head_ext::HeaderMapExt;
 use crate::params::HReqParams;
 use crate::uninit::UninitBuf;
+use crate::upgrade::is_upgrade_response;
 use crate::Error;
 use crate::AGENT_IDENT;
 use crate::{AsyncRead, AsyncWrite};
@@ -29,6 +30,7 @@ pub(crate) struct Connection<Stream> {
     bw: Option<BandwidthMonitor>,
 }
 
+#[allow(clippy::large_enum_variant)]
 enum Inner<Stream> {
     H1(H1Connection<Stream>),
     H2(H2Connection<Compat<Stream>, Bytes>),
@@ -45,17 +47,17 @@ where
         }
     }
 
-    pub fn new_h2(conn: H2Connection<Compat<Stream>, Bytes>, bw: BandwidthMonitor) -> Self {
+    pub fn new_h2(conn: H2Connection<Compat<Stream>, Bytes>, bw: Option<BandwidthMonitor>) -> Self {
         Connection {
             inner: Inner::H2(conn),
-            bw: Some(bw),
+            bw,
         }
     }
 
     pub async fn accept(
         &mut self,
         local_addr: SocketAddr,
-        remote_addr: SocketAddr,
+        remote_addr: Option<SocketAddr>,
     ) -> Option<Result<(http::Request<Body>, SendResponse), Error>> {
         // cheap clone, either None or a Arc<Mutex<_>>
         let bw_acc = self.bw.clone();
@@ -68,7 +70,17 @@ where
                         Ok(v) => {
                             let (req, send) = v;
 
-                            let (parts, recv) = req.into_parts();
+                            let (mut parts, recv) = req.into_parts();
+
+                            // hreq-h1 parses the authority-form "host:port" of CONNECT
+                            // as a path.
+                            if parts.method == http::Method::CONNECT
+                                && parts.uri.authority().is_none()
+                            {
+                                if let Ok(uri) = parts.uri.path().parse() {
+                                    parts.uri = uri;
+                                }
+                            }
 
                             let body = Body::new(BodyImpl::Http1(recv), None, false);
                             let send = SendResponse::H1(send);
@@ -87,17 +99,30 @@ where
                 trace!("H1 accept incoming end");
             }
             Inner::H2(c) => {
-                let mut bw_acc = bw_acc.expect("h2 requires bandwidth monitor");
+                let mut bw_acc = bw_acc;
 
                 let bw_req = bw_acc.clone();
 
-                // piggy-back the bandwidth monitor on accepting requests from connection
+                // piggy-back the bandwidth monitor and keep-alive on accepting requests
+                // from connection
                 let accept_and_bw = poll_fn(move |cx| {
-                    if let Poll::Ready(window_size) = bw_acc.poll_window_update(cx) {
-                        trace!("Update h2 window size: {}", window_size);
-                        c.set_target_window_size(window_size);
-                        c.set_initial_window_size(window_size)?;
-                    };
+                    if let Some(bw_acc) = &mut bw_acc {
+                        match bw_acc.poll_ping(cx) {
+                            Poll::Ready(PingEvent::WindowUpdate(window_size)) => {
+                                trace!("Update h2 window size: {}", window_size);
+                                c.set_target_window_size(window_size);
+                                c.set_initial_window_size(window_size)?;
+                            }
+                            Poll::Ready(PingEvent::KeepAliveTimeout) => {
+                                // ends the connection
+                                return Poll::Ready(None);
+                            }
+                            Poll::Ready(PingEvent::Failed(e)) => {
+                                return Poll::Ready(Some(Err(e)));
+                            }
+                            Poll::Pending => {}
+                        }
+                    }
                     Pin::new(&mut *c).poll_accept(cx)
                 });
 
@@ -107,10 +132,14 @@ where
                         Ok(v) => {
                             let (req, send) = v;
 
+                            if let Some(bw) = &bw_req {
+                                bw.record_activity();
+                            }
+
                             let (parts, recv) = req.into_parts();
 
                             let body = Body::new(BodyImpl::Http2(recv), None, false);
-                            let send = SendResponse::H2(send);
+                            let send = SendResponse::H2(send, bw_req.clone());
 
                             return Some(Ok(Self::configure(
                                 parts,
@@ -118,7 +147,7 @@ where
                                 local_addr,
                                 remote_addr,
                                 send,
-                                Some(bw_req),
+                                bw_req,
                             )));
                         }
                     }
@@ -133,7 +162,7 @@ where
         mut parts: http::request::Parts,
         mut body: Body,
         local_addr: SocketAddr,
-        remote_addr: SocketAddr,
+        remote_addr: Option<SocketAddr>,
         send: SendResponse,
         bw: Option<BandwidthMonitor>,
     ) -> (http::Request<Body>, SendResponse) {
@@ -154,7 +183,8 @@ where
 
 pub(crate) enum SendResponse {
     H1(H1SendResponse),
-    H2(H2SendResponse<Bytes>),
+    /// The monitor of the connection, if any, passed on to the body sender.
+    H2(H2SendResponse<Bytes>, Option<BandwidthMonitor>),
 }
 
 impl SendResponse {
@@ -170,8 +200,48 @@ impl SendResponse {
         Ok(())
     }
 
+    /// Send the response to an upgrade request.
+    ///
+    /// Returns true if the response agrees to the upgrade, in which case it's sent
+    /// without a body and the connection is to be taken over.
+    pub async fn send_upgrade_response(
+        self,
+        result: Result<http::Response<Body>, Error>,
+        req_params: HReqParams,
+        method: &http::Method,
+    ) -> Result<bool, Error> {
+        let res = match result {
+            Ok(res) if is_upgrade_response(method, res.status()) => res,
+            result => {
+                self.send_response(result, req_params).await?;
+                return Ok(false);
+            }
+        };
+
+        let (mut parts, _) = res.into_parts();
+
+        // 1xx and 2xx to CONNECT must not have content-length/transfer-encoding,
+        // so we only add the usual server headers.
+        parts.headers.remove("content-length");
+        parts.headers.remove("transfer-encoding");
+        configure_server_headers(&mut parts);
+
+        let res = http::Response::from_parts(parts, ());
+
+        match self {
+            SendResponse::H1(send) => {
+                send.send_response(res, true).await?;
+            }
+            SendResponse::H2(..) => {
+                return Err(Error::Proto("Upgrade is not possible over http2".into()));
+            }
+        }
+
+        Ok(true)
+    }
+
     fn is_http2(&self) -> bool {
-        if let SendResponse::H2(_) = self {
+        if let SendResponse::H2(..) = self {
             return true;
         }
         false
@@ -208,12 +278,19 @@ impl SendResponse {
         // this buffer should probably be less than h2 window size
         let mut buf = UninitBuf::with_capacity(START_BUF_SIZE, MAX_BUF_SIZE);
 
+        let throttle = params.throttle.as_ref().map(|t| t.for_body());
+
         if !body.is_definitely_no_body() {
             loop {
                 buf.clear();
 
                 let amount_read = buf.read_from_async(&mut body).await?;
 
+                // Pay off any excess before these bytes go out.
+                if let Some(throttle) = &throttle {
+                    throttle.transferred(amount_read).await;
+                }
+
                 // Ship it to they underlying http1.1/http2 layer.
                 body_send.send_data(&buf[0..amount_read]).await?;
 
@@ -223,7 +300,7 @@ impl SendResponse {
             }
         }
 
-        body_send.send_end().await?;
+        body_send.send_end(body.trailers()).await?;
 
         Ok(())
     }
@@ -234,9 +311,9 @@ impl SendResponse {
                 let send_body = send.send_response(res, false).await?;
                 BodySender::H1(send_body)
             }
-            SendResponse::H2(mut send) => {
+            SendResponse::H2(mut send, bw) => {
                 let send_body = send.send_response(res, false)?;
-                BodySender::H2(send_body)
+                BodySender::H2(send_body, bw)
             }
         })
     }
@@ -248,7 +325,7 @@ impl SendResponse {
 
         let mut body_send = self.do_send(res).await?;
 
-        body_send.send_end().await?;
+        body_send.send_end(None).await?;
 
         Ok(())
     }
@@ -289,6 +366,11 @@ pub(crate) fn configure_response(parts: &mut http::response::Parts, body: &Body,
         }
     }
 
+    configure_server_headers(parts);
+}
+
+/// Ensure server and date headers.
+pub(crate) fn configure_server_headers(parts: &mut http::response::Parts) {
     if parts.headers.get("server").is_none() {
         parts.headers.set("server", &*AGENT_IDENT);
     }
@@ -65,7 +65,7 @@
 //! * `server.at("/user/:userId")` matches `/user/abc123` with path parameter `userId` set to `abc123`.
 //! * `server.at("/user/:userId")` does not math `/user/abc123/hello`.
 //! * `server.at("/user/:userId/*whatever")` matches `/user/abc123/hello` with
-//!    path parameter `userId` set to `abc123` and `whatever` set to `hello`.
+//!   path parameter `userId` set to `abc123` and `whatever` set to `hello`.
 //!
 //! ```
 //! use hreq::prelude::*;
@@ -148,18 +148,28 @@
 //! [`Clone`]: https://doc.rust-lang.org/std/clone/trait.Clone.html
 //! [`path_param()`]: trait.ServerRequestExt.html#tymethod.path_param
 
+use crate::async_impl::{with_runtime, Listener, RuntimeHandle};
 use crate::bw::BandwidthMonitor;
+use crate::h2_config::Http2Config;
+use crate::h2c::{self, is_h2c_upgrade, H2_PREFACE};
+use crate::head_ext::HeaderMapExt;
+use crate::inproc::InProcess;
 use crate::params::resolve_hreq_params;
 use crate::params::HReqParams;
 use crate::proto::Protocol;
+use crate::upgrade::{is_upgrade_request, on_upgrade_pair, parse_req_head, Takeover};
+use crate::upgrade::{Upgraded, MAX_HEAD_SIZE};
 use crate::AsyncRuntime;
 use crate::Body;
 use crate::Error;
 use crate::Stream;
+use futures_util::io::{AsyncReadExt, AsyncWriteExt};
 use peek::Peekable;
 use std::fmt;
 use std::net::SocketAddr;
-use std::sync::Arc;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio_util::compat::FuturesAsyncReadCompatExt;
 
@@ -181,7 +191,7 @@ mod statik;
 #[cfg(feature = "tls")]
 mod tls_config;
 
-use conn::Connection;
+use conn::{Connection, SendResponse};
 use serv_handle::EndFut;
 
 pub use chain::Next;
@@ -198,6 +208,10 @@ pub use statik::Static;
 #[cfg(feature = "tls")]
 pub use tls_config::TlsConfig;
 
+/// Largest request body of an h2c upgrade. It is handed to the http2 layer before any
+/// window updates, so it must fit the default http2 connection window.
+const MAX_H2C_BODY: u64 = 65_535;
+
 /// Server of http requests.
 ///
 /// See module documentation for example.
@@ -205,6 +219,8 @@ pub use tls_config::TlsConfig;
 pub struct Server<State> {
     state: Arc<State>,
     router: Router<State>,
+    h2_config: Http2Config,
+    runtime: Option<RuntimeHandle>,
 }
 
 impl Server<()> {
@@ -223,12 +239,14 @@ where
         Server {
             state: Arc::new(state),
             router: Router::new(),
+            h2_config: Http2Config::new(),
+            runtime: None,
         }
     }
 
     /// Get a reference to the current state.
     pub fn state(&self) -> &State {
-        &*self.state
+        &self.state
     }
 
     /// Configure a route for this server.
@@ -249,6 +267,62 @@ where
         self.router.at(path)
     }
 
+    /// Changes the settings for incoming HTTP/2 connections.
+    ///
+    /// Must be set before the call to `listen`.
+    ///
+    /// ```
+    /// use hreq::prelude::*;
+    /// use hreq::Http2Config;
+    ///
+    /// let mut server = Server::new();
+    ///
+    /// server.http2_config(
+    ///     Http2Config::new()
+    ///         .max_concurrent_streams(100)
+    ///         .max_header_list_size(16 * 1024),
+    /// );
+    /// ```
+    pub fn http2_config(&mut self, config: Http2Config) {
+        self.h2_config = config;
+    }
+
+    /// Uses the given tokio runtime for this server instead of the global [`AsyncRuntime`].
+    ///
+    /// The listener is bound, and connections are accepted and handled, in this runtime.
+    /// Servers without a runtime of their own use the global default.
+    ///
+    /// Must be set before the call to `listen`.
+    ///
+    /// The runtime must be driven for as long as the server runs. A multi threaded
+    /// runtime drives itself, a current thread runtime only runs inside its `block_on`.
+    /// Awaiting the server with `.block()` drives the global runtime, not this one,
+    /// and would wait forever.
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    ///
+    /// let rt = tokio::runtime::Builder::new_current_thread()
+    ///     .enable_all()
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let mut server = Server::new();
+    /// server.runtime(rt.handle().clone());
+    /// server.at("/hello").get(|_: http::Request<Body>| async move { "Hello" });
+    ///
+    /// rt.block_on(async {
+    ///     let (handle, _addr) = server.listen(3000).await.unwrap();
+    ///     handle.keep_alive().await;
+    /// });
+    /// ```
+    ///
+    /// [`AsyncRuntime`]: ../enum.AsyncRuntime.html
+    #[cfg(feature = "tokio")]
+    pub fn runtime(&mut self, handle: tokio::runtime::Handle) {
+        self.runtime = Some(RuntimeHandle::new(handle));
+    }
+
     /// Bind and listen to the port (without TLS).
     ///
     /// The address bound will be `0.0.0.0:<port>`. Use port `0` to get a random port.
@@ -259,7 +333,7 @@ where
     pub async fn listen(&self, port: u16) -> Result<(ServerHandle, SocketAddr), Error> {
         #[cfg(feature = "tls")]
         {
-            Ok(self.do_listen(port, None).await?)
+            self.do_listen(port, None).await
         }
         #[cfg(not(feature = "tls"))]
         {
@@ -281,7 +355,7 @@ where
         config: TlsConfig,
     ) -> Result<(ServerHandle, SocketAddr), Error> {
         let rustls_config = config.into_rustls_config()?;
-        Ok(self.listen_tls_rustls(port, rustls_config).await?)
+        self.listen_tls_rustls(port, rustls_config).await
     }
 
     /// Bind and listen to the port with TLS using a specific Rustls config.
@@ -297,7 +371,7 @@ where
         port: u16,
         tls: rustls::ServerConfig,
     ) -> Result<(ServerHandle, SocketAddr), Error> {
-        Ok(self.do_listen(port, Some(tls)).await?)
+        self.do_listen(port, Some(tls)).await
     }
 
     async fn do_listen(
@@ -308,15 +382,76 @@ where
         // TODO: async dns lookup in those cases where the async impl can do that.
         let bind_addr: SocketAddr = format!("0.0.0.0:{}", port).parse()?;
 
-        let mut listener = AsyncRuntime::listen(bind_addr).await?;
+        let listen = AsyncRuntime::listen(bind_addr);
+        let listener = with_runtime(self.runtime.as_ref(), listen).await?;
+        let local_addr = listener.local_addr()?;
+
+        #[cfg(feature = "tls")]
+        let shut = self.serve(listener, local_addr, tls);
+
+        #[cfg(not(feature = "tls"))]
+        let shut = self.serve(listener, local_addr);
+
+        Ok((shut, local_addr))
+    }
+
+    /// Bind and listen to a Unix domain socket (without TLS).
+    ///
+    /// The socket file is created by this call, and it's an error if the file already
+    /// exists. The file is not removed when the server shuts down.
+    ///
+    /// Unix sockets have no IP address, which means [`remote_addr`] is `None` for the
+    /// requests. Use [`unix_socket`] to send requests to this server.
+    ///
+    /// The internal router is cloned on this call. That means all routes must be added
+    /// already. Routes added after this call will not cause an error, but will not
+    /// be dispatched to either.
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    ///
+    /// async fn start_server() {
+    ///    let mut server = Server::new();
+    ///
+    ///    server.at("/hello").get(|_req| async { "Hello over a socket" });
+    ///
+    ///    let handle = server.listen_unix("/tmp/my-sidecar.sock").await.unwrap();
+    ///
+    ///    handle.keep_alive().await;
+    /// }
+    /// ```
+    ///
+    /// [`remote_addr`]: trait.ServerRequestExt.html#tymethod.remote_addr
+    /// [`unix_socket`]: ../trait.RequestBuilderExt.html#tymethod.unix_socket
+    #[cfg(unix)]
+    pub async fn listen_unix(&self, path: impl AsRef<Path>) -> Result<ServerHandle, Error> {
+        let listen = AsyncRuntime::listen_unix(path.as_ref());
+        let listener = with_runtime(self.runtime.as_ref(), listen).await?;
         let local_addr = listener.local_addr()?;
 
-        let (shut, end) = ServerHandle::new().await;
+        #[cfg(feature = "tls")]
+        let shut = self.serve(listener, local_addr, None);
+
+        #[cfg(not(feature = "tls"))]
+        let shut = self.serve(listener, local_addr);
+
+        Ok(shut)
+    }
+
+    /// Accept connections from the listener in a task of its own.
+    fn serve(
+        &self,
+        mut listener: Listener,
+        local_addr: SocketAddr,
+        #[cfg(feature = "tls")] tls: Option<rustls::ServerConfig>,
+    ) -> ServerHandle {
+        let (shut, end) = ServerHandle::new();
 
         // Driver that is cheap to clone.
         let driver = Arc::new(Driver::new(
             self.router.clone(),
             self.state.clone(),
+            self.h2_config.clone(),
             end.clone(),
         ));
 
@@ -348,7 +483,7 @@ where
                     Ok(v) => {
                         let (stream, remote_addr) = v;
 
-                        trace!("Connection from: {}", remote_addr);
+                        trace!("Connection from: {}", peer(remote_addr));
 
                         // Local clone for this connection.
                         let driver = driver.clone();
@@ -391,14 +526,21 @@ where
             Some(())
         };
 
-        AsyncRuntime::spawn(listen_task);
+        match &self.runtime {
+            Some(rt) => rt.spawn(listen_task),
+            None => AsyncRuntime::spawn(listen_task),
+        }
 
-        Ok((shut, local_addr))
+        shut
     }
 
     /// Manually dispatch a request to this server.
     ///
     /// This is mainly useful for building tests without binding a port.
+    /// To also exercise the client side, such as cookies and redirects, see
+    /// [`Agent::in_process`].
+    ///
+    /// [`Agent::in_process`]: ../struct.Agent.html#method.in_process
     pub async fn handle<B: Into<Body>>(
         &self,
         req: http::Request<B>,
@@ -445,7 +587,11 @@ where
         // 2. make server request using parts/body from 1.
         let (req, server_req_params) = {
             let len = body.content_encoded_length();
+            let trailers = body.trailers().cloned();
             let mut body = Body::from_async_read(body, len);
+            if let Some(trailers) = trailers {
+                body = body.with_trailers(trailers);
+            }
             let params = HReqParams::new();
             body.configure(&params, &parts.headers, true);
             parts.extensions.insert(params.clone());
@@ -480,7 +626,11 @@ where
         let (parts, body) = {
             let len = body.content_encoded_length();
             conn::configure_response(&mut parts, &body, false);
+            let trailers = body.trailers().cloned();
             let mut client_body = Body::from_async_read(body, len);
+            if let Some(trailers) = trailers {
+                client_body = client_body.with_trailers(trailers);
+            }
             client_body.configure(&client_req_params, &parts.headers, true);
             parts.extensions.insert(client_req_params.clone());
             (parts, client_body)
@@ -488,12 +638,58 @@ where
 
         Ok(http::Response::from_parts(parts, body))
     }
+
+    /// Connector for agents to send requests straight into this server.
+    ///
+    /// Like for `listen`, the internal router is cloned on this call.
+    pub(crate) fn in_process(&self) -> InProcess {
+        let (shut, end) = ServerHandle::new();
+
+        let driver = Arc::new(Driver::new(
+            self.router.clone(),
+            self.state.clone(),
+            self.h2_config.clone(),
+            end,
+        ));
+
+        // There is no port to tell, handlers see the loopback address and no remote.
+        let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+
+        // the server works as long as some agent holds on to the connector.
+        let shut = Mutex::new(shut);
+
+        let runtime = self.runtime.clone();
+
+        InProcess::new(move |pipe| {
+            let _keep_alive = &shut;
+
+            let driver = driver.clone();
+
+            let conn_task = async move {
+                #[cfg(feature = "tls")]
+                let ret = driver.connect(pipe, addr, None, None).await;
+
+                #[cfg(not(feature = "tls"))]
+                let ret = driver.connect(pipe, addr, None).await;
+
+                if let Err(e) = ret {
+                    debug!("In-process connection failed: {}", e);
+                }
+            };
+
+            match &runtime {
+                Some(rt) => rt.spawn(conn_task),
+                None => AsyncRuntime::spawn(conn_task),
+            }
+        })
+    }
 }
 
 /// Connects TLS, routes requests and responses.
 struct Driver<State> {
     router: Router<State>,
     state: Arc<State>,
+    h2_config: Http2Config,
     end: EndFut,
 }
 
@@ -501,8 +697,13 @@ impl<State> Driver<State>
 where
     State: Clone + Unpin + Send + Sync + 'static,
 {
-    fn new(router: Router<State>, state: Arc<State>, end: EndFut) -> Self {
-        Driver { router, state, end }
+    fn new(router: Router<State>, state: Arc<State>, h2_config: Http2Config, end: EndFut) -> Self {
+        Driver {
+            router,
+            state,
+            h2_config,
+            end,
+        }
     }
 
     /// Optionally connects the incoming stream in TLS and figures out the protocol
@@ -511,13 +712,13 @@ where
         self: Arc<Self>,
         tcp: impl Stream,
         local_addr: SocketAddr,
-        remote_addr: SocketAddr,
+        remote_addr: Option<SocketAddr>,
         #[cfg(feature = "tls")] config: Option<Arc<rustls::ServerConfig>>,
     ) -> Result<(), Error> {
         //
 
         // Maybe wrap in TLS.
-        let (stream, alpn_proto) = {
+        let (stream, alpn_proto, is_tls) = {
             #[cfg(feature = "tls")]
             {
                 use crate::either::Either;
@@ -526,23 +727,21 @@ where
                 if let Some(config) = config {
                     // wrap in tls
                     let (tls, proto) = wrap_tls_server(tcp, config).await?;
-                    (Either::A(tls), proto)
+                    (Either::A(tls), proto, true)
                 } else {
                     // tls feature on, but not using it.
-                    (Either::B(tcp), Protocol::Unknown)
+                    (Either::B(tcp), Protocol::Unknown, false)
                 }
             }
 
             #[cfg(not(feature = "tls"))]
             {
                 // tls feature is off.
-                (tcp, Protocol::Unknown)
+                (tcp, Protocol::Unknown, false)
             }
         };
 
-        const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
-
-        let mut peek = Peekable::new(stream, H2_PREFACE.len());
+        let mut peek = Peekable::new(stream, H2_PREFACE.len(), MAX_HEAD_SIZE);
 
         // If we don't know what the protocol is by from tls ALPN,
         // we fall back on peeking the incoming bytes for the
@@ -556,16 +755,201 @@ where
                 Protocol::Http11
             };
 
-            trace!("Protocol by peek ({}): {:?}", remote_addr, p);
+            trace!("Protocol by peek ({}): {:?}", peer(remote_addr), p);
             p
         } else {
-            trace!("Protocol by ALPN ({}): {:?}", remote_addr, alpn_proto);
+            trace!("Protocol by ALPN ({}): {:?}", peer(remote_addr), alpn_proto);
             alpn_proto
         };
 
-        Ok(self
-            .handle_incoming(peek, local_addr, remote_addr, proto)
-            .await?)
+        if proto == Protocol::Http11 {
+            // An h2c upgrade switches the connection to http2 before answering the
+            // request, and an upgrade or CONNECT can take over the connection. We peek
+            // the first request to know whether to handle either.
+            let first = match peek.peek_head(MAX_HEAD_SIZE).await? {
+                Some(head) => parse_req_head(head).ok(),
+                None => None,
+            };
+
+            if let Some(req) = first {
+                let headers = req.headers();
+
+                // h2c is only for plain http.
+                if !is_tls && is_h2c_upgrade(headers) {
+                    // The body is read over http/1.1 and handed to the http2 layer, which
+                    // means it must be of known length and fit the initial window.
+                    let max_body = (self.h2_config.server_stream_window() as u64).min(MAX_H2C_BODY);
+
+                    let body_len = if headers.get("transfer-encoding").is_some() {
+                        None
+                    } else {
+                        Some(headers.get_as::<u64>("content-length").unwrap_or(0))
+                            .filter(|len| *len <= max_body)
+                    };
+
+                    match (h2c::decode_settings_header(headers), body_len) {
+                        (Some(settings), Some(body_len)) => {
+                            return self
+                                .handle_h2c(peek, settings, body_len, local_addr, remote_addr)
+                                .await;
+                        }
+                        _ => {
+                            debug!("Ignore h2c upgrade ({}): {}", peer(remote_addr), req.uri());
+                        }
+                    }
+                }
+
+                // The stream is only taken back from the http/1.1 layer after a known
+                // length of body.
+                if is_upgrade_request(req.method(), headers)
+                    && headers.get("transfer-encoding").is_none()
+                {
+                    let body_len = headers.get_as::<u64>("content-length").unwrap_or(0);
+
+                    let takeover = Takeover::new(peek, body_len);
+                    let h1conn = hreq_h1::server::handshake(takeover.clone());
+
+                    return self
+                        .serve(
+                            Connection::new_h1(h1conn),
+                            Some(takeover),
+                            local_addr,
+                            remote_addr,
+                        )
+                        .await;
+                }
+            }
+        }
+
+        self.handle_incoming(peek, local_addr, remote_addr, proto)
+            .await
+    }
+
+    /// Handle an `Upgrade: h2c` request being the first request of an http/1.1 connection.
+    ///
+    /// We read the request body, switch protocol and hand the upgrade request to the http2
+    /// layer as stream 1, which means it's answered over http2.
+    async fn handle_h2c(
+        self: Arc<Self>,
+        stream: impl Stream,
+        settings: Vec<u8>,
+        body_len: u64,
+        local_addr: SocketAddr,
+        remote_addr: Option<SocketAddr>,
+    ) -> Result<(), Error> {
+        // reads stop after the body, where the client sends the connection preface.
+        let takeover = Takeover::new(stream, body_len);
+        let mut h1conn = hreq_h1::server::handshake(takeover.clone());
+
+        let (req, send) = match h1conn.accept().await {
+            Some(next) => next?,
+            None => return Ok(()),
+        };
+
+        let (parts, mut recv) = req.into_parts();
+
+        let mut body = Vec::with_capacity(body_len as usize);
+        let mut buf = [0; 8_192];
+        loop {
+            let amount = recv.read(&mut buf).await?;
+            if amount == 0 {
+                break;
+            }
+            body.extend_from_slice(&buf[..amount]);
+        }
+
+        let req = http::Request::from_parts(parts, ());
+
+        let mut parts = http::Response::builder()
+            .status(http::StatusCode::SWITCHING_PROTOCOLS)
+            .header("connection", "Upgrade")
+            .header("upgrade", "h2c")
+            .body(())?
+            .into_parts()
+            .0;
+        conn::configure_server_headers(&mut parts);
+
+        send.send_response(http::Response::from_parts(parts, ()), true)
+            .await?;
+
+        let mut stream = takeover
+            .take_stream()
+            .ok_or_else(|| Error::Proto("Failed to take over h2c upgraded stream".into()))?;
+        stream.flush().await?;
+
+        trace!(
+            "h2c upgrade ({}): {} {}",
+            peer(remote_addr),
+            req.method(),
+            req.uri()
+        );
+
+        // The client must follow up with the connection preface and a SETTINGS frame.
+        // The upgrade request is injected as stream 1 after those.
+        let mut preface = vec![0; H2_PREFACE.len()];
+        stream.read_exact(&mut preface).await?;
+
+        if preface != H2_PREFACE {
+            return Err(Error::Proto(
+                "Expected http2 preface after h2c upgrade".into(),
+            ));
+        }
+
+        let mut frame = vec![0; 9];
+        stream.read_exact(&mut frame).await?;
+
+        let payload_len = h2c::settings_payload_len(&frame)?;
+        frame.resize(9 + payload_len, 0);
+        stream.read_exact(&mut frame[9..]).await?;
+
+        let mut preamble = preface;
+        preamble.extend_from_slice(&h2c::merge_settings(&settings, &frame)?);
+        preamble.extend_from_slice(&h2c::encode_request_frames(&req, &body));
+
+        let stream = Upgraded::new(stream, preamble);
+
+        self.handle_incoming(stream, local_addr, remote_addr, Protocol::Http2)
+            .await
+    }
+
+    /// Handle an `Upgrade` or `CONNECT` request. Returns true if the connection was upgraded.
+    ///
+    /// If the handler accepts the upgrade, the stream is handed over to the `OnUpgrade`
+    /// in the request. Otherwise the response is sent as usual.
+    async fn handle_upgrade(
+        self: Arc<Self>,
+        next: (http::Request<Body>, SendResponse),
+        takeover: &Takeover,
+    ) -> Result<bool, Error> {
+        let (mut req, send) = next;
+
+        let params = req
+            .extensions()
+            .get::<HReqParams>()
+            .expect("Missing hreq_params in request")
+            .clone();
+
+        let (tx, on_upgrade) = on_upgrade_pair();
+        req.extensions_mut().insert(on_upgrade);
+
+        let method = req.method().clone();
+
+        debug!("Upgrade request {} {}", req.method(), req.uri());
+
+        let result = self.router.run(self.state.clone(), req).await.into_result();
+
+        if !send.send_upgrade_response(result, params, &method).await? {
+            // dropping tx fails the OnUpgrade.
+            takeover.release();
+            return Ok(false);
+        }
+
+        let mut upgraded = takeover.take_stream().expect("Upgraded stream");
+        upgraded.flush().await?;
+
+        tx.send(upgraded);
+
+        Ok(true)
     }
 
     /// Handle all incoming requests from the given stream.
@@ -573,27 +957,22 @@ where
         self: Arc<Self>,
         stream: impl Stream,
         local_addr: SocketAddr,
-        remote_addr: SocketAddr,
+        remote_addr: Option<SocketAddr>,
         proto: Protocol,
     ) -> Result<(), Error> {
         //
 
         // Make h1 or h2 abstraction over the connection.
-        let mut conn = if proto == Protocol::Http2 {
-            const DEFAULT_CONN_WINDOW: u32 = 1024 * 1024;
-            const DEFAULT_STREAM_WINDOW: u32 = 1024 * 1024;
-            const DEFAULT_MAX_FRAME_SIZE: u32 = 16 * 1024;
-
-            let mut builder = h2::server::Builder::default();
-            builder
-                .initial_window_size(DEFAULT_STREAM_WINDOW)
-                .initial_connection_window_size(DEFAULT_CONN_WINDOW)
-                .max_frame_size(DEFAULT_MAX_FRAME_SIZE);
+        let conn = if proto == Protocol::Http2 {
+            let builder = self.h2_config.server_builder();
 
             let mut h2conn = builder.handshake(stream.compat()).await?;
 
-            let pinger = h2conn.ping_pong().expect("ping_pong of h2 conn");
-            let bw = BandwidthMonitor::new(pinger);
+            let window = self.h2_config.server_window();
+
+            let bw = BandwidthMonitor::from_config(&self.h2_config, window, || {
+                h2conn.ping_pong().expect("ping_pong of h2 conn")
+            });
 
             Connection::new_h2(h2conn, bw)
         } else {
@@ -601,7 +980,24 @@ where
             Connection::new_h1(h1conn)
         };
 
-        debug!("Handshake done, waiting for requests: {}", remote_addr);
+        self.serve(conn, None, local_addr, remote_addr).await
+    }
+
+    /// Serve the requests of a connection.
+    ///
+    /// With a takeover, the first request takes over the connection if it's an
+    /// `Upgrade` or `CONNECT` the handler agrees to.
+    async fn serve<S: Stream>(
+        self: Arc<Self>,
+        mut conn: Connection<S>,
+        mut takeover: Option<Takeover>,
+        local_addr: SocketAddr,
+        remote_addr: Option<SocketAddr>,
+    ) -> Result<(), Error> {
+        debug!(
+            "Handshake done, waiting for requests: {}",
+            peer(remote_addr)
+        );
 
         loop {
             // Process each incoming request in turn.
@@ -618,6 +1014,17 @@ where
                 return Ok(());
             };
 
+            if let Some(takeover) = takeover.take() {
+                if is_upgrade_request(next.0.method(), next.0.headers()) {
+                    if self.clone().handle_upgrade(next, &takeover).await? {
+                        trace!("Upgraded connection: {}", peer(remote_addr));
+                        return Ok(());
+                    }
+                    continue;
+                }
+                takeover.release();
+            }
+
             // Cloning the driver is cheap for the inner spawn.
             let driver = self.clone();
 
@@ -665,6 +1072,13 @@ impl<State> fmt::Debug for Server<State> {
     }
 }
 
+/// Remote address for log lines. Unix sockets and in-process connections have none.
+fn peer(remote_addr: Option<SocketAddr>) -> String {
+    remote_addr
+        .map(|a| a.to_string())
+        .unwrap_or_else(|| "no address".into())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
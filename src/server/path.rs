@@ -42,7 +42,7 @@ impl ParsedPath {
             for seg in &self.segments {
                 if let Segment::Wildcard(_, name) = seg {
                     if !name.is_empty() {
-                        let m = cap.name(&name).expect("Path match without param");
+                        let m = cap.name(name).expect("Path match without param");
                         ret.add(&name[..], m.as_str());
                     }
                 }
@@ -1,4 +1,5 @@
 use crate::uninit::UninitBuf;
+use crate::upgrade::find_end_of_head;
 use crate::{AsyncRead, AsyncSeek, AsyncWrite};
 use std::io;
 use std::pin::Pin;
@@ -13,10 +14,10 @@ pub(crate) struct Peekable<S> {
 }
 
 impl<S> Peekable<S> {
-    pub fn new(stream: S, capacity: usize) -> Self {
+    pub fn new(stream: S, capacity: usize, max_size: usize) -> Self {
         Peekable {
             stream,
-            buf: UninitBuf::with_capacity(capacity, capacity),
+            buf: UninitBuf::with_capacity(capacity, max_size),
             idx: 0,
             finished: false,
         }
@@ -46,6 +47,31 @@ impl<S: AsyncRead + Unpin> Peekable<S> {
             }
         }
     }
+
+    /// Peek until the end of an http/1.1 head (`\r\n\r\n`), or `max` bytes.
+    ///
+    /// Returns the entire head, or `None` if the end was not found.
+    pub async fn peek_head(&mut self, max: usize) -> Result<Option<&[u8]>, io::Error> {
+        if self.idx > 0 {
+            panic!("peek_head() before fully reading previous peeked amount");
+        }
+
+        loop {
+            if let Some(end) = find_end_of_head(&self.buf) {
+                return Ok(Some(&self.buf[0..end]));
+            }
+
+            if self.buf.len() >= max || self.finished {
+                return Ok(None);
+            }
+
+            let amt = self.buf.read_from_async(&mut self.stream).await?;
+
+            if amt == 0 {
+                self.finished = true;
+            }
+        }
+    }
 }
 
 impl<S: AsyncRead + Unpin> AsyncRead for Peekable<S> {
@@ -62,7 +88,7 @@ impl<S: AsyncRead + Unpin> AsyncRead for Peekable<S> {
             // max amount we can read from the peeked bytes
             let max = left.min(buf.len());
 
-            (&mut buf[0..max]).copy_from_slice(&this.buf[this.idx..(this.idx + max)]);
+            buf[0..max].copy_from_slice(&this.buf[this.idx..(this.idx + max)]);
 
             this.idx += max;
 
@@ -151,7 +151,7 @@ impl Reply {
 }
 
 #[allow(clippy::unit_arg)]
-impl<'a> From<()> for Reply {
+impl From<()> for Reply {
     fn from(v: ()) -> Self {
         Reply::from(v.into())
     }
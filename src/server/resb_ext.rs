@@ -1,7 +1,10 @@
 //! Extension trait for `http::request::Builder`
 
+use crate::format::BodyFormat;
 use crate::params::{AutoCharset, HReqParams};
+use crate::throttle::Throttle;
 use crate::Body;
+use crate::Error;
 use encoding_rs::Encoding;
 use http::response;
 use http::Response;
@@ -172,6 +175,24 @@ where
     /// Use this toggle to turn this behavior off.
     fn prebuffer_response_body(self, enable: bool) -> Self;
 
+    /// Limit the rate of sending the response body, in bytes per second.
+    ///
+    /// The first second's worth of bytes go in a burst, after that the body is sent at
+    /// the given rate.
+    ///
+    /// ```
+    /// use hreq::prelude::*;
+    ///
+    /// async fn handle(req: http::Request<hreq::Body>) -> http::Response<Vec<u8>> {
+    ///     http::Response::builder()
+    ///         // at most 1MB/s
+    ///         .throttle(1024 * 1024)
+    ///         .body(vec![0; 10 * 1024 * 1024])
+    ///         .unwrap()
+    /// }
+    /// ```
+    fn throttle(self, bytes_per_second: u64) -> Self;
+
     /// Finish building the response by providing an object serializable to JSON.
     ///
     /// Objects made serializable with serde_derive can be automatically turned into
@@ -202,6 +223,40 @@ where
     /// }
     /// ```
     fn with_json<B: Serialize + ?Sized>(self, body: &B) -> http::Result<Response<Body>>;
+
+    /// Finish building the response by providing an object serialized in a [`BodyFormat`].
+    ///
+    /// This sets both `content-type` of the format and `content-length`. Fails if
+    /// the object can't be serialized in the format. The request body is read in a
+    /// format using [`read_as`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hreq::prelude::*;
+    /// use hreq::{Body, Json};
+    /// use serde_derive::{Deserialize, Serialize};
+    ///
+    /// #[derive(Deserialize, Serialize)]
+    /// struct MyThing {
+    ///   name: String,
+    ///   age: String,
+    /// }
+    ///
+    /// async fn handle(mut req: http::Request<Body>) -> Result<http::Response<Body>, hreq::Error> {
+    ///     let thing: MyThing = req.body_mut().read_as::<Json, _>().await?;
+    ///
+    ///     http::Response::builder()
+    ///         .with_serde::<Json, _>(&thing)
+    /// }
+    /// ```
+    ///
+    /// [`BodyFormat`]: ../trait.BodyFormat.html
+    /// [`read_as`]: ../struct.Body.html#method.read_as
+    fn with_serde<F, B>(self, body: &B) -> Result<Response<Body>, Error>
+    where
+        F: BodyFormat,
+        B: Serialize + ?Sized;
 }
 
 impl ResponseBuilderExt for response::Builder {
@@ -243,10 +298,25 @@ impl ResponseBuilderExt for response::Builder {
         })
     }
 
+    fn throttle(self, bytes_per_second: u64) -> Self {
+        with_hreq_params(self, |params| {
+            params.throttle = Some(Throttle::per_body(bytes_per_second));
+        })
+    }
+
     fn with_json<B: Serialize + ?Sized>(self, body: &B) -> http::Result<Response<Body>> {
         let body = Body::from_json(body);
         self.body(body)
     }
+
+    fn with_serde<F, B>(self, body: &B) -> Result<Response<Body>, Error>
+    where
+        F: BodyFormat,
+        B: Serialize + ?Sized,
+    {
+        let body = Body::from_serde::<F, B>(body)?;
+        Ok(self.body(body)?)
+    }
 }
 
 fn get_or_insert<T: Send + Sync + 'static, F: FnOnce() -> T>(
@@ -114,7 +114,12 @@ where
 
     pub(crate) async fn run(&self, state: Arc<State>, mut req: Request<Body>) -> Reply {
         let uri = req.uri();
-        let full_path = uri.path();
+        // CONNECT has an authority-form uri ("host:port") without path, we route those as "/".
+        let full_path = if uri.path().is_empty() {
+            "/"
+        } else {
+            uri.path()
+        };
 
         assert!(full_path.starts_with(&self.prefix));
         let path = full_path.replacen(&self.prefix, "", 1);
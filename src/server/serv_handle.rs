@@ -2,46 +2,40 @@ use std::fmt;
 use std::future::Future;
 use std::sync::Arc;
 
-use hreq_h1::mpsc::{Receiver, Sender};
+use crate::cancel::{Cancel, CancelToken};
 
 /// Handle to a running server.
 ///
 /// The server functions as long as this handle is not dropped.
 pub struct ServerHandle {
-    tx_shutdown: Sender<()>,
-    rx_confirm: Receiver<()>,
+    shutdown: CancelToken,
+    confirm: Cancel,
 }
 
 impl ServerHandle {
-    pub(crate) async fn new() -> (Self, EndFut) {
-        let (tx_shutdown, rx_shutdown) = Receiver::new(1);
-        let (tx_confirm, rx_confirm) = Receiver::new(1);
+    pub(crate) fn new() -> (Self, EndFut) {
+        let shutdown = CancelToken::new();
+        let confirm = CancelToken::new();
 
         (
             ServerHandle {
-                tx_shutdown,
-                rx_confirm,
+                shutdown: shutdown.clone(),
+                confirm: Cancel::new(&confirm),
             },
             EndFut {
-                rx_shutdown,
-                tx_confirm: Arc::new(tx_confirm),
+                shutdown: Cancel::new(&shutdown),
+                confirm: Arc::new(Confirm(confirm)),
             },
         )
     }
 
     /// Signal to the server to close down. Stop listening to the port and exit.
     pub async fn shutdown(self) {
-        // When we drop the tx_shutdown sender, all connected
-        // receivers are woken up and realise it's gone.
-        let ServerHandle {
-            tx_shutdown,
-            rx_confirm,
-        } = self;
-
-        drop(tx_shutdown);
+        // all racing EndFut are woken up and stop.
+        self.shutdown.cancel();
 
         trace!("Await server shutdown confirmation");
-        rx_confirm.recv().await;
+        self.confirm.listen().await;
     }
 
     /// Await this to keep the server alive forever. Will never return.
@@ -51,10 +45,25 @@ impl ServerHandle {
     }
 }
 
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct EndFut {
-    rx_shutdown: Receiver<()>,
-    tx_confirm: Arc<Sender<()>>,
+    shutdown: Cancel,
+    confirm: Arc<Confirm>,
+}
+
+/// Confirms the shutdown when the last EndFut is dropped.
+struct Confirm(CancelToken);
+
+impl Drop for Confirm {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
 }
 
 impl EndFut {
@@ -70,7 +79,7 @@ impl EndFut {
         });
 
         let wait_for_end = Box::pin(async {
-            self.rx_shutdown.recv().await;
+            self.shutdown.listen().await;
             None
         });
 
@@ -84,7 +93,7 @@ impl EndFut {
 
 impl Drop for EndFut {
     fn drop(&mut self) {
-        let count = Arc::strong_count(&self.tx_confirm);
+        let count = Arc::strong_count(&self.confirm);
         trace!("EndFut instances left: {}", count - 1);
     }
 }
@@ -1,8 +1,10 @@
 use super::path::PathMatch;
 use crate::params::{AutoCharset, HReqParams};
 use crate::Body;
+use crate::OnUpgrade;
 use encoding_rs::Encoding;
 use http::Request;
+use std::net::SocketAddr;
 use std::str::FromStr;
 
 /// Extends [`http::Request`] with ergonomic extras for server requests to hreq.
@@ -117,6 +119,71 @@ pub trait ServerRequestExt {
     ///
     /// If we want to keep the body data compressed, we can turn off the default behavior.
     fn content_decode(self, enable: bool) -> Self;
+
+    /// The address of the client that sent the request.
+    ///
+    /// This is `None` for requests over a Unix domain socket, or sent straight into the
+    /// server in the same process, since those have no IP address.
+    ///
+    /// # Example
+    ///
+    ///  ```
+    ///  use hreq::prelude::*;
+    ///
+    ///  async fn whoami(req: http::Request<Body>) -> String {
+    ///     match req.remote_addr() {
+    ///         Some(addr) => format!("Hello {}", addr.ip()),
+    ///         None => "Hello local".into(),
+    ///     }
+    ///  }
+    ///  ```
+    fn remote_addr(&self) -> Option<SocketAddr>;
+
+    /// Take the connection of an `Upgrade` or `CONNECT` request.
+    ///
+    /// The returned future resolves to the raw stream once the handler's response has
+    /// been sent, provided that response is `101 Switching Protocols` (or any `2xx` for
+    /// `CONNECT`). For any other response the future fails.
+    ///
+    /// Returns `None` for requests not asking to upgrade. Upgrades are only possible
+    /// for the first request of an http/1.1 connection, with a body of known length.
+    ///
+    /// # Example
+    ///
+    ///  ```
+    ///  use hreq::prelude::*;
+    ///  use hreq::AsyncRuntime;
+    ///  use futures_util::io::AsyncReadExt;
+    ///
+    ///  async fn start_server() {
+    ///     let mut server = Server::new();
+    ///
+    ///     server.at("/chat").get(chat);
+    ///
+    ///     let (handle, _) = server.listen(3000).await.unwrap();
+    ///
+    ///     handle.keep_alive().await;
+    ///  }
+    ///
+    ///  async fn chat(mut req: http::Request<Body>) -> http::Response<()> {
+    ///     if let Some(on_upgrade) = req.on_upgrade() {
+    ///         AsyncRuntime::spawn(async move {
+    ///             let mut upgraded = on_upgrade.await.unwrap();
+    ///             let mut buf = vec![0; 1024];
+    ///             let n = upgraded.read(&mut buf).await.unwrap();
+    ///             // ...
+    ///         });
+    ///     }
+    ///
+    ///     http::Response::builder()
+    ///         .status(101)
+    ///         .header("connection", "upgrade")
+    ///         .header("upgrade", "my-chat-protocol")
+    ///         .body(())
+    ///         .unwrap()
+    ///  }
+    ///  ```
+    fn on_upgrade(&mut self) -> Option<OnUpgrade>;
 }
 
 impl ServerRequestExt for Request<Body> {
@@ -134,7 +201,7 @@ impl ServerRequestExt for Request<Body> {
         self.extensions()
             .get::<PathMatch>()
             .map(|m| m.all_params())
-            .unwrap_or_else(Vec::new)
+            .unwrap_or_default()
     }
 
     fn charset_decode(self, enable: bool) -> Self {
@@ -176,4 +243,14 @@ impl ServerRequestExt for Request<Body> {
 
         http::Request::from_parts(parts, body)
     }
+
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.extensions()
+            .get::<HReqParams>()
+            .and_then(|p| p.remote_addr)
+    }
+
+    fn on_upgrade(&mut self) -> Option<OnUpgrade> {
+        self.extensions_mut().remove::<OnUpgrade>()
+    }
 }
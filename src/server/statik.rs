@@ -1,9 +1,11 @@
 use super::Reply;
 use crate::head_ext::HeaderMapExt;
+use crate::params::HReqParams;
 use crate::server::handler::Handler;
 use crate::server::limit::ContentLengthRead;
 use crate::server::peek::Peekable;
 use crate::server::{ResponseBuilderExt, ServerRequestExt};
+use crate::throttle::Throttle;
 use crate::AsyncReadSeek;
 use crate::AsyncRuntime;
 use crate::Body;
@@ -26,6 +28,7 @@ use std::time::SystemTime;
 /// * Maps file extension to `content-type` using [mime-guess].
 /// * Guesses character encoding of `text/*` mime types using [chardetng].
 /// * Supports [range requests].
+/// * Optional bandwidth limit shared by all files served.
 ///
 /// # Example
 ///
@@ -57,6 +60,7 @@ pub struct Static {
     root: PathBuf,
     use_path_param: bool,
     index_file: Option<String>,
+    throttle: Option<Throttle>,
 }
 
 impl Static {
@@ -172,6 +176,7 @@ impl Static {
             root,
             use_path_param,
             index_file,
+            throttle: None,
         }
     }
 
@@ -185,6 +190,27 @@ impl Static {
         self
     }
 
+    /// Limit the rate of sending files, in bytes per second.
+    ///
+    /// The limit is shared by all responses of this handler, which keeps many concurrent
+    /// downloads from taking more than the given bandwidth together.
+    ///
+    /// ```no_run
+    /// use hreq::prelude::*;
+    /// use hreq::server::Static;
+    ///
+    /// let mut server = Server::new();
+    ///
+    /// // at most 10MB/s
+    /// server
+    ///     .at("/download/*file")
+    ///     .get(Static::dir("/www/downloads").throttle(10 * 1024 * 1024));
+    /// ```
+    pub fn throttle(mut self, bytes_per_second: u64) -> Self {
+        self.throttle = Some(Throttle::new(bytes_per_second));
+        self
+    }
+
     fn resolve_path(&self, path: Option<&Path>) -> io::Result<PathBuf> {
         // Use the segment from the /*name appended to the dir we use.
         // This could be relative such as `"/path/to/serve"` + `"blah/../foo.txt"`
@@ -194,7 +220,7 @@ impl Static {
         let root_canon = root.canonicalize()?;
 
         if let Some(path) = path {
-            root.push(&path);
+            root.push(path);
         }
 
         // By canonicalizing we remove any `..`. This errors if the file doesn't exist.
@@ -242,7 +268,17 @@ impl Static {
 
         let d = Dispatch::new(absolute, req);
 
-        Ok(d.into_response().await?)
+        let mut res = d.into_response().await?;
+
+        if let Some(throttle) = &self.throttle {
+            let ext = res.extensions_mut();
+            if ext.get::<HReqParams>().is_none() {
+                ext.insert(HReqParams::new());
+            }
+            ext.get_mut::<HReqParams>().unwrap().throttle = Some(throttle.clone());
+        }
+
+        Ok(res)
     }
 }
 
@@ -283,8 +319,9 @@ fn err(status: http::StatusCode, msg: &str) -> http::Response<Body> {
 struct Dispatch {
     file: PathBuf,
     if_modified_since: Option<SystemTime>,
+    if_range: Option<String>,
     is_head: bool,
-    range: Option<(u64, u64)>,
+    range: Option<(u64, Option<u64>)>,
 }
 
 impl Dispatch {
@@ -302,32 +339,31 @@ impl Dispatch {
         // A server MUST ignore a Range header field received with a request method other than GET.
         //
         // Range: bytes=0-1023
+        // Range: bytes=1024-
         let range = if is_get {
             req.headers()
                 .get("range")
                 .and_then(|v| v.to_str().ok())
                 .filter(|v| v.starts_with("bytes="))
                 .map(|v| &v[6..])
-                .and_then(|v| {
-                    if let Some(i) = v.find('-') {
-                        Some((&v[0..i], &v[i + 1..]))
-                    } else {
-                        None
-                    }
-                })
-                .and_then(|(s, e)| match (s.parse::<u64>(), e.parse::<u64>()) {
-                    (Ok(s), Ok(e)) => Some((s, e)),
+                .and_then(|v| v.find('-').map(|i| (&v[0..i], &v[i + 1..])))
+                .and_then(|(s, e)| match (s.parse::<u64>(), e) {
+                    (Ok(s), "") => Some((s, None)),
+                    (Ok(s), e) => e.parse::<u64>().ok().map(|e| (s, Some(e))),
                     _ => None,
                 })
                 // incoming range is end inclusive, internal arithmetic is exclusive.
-                .map(|(s, e)| (s, e + 1))
+                .map(|(s, e)| (s, e.map(|e| e + 1)))
         } else {
             None
         };
 
+        let if_range = req.headers().get_as::<String>("if-range");
+
         Dispatch {
             file,
             if_modified_since,
+            if_range,
             is_head,
             range,
         }
@@ -383,7 +419,7 @@ impl Dispatch {
 
         let read = AsyncRuntime::file_to_reader(file);
         const PEEK_LEN: usize = 1024;
-        let mut peek = Peekable::new(read, PEEK_LEN);
+        let mut peek = Peekable::new(read, PEEK_LEN, PEEK_LEN);
 
         // For text files, we try to guess the character encoding.
         if content_type.starts_with("text/") {
@@ -407,7 +443,18 @@ impl Dispatch {
             .charset_encode(false) // serve text files as is
             .header("last-modified", httpdate::fmt_http_date(modified));
 
-        let (body, res) = self.create_body(length, peek, res).await?;
+        // https://tools.ietf.org/html/rfc7233#section-3.2
+        // The range is only served if the representation is unchanged. We have no
+        // ETag, so only an exact Last-Modified date matches.
+        let range = match &self.if_range {
+            Some(v) if *v != fmt_http_date(modified) => {
+                debug!("If-Range does not match, ignore range: {}", v);
+                None
+            }
+            _ => self.range,
+        };
+
+        let (body, res) = self.create_body(length, range, peek, res).await?;
 
         Ok(res.body(body).unwrap())
     }
@@ -415,6 +462,7 @@ impl Dispatch {
     async fn create_body<Z: AsyncReadSeek + Unpin + Send + Sync + 'static>(
         &self,
         length: u64,
+        range: Option<(u64, Option<u64>)>,
         mut reader: Z,
         mut res: http::response::Builder,
     ) -> io::Result<(Body, http::response::Builder)> {
@@ -422,11 +470,16 @@ impl Dispatch {
             res = res.header("content-length", length.to_string());
 
             Body::empty()
-        } else if let Some((start, end)) = self.range {
+        } else if let Some((start, end)) = range {
+            // open ended range means to the end of the file.
+            let end = end.unwrap_or(length);
+
             if end <= start || start >= length || end > length {
                 debug!("Bad range [{}..{}] of {}", start, end, length);
 
-                res = res.status(http::StatusCode::RANGE_NOT_SATISFIABLE);
+                res = res
+                    .status(http::StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header("content-range", format!("bytes */{}", length));
 
                 Body::empty()
             } else {
@@ -0,0 +1,363 @@
+//! Charset sniffing of bodies without a declared charset.
+//!
+//! Follows the WHATWG encoding sniffing algorithm: BOM first, then a prescan of
+//! up to 1024 bytes for `<meta charset>` in HTML or the declaration in XML,
+//! and lastly guessing using chardetng.
+//!
+//! https://html.spec.whatwg.org/multipage/parsing.html#encoding-sniffing-algorithm
+
+use encoding_rs::Encoding;
+
+/// Number of bytes to prescan.
+pub(crate) const SNIFF_LEN: usize = 1024;
+
+/// The kind of text to sniff, which decides how to prescan.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Markup {
+    Html,
+    Xml,
+    Text,
+}
+
+impl Markup {
+    /// The kind of a `text/*` mime type.
+    pub fn from_mime(mime: &str) -> Self {
+        match mime {
+            "text/html" => Markup::Html,
+            "text/xml" => Markup::Xml,
+            _ => Markup::Text,
+        }
+    }
+}
+
+/// Figures out the encoding of the start of a body.
+///
+/// `is_end` tells whether `buf` is the entire body.
+pub(crate) fn sniff_encoding(buf: &[u8], markup: Markup, is_end: bool) -> &'static Encoding {
+    if let Some((enc, _)) = Encoding::for_bom(buf) {
+        return enc;
+    }
+
+    let buf = &buf[..buf.len().min(SNIFF_LEN)];
+
+    let prescanned = match markup {
+        Markup::Html => prescan_html(buf),
+        Markup::Xml => prescan_xml(buf),
+        Markup::Text => None,
+    };
+
+    if let Some(enc) = prescanned {
+        return enc;
+    }
+
+    let mut det = chardetng::EncodingDetector::new();
+    det.feed(buf, is_end);
+    det.guess(None, true)
+}
+
+/// Label found in the document to encoding, with the adjustments of the spec.
+fn label_to_encoding(label: &[u8]) -> Option<&'static Encoding> {
+    let enc = Encoding::for_label(label)?;
+
+    // a document that can be prescanned as ascii can't be utf-16.
+    if enc == encoding_rs::UTF_16BE || enc == encoding_rs::UTF_16LE {
+        return Some(encoding_rs::UTF_8);
+    }
+
+    if enc == encoding_rs::X_USER_DEFINED {
+        return Some(encoding_rs::WINDOWS_1252);
+    }
+
+    Some(enc)
+}
+
+fn is_space(c: u8) -> bool {
+    matches!(c, b'\t' | b'\n' | b'\x0c' | b'\r' | b' ')
+}
+
+fn starts_with_ci(buf: &[u8], prefix: &[u8]) -> bool {
+    buf.len() >= prefix.len() && buf[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+fn find(buf: &[u8], needle: &[u8]) -> Option<usize> {
+    buf.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Prescan for `<meta charset="...">` or `<meta http-equiv="content-type" content="...">`.
+fn prescan_html(buf: &[u8]) -> Option<&'static Encoding> {
+    let mut i = 0;
+
+    while i < buf.len() {
+        let rest = &buf[i..];
+
+        if rest.starts_with(b"<!--") {
+            // comments can contain anything, also meta tags.
+            i += find(&rest[2..], b"-->")? + 5;
+        } else if starts_with_ci(rest, b"<meta")
+            && rest.get(5).map(|c| is_space(*c) || *c == b'/') == Some(true)
+        {
+            i += 5;
+            let (enc, read) = prescan_meta(&buf[i..]);
+            if enc.is_some() {
+                return enc;
+            }
+            i += read;
+        } else if rest.len() > 1
+            && rest[0] == b'<'
+            && (rest[1].is_ascii_alphabetic() || rest[1] == b'/')
+        {
+            // some other tag, skip its attributes.
+            i += 2;
+            while let Some((_, _, read)) = attribute(&buf[i..]) {
+                i += read;
+            }
+            i += 1;
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"<?") {
+            i += rest.iter().position(|c| *c == b'>')? + 1;
+        } else {
+            i += 1;
+        }
+    }
+
+    None
+}
+
+/// Attributes of a meta tag. Returns the encoding, if any, and the number of bytes read.
+fn prescan_meta(buf: &[u8]) -> (Option<&'static Encoding>, usize) {
+    let mut i = 0;
+
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    let mut charset = None;
+
+    while let Some((name, value, read)) = attribute(&buf[i..]) {
+        i += read;
+
+        match &name[..] {
+            b"http-equiv" if value.eq_ignore_ascii_case(b"content-type") => {
+                got_pragma = true;
+            }
+            b"content" if charset.is_none() => {
+                if let Some(enc) = charset_from_content(&value) {
+                    charset = Some(enc);
+                    need_pragma = Some(true);
+                }
+            }
+            b"charset" if charset.is_none() => {
+                charset = label_to_encoding(&value);
+                need_pragma = Some(false);
+            }
+            _ => {}
+        }
+    }
+
+    let enc = match need_pragma {
+        Some(true) if !got_pragma => None,
+        Some(_) => charset,
+        None => None,
+    };
+
+    (enc, i)
+}
+
+/// One attribute as lowercase name and value, and the number of bytes read.
+///
+/// Returns `None` at the end of the tag.
+fn attribute(buf: &[u8]) -> Option<(Vec<u8>, Vec<u8>, usize)> {
+    let mut i = 0;
+
+    while i < buf.len() && (is_space(buf[i]) || buf[i] == b'/') {
+        i += 1;
+    }
+
+    if i >= buf.len() || buf[i] == b'>' {
+        return None;
+    }
+
+    let mut name = vec![];
+    while i < buf.len() && !is_space(buf[i]) && !matches!(buf[i], b'=' | b'>' | b'/') {
+        name.push(buf[i].to_ascii_lowercase());
+        i += 1;
+    }
+
+    while i < buf.len() && is_space(buf[i]) {
+        i += 1;
+    }
+
+    if i >= buf.len() || buf[i] != b'=' {
+        return Some((name, vec![], i));
+    }
+    i += 1;
+
+    while i < buf.len() && is_space(buf[i]) {
+        i += 1;
+    }
+
+    let mut value = vec![];
+    if i < buf.len() && (buf[i] == b'"' || buf[i] == b'\'') {
+        let quote = buf[i];
+        i += 1;
+        while i < buf.len() && buf[i] != quote {
+            value.push(buf[i].to_ascii_lowercase());
+            i += 1;
+        }
+        i += 1;
+    } else {
+        while i < buf.len() && !is_space(buf[i]) && buf[i] != b'>' {
+            value.push(buf[i].to_ascii_lowercase());
+            i += 1;
+        }
+    }
+
+    Some((name, value, i.min(buf.len())))
+}
+
+/// The charset of a `content` attribute value such as `text/html; charset=utf-8`.
+fn charset_from_content(content: &[u8]) -> Option<&'static Encoding> {
+    let mut i = find(content, b"charset")? + 7;
+
+    while i < content.len() && is_space(content[i]) {
+        i += 1;
+    }
+
+    if content.get(i) != Some(&b'=') {
+        // there might be another "charset" further on.
+        return charset_from_content(&content[i..]);
+    }
+    i += 1;
+
+    while i < content.len() && is_space(content[i]) {
+        i += 1;
+    }
+
+    let rest = &content[i..];
+    let label = match rest.first() {
+        Some(q @ b'"') | Some(q @ b'\'') => {
+            let end = rest[1..].iter().position(|c| c == q)?;
+            &rest[1..end + 1]
+        }
+        _ => {
+            let end = rest
+                .iter()
+                .position(|c| is_space(*c) || *c == b';')
+                .unwrap_or(rest.len());
+            &rest[..end]
+        }
+    };
+
+    label_to_encoding(label)
+}
+
+/// Prescan for `<?xml version="1.0" encoding="...">`.
+fn prescan_xml(buf: &[u8]) -> Option<&'static Encoding> {
+    if !buf.starts_with(b"<?xml") {
+        return None;
+    }
+
+    let decl = &buf[..find(buf, b"?>")?];
+    let mut i = find(decl, b"encoding")? + 8;
+
+    while i < decl.len() && is_space(decl[i]) {
+        i += 1;
+    }
+    if decl.get(i) != Some(&b'=') {
+        return None;
+    }
+    i += 1;
+    while i < decl.len() && is_space(decl[i]) {
+        i += 1;
+    }
+
+    let quote = *decl.get(i).filter(|c| **c == b'"' || **c == b'\'')?;
+    let rest = &decl[i + 1..];
+    let end = rest.iter().position(|c| *c == quote)?;
+
+    label_to_encoding(&rest[..end])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn html(s: &str) -> &'static str {
+        sniff_encoding(s.as_bytes(), Markup::Html, true).name()
+    }
+
+    #[test]
+    fn bom() {
+        let enc = sniff_encoding(b"\xef\xbb\xbf<meta charset=latin1>", Markup::Html, true);
+        assert_eq!(enc.name(), "UTF-8");
+    }
+
+    #[test]
+    fn meta_charset() {
+        assert_eq!(
+            html("<html><head><meta charset=\"Shift_JIS\">"),
+            "Shift_JIS"
+        );
+        assert_eq!(html("<META CHARSET=euc-jp>"), "EUC-JP");
+        assert_eq!(html("<meta charset='utf-16'>"), "UTF-8");
+    }
+
+    #[test]
+    fn meta_http_equiv() {
+        assert_eq!(
+            html("<meta http-equiv=\"Content-Type\" content=\"text/html; charset=iso-8859-2\">"),
+            "ISO-8859-2"
+        );
+        assert_eq!(
+            html("<meta content='text/html; charset=koi8-r' http-equiv='content-type'>"),
+            "KOI8-R"
+        );
+        // content without http-equiv doesn't count.
+        assert_ne!(
+            html("<meta content=\"text/html; charset=koi8-r\">"),
+            "KOI8-R"
+        );
+    }
+
+    #[test]
+    fn meta_in_comment() {
+        assert_eq!(
+            html("<!-- <meta charset=koi8-r> --><meta charset=gbk>"),
+            "GBK"
+        );
+    }
+
+    #[test]
+    fn meta_in_other_attribute() {
+        assert_eq!(
+            html("<div title='<meta charset=koi8-r>'></div><meta charset=big5>"),
+            "Big5"
+        );
+    }
+
+    #[test]
+    fn xml_declaration() {
+        let enc = sniff_encoding(
+            b"<?xml version=\"1.0\" encoding=\"windows-1251\"?><a/>",
+            Markup::Xml,
+            true,
+        );
+        assert_eq!(enc.name(), "windows-1251");
+    }
+
+    #[test]
+    fn guess() {
+        let text = "こんにちは、世界。これは日本語のテキストです。".repeat(4);
+        let (text, _, _) = encoding_rs::SHIFT_JIS.encode(&text);
+        let enc = sniff_encoding(&text, Markup::Html, true);
+        assert_eq!(enc.name(), "Shift_JIS");
+    }
+
+    #[test]
+    fn text_without_bom() {
+        let text = "こんにちは、世界。これは日本語のテキストです。".repeat(4);
+        let (text, _, _) = encoding_rs::SHIFT_JIS.encode(&text);
+        let enc = sniff_encoding(&text, Markup::Text, true);
+        assert_eq!(enc.name(), "Shift_JIS");
+
+        let enc = sniff_encoding(b"\xff\xfeh\0i\0", Markup::Text, true);
+        assert_eq!(enc.name(), "UTF-16LE");
+    }
+}
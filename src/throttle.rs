@@ -0,0 +1,127 @@
+//! Bandwidth throttling of body data.
+
+use crate::AsyncRuntime;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Limits the rate of body bytes sent or read.
+///
+/// Consists of one or more token buckets, which might be shared between several bodies,
+/// such as an agent's budget for all its requests. Every bucket must allow the bytes.
+///
+/// Limits that apply to each body separately get a bucket of their own for every body,
+/// using [`for_body`].
+///
+/// [`for_body`]: #method.for_body
+#[derive(Clone)]
+pub(crate) struct Throttle {
+    buckets: Vec<Limit>,
+}
+
+#[derive(Clone)]
+enum Limit {
+    Shared(Arc<Mutex<Bucket>>),
+    /// Bytes per second, each body gets a new bucket.
+    PerBody(u64),
+}
+
+struct Bucket {
+    bytes_per_second: f64,
+    /// Goes negative when bytes are transferred faster than allowed.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// A bucket allowing a burst of one second's worth of bytes.
+    fn new(bytes_per_second: u64) -> Arc<Mutex<Bucket>> {
+        Arc::new(Mutex::new(Bucket {
+            bytes_per_second: bytes_per_second as f64,
+            tokens: bytes_per_second as f64,
+            updated: Instant::now(),
+        }))
+    }
+}
+
+impl Throttle {
+    /// A limit shared by all bodies using (clones of) this throttle.
+    pub fn new(bytes_per_second: u64) -> Self {
+        assert!(bytes_per_second > 0, "throttle of 0 bytes per second");
+
+        Throttle {
+            buckets: vec![Limit::Shared(Bucket::new(bytes_per_second))],
+        }
+    }
+
+    /// A limit applied to each body separately.
+    pub fn per_body(bytes_per_second: u64) -> Self {
+        assert!(bytes_per_second > 0, "throttle of 0 bytes per second");
+
+        Throttle {
+            buckets: vec![Limit::PerBody(bytes_per_second)],
+        }
+    }
+
+    /// The throttle to use for one body. Per body limits get a new bucket.
+    pub fn for_body(&self) -> Self {
+        let buckets = self
+            .buckets
+            .iter()
+            .map(|l| match l {
+                Limit::Shared(b) => Limit::Shared(b.clone()),
+                Limit::PerBody(bps) => Limit::Shared(Bucket::new(*bps)),
+            })
+            .collect();
+
+        Throttle { buckets }
+    }
+
+    /// Combine with the buckets of another throttle.
+    pub fn and(mut self, other: &Throttle) -> Self {
+        self.buckets.extend(other.buckets.iter().cloned());
+        self
+    }
+
+    /// Count bytes transferred. Returns how long to pause before transferring more.
+    pub fn consume(&self, amount: usize) -> Option<Duration> {
+        let now = Instant::now();
+
+        let mut pause: Option<Duration> = None;
+
+        for limit in &self.buckets {
+            let bucket = match limit {
+                Limit::Shared(b) => b,
+                // only used through for_body()
+                Limit::PerBody(_) => continue,
+            };
+
+            let mut b = bucket.lock().unwrap();
+
+            let earned = (now - b.updated).as_secs_f64() * b.bytes_per_second;
+            b.tokens = (b.tokens + earned).min(b.bytes_per_second) - amount as f64;
+            b.updated = now;
+
+            if b.tokens < 0.0 {
+                let p = Duration::from_secs_f64(-b.tokens / b.bytes_per_second);
+                pause = Some(pause.map(|x| x.max(p)).unwrap_or(p));
+            }
+        }
+
+        pause
+    }
+
+    /// Count bytes transferred, and pause if that's too many.
+    pub async fn transferred(&self, amount: usize) {
+        if let Some(pause) = self.consume(amount) {
+            trace!("Throttle pause: {:?}", pause);
+            AsyncRuntime::timeout(pause).await;
+        }
+    }
+}
+
+impl fmt::Debug for Throttle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Throttle")
+    }
+}
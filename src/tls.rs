@@ -14,12 +14,14 @@ use webpki_roots::TLS_SERVER_ROOTS;
 ///
 /// The TLS certificate will be validated against the (DNS) domain name provided.
 /// Negotiates ALPN and we prefer http2 over http11. The [`protocol`] resulting from
-/// the negotiation is returned with the wrapped stream.
+/// the negotiation is returned with the wrapped stream. With `allow_http2` false, only
+/// http/1.1 is offered.
 ///
 /// [`protocol`]: ../proto/enum.Protocol.html
 pub(crate) async fn wrap_tls_client(
     stream: impl Stream,
     domain: &str,
+    allow_http2: bool,
     tls_disable_verify: bool,
 ) -> Result<(impl Stream, Protocol), Error> {
     //
@@ -35,7 +37,11 @@ pub(crate) async fn wrap_tls_client(
             .set_certificate_verifier(Arc::new(DisabledCertVerified));
     }
 
-    config.alpn_protocols = vec![ALPN_H2.to_owned(), ALPN_H1.to_owned()];
+    config.alpn_protocols = if allow_http2 {
+        vec![ALPN_H2.to_owned(), ALPN_H1.to_owned()]
+    } else {
+        vec![ALPN_H1.to_owned()]
+    };
 
     let config = Arc::new(config);
     let dnsname = DNSNameRef::try_from_ascii_str(domain)?;
@@ -0,0 +1,629 @@
+//! HTTP/1.1 `Upgrade` and `CONNECT`, taking over the raw stream.
+
+use crate::head_ext::HeaderMapExt;
+use crate::Error;
+use crate::Stream;
+use crate::{AsyncRead, AsyncWrite};
+use futures_util::io::{AsyncReadExt, AsyncWriteExt, Take};
+use futures_util::ready;
+use hreq_h1::buf_reader::BufIo;
+use hreq_h1::chunked::ChunkedDecoder;
+use hreq_h1::mpsc::{Receiver, Sender};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// Max size of an http/1.1 request/response head we accept when handling upgrades.
+pub(crate) const MAX_HEAD_SIZE: usize = 16_384;
+
+const MAX_HEADERS: usize = 128;
+
+/// The raw bidirectional stream of an upgraded connection.
+///
+/// Obtained by awaiting an [`OnUpgrade`]. Once upgraded, hreq no longer
+/// speaks http over the connection, the bytes read and written are whatever
+/// protocol the client and server agreed on.
+///
+/// [`OnUpgrade`]: struct.OnUpgrade.html
+pub struct Upgraded {
+    // Mutex to make Upgraded Sync, which makes it possible to use in a Body.
+    stream: Mutex<Box<dyn Stream>>,
+    // Bytes already read from the stream after the http head.
+    leftover: Option<Cursor<Vec<u8>>>,
+}
+
+impl Upgraded {
+    pub(crate) fn new(stream: impl Stream, leftover: Vec<u8>) -> Self {
+        Upgraded {
+            stream: Mutex::new(Box::new(stream)),
+            leftover: if leftover.is_empty() {
+                None
+            } else {
+                Some(Cursor::new(leftover))
+            },
+        }
+    }
+}
+
+/// Future resolving to the [`Upgraded`] stream of an upgraded connection.
+///
+/// On the server side, this is obtained from an incoming request using
+/// [`ServerRequestExt::on_upgrade`]. The future resolves once the handler's
+/// response has been sent, if the response status is `101 Switching Protocols`
+/// (or `2xx` for `CONNECT`).
+///
+/// On the client side, this is obtained from the response using
+/// [`ResponseExt::on_upgrade`]. For a successful upgrade it resolves immediately.
+///
+/// Fails with an error if the connection was not upgraded.
+///
+/// [`Upgraded`]: struct.Upgraded.html
+/// [`ServerRequestExt::on_upgrade`]: server/trait.ServerRequestExt.html#tymethod.on_upgrade
+/// [`ResponseExt::on_upgrade`]: trait.ResponseExt.html#tymethod.on_upgrade
+pub struct OnUpgrade {
+    rx: Receiver<Upgraded>,
+}
+
+/// Sending half of an `OnUpgrade`.
+pub(crate) struct UpgradeSender {
+    tx: Sender<Upgraded>,
+}
+
+/// Create a connected pair to hand over an upgraded stream.
+pub(crate) fn on_upgrade_pair() -> (UpgradeSender, OnUpgrade) {
+    let (tx, rx) = Receiver::new(1);
+    (UpgradeSender { tx }, OnUpgrade { rx })
+}
+
+impl UpgradeSender {
+    pub fn send(self, upgraded: Upgraded) {
+        if !self.tx.send(upgraded) {
+            debug!("OnUpgrade dropped before receiving upgraded stream");
+        }
+    }
+}
+
+impl Future for OnUpgrade {
+    type Output = Result<Upgraded, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        match ready!(Pin::new(&this.rx).poll_recv(cx, true)) {
+            Some(upgraded) => Ok(upgraded).into(),
+            // the sender is dropped without sending, i.e. no upgrade
+            None => Err(Error::Proto("Connection was not upgraded".into())).into(),
+        }
+    }
+}
+
+/// Stream of the http/1.1 layer, which can be taken back for an upgrade.
+///
+/// The http/1.1 layer reads in chunks, which means it could read past the request or
+/// response taking over the connection. Reads stop after the first head and the given
+/// length of body, until the stream is either taken or released back to http/1.1.
+#[derive(Clone)]
+pub(crate) struct Takeover(Arc<Mutex<TakeoverInner>>);
+
+struct TakeoverInner {
+    // None once taken.
+    stream: Option<Box<dyn Stream>>,
+    // bytes of the "\r\n\r\n" ending the head matched so far.
+    matched: usize,
+    head_len: usize,
+    body_len: u64,
+    // body bytes left to read, set once the head is read.
+    body_left: Option<u64>,
+    // bytes read past the stop.
+    held: Vec<u8>,
+    released: bool,
+    waker: Option<Waker>,
+}
+
+impl Takeover {
+    /// Stream that stops after one head and `body_len` bytes of body.
+    pub fn new(stream: impl Stream, body_len: u64) -> Self {
+        Takeover(Arc::new(Mutex::new(TakeoverInner {
+            stream: Some(Box::new(stream)),
+            matched: 0,
+            head_len: 0,
+            body_len,
+            body_left: None,
+            held: Vec::new(),
+            released: false,
+            waker: None,
+        })))
+    }
+
+    /// Take the stream, with the bytes read past the stop.
+    ///
+    /// Reads of the http/1.1 layer end, and writes fail, after this.
+    pub fn take_stream(&self) -> Option<Upgraded> {
+        let mut inner = self.0.lock().unwrap();
+
+        if inner.released || inner.body_left != Some(0) {
+            return None;
+        }
+
+        let stream = inner.stream.take()?;
+        let leftover = std::mem::take(&mut inner.held);
+
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+
+        Some(Upgraded::new(stream, leftover))
+    }
+
+    /// Hand the stream back to the http/1.1 layer, which reads on past the stop.
+    pub fn release(&self) {
+        let mut inner = self.0.lock().unwrap();
+
+        inner.released = true;
+
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl TakeoverInner {
+    /// Moves past the read bytes, returns how many of them are before the stop.
+    fn advance(&mut self, buf: &[u8]) -> usize {
+        let mut pos = 0;
+
+        while self.body_left.is_none() && pos < buf.len() {
+            let c = buf[pos];
+            pos += 1;
+            self.head_len += 1;
+
+            self.matched = if c == END_OF_HEAD[self.matched] {
+                self.matched + 1
+            } else if c == b'\r' {
+                1
+            } else {
+                0
+            };
+
+            if self.matched == END_OF_HEAD.len() {
+                self.body_left = Some(self.body_len);
+            }
+        }
+
+        if let Some(left) = &mut self.body_left {
+            let amount = ((buf.len() - pos) as u64).min(*left);
+            *left -= amount;
+            pos += amount as usize;
+        }
+
+        pos
+    }
+}
+
+const END_OF_HEAD: &[u8] = b"\r\n\r\n";
+
+impl AsyncRead for Takeover {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut inner = self.0.lock().unwrap();
+        let inner = &mut *inner;
+
+        let stream = match &mut inner.stream {
+            Some(v) => v,
+            // taken over, this looks like the end to the http/1.1 layer.
+            None => return Ok(0).into(),
+        };
+
+        if inner.released {
+            if !inner.held.is_empty() {
+                let amount = buf.len().min(inner.held.len());
+                buf[..amount].copy_from_slice(&inner.held[..amount]);
+                inner.held.drain(..amount);
+                return Ok(amount).into();
+            }
+
+            return Pin::new(&mut **stream).poll_read(cx, buf);
+        }
+
+        if inner.body_left == Some(0) {
+            // wait for take() or release()
+            inner.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let amount = ready!(Pin::new(&mut **stream).poll_read(cx, buf))?;
+
+        let before_stop = inner.advance(&buf[..amount]);
+        inner.held.extend_from_slice(&buf[before_stop..amount]);
+
+        if inner.body_left.is_none() && inner.head_len > MAX_HEAD_SIZE {
+            // not a head we can handle, leave it to the http/1.1 layer.
+            debug!("No http head found in {} bytes", MAX_HEAD_SIZE);
+            inner.released = true;
+        }
+
+        Ok(before_stop).into()
+    }
+}
+
+impl AsyncWrite for Takeover {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let mut inner = self.0.lock().unwrap();
+        match &mut inner.stream {
+            Some(stream) => Pin::new(&mut **stream).poll_write(cx, buf),
+            None => Err(taken_over()).into(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        let mut inner = self.0.lock().unwrap();
+        match &mut inner.stream {
+            Some(stream) => Pin::new(&mut **stream).poll_flush(cx),
+            None => Ok(()).into(),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        let mut inner = self.0.lock().unwrap();
+        match &mut inner.stream {
+            Some(stream) => Pin::new(&mut **stream).poll_close(cx),
+            None => Ok(()).into(),
+        }
+    }
+}
+
+fn taken_over() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "Connection is upgraded")
+}
+
+/// Tells whether the request asks for the connection to be taken over.
+///
+/// That is either a `CONNECT` or a request with `connection: upgrade` and
+/// an `upgrade` header.
+pub(crate) fn is_upgrade_request(method: &http::Method, headers: &http::HeaderMap) -> bool {
+    if method == http::Method::CONNECT {
+        return true;
+    }
+
+    let conn_upgrade = headers
+        .get_all("connection")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim().eq_ignore_ascii_case("upgrade"));
+
+    conn_upgrade && headers.get("upgrade").is_some()
+}
+
+/// Tells whether the response status means the connection is taken over.
+pub(crate) fn is_upgrade_response(method: &http::Method, status: http::StatusCode) -> bool {
+    if method == http::Method::CONNECT {
+        status.is_success()
+    } else {
+        status == http::StatusCode::SWITCHING_PROTOCOLS
+    }
+}
+
+// hreq-h1 can't send `CONNECT`, which means the client writes the request and reads
+// the response itself.
+
+/// Read an http/1.1 head from the stream.
+///
+/// Returns the head (including the final `\r\n\r\n`) and any extra bytes read beyond it.
+pub(crate) async fn read_head<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let mut buf = vec![0_u8; MAX_HEAD_SIZE];
+    let mut len = 0;
+
+    loop {
+        if let Some(end) = find_end_of_head(&buf[..len]) {
+            let leftover = buf[end..len].to_vec();
+            buf.truncate(end);
+            return Ok((buf, leftover));
+        }
+
+        if len == buf.len() {
+            return Err(Error::Proto(format!(
+                "No http head found in {} bytes",
+                MAX_HEAD_SIZE
+            )));
+        }
+
+        let amt = stream.read(&mut buf[len..]).await?;
+
+        if amt == 0 {
+            return Err(
+                io::Error::new(io::ErrorKind::UnexpectedEof, "EOF before http head").into(),
+            );
+        }
+
+        len += amt;
+    }
+}
+
+/// Parse a complete http/1.1 response head.
+pub(crate) fn parse_res_head(head: &[u8]) -> Result<http::Response<()>, Error> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut parser = httparse::Response::new(&mut headers);
+
+    let status = parser.parse(head).map_err(Error::Http11Parser)?;
+
+    if status.is_partial() {
+        return Err(Error::Proto("Partial http response head".into()));
+    }
+
+    let mut bld = http::Response::builder()
+        .status(parser.code.unwrap_or(500))
+        .version(version_of(parser.version));
+
+    for h in parser.headers.iter() {
+        bld = bld.header(h.name, h.value);
+    }
+
+    Ok(bld.body(())?)
+}
+
+/// Write the head of a `CONNECT` request.
+#[allow(clippy::write_with_newline)]
+pub(crate) fn write_connect_head(req: &http::Request<()>) -> Result<Vec<u8>, Error> {
+    let mut w = Vec::with_capacity(1024);
+
+    // CONNECT uses the authority-form.
+    let target = req
+        .uri()
+        .authority()
+        .map(|a| a.as_str())
+        .ok_or_else(|| Error::User(format!("CONNECT without authority: {}", req.uri())))?;
+
+    write!(w, "CONNECT {} HTTP/1.1\r\n", target)?;
+
+    if req.headers().get("host").is_none() {
+        if let Some(auth) = req.uri().authority() {
+            write!(w, "host: {}\r\n", auth.as_str())?;
+        }
+    }
+
+    write_headers(&mut w, req.headers())?;
+
+    Ok(w)
+}
+
+#[allow(clippy::write_with_newline)]
+fn write_headers(w: &mut Vec<u8>, headers: &http::HeaderMap) -> io::Result<()> {
+    for (name, value) in headers {
+        write!(w, "{}: ", name)?;
+        Write::write_all(w, value.as_bytes())?;
+        write!(w, "\r\n")?;
+    }
+    write!(w, "\r\n")
+}
+
+/// Write all of `buf` to the stream and flush.
+pub(crate) async fn write_all<S: AsyncWrite + Unpin>(stream: &mut S, buf: &[u8]) -> io::Result<()> {
+    stream.write_all(buf).await?;
+    stream.flush().await
+}
+
+/// Body of a response refusing an upgrade, read straight off the connection.
+///
+/// The connection is closed after such a response, which means a body
+/// without length is read until the connection ends.
+pub(crate) enum RefusedBody {
+    Length(Take<Upgraded>),
+    Chunked(ChunkedDecoder, BufIo<Upgraded>),
+    Close(Upgraded),
+}
+
+impl RefusedBody {
+    pub fn new(headers: &http::HeaderMap, no_body: bool, stream: Upgraded) -> (Self, Option<u64>) {
+        let is_chunked = headers
+            .get_str("transfer-encoding")
+            .map(|v| v.to_ascii_lowercase().contains("chunked"))
+            .unwrap_or(false);
+
+        if no_body {
+            (RefusedBody::Length(stream.take(0)), Some(0))
+        } else if is_chunked {
+            let bufio = BufIo::with_capacity(MAX_HEAD_SIZE, stream);
+            (RefusedBody::Chunked(ChunkedDecoder::new(), bufio), None)
+        } else if let Some(len) = headers.get_as::<u64>("content-length") {
+            (RefusedBody::Length(stream.take(len)), Some(len))
+        } else {
+            (RefusedBody::Close(stream), None)
+        }
+    }
+}
+
+impl AsyncRead for RefusedBody {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            RefusedBody::Length(r) => Pin::new(r).poll_read(cx, buf),
+            RefusedBody::Chunked(dec, bufio) => {
+                if dec.is_end() {
+                    return Ok(0).into();
+                }
+                dec.poll_read(cx, bufio, buf)
+            }
+            RefusedBody::Close(r) => Pin::new(r).poll_read(cx, buf),
+        }
+    }
+}
+
+/// Index right after the `\r\n\r\n` ending an http head.
+pub(crate) fn find_end_of_head(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4)
+}
+
+/// Parse a complete http/1.1 request head.
+#[cfg(feature = "server")]
+pub(crate) fn parse_req_head(head: &[u8]) -> Result<http::Request<()>, Error> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut parser = httparse::Request::new(&mut headers);
+
+    let status = parser.parse(head).map_err(Error::Http11Parser)?;
+
+    if status.is_partial() {
+        return Err(Error::Proto("Partial http request head".into()));
+    }
+
+    let method = parser.method.unwrap_or("GET");
+    let target = parser.path.unwrap_or("/");
+
+    let mut bld = http::Request::builder()
+        .method(method)
+        // CONNECT uses authority-form "host:port", which http::Uri parses as is.
+        .uri(target)
+        .version(version_of(parser.version));
+
+    for h in parser.headers.iter() {
+        bld = bld.header(h.name, h.value);
+    }
+
+    Ok(bld.body(())?)
+}
+
+fn version_of(v: Option<u8>) -> http::Version {
+    if v == Some(0) {
+        http::Version::HTTP_10
+    } else {
+        http::Version::HTTP_11
+    }
+}
+
+impl AsyncRead for Upgraded {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        // bytes read past the http head go first.
+        if let Some(leftover) = &mut this.leftover {
+            let amt = leftover.read(buf)?;
+
+            if leftover.position() == leftover.get_ref().len() as u64 {
+                this.leftover = None;
+            }
+
+            if amt > 0 {
+                return Ok(amt).into();
+            }
+        }
+
+        let stream = this.stream.get_mut().unwrap();
+        Pin::new(&mut **stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Upgraded {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let stream = self.get_mut().stream.get_mut().unwrap();
+        Pin::new(&mut **stream).poll_write(cx, buf)
+    }
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        bufs: &[io::IoSlice],
+    ) -> Poll<Result<usize, io::Error>> {
+        let stream = self.get_mut().stream.get_mut().unwrap();
+        Pin::new(&mut **stream).poll_write_vectored(cx, bufs)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        let stream = self.get_mut().stream.get_mut().unwrap();
+        Pin::new(&mut **stream).poll_flush(cx)
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        let stream = self.get_mut().stream.get_mut().unwrap();
+        Pin::new(&mut **stream).poll_close(cx)
+    }
+}
+
+impl fmt::Debug for Upgraded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Upgraded")
+    }
+}
+
+impl fmt::Debug for Takeover {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Takeover")
+    }
+}
+
+impl fmt::Debug for OnUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OnUpgrade")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn upgrade_request() {
+        let req = http::Request::get("/")
+            .header("connection", "keep-alive, Upgrade")
+            .header("upgrade", "foo")
+            .body(())
+            .unwrap();
+        assert!(is_upgrade_request(req.method(), req.headers()));
+
+        let req = http::Request::get("/")
+            .header("upgrade", "foo")
+            .body(())
+            .unwrap();
+        assert!(!is_upgrade_request(req.method(), req.headers()));
+
+        let req = http::Request::connect("example.com:443").body(()).unwrap();
+        assert!(is_upgrade_request(req.method(), req.headers()));
+    }
+
+    #[test]
+    #[cfg(feature = "server")]
+    fn connect_head_roundtrip() {
+        let req = http::Request::connect("example.com:443").body(()).unwrap();
+        let head = write_connect_head(&req).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&head).unwrap(),
+            "CONNECT example.com:443 HTTP/1.1\r\nhost: example.com:443\r\n\r\n"
+        );
+        let parsed = parse_req_head(&head).unwrap();
+        assert_eq!(parsed.method(), http::Method::CONNECT);
+        assert_eq!(parsed.uri().authority().unwrap(), "example.com:443");
+    }
+
+    #[test]
+    fn takeover_stops_after_head_and_body() {
+        let takeover = Takeover::new(futures_util::io::Cursor::new(vec![]), 3);
+        let mut inner = takeover.0.lock().unwrap();
+
+        assert_eq!(inner.advance(b"GET / HTTP/1.1\r\nhost: a\r"), 24);
+        assert_eq!(inner.body_left, None);
+        assert_eq!(inner.advance(b"\n\r\nabcdef"), 6);
+        assert_eq!(inner.body_left, Some(0));
+    }
+}
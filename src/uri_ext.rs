@@ -55,7 +55,7 @@ impl UriExt for http::Uri {
 
         // Special case when the redirect is just a scheme.
         if from.ends_with("://") {
-            if let Ok(scheme) = (&from[..(from.len() - 3)]).parse() {
+            if let Ok(scheme) = from[..(from.len() - 3)].parse() {
                 let mut parts = self.clone().into_parts();
                 parts.scheme = Some(scheme);
 
@@ -182,6 +182,9 @@ impl UriExt for http::Uri {
     }
 }
 
+/// Host, port and whether to use TLS, for where to connect to.
+///
+/// Displays as `host:port`.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct HostPort {
     host: String,
@@ -190,6 +193,7 @@ pub struct HostPort {
 }
 
 impl HostPort {
+    /// Creates a new instance.
     pub fn new(host: &str, port: u16, tls: bool) -> Self {
         HostPort {
             host: host.to_string(),
@@ -200,7 +204,7 @@ impl HostPort {
 }
 
 impl HostPort {
-    pub fn from_uri(uri: &http::Uri) -> Result<Self, Error> {
+    pub(crate) fn from_uri(uri: &http::Uri) -> Result<Self, Error> {
         let scheme = uri
             .scheme()
             .unwrap_or_else(|| {
@@ -229,11 +233,17 @@ impl HostPort {
         Ok(hostport)
     }
 
-    #[cfg(feature = "tls")]
+    /// The host name or IP address.
     pub fn host(&self) -> &str {
         &self.host
     }
 
+    /// The port.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Whether the connection is to use TLS, i.e. for `https` uris.
     pub fn is_tls(&self) -> bool {
         self.is_tls
     }
@@ -0,0 +1,445 @@
+use hreq::prelude::*;
+use hreq::{Agent, Cache, Error};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+mod common;
+
+fn get(agent: &mut Agent, uri: &str) -> Result<(http::Response<Body>, String), Error> {
+    let req = Request::get(uri).with_body(())?;
+    let mut res = agent.send(req).block()?;
+    let body = res.body_mut().read_to_string().block()?;
+    Ok((res, body))
+}
+
+#[test]
+fn cache_fresh_hit() -> Result<(), Error> {
+    common::setup_logger();
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let count_h = count.clone();
+
+    let mut server = Server::new();
+    server.at("/path").get(move |_req: http::Request<Body>| {
+        count_h.fetch_add(1, Ordering::SeqCst);
+        async move {
+            http::Response::builder()
+                .header("cache-control", "max-age=60")
+                .body("hello")
+                .unwrap()
+        }
+    });
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let mut agent = Agent::new();
+    agent.cache(Cache::memory());
+
+    let (_, body) = get(&mut agent, &uri)?;
+    assert_eq!(body, "hello");
+
+    let (res, body) = get(&mut agent, &uri)?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(body, "hello");
+    assert!(res.header("age").is_some());
+
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn cache_revalidate_not_modified() -> Result<(), Error> {
+    common::setup_logger();
+
+    let revalidated = Arc::new(AtomicUsize::new(0));
+    let revalidated_h = revalidated.clone();
+
+    let mut server = Server::new();
+    server.at("/path").get(move |req: http::Request<Body>| {
+        let revalidated = revalidated_h.clone();
+        async move {
+            if req.header("if-none-match") == Some("\"v1\"") {
+                let n = revalidated.fetch_add(1, Ordering::SeqCst) + 1;
+                return http::Response::builder()
+                    .status(304)
+                    .header("etag", "\"v1\"")
+                    .header("x-revalidated", n.to_string())
+                    .body("")
+                    .unwrap();
+            }
+            http::Response::builder()
+                .header("cache-control", "max-age=0")
+                .header("etag", "\"v1\"")
+                .body("hello")
+                .unwrap()
+        }
+    });
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let mut agent = Agent::new();
+    agent.cache(Cache::memory());
+
+    let (res, _) = get(&mut agent, &uri)?;
+    assert!(res.header("x-revalidated").is_none());
+
+    for n in 1..3 {
+        let (res, body) = get(&mut agent, &uri)?;
+        assert_eq!(res.status(), 200);
+        assert_eq!(body, "hello");
+        assert_eq!(res.header("x-revalidated"), Some(n.to_string().as_str()));
+    }
+
+    assert_eq!(revalidated.load(Ordering::SeqCst), 2);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn cache_vary() -> Result<(), Error> {
+    common::setup_logger();
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let count_h = count.clone();
+
+    let mut server = Server::new();
+    server.at("/path").get(move |req: http::Request<Body>| {
+        count_h.fetch_add(1, Ordering::SeqCst);
+        async move {
+            let lang = req.header("accept-language").unwrap_or("none").to_string();
+            http::Response::builder()
+                .header("cache-control", "max-age=60")
+                .header("vary", "accept-language")
+                .body(lang)
+                .unwrap()
+        }
+    });
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let mut agent = Agent::new();
+    agent.cache(Cache::memory());
+
+    for (lang, expected_count) in &[("en", 1), ("en", 1), ("sv", 2), ("en", 2), ("sv", 2)] {
+        let req = Request::get(&uri)
+            .header("accept-language", *lang)
+            .with_body(())?;
+        let mut res = agent.send(req).block()?;
+        assert_eq!(res.body_mut().read_to_string().block()?, *lang);
+        assert_eq!(count.load(Ordering::SeqCst), *expected_count);
+    }
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn cache_stale_while_revalidate() -> Result<(), Error> {
+    common::setup_logger();
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let count_h = count.clone();
+
+    let mut server = Server::new();
+    server.at("/path").get(move |_req: http::Request<Body>| {
+        let n = count_h.fetch_add(1, Ordering::SeqCst) + 1;
+        async move {
+            http::Response::builder()
+                .header("cache-control", "max-age=0, stale-while-revalidate=60")
+                .header("etag", format!("\"v{}\"", n))
+                .body(format!("v{}", n))
+                .unwrap()
+        }
+    });
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let mut agent = Agent::new();
+    agent.cache(Cache::memory());
+
+    let (_, body) = get(&mut agent, &uri)?;
+    assert_eq!(body, "v1");
+
+    // stale response served straight away, revalidated in the background.
+    let (_, body) = get(&mut agent, &uri)?;
+    assert_eq!(body, "v1");
+
+    let mut updated = false;
+    for _ in 0..100 {
+        async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        .block();
+
+        let (_, body) = get(&mut agent, &uri)?;
+        if body != "v1" {
+            updated = true;
+            break;
+        }
+    }
+
+    assert!(updated);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn cache_no_store() -> Result<(), Error> {
+    common::setup_logger();
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let count_h = count.clone();
+
+    let mut server = Server::new();
+    server.at("/path").get(move |_req: http::Request<Body>| {
+        count_h.fetch_add(1, Ordering::SeqCst);
+        async move {
+            http::Response::builder()
+                .header("cache-control", "no-store")
+                .header("etag", "\"v1\"")
+                .body("hello")
+                .unwrap()
+        }
+    });
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let mut agent = Agent::new();
+    agent.cache(Cache::memory());
+
+    for _ in 0..2 {
+        let (_, body) = get(&mut agent, &uri)?;
+        assert_eq!(body, "hello");
+    }
+
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn cache_streams_stored_body() -> Result<(), Error> {
+    common::setup_logger();
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let port = listener.local_addr()?.port();
+
+    // the end of the body is held back until the client got the first chunk.
+    let (tx_release, rx_release) = mpsc::channel::<()>();
+
+    // only one request reaches the server.
+    let server = thread::spawn(move || -> io::Result<()> {
+        let (mut tcp, _) = listener.accept()?;
+        let mut req = [0; 1024];
+        let _ = tcp.read(&mut req)?;
+        tcp.write_all(
+            b"HTTP/1.1 200 OK\r\ncache-control: max-age=60\r\n\
+              transfer-encoding: chunked\r\n\r\n6\r\nhello \r\n",
+        )?;
+        rx_release.recv().unwrap();
+        tcp.write_all(b"5\r\nworld\r\n0\r\n\r\n")?;
+        Ok(())
+    });
+
+    let uri = format!("http://127.0.0.1:{}/path", port);
+
+    let mut agent = Agent::new();
+    agent.cache(Cache::memory());
+
+    let req = Request::get(&uri).with_body(())?;
+    let mut res = agent.send(req).block()?;
+
+    let mut buf = [0; 100];
+    let n = res.body_mut().read(&mut buf).block()?;
+    assert_eq!(&buf[..n], b"hello ");
+
+    tx_release.send(()).unwrap();
+    assert_eq!(res.body_mut().read_to_string().block()?, "world");
+
+    let (res, body) = get(&mut agent, &uri)?;
+    assert_eq!(body, "hello world");
+    assert_eq!(res.header("content-length"), Some("11"));
+
+    server.join().unwrap()?;
+    Ok(())
+}
+
+#[test]
+fn cache_not_stored_when_not_read_to_end() -> Result<(), Error> {
+    common::setup_logger();
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let count_h = count.clone();
+
+    let mut server = Server::new();
+    server.at("/path").get(move |_req: http::Request<Body>| {
+        count_h.fetch_add(1, Ordering::SeqCst);
+        async move {
+            http::Response::builder()
+                .header("cache-control", "max-age=60")
+                .body("hello")
+                .unwrap()
+        }
+    });
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let mut agent = Agent::new();
+    agent.cache(Cache::memory());
+
+    let req = Request::get(&uri).with_body(())?;
+    let mut res = agent.send(req).block()?;
+    let mut buf = [0; 2];
+    res.body_mut().read(&mut buf).block()?;
+    drop(res);
+
+    let (_, body) = get(&mut agent, &uri)?;
+    assert_eq!(body, "hello");
+
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn cache_invalidate_on_post() -> Result<(), Error> {
+    common::setup_logger();
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let count_h = count.clone();
+
+    let mut server = Server::new();
+    server
+        .at("/path")
+        .get(move |_req: http::Request<Body>| {
+            count_h.fetch_add(1, Ordering::SeqCst);
+            async move {
+                http::Response::builder()
+                    .header("cache-control", "max-age=60")
+                    .body("hello")
+                    .unwrap()
+            }
+        })
+        .post(|_req: http::Request<Body>| async move { "ok" });
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let mut agent = Agent::new();
+    agent.cache(Cache::memory());
+
+    get(&mut agent, &uri)?;
+    get(&mut agent, &uri)?;
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    let req = Request::post(&uri).with_body("data")?;
+    agent
+        .send(req)
+        .block()?
+        .body_mut()
+        .read_and_discard()
+        .block()?;
+
+    get(&mut agent, &uri)?;
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn cache_disk() -> Result<(), Error> {
+    common::setup_logger();
+
+    let dir = std::env::temp_dir().join(format!("hreq-cache-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let count_h = count.clone();
+
+    let mut server = Server::new();
+    server.at("/path").get(move |_req: http::Request<Body>| {
+        count_h.fetch_add(1, Ordering::SeqCst);
+        async move {
+            http::Response::builder()
+                .header("cache-control", "max-age=60")
+                .header("x-foo", "bar")
+                .body("hello")
+                .unwrap()
+        }
+    });
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let mut agent = Agent::new();
+    agent.cache(Cache::disk(&dir));
+    get(&mut agent, &uri)?;
+
+    // a new cache using the same directory.
+    let mut agent = Agent::new();
+    agent.cache(Cache::disk(&dir));
+    let (res, body) = get(&mut agent, &uri)?;
+
+    assert_eq!(body, "hello");
+    assert_eq!(res.header("x-foo"), Some("bar"));
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    shut.shutdown().block();
+    let _ = fs::remove_dir_all(&dir);
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn cache_decoded_headers() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server
+        .at("/path")
+        .get(|_req: http::Request<Body>| async move {
+            http::Response::builder()
+                .header("cache-control", "max-age=60")
+                .header("content-encoding", "gzip")
+                .header("content-type", "text/plain; charset=utf-8")
+                .body("hello")
+                .unwrap()
+        });
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let mut agent = Agent::new();
+    agent.cache(Cache::memory());
+
+    // both the stored and the served response describe the decoded body. the
+    // decoded length is only known once stored.
+    for length in &[None, Some("5")] {
+        let (res, body) = get(&mut agent, &uri)?;
+        assert_eq!(body, "hello");
+        assert_eq!(res.header("content-encoding"), None);
+        assert_eq!(res.header("content-length"), *length);
+        assert_eq!(res.header("content-type"), Some("text/plain"));
+    }
+
+    shut.shutdown().block();
+    Ok(())
+}
@@ -0,0 +1,165 @@
+use futures_util::io::AsyncRead;
+use hreq::prelude::*;
+use hreq::{Agent, CancelToken, Error};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+
+/// A body that never ends, a chunk every 10ms.
+struct Endless {
+    sleep: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl AsyncRead for Endless {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        let sleep = this
+            .sleep
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep(Duration::from_millis(10))));
+
+        if sleep.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        this.sleep = None;
+
+        buf[..6].copy_from_slice(b"chunk\n");
+        Poll::Ready(Ok(6))
+    }
+}
+
+fn cancel_in(token: &CancelToken, millis: u64) {
+    let token = token.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(millis));
+        token.cancel();
+    });
+}
+
+#[test]
+fn cancel_body_read() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server
+        .at("/endless")
+        .get(|_: http::Request<Body>| async move {
+            Response::builder()
+                .prebuffer_response_body(false)
+                .body(Body::from_async_read(Endless { sleep: None }, None))
+        });
+    let (shut, addr) = server.listen(0).block()?;
+    let port = addr.port();
+
+    for force_http2 in &[false, true] {
+        let token = CancelToken::new();
+
+        let mut res = Request::get(&format!("http://127.0.0.1:{}/endless", port))
+            .force_http2(*force_http2)
+            .cancel_token(&token)
+            .call()
+            .block()?;
+
+        cancel_in(&token, 200);
+
+        let err = res
+            .body_mut()
+            .read_to_vec(100 * 1024 * 1024)
+            .block()
+            .unwrap_err();
+
+        assert!(err.is_cancelled());
+        assert_eq!(err.to_string(), "cancelled");
+
+        // and stays cancelled.
+        let mut buf = [0_u8; 10];
+        assert!(res
+            .body_mut()
+            .read(&mut buf)
+            .block()
+            .unwrap_err()
+            .is_cancelled());
+    }
+
+    drop(shut.shutdown());
+    Ok(())
+}
+
+#[test]
+fn cancel_waiting_for_response() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server.at("/slow").get(|_: http::Request<Body>| async move {
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        "Ok"
+    });
+    let (shut, addr) = server.listen(0).block()?;
+    let port = addr.port();
+
+    let token = CancelToken::new();
+    cancel_in(&token, 100);
+
+    let start = Instant::now();
+
+    let err = Request::get(&format!("http://127.0.0.1:{}/slow", port))
+        .cancel_token(&token)
+        .call()
+        .block()
+        .unwrap_err();
+
+    assert!(err.is_cancelled());
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    drop(shut.shutdown());
+    Ok(())
+}
+
+#[test]
+fn cancel_agent() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server
+        .at("/fast")
+        .get(|_: http::Request<Body>| async move { "Ok" });
+    let (shut, addr) = server.listen(0).block()?;
+    let port = addr.port();
+    let uri = format!("http://127.0.0.1:{}/fast", port);
+
+    let mut agent = Agent::new();
+
+    let token = CancelToken::new();
+    agent.cancel_token(&token);
+
+    let req = Request::get(&uri).with_body(())?;
+    agent.send(req).block()?;
+
+    token.cancel();
+
+    // a request with a token of its own is still cancelled by the agent.
+    let req = Request::get(&uri)
+        .cancel_token(&CancelToken::new())
+        .with_body(())?;
+    assert!(agent.send(req).block().unwrap_err().is_cancelled());
+
+    // until there's a new token.
+    agent.cancel_token(&CancelToken::new());
+
+    let req = Request::get(&uri).with_body(())?;
+    let mut res = agent.send(req).block()?;
+    assert_eq!(res.body_mut().read_to_string().block()?, "Ok");
+
+    drop(shut.shutdown());
+    Ok(())
+}
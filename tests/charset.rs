@@ -1,7 +1,13 @@
+use futures_util::io::AsyncReadExt;
 use hreq::prelude::*;
-use hreq::Error;
+use hreq::{CharsetError, CharsetReader, Error, Malformed};
 use std::fs::File;
-use std::io::BufReader;
+use std::io;
+use std::io::{BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
 mod common;
 
@@ -90,6 +96,133 @@ fn from_charset_shift_jis() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn from_charset_sniff_html_meta() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server.at("/path").all(|_: http::Request<Body>| async move {
+        let mut html = b"<html><head><meta charset=\"Shift_JIS\"></head><body>".to_vec();
+        html.extend_from_slice(&std::fs::read("tests/data/shiftjis.txt").unwrap());
+        html.extend_from_slice(b"</body></html>");
+        http::Response::builder()
+            .header("content-type", "text/html")
+            .body(html)
+            .unwrap()
+    });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let res = http::Request::get(uri).call().block()?;
+
+    let s = res.into_body().read_to_string().block()?;
+
+    assert_eq!(
+        s,
+        "<html><head><meta charset=\"Shift_JIS\"></head><body>おはよう世界\n</body></html>"
+    );
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn from_charset_sniff_streamed() -> Result<(), Error> {
+    common::setup_logger();
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let port = listener.local_addr()?.port();
+    let ctypes = ["text/plain", "text/html", "text/event-stream"];
+
+    // the end of the body is held back until the client got the first chunk.
+    let (tx_release, rx_release) = mpsc::channel::<()>();
+
+    let server = thread::spawn(move || -> io::Result<()> {
+        for ctype in &ctypes {
+            let (mut tcp, _) = listener.accept()?;
+            let mut req = [0; 1024];
+            let _ = tcp.read(&mut req)?;
+            write!(
+                tcp,
+                "HTTP/1.1 200 OK\r\ncontent-type: {}\r\ntransfer-encoding: chunked\r\n\r\n\
+                 d\r\ndata: hello\n\n\r\n",
+                ctype
+            )?;
+            rx_release.recv().unwrap();
+            tcp.write_all(b"0\r\n\r\n")?;
+        }
+        Ok(())
+    });
+
+    for ctype in &ctypes {
+        let uri = format!("http://127.0.0.1:{}/path", port);
+        let mut res = http::Request::get(uri)
+            .timeout(Duration::from_secs(5))
+            .call()
+            .block()?;
+
+        let mut buf = [0; 100];
+        let n = res.body_mut().read(&mut buf).block()?;
+        assert_eq!(&buf[..n], b"data: hello\n\n", "{}", ctype);
+
+        tx_release.send(()).unwrap();
+        assert_eq!(res.body_mut().read_to_string().block()?, "");
+    }
+
+    server.join().unwrap()?;
+    Ok(())
+}
+
+#[test]
+fn from_charset_sniff_meta_in_later_chunk() -> Result<(), Error> {
+    common::setup_logger();
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let port = listener.local_addr()?.port();
+
+    let server = thread::spawn(move || -> io::Result<()> {
+        let (mut tcp, _) = listener.accept()?;
+        let mut req = [0; 1024];
+        let _ = tcp.read(&mut req)?;
+
+        let first = b"<html><head><title>hi</title>";
+        write!(
+            tcp,
+            "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ntransfer-encoding: chunked\r\n\r\n\
+             {:x}\r\n",
+            first.len()
+        )?;
+        tcp.write_all(first)?;
+        tcp.write_all(b"\r\n")?;
+        tcp.flush()?;
+
+        // the meta tag arrives in a separate read.
+        thread::sleep(Duration::from_millis(20));
+
+        let mut second = b"<meta charset=\"Shift_JIS\"></head><body>".to_vec();
+        second.extend_from_slice(&std::fs::read("tests/data/shiftjis.txt")?);
+        write!(tcp, "{:x}\r\n", second.len())?;
+        tcp.write_all(&second)?;
+        tcp.write_all(b"\r\n0\r\n\r\n")?;
+        Ok(())
+    });
+
+    let uri = format!("http://127.0.0.1:{}/path", port);
+    let res = http::Request::get(uri).call().block()?;
+
+    let s = res.into_body().read_to_string().block()?;
+
+    assert_eq!(
+        s,
+        "<html><head><title>hi</title><meta charset=\"Shift_JIS\"></head><body>おはよう世界\n"
+    );
+
+    server.join().unwrap()?;
+    Ok(())
+}
+
 #[test]
 fn to_charset_iso8859() -> Result<(), Error> {
     common::setup_logger();
@@ -214,6 +347,119 @@ fn to_charset_shift_jis_string() -> Result<(), Error> {
     Ok(())
 }
 
+#[test]
+fn charset_reader_file() -> Result<(), Error> {
+    let file = File::open("tests/data/shiftjis.txt")?;
+    let mut reader = CharsetReader::new(
+        futures_util::io::AllowStdIo::new(file),
+        encoding_rs::SHIFT_JIS,
+        encoding_rs::EUC_JP,
+    );
+
+    let mut vec = vec![];
+    reader.read_to_end(&mut vec).block()?;
+
+    assert_eq!(
+        vec,
+        &[164_u8, 170, 164, 207, 164, 232, 164, 166, 192, 164, 179, 166, 10]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn charset_reader_small_reads() -> Result<(), Error> {
+    let input: &[u8] = "ÄEÖ räksmörgås".as_bytes();
+    let mut reader = CharsetReader::new(input, encoding_rs::UTF_8, encoding_rs::WINDOWS_1252);
+
+    let mut vec = vec![];
+    let mut buf = [0_u8; 1];
+    loop {
+        let amount = reader.read(&mut buf).block()?;
+        if amount == 0 {
+            break;
+        }
+        vec.extend_from_slice(&buf[..amount]);
+    }
+
+    assert_eq!(&vec[..], &b"\xc4E\xd6 r\xe4ksm\xf6rg\xe5s"[..]);
+
+    Ok(())
+}
+
+#[test]
+fn charset_reader_malformed_replace() -> Result<(), Error> {
+    let input: &[u8] = b"abc\xffdef";
+    let mut reader = CharsetReader::new(input, encoding_rs::UTF_8, encoding_rs::UTF_8);
+
+    let mut s = String::new();
+    reader.read_to_string(&mut s).block()?;
+
+    assert_eq!(s, "abc\u{fffd}def");
+
+    Ok(())
+}
+
+#[test]
+fn charset_reader_malformed_error() -> Result<(), Error> {
+    let input: &[u8] = b"abc\xffdef";
+    let mut reader = CharsetReader::new(input, encoding_rs::UTF_8, encoding_rs::WINDOWS_1252)
+        .malformed(Malformed::Error);
+
+    let mut vec = vec![];
+    let err = reader.read_to_end(&mut vec).block().unwrap_err();
+
+    let err = CharsetError::from_io(&err).unwrap();
+    assert_eq!(err.offset(), 3);
+    assert_eq!(err.unmappable(), None);
+    // output up to the error is read.
+    assert_eq!(vec, b"abc");
+
+    Ok(())
+}
+
+#[test]
+fn charset_reader_unmappable_error() -> Result<(), Error> {
+    let input: &[u8] = "aé日b".as_bytes();
+    let mut reader = CharsetReader::new(input, encoding_rs::UTF_8, encoding_rs::WINDOWS_1252)
+        .malformed(Malformed::Error);
+
+    let mut vec = vec![];
+    let err = reader.read_to_end(&mut vec).block().unwrap_err();
+
+    let err = CharsetError::from_io(&err).unwrap();
+    assert_eq!(err.offset(), 3);
+    assert_eq!(err.unmappable(), Some('日'));
+    assert_eq!(vec, b"a\xe9");
+
+    Ok(())
+}
+
+#[test]
+fn charset_reader_unmappable_error_multibyte_source() -> Result<(), Error> {
+    let (input, _, _) = encoding_rs::GBK.encode("a中文们b");
+    let mut reader = CharsetReader::new(&input[..], encoding_rs::GBK, encoding_rs::SHIFT_JIS)
+        .malformed(Malformed::Error);
+
+    let mut vec = vec![];
+    let err = loop {
+        let mut buf = [0_u8; 3];
+        match reader.read(&mut buf).block() {
+            Ok(0) => panic!("Expected unmappable error"),
+            Ok(amount) => vec.extend_from_slice(&buf[..amount]),
+            Err(e) => break e,
+        }
+    };
+
+    let err = CharsetError::from_io(&err).unwrap();
+    assert_eq!(err.offset(), 5);
+    assert_eq!(err.unmappable(), Some('们'));
+    let (expected, _, _) = encoding_rs::SHIFT_JIS.encode("a中文");
+    assert_eq!(vec, &expected[..]);
+
+    Ok(())
+}
+
 fn iso8859_server() -> Server<()> {
     common::setup_logger();
 
@@ -0,0 +1,153 @@
+use hreq::prelude::*;
+use hreq::{Agent, ConnectFuture, Connector, Error, HostPort, Protocol, Stream};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+mod common;
+
+/// Connects everything to the test server, remembering what was asked for.
+struct ToServer {
+    addr: SocketAddr,
+    proto: Protocol,
+    seen: Arc<Mutex<Vec<String>>>,
+}
+
+impl ToServer {
+    fn new(addr: SocketAddr, proto: Protocol) -> (Self, Arc<Mutex<Vec<String>>>) {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let to_server = ToServer {
+            addr,
+            proto,
+            seen: seen.clone(),
+        };
+        (to_server, seen)
+    }
+}
+
+impl Connector for ToServer {
+    fn connect(&self, host_port: HostPort) -> ConnectFuture {
+        let seen = format!("{} {}", host_port, host_port.is_tls());
+        self.seen.lock().unwrap().push(seen);
+
+        let addr = self.addr;
+        let proto = self.proto;
+
+        Box::pin(async move {
+            let tcp = tokio::net::TcpStream::connect(addr).await?;
+            Ok((Box::new(tcp.compat()) as Box<dyn Stream>, proto))
+        })
+    }
+}
+
+#[test]
+fn connector_redirect_cookies() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server
+        .at("/login")
+        .get(|_req: http::Request<Body>| async move {
+            http::Response::builder()
+                .status(302)
+                .header("set-cookie", "session=secret")
+                .header("location", "http://other-service/whoami")
+                .body(())
+                .unwrap()
+        });
+    server
+        .at("/whoami")
+        .get(|req: http::Request<Body>| async move {
+            format!(
+                "{} {:?} {}",
+                req.uri()
+                    .host()
+                    .or_else(|| req.header("host"))
+                    .unwrap_or("-"),
+                req.version(),
+                req.header("cookie").unwrap_or("nobody")
+            )
+        });
+    let (shut, addr) = server.listen(0).block()?;
+    let (connector, seen) = ToServer::new(addr, Protocol::Unknown);
+
+    let mut agent = Agent::new();
+    agent.connector(connector);
+
+    let req = Request::get("https://my-service/login").with_body(())?;
+    let mut res = agent.send(req).block()?;
+
+    // the cookie is for my-service, not other-service.
+    assert_eq!(
+        res.body_mut().read_to_string().block()?,
+        "other-service HTTP/1.1 nobody"
+    );
+
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec!["my-service:443 true", "other-service:80 false"]
+    );
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn connector_protocol_and_override() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server
+        .at("/whoami")
+        .get(|req: http::Request<Body>| async move {
+            format!(
+                "{} {:?} {}",
+                req.uri()
+                    .host()
+                    .or_else(|| req.header("host"))
+                    .unwrap_or("-"),
+                req.version(),
+                req.header("cookie").unwrap_or("nobody")
+            )
+        });
+    let (shut, addr) = server.listen(0).block()?;
+    let (connector, seen) = ToServer::new(addr, Protocol::Http2);
+
+    let mut agent = Agent::new();
+    agent.connector(connector);
+
+    let req = Request::get("http://my-service/whoami")
+        .with_override("10.0.0.1", 8080, false)
+        .with_body(())?;
+    let mut res = agent.send(req).block()?;
+
+    assert_eq!(res.version(), http::Version::HTTP_2);
+    assert_eq!(
+        res.body_mut().read_to_string().block()?,
+        "my-service HTTP/2.0 nobody"
+    );
+
+    assert_eq!(*seen.lock().unwrap(), vec!["10.0.0.1:8080 false"]);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn connector_error() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut agent = Agent::new();
+    agent.retries(0);
+    agent.connector(|_host_port: HostPort| async move {
+        Err::<(tokio_util::compat::Compat<tokio::net::TcpStream>, _), _>(Error::User(
+            "no tunnel".into(),
+        ))
+    });
+
+    let req = Request::get("http://my-service/").with_body(())?;
+    let err = agent.send(req).block().unwrap_err();
+    assert_eq!(err.to_string(), "no tunnel");
+
+    Ok(())
+}
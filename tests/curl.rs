@@ -0,0 +1,206 @@
+use hreq::prelude::*;
+use hreq::{CurlExt, Error};
+
+mod common;
+
+#[test]
+fn curl_render() -> Result<(), Error> {
+    common::setup_logger();
+
+    let req = Request::put("http://my-api/items")
+        .query("q", "a b")
+        .header("x-trace", "1")
+        .timeout_millis(1_500)
+        .with_override("127.0.0.1", 80, false)
+        .force_http2(true)
+        .with_json(&vec![1, 2])?;
+
+    assert_eq!(
+        req.to_curl(),
+        "curl -X PUT 'http://my-api/items?q=a%20b' -H 'x-trace: 1' \
+         -H 'content-type: application/json; charset=utf-8' --data-binary '[1,2]' \
+         --max-time 1.5 --resolve my-api:80:127.0.0.1 --http2-prior-knowledge"
+    );
+
+    let req = Request::get("https://my-api/")
+        .with_override("localhost", 8443, true)
+        .with_body(())?;
+
+    assert_eq!(
+        req.to_curl(),
+        "curl https://my-api/ --connect-to my-api:443:localhost:8443"
+    );
+
+    // bodies from readers are not rendered.
+    let reader = std::io::Cursor::new(vec![1, 2, 3]);
+    let req = Request::post("http://my-api/").with_body(Body::from_sync_read(reader, None))?;
+
+    assert_eq!(req.to_curl(), "curl -X POST http://my-api/");
+
+    Ok(())
+}
+
+#[test]
+fn curl_parse_and_send() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server
+        .at("/*any")
+        .all(|mut req: http::Request<Body>| async move {
+            let body = req.body_mut().read_to_string().await?;
+            let header = |name| req.header(name).unwrap_or("-").to_string();
+            Ok::<_, Error>(format!(
+                "{} {} {} {} {}",
+                req.method(),
+                req.uri(),
+                header("content-type"),
+                header("authorization"),
+                body
+            ))
+        });
+    let (shut, addr) = server.listen(0).block()?;
+    let port = addr.port();
+
+    // as pasted in a bug report, to a host that only resolves via --resolve.
+    let cmd = format!(
+        r#"curl -sSL -XPATCH "http://my-api:{port}/items?id=1" \
+             -H 'Content-Type: text/plain' \
+             -u martin:secret \
+             --compressed \
+             --resolve my-api:{port}:127.0.0.1 \
+             -d 'hello' -d "world""#,
+        port = port
+    );
+
+    let req = Request::from_curl(&cmd)?;
+    let mut res = req.send().block()?;
+
+    assert_eq!(
+        res.body_mut().read_to_string().block()?,
+        "PATCH /items?id=1 text/plain Basic bWFydGluOnNlY3JldA== hello&world"
+    );
+
+    // -G moves the data to the query.
+    let cmd = format!("curl -G localhost:{}/search -d q=rust --max-time 5", port);
+    let mut res = Request::from_curl(&cmd)?.send().block()?;
+
+    let echo = res.body_mut().read_to_string().block()?;
+    assert!(echo.starts_with("GET /search?q=rust "));
+    assert!(echo.ends_with(" - "));
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn curl_redirects() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server
+        .at("/old")
+        .get(|_req: http::Request<Body>| async move {
+            http::Response::builder()
+                .status(302)
+                .header("location", "/new")
+                .body(())
+                .unwrap()
+        });
+    server
+        .at("/new")
+        .get(|_req: http::Request<Body>| async move { "new" });
+    let (shut, addr) = server.listen(0).block()?;
+
+    // like curl, redirects are only followed with -L.
+    let cmd = format!("curl http://127.0.0.1:{}/old", addr.port());
+    let res = Request::from_curl(&cmd)?.send().block()?;
+    assert_eq!(res.status(), 302);
+
+    let cmd = format!("curl -sL http://127.0.0.1:{}/old", addr.port());
+    let mut res = Request::from_curl(&cmd)?.send().block()?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.body_mut().read_to_string().block()?, "new");
+
+    let cmd = format!(
+        "curl -L --max-redirs 0 http://127.0.0.1:{}/old",
+        addr.port()
+    );
+    let res = Request::from_curl(&cmd)?.send().block()?;
+    assert_eq!(res.status(), 302);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn curl_data_newlines() -> Result<(), Error> {
+    common::setup_logger();
+
+    let path = std::env::temp_dir().join(format!("hreq-curl-{}.txt", std::process::id()));
+    std::fs::write(&path, "a\r\nb\n")?;
+
+    // newlines are only stripped from -d @file.
+    let cmd = format!("curl http://my-api/ -d $'x\\ny' -d @{}", path.display());
+    let req = Request::from_curl(&cmd)?;
+    assert!(req.to_curl().ends_with("--data-binary 'x\ny&ab'"));
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn curl_round_trip() -> Result<(), Error> {
+    common::setup_logger();
+
+    let req = Request::post("https://my-api/upload")
+        .header("x-empty", "")
+        .timeout_millis(250)
+        .connect_timeout(std::time::Duration::from_secs(3))
+        .h2c_upgrade(true)
+        .with_body(vec![0_u8, 0xff, b'\'', b'\n'])?;
+
+    let cmd = req.to_curl();
+    assert!(cmd.contains(r"--data-binary $'\x00\xff\'\n'"));
+    assert!(cmd.contains("--max-time 0.25 --connect-timeout 3"));
+
+    let parsed = Request::from_curl(&cmd)?;
+    assert_eq!(parsed.method(), "POST");
+    assert_eq!(parsed.header("x-empty"), Some(""));
+    assert_eq!(parsed.to_curl(), cmd);
+
+    Ok(())
+}
+
+#[test]
+fn curl_round_trip_max_redirs() -> Result<(), Error> {
+    common::setup_logger();
+
+    let req = Request::from_curl("curl -L --max-redirs 3 http://my-api/")?;
+    let cmd = req.to_curl();
+    assert_eq!(cmd, "curl http://my-api/ --location --max-redirs 3");
+    assert_eq!(Request::from_curl(&cmd)?.to_curl(), cmd);
+
+    // like curl, --max-redirs does nothing without -L.
+    let req = Request::from_curl("curl --max-redirs 3 http://my-api/")?;
+    assert_eq!(req.to_curl(), "curl http://my-api/");
+
+    let req = Request::from_curl("curl -L --max-redirs -1 http://my-api/")?;
+    assert!(req.to_curl().ends_with("--max-redirs 127"));
+
+    assert!(Request::from_curl("curl -L --max-redirs -2 http://my-api/").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn curl_parse_errors() {
+    common::setup_logger();
+
+    let err = Request::from_curl("curl -F file=@x.png http://my-api/").unwrap_err();
+    assert!(err.to_string().contains("-F"));
+
+    assert!(Request::from_curl("curl -H 'x: y'").is_err());
+    assert!(Request::from_curl("curl 'http://my-api/").is_err());
+    assert!(Request::from_curl("curl http://my-api/ -d @-").is_err());
+}
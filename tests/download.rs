@@ -0,0 +1,357 @@
+use hreq::prelude::*;
+use hreq::server::{Middleware, Next, Static};
+use hreq::Agent;
+use hreq::Error;
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+mod common;
+
+use common::AsyncRead;
+
+const SIZE: usize = 512 * 1024;
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hreq-download-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn test_data() -> Vec<u8> {
+    (0..SIZE).map(|i| (i % 251) as u8).collect()
+}
+
+/// Body failing after half the data, to simulate a connection reset.
+struct FailHalfway {
+    data: io::Cursor<Vec<u8>>,
+    left: usize,
+}
+
+impl AsyncRead for FailHalfway {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.left == 0 {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "broken",
+            )));
+        }
+        let max = buf.len().min(this.left);
+        let amount = this.data.read(&mut buf[..max])?;
+        this.left -= amount;
+        Poll::Ready(Ok(amount))
+    }
+}
+
+/// Middleware failing the first response halfway through the body.
+fn fail_first() -> impl Middleware {
+    let failed = Arc::new(AtomicBool::new(false));
+
+    move |req: http::Request<Body>, next: Next| {
+        let failed = failed.clone();
+        async move {
+            let res = next.run(req).await?;
+            if failed.swap(true, Ordering::SeqCst) {
+                return Ok::<_, Error>(res);
+            }
+            // keep the headers, but replace the body with a failing one.
+            let (parts, _) = res.into_parts();
+            let body = Body::from_async_read(
+                FailHalfway {
+                    data: io::Cursor::new(test_data()),
+                    left: SIZE / 2,
+                },
+                Some(SIZE as u64),
+            );
+            Ok(http::Response::from_parts(parts, body))
+        }
+    }
+}
+
+#[test]
+fn download_complete() -> Result<(), Error> {
+    common::setup_logger();
+
+    let dir = test_dir("complete");
+    let src = dir.join("src.bin");
+    fs::write(&src, test_data())?;
+
+    let mut server = Server::new();
+    server.at("/data").get(Static::file(&src));
+    let (shut, addr) = server.listen(0).block()?;
+
+    let target = dir.join("target.bin");
+    let uri = format!("http://127.0.0.1:{}/data", addr.port());
+
+    let mut agent = Agent::new();
+    let size = agent.download(uri.as_str(), &target).block()?;
+
+    assert_eq!(size, SIZE as u64);
+    assert_eq!(fs::read(&target)?, test_data());
+    assert!(!dir.join("target.bin.part").exists());
+    assert!(!dir.join("target.bin.part.validator").exists());
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn download_resume() -> Result<(), Error> {
+    common::setup_logger();
+
+    let dir = test_dir("resume");
+    let src = dir.join("src.bin");
+    fs::write(&src, test_data())?;
+
+    let ranges = Arc::new(AtomicUsize::new(0));
+    let ranges_mid = ranges.clone();
+
+    let mut server = Server::new();
+    server
+        .at("/data")
+        .middleware(move |req: http::Request<Body>, next: Next| {
+            if req.header("range").is_some() {
+                ranges_mid.fetch_add(1, Ordering::SeqCst);
+            }
+            next.run(req)
+        })
+        .middleware(fail_first())
+        .get(Static::file(&src));
+    let (shut, addr) = server.listen(0).block()?;
+
+    let target = dir.join("target.bin");
+    let uri = format!("http://127.0.0.1:{}/data", addr.port());
+
+    let mut agent = Agent::new();
+    agent.retries(0);
+
+    // first attempt is interrupted, leaving a partial file.
+    assert!(agent.download(uri.as_str(), &target).block().is_err());
+    assert!(!target.exists());
+
+    let part = fs::metadata(dir.join("target.bin.part"))?.len();
+    assert!(part > 0 && part < SIZE as u64);
+
+    // second attempt continues using a range request.
+    let size = agent.download(uri.as_str(), &target).block()?;
+
+    assert_eq!(size, SIZE as u64);
+    assert_eq!(fs::read(&target)?, test_data());
+    assert_eq!(ranges.load(Ordering::SeqCst), 1);
+    assert!(!dir.join("target.bin.part").exists());
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn download_range_ignored() -> Result<(), Error> {
+    common::setup_logger();
+
+    let dir = test_dir("ignored");
+
+    let mut server = Server::new();
+    server
+        .at("/data")
+        .middleware(fail_first())
+        .get(|_req: http::Request<Body>| async move {
+            // a server without range support answers 200 with all the data.
+            http::Response::builder()
+                .header("etag", "\"v1\"")
+                .body(test_data())
+                .unwrap()
+        });
+    let (shut, addr) = server.listen(0).block()?;
+
+    let target = dir.join("target.bin");
+    let uri = format!("http://127.0.0.1:{}/data", addr.port());
+
+    let mut agent = Agent::new();
+    agent.retries(0);
+
+    assert!(agent.download(uri.as_str(), &target).block().is_err());
+    assert_eq!(
+        fs::read_to_string(dir.join("target.bin.part.validator"))?,
+        "\"v1\""
+    );
+
+    let size = agent.download(uri.as_str(), &target).block()?;
+
+    assert_eq!(size, SIZE as u64);
+    assert_eq!(fs::read(&target)?, test_data());
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+/// Middleware counting range requests, optionally failing the first segment (after the
+/// probe for range support) straight away.
+fn count_ranges(count: Arc<AtomicUsize>, fail_segment: bool) -> impl Middleware {
+    move |req: http::Request<Body>, next: Next| {
+        let count = count.clone();
+        async move {
+            let is_range = req.header("range").is_some();
+            let res = next.run(req).await?;
+            if !is_range {
+                return Ok::<_, Error>(res);
+            }
+            let n = count.fetch_add(1, Ordering::SeqCst);
+            if n != 1 || !fail_segment {
+                return Ok(res);
+            }
+            // bytes 0-99/1234
+            let range = res.header("content-range").unwrap().to_string();
+            let (start, end) =
+                range[6..range.find('/').unwrap()].split_at(range.find('-').unwrap() - 6);
+            let len = end[1..].parse::<u64>().unwrap() - start.parse::<u64>().unwrap() + 1;
+            let (parts, _) = res.into_parts();
+            let body = Body::from_async_read(
+                FailHalfway {
+                    data: io::Cursor::new(vec![]),
+                    left: 0,
+                },
+                Some(len),
+            );
+            Ok(http::Response::from_parts(parts, body))
+        }
+    }
+}
+
+#[test]
+fn download_segmented() -> Result<(), Error> {
+    common::setup_logger();
+
+    let dir = test_dir("segmented");
+    let src = dir.join("src.bin");
+    fs::write(&src, test_data())?;
+
+    let ranges = Arc::new(AtomicUsize::new(0));
+
+    let mut server = Server::new();
+    server
+        .at("/data")
+        .middleware(count_ranges(ranges.clone(), false))
+        .get(Static::file(&src));
+    let (shut, addr) = server.listen(0).block()?;
+
+    let target = dir.join("target.bin");
+    let uri = format!("http://127.0.0.1:{}/data", addr.port());
+
+    let mut agent = Agent::new();
+    let size = agent.download_segmented(uri.as_str(), &target, 4).block()?;
+
+    assert_eq!(size, SIZE as u64);
+    assert_eq!(fs::read(&target)?, test_data());
+    // probe + segments
+    assert_eq!(ranges.load(Ordering::SeqCst), 5);
+    assert!(!dir.join("target.bin.part").exists());
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn download_segmented_retry() -> Result<(), Error> {
+    common::setup_logger();
+
+    let dir = test_dir("segmented-retry");
+    let src = dir.join("src.bin");
+    fs::write(&src, test_data())?;
+
+    let ranges = Arc::new(AtomicUsize::new(0));
+
+    let mut server = Server::new();
+    server
+        .at("/data")
+        .middleware(count_ranges(ranges.clone(), true))
+        .get(Static::file(&src));
+    let (shut, addr) = server.listen(0).block()?;
+
+    let target = dir.join("target.bin");
+    let uri = format!("http://127.0.0.1:{}/data", addr.port());
+
+    let mut agent = Agent::new();
+    let size = agent.download_segmented(uri.as_str(), &target, 4).block()?;
+
+    assert_eq!(size, SIZE as u64);
+    assert_eq!(fs::read(&target)?, test_data());
+    // probe + segments + one failed segment retried once.
+    assert_eq!(ranges.load(Ordering::SeqCst), 6);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn download_segmented_no_ranges() -> Result<(), Error> {
+    common::setup_logger();
+
+    let dir = test_dir("segmented-no-ranges");
+
+    let mut server = Server::new();
+    server
+        .at("/data")
+        .all(|_req: http::Request<Body>| async move { test_data() });
+    let (shut, addr) = server.listen(0).block()?;
+
+    let target = dir.join("target.bin");
+    let uri = format!("http://127.0.0.1:{}/data", addr.port());
+
+    let mut agent = Agent::new();
+    let size = agent.download_segmented(uri.as_str(), &target, 4).block()?;
+
+    assert_eq!(size, SIZE as u64);
+    assert_eq!(fs::read(&target)?, test_data());
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn download_segmented_cookies() -> Result<(), Error> {
+    common::setup_logger();
+
+    let dir = test_dir("segmented-cookies");
+    let src = dir.join("src.bin");
+    fs::write(&src, test_data())?;
+
+    let mut server = Server::new();
+    server
+        .at("/data")
+        .middleware(|req: http::Request<Body>, next: Next| async move {
+            // only the segments, not the probe, set the cookie.
+            let is_segment = req.header("range").map(|r| r != "bytes=0-0") == Some(true);
+            let mut res = next.run(req).await?;
+            if is_segment {
+                res.headers_mut()
+                    .insert("set-cookie", "segment=yes".parse().unwrap());
+            }
+            Ok::<_, Error>(res)
+        })
+        .get(Static::file(&src));
+    let (shut, addr) = server.listen(0).block()?;
+
+    let target = dir.join("target.bin");
+    let uri = format!("http://127.0.0.1:{}/data", addr.port());
+
+    let mut agent = Agent::new();
+    agent.download_segmented(uri.as_str(), &target, 4).block()?;
+
+    let uri: http::Uri = uri.parse().unwrap();
+    let cookies = agent.get_cookies(&uri);
+    assert_eq!(cookies.len(), 1);
+    assert_eq!(cookies[0].name(), "segment");
+
+    shut.shutdown().block();
+    Ok(())
+}
@@ -0,0 +1,259 @@
+use hreq::prelude::*;
+use hreq::{Agent, Error, Fixtures};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+mod common;
+
+fn fixture_path(name: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join(format!("hreq-fixtures-{}", std::process::id()))
+        .join(format!("{}.json", name))
+}
+
+fn get(agent: &mut Agent, uri: &str) -> Result<String, Error> {
+    let req = Request::get(uri).with_body(())?;
+    agent.send(req).block()?.body_mut().read_to_string().block()
+}
+
+fn post(agent: &mut Agent, uri: &str, body: &str) -> Result<String, Error> {
+    let req = Request::post(uri).with_body(body.to_string())?;
+    agent.send(req).block()?.body_mut().read_to_string().block()
+}
+
+#[test]
+fn fixtures_record_replay() -> Result<(), Error> {
+    common::setup_logger();
+
+    let path = fixture_path("record_replay");
+
+    let mut server = Server::new();
+    server
+        .at("/login")
+        .get(|_req: http::Request<Body>| async move {
+            http::Response::builder()
+                .status(302)
+                .header("set-cookie", "session=secret")
+                .header("location", "/whoami")
+                .body(())
+                .unwrap()
+        });
+    server
+        .at("/whoami")
+        .get(|req: http::Request<Body>| async move {
+            http::Response::builder()
+                .header("content-type", "text/plain; charset=utf-8")
+                .header("content-encoding", "gzip")
+                .body(req.header("cookie").unwrap_or("nobody").to_string())
+                .unwrap()
+        });
+    server
+        .at("/echo")
+        .post(|mut req: http::Request<Body>| async move {
+            let body = req.body_mut().read_to_string().await?;
+            Ok::<_, Error>(format!("echo {}", body))
+        });
+    let (shut, addr) = server.listen(0).block()?;
+    let base = format!("http://127.0.0.1:{}", addr.port());
+
+    let fixtures = Fixtures::record(&path);
+    let mut agent = Agent::new();
+    agent.fixtures(fixtures.clone());
+
+    assert_eq!(
+        get(&mut agent, &format!("{}/login", base))?,
+        "session=secret"
+    );
+    assert_eq!(
+        post(&mut agent, &format!("{}/echo", base), "hi")?,
+        "echo hi"
+    );
+
+    fixtures.save()?;
+    shut.shutdown().block();
+
+    // the redirect is an exchange of its own, and the gzip body is recorded decoded
+    // without the charset it was decoded from.
+    let json = std::fs::read_to_string(&path)?;
+    assert_eq!(json.matches("\"request\"").count(), 3);
+    assert!(json.contains("\"body\": \"session=secret\""));
+    assert!(!json.contains("gzip"));
+    assert_eq!(json.matches("\"text/plain\"").count(), 3);
+
+    // the server is gone, everything comes from the file.
+    let mut agent = Agent::new();
+    agent.fixtures(Fixtures::replay(&path)?);
+
+    assert_eq!(
+        get(&mut agent, &format!("{}/login", base))?,
+        "session=secret"
+    );
+    assert_eq!(
+        post(&mut agent, &format!("{}/echo", base), "hi")?,
+        "echo hi"
+    );
+
+    // cookies from replayed responses are kept as usual.
+    let uri: http::Uri = format!("{}/whoami", base).parse().unwrap();
+    assert_eq!(agent.get_cookies(&uri).len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn fixtures_unmatched() -> Result<(), Error> {
+    common::setup_logger();
+
+    let path = fixture_path("unmatched");
+
+    let mut server = Server::new();
+    server
+        .at("/echo")
+        .post(|mut req: http::Request<Body>| async move {
+            let body = req.body_mut().read_to_string().await?;
+            Ok::<_, Error>(format!("echo {}", body))
+        });
+    let (shut, addr) = server.listen(0).block()?;
+    let base = format!("http://127.0.0.1:{}", addr.port());
+
+    let fixtures = Fixtures::record(&path);
+    let mut agent = Agent::new();
+    agent.fixtures(fixtures.clone());
+    assert_eq!(
+        post(&mut agent, &format!("{}/echo", base), "hi")?,
+        "echo hi"
+    );
+
+    // written when the last clone is dropped.
+    drop(agent);
+    drop(fixtures);
+    shut.shutdown().block();
+
+    let mut agent = Agent::new();
+    agent.fixtures(Fixtures::replay(&path)?);
+
+    let err = get(&mut agent, &format!("{}/echo", base)).unwrap_err();
+    assert!(err.to_string().contains("No recorded response"));
+    assert!(err.to_string().contains("GET"));
+
+    Ok(())
+}
+
+#[test]
+fn fixtures_match_body() -> Result<(), Error> {
+    common::setup_logger();
+
+    let path = fixture_path("match_body");
+
+    let mut server = Server::new();
+    server
+        .at("/echo")
+        .post(|mut req: http::Request<Body>| async move {
+            let body = req.body_mut().read_to_string().await?;
+            Ok::<_, Error>(format!("echo {}", body))
+        });
+    let (shut, addr) = server.listen(0).block()?;
+    let base = format!("http://127.0.0.1:{}", addr.port());
+    let uri = format!("{}/echo", base);
+
+    let fixtures = Fixtures::record(&path);
+    let mut agent = Agent::new();
+    agent.fixtures(fixtures.clone());
+    assert_eq!(post(&mut agent, &uri, "one")?, "echo one");
+    assert_eq!(post(&mut agent, &uri, "two")?, "echo two");
+
+    fixtures.save()?;
+    shut.shutdown().block();
+
+    let mut agent = Agent::new();
+    agent.fixtures(Fixtures::replay(&path)?.match_body(true));
+
+    assert_eq!(post(&mut agent, &uri, "two")?, "echo two");
+    assert_eq!(post(&mut agent, &uri, "one")?, "echo one");
+    assert!(post(&mut agent, &uri, "three").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn fixtures_match_headers() -> Result<(), Error> {
+    common::setup_logger();
+
+    let path = fixture_path("match_headers");
+
+    let mut server = Server::new();
+    server
+        .at("/count")
+        .get(|_req: http::Request<Body>| async move {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            COUNT.fetch_add(1, Ordering::SeqCst).to_string()
+        });
+    let (shut, addr) = server.listen(0).block()?;
+    let base = format!("http://127.0.0.1:{}", addr.port());
+    let uri = format!("{}/count", base);
+
+    let send = |agent: &mut Agent, version: &str| -> Result<String, Error> {
+        let req = Request::get(&uri)
+            .header("x-api-version", version)
+            .with_body(())?;
+        agent.send(req).block()?.body_mut().read_to_string().block()
+    };
+
+    let fixtures = Fixtures::record(&path);
+    let mut agent = Agent::new();
+    agent.fixtures(fixtures.clone());
+    assert_eq!(send(&mut agent, "1")?, "0");
+    assert_eq!(send(&mut agent, "2")?, "1");
+
+    fixtures.save()?;
+    shut.shutdown().block();
+
+    let mut agent = Agent::new();
+    agent.fixtures(Fixtures::replay(&path)?.match_headers(&["x-api-version"])?);
+
+    assert_eq!(send(&mut agent, "2")?, "1");
+    assert_eq!(send(&mut agent, "1")?, "0");
+    assert!(send(&mut agent, "3").is_err());
+
+    assert!(Fixtures::replay(&path)?.match_headers(&["x api"]).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn fixtures_replay_in_order() -> Result<(), Error> {
+    common::setup_logger();
+
+    let path = fixture_path("in_order");
+
+    let mut server = Server::new();
+    server
+        .at("/count")
+        .get(|_req: http::Request<Body>| async move {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            COUNT.fetch_add(1, Ordering::SeqCst).to_string()
+        });
+    let (shut, addr) = server.listen(0).block()?;
+    let base = format!("http://127.0.0.1:{}", addr.port());
+    let uri = format!("{}/count", base);
+
+    let fixtures = Fixtures::record(&path);
+    let mut agent = Agent::new();
+    agent.fixtures(fixtures.clone());
+    for i in 0..3 {
+        assert_eq!(get(&mut agent, &uri)?, i.to_string());
+    }
+
+    fixtures.save()?;
+    shut.shutdown().block();
+
+    let mut agent = Agent::new();
+    agent.fixtures(Fixtures::replay(&path)?);
+
+    // played back in order, repeating the last.
+    for expected in &["0", "1", "2", "2"] {
+        assert_eq!(get(&mut agent, &uri)?, *expected);
+    }
+
+    Ok(())
+}
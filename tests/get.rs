@@ -122,7 +122,7 @@ fn res_body10mb_with_size() -> Result<(), Error> {
 fn res_body_larger_than_limit() -> Result<(), Error> {
     common::setup_logger();
 
-    const AMOUNT: usize = 1 * 1024 * 1024;
+    const AMOUNT: usize = 1024 * 1024;
     let mut server = Server::new();
 
     server.at("/path").all(|_: http::Request<Body>| async move {
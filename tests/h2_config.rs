@@ -0,0 +1,325 @@
+use hreq::prelude::*;
+use hreq::Agent;
+use hreq::Error;
+use hreq::Http2Config;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+
+#[test]
+fn h2_config_large_body() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server.http2_config(
+        Http2Config::new()
+            .initial_stream_window_size(256 * 1024)
+            .initial_connection_window_size(512 * 1024)
+            .max_frame_size(64 * 1024)
+            .max_concurrent_streams(10)
+            .bandwidth_auto_tune(false),
+    );
+
+    server
+        .at("/path")
+        .post(|mut req: http::Request<Body>| async move {
+            let v = req.body_mut().read_to_vec(10 * 1024 * 1024).await?;
+            Ok::<_, Error>(v)
+        });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let mut agent = Agent::new();
+
+    agent.http2_config(
+        Http2Config::new()
+            .initial_stream_window_size(1024 * 1024)
+            .initial_connection_window_size(1024 * 1024)
+            .max_frame_size(32 * 1024)
+            .bandwidth_auto_tune(false),
+    );
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    for _ in 0..3 {
+        let req = Request::post(&uri)
+            .force_http2(true)
+            .with_body(vec![42_u8; 2 * 1024 * 1024])?;
+
+        let mut res = agent.send(req).block()?;
+
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.version(), http::Version::HTTP_2);
+
+        let v = res.body_mut().read_to_vec(10 * 1024 * 1024).block()?;
+        assert_eq!(v.len(), 2 * 1024 * 1024);
+    }
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn h2_config_max_header_list_size() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server.http2_config(Http2Config::new().max_header_list_size(1024));
+
+    server
+        .at("/path")
+        .get(|_req: http::Request<Body>| async move { "ok" });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let req = Request::get(&uri)
+        .force_http2(true)
+        .header("x-big", "x".repeat(4096))
+        .with_body(())?;
+
+    let res = req.send().block();
+
+    // the server refuses the oversized header list.
+    assert!(res.is_err() || res.unwrap().status() != 200);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+#[should_panic(expected = "max_frame_size must be between 16KiB and 16MiB")]
+fn h2_config_max_frame_size_too_small() {
+    Http2Config::new().max_frame_size(1024);
+}
+
+#[test]
+#[should_panic(expected = "max_frame_size must be between 16KiB and 16MiB")]
+fn h2_config_max_frame_size_too_large() {
+    Http2Config::new().max_frame_size(16 * 1024 * 1024);
+}
+
+#[test]
+fn h2_keep_alive_client_dead_peer() -> Result<(), Error> {
+    common::setup_logger();
+
+    // a peer that accepts the connection, but never answers anything.
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let port = listener.local_addr()?.port();
+
+    let peer = thread::spawn(move || {
+        let (mut tcp, _) = listener.accept().unwrap();
+        let mut buf = vec![0; 16_384];
+        // read until the client gives up on the connection.
+        while let Ok(n) = tcp.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
+    });
+
+    let mut agent = Agent::new();
+    agent.retries(0);
+
+    agent.http2_config(
+        Http2Config::new()
+            .keep_alive_interval(Duration::from_millis(100))
+            .keep_alive_timeout(Duration::from_millis(200)),
+    );
+
+    let uri = format!("http://127.0.0.1:{}/path", port);
+
+    let req = Request::get(&uri)
+        .force_http2(true)
+        .timeout(Duration::from_secs(30))
+        .with_body(())?;
+
+    let start = Instant::now();
+    let res = agent.send(req).block();
+
+    // the dead connection fails the request instead of hanging until the timeout.
+    let err = res.unwrap_err();
+    assert!(!err.is_timeout());
+    assert!(start.elapsed() < Duration::from_secs(10));
+
+    peer.join().unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn h2_keep_alive_server_dead_peer() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server.http2_config(
+        Http2Config::new()
+            .keep_alive_interval(Duration::from_millis(100))
+            .keep_alive_timeout(Duration::from_millis(200)),
+    );
+
+    server
+        .at("/path")
+        .get(|_req: http::Request<Body>| async move { "ok" });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let port = addr.port();
+    let done = Arc::new(AtomicBool::new(false));
+    let done_peer = done.clone();
+
+    // a peer that starts an h2 connection and then goes silent.
+    let peer = thread::spawn(move || {
+        let mut tcp = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        tcp.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+
+        // h2 preface followed by an empty SETTINGS frame.
+        tcp.write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n").unwrap();
+        tcp.write_all(&[0, 0, 0, 4, 0, 0, 0, 0, 0]).unwrap();
+
+        let start = Instant::now();
+        let mut buf = vec![0; 16_384];
+        let mut received = vec![];
+
+        loop {
+            let n = tcp.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            received.extend_from_slice(&buf[..n]);
+        }
+
+        done_peer.store(true, Ordering::SeqCst);
+
+        (start.elapsed(), received)
+    });
+
+    // drive the server while the peer waits.
+    async {
+        while !done.load(Ordering::SeqCst) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+    .block();
+
+    let (elapsed, received) = peer.join().unwrap();
+
+    // the server closed the connection after pinging us.
+    assert!(elapsed < Duration::from_secs(10));
+    assert!(has_frame_type(&received, 6));
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn h2_keep_alive_only_when_idle() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server.http2_config(
+        Http2Config::new()
+            .bandwidth_auto_tune(false)
+            .keep_alive_interval(Duration::from_millis(200))
+            .keep_alive_timeout(Duration::from_secs(5)),
+    );
+
+    server
+        .at("/")
+        .get(|_req: http::Request<Body>| async move { "ok" });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let port = addr.port();
+    let done = Arc::new(AtomicBool::new(false));
+    let done_peer = done.clone();
+
+    let peer = thread::spawn(move || {
+        let mut tcp = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        tcp.set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        let mut write = tcp.try_clone().unwrap();
+
+        write
+            .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n")
+            .unwrap();
+        write.write_all(&[0, 0, 0, 4, 0, 0, 0, 0, 0]).unwrap();
+
+        let mut buf = vec![0; 16_384];
+        let mut received = vec![];
+        let mut read_some = |received: &mut Vec<u8>| {
+            if let Ok(n) = tcp.read(&mut buf) {
+                received.extend_from_slice(&buf[..n]);
+            }
+        };
+
+        // a request every 50ms keeps the connection busy for longer than the interval.
+        for i in 0..12_u8 {
+            // HEADERS, END_STREAM | END_HEADERS, GET http /
+            let stream_id = 1 + 2 * i;
+            let frame = [0, 0, 3, 1, 5, 0, 0, 0, stream_id, 0x82, 0x86, 0x84];
+            write.write_all(&frame).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            read_some(&mut received);
+        }
+
+        let busy_len = received.len();
+
+        // then idle, until a ping comes.
+        let start = Instant::now();
+        while first_frame_of_type(&received, 6).is_none()
+            && start.elapsed() < Duration::from_secs(5)
+        {
+            read_some(&mut received);
+        }
+
+        done_peer.store(true, Ordering::SeqCst);
+
+        (busy_len, first_frame_of_type(&received, 6))
+    });
+
+    async {
+        while !done.load(Ordering::SeqCst) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+    .block();
+
+    let (busy_len, ping_at) = peer.join().unwrap();
+
+    // no ping while busy, but one when idle.
+    let ping_at = ping_at.expect("Keep-alive ping when idle");
+    assert!(ping_at >= busy_len);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+fn has_frame_type(frames: &[u8], typ: u8) -> bool {
+    first_frame_of_type(frames, typ).is_some()
+}
+
+/// Offset of the first frame of the type.
+fn first_frame_of_type(frames: &[u8], typ: u8) -> Option<usize> {
+    let mut pos = 0;
+    while frames.len() >= pos + 9 {
+        if frames[pos + 3] == typ {
+            return Some(pos);
+        }
+        let len = (frames[pos] as usize) << 16
+            | (frames[pos + 1] as usize) << 8
+            | frames[pos + 2] as usize;
+        pos += 9 + len;
+    }
+    None
+}
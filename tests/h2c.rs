@@ -0,0 +1,151 @@
+use hreq::prelude::*;
+use hreq::Agent;
+use hreq::Error;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+mod common;
+
+#[test]
+fn h2c_upgrade() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server
+        .at("/path")
+        .post(|mut req: http::Request<Body>| async move {
+            assert_eq!(req.version(), http::Version::HTTP_2);
+            let s = req.body_mut().read_to_string().await?;
+            Ok::<_, Error>(format!("{} {}", req.header("x-n").unwrap(), s))
+        });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let mut agent = Agent::new();
+
+    for n in 0..3 {
+        let req = Request::post(&uri)
+            .h2c_upgrade(true)
+            .header("x-n", n.to_string())
+            .with_body("hello")?;
+
+        let mut res = agent.send(req).block()?;
+
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.version(), http::Version::HTTP_2);
+        assert_eq!(
+            res.body_mut().read_to_string().block()?,
+            format!("{} hello", n)
+        );
+    }
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn h2c_upgrade_no_body() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server
+        .at("/path")
+        .get(|req: http::Request<Body>| async move {
+            assert_eq!(req.version(), http::Version::HTTP_2);
+            req.uri().path().to_string()
+        });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path?x=1", addr.port());
+
+    let mut res = Request::get(&uri).h2c_upgrade(true).call().block()?;
+
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.version(), http::Version::HTTP_2);
+    assert_eq!(res.body_mut().read_to_string().block()?, "/path");
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn h2c_upgrade_large_body() -> Result<(), Error> {
+    common::setup_logger();
+
+    const SIZE: usize = 100 * 1024;
+
+    let mut server = Server::new();
+
+    // too large to hand over to http2, the server answers over http/1.1
+    server
+        .at("/path")
+        .post(|mut req: http::Request<Body>| async move {
+            assert_eq!(req.version(), http::Version::HTTP_11);
+            let v = req.body_mut().read_to_vec(SIZE * 2).await?;
+            Ok::<_, Error>(format!("{}", v.len()))
+        });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let req = Request::post(&uri)
+        .h2c_upgrade(true)
+        .with_body(vec![42_u8; SIZE])?;
+
+    let mut res = req.send().block()?;
+
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.version(), http::Version::HTTP_11);
+    assert_eq!(res.body_mut().read_to_string().block()?, SIZE.to_string());
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn h2c_upgrade_refused() -> Result<(), Error> {
+    common::setup_logger();
+
+    // an http/1.1 server that doesn't know about h2c.
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let port = listener.local_addr()?.port();
+
+    let server = thread::spawn(move || {
+        let (mut tcp, _) = listener.accept().unwrap();
+
+        let mut head = vec![];
+        let mut buf = [0_u8; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            tcp.read_exact(&mut buf).unwrap();
+            head.push(buf[0]);
+        }
+        tcp.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+            .unwrap();
+
+        String::from_utf8(head).unwrap()
+    });
+
+    let uri = format!("http://127.0.0.1:{}/path", port);
+
+    let mut res = Request::get(&uri).h2c_upgrade(true).call().block()?;
+
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.version(), http::Version::HTTP_11);
+    assert_eq!(res.body_mut().read_to_string().block()?, "ok");
+
+    let head = server.join().unwrap();
+
+    // the request itself asks for the upgrade.
+    assert!(head.starts_with("GET /path HTTP/1.1\r\n"));
+    assert!(head.contains("upgrade: h2c\r\n"));
+    assert!(head.contains("http2-settings: AAIAAAAA\r\n"));
+
+    Ok(())
+}
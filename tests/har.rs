@@ -0,0 +1,171 @@
+use hreq::prelude::*;
+use hreq::{Agent, Error, Har};
+use serde_json::Value;
+use std::time::Duration;
+
+mod common;
+
+fn entries(har: &Har) -> Vec<Value> {
+    let json: Value = serde_json::from_str(&har.to_json()).unwrap();
+    assert_eq!(json["log"]["version"], "1.2");
+    assert_eq!(json["log"]["creator"]["name"], "hreq");
+    json["log"]["entries"].as_array().unwrap().clone()
+}
+
+fn header<'a>(headers: &'a Value, name: &str) -> Option<&'a str> {
+    headers
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|h| h["name"] == name)
+        .and_then(|h| h["value"].as_str())
+}
+
+#[test]
+fn har_redirect_cookies_bodies() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server
+        .at("/login")
+        .get(|_req: http::Request<Body>| async move {
+            http::Response::builder()
+                .status(302)
+                .header("set-cookie", "session=secret; Path=/; HttpOnly")
+                .header("location", "/whoami")
+                .body(())
+                .unwrap()
+        });
+    server
+        .at("/whoami")
+        .get(|req: http::Request<Body>| async move {
+            req.header("cookie").unwrap_or("nobody").to_string()
+        });
+    server
+        .at("/echo")
+        .post(|mut req: http::Request<Body>| async move {
+            let body = req.body_mut().read_to_string().await?;
+            Ok::<_, Error>(format!("echo {}", body))
+        });
+    let (shut, addr) = server.listen(0).block()?;
+    let base = format!("http://127.0.0.1:{}", addr.port());
+
+    let har = Har::new().bodies(true);
+
+    let mut agent = Agent::new();
+    agent.har(har.clone());
+
+    let req = Request::get(format!("{}/login?user=martin", base)).with_body(())?;
+    let mut res = agent.send(req).block()?;
+    assert_eq!(res.body_mut().read_to_string().block()?, "session=secret");
+
+    let req = Request::post(format!("{}/echo", base)).with_body("hello")?;
+    let mut res = agent.send(req).block()?;
+    assert_eq!(res.body_mut().read_to_string().block()?, "echo hello");
+
+    let entries = entries(&har);
+    assert_eq!(entries.len(), 3);
+
+    // the redirect
+    let e = &entries[0];
+    assert_eq!(e["request"]["method"], "GET");
+    assert_eq!(e["request"]["queryString"][0]["name"], "user");
+    assert_eq!(e["request"]["queryString"][0]["value"], "martin");
+    assert_eq!(e["response"]["status"], 302);
+    assert_eq!(e["response"]["redirectURL"], "/whoami");
+    assert_eq!(e["response"]["cookies"][0]["name"], "session");
+    assert_eq!(e["response"]["cookies"][0]["httpOnly"], true);
+    assert!(e["startedDateTime"].as_str().unwrap().ends_with('Z'));
+    assert!(e["timings"]["wait"].as_f64().unwrap() >= 0.0);
+
+    // following it, with the cookie
+    let e = &entries[1];
+    assert_eq!(e["request"]["cookies"][0]["value"], "secret");
+    assert_eq!(e["response"]["status"], 200);
+    assert_eq!(e["response"]["content"]["text"], "session=secret");
+    assert_eq!(e["response"]["content"]["size"], 14);
+
+    let e = &entries[2];
+    assert_eq!(e["request"]["method"], "POST");
+    assert_eq!(e["request"]["postData"]["text"], "hello");
+    assert_eq!(e["request"]["bodySize"], 5);
+    assert_eq!(
+        header(&e["request"]["headers"], "content-length"),
+        Some("5")
+    );
+    assert_eq!(e["response"]["content"]["text"], "echo hello");
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn har_without_bodies() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server
+        .at("/echo")
+        .post(|mut req: http::Request<Body>| async move {
+            let body = req.body_mut().read_to_string().await?;
+            Ok::<_, Error>(format!("echo {}", body))
+        });
+    let (shut, addr) = server.listen(0).block()?;
+    let base = format!("http://127.0.0.1:{}", addr.port());
+
+    let har = Har::new();
+
+    let mut agent = Agent::new();
+    agent.har(har.clone());
+
+    let req = Request::post(format!("{}/echo", base)).with_body("hello")?;
+    let mut res = agent.send(req).block()?;
+    assert_eq!(res.body_mut().read_to_string().block()?, "echo hello");
+
+    let entries = entries(&har);
+    assert_eq!(entries.len(), 1);
+
+    let e = &entries[0];
+    assert!(e["request"].get("postData").is_none());
+    assert!(e["response"]["content"].get("text").is_none());
+    assert_eq!(e["response"]["content"]["size"], 10);
+
+    har.clear();
+    assert!(har.is_empty());
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn har_failed_request() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server
+        .at("/slow")
+        .get(|_req: http::Request<Body>| async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            "slow"
+        });
+    let (shut, addr) = server.listen(0).block()?;
+    let base = format!("http://127.0.0.1:{}", addr.port());
+
+    let har = Har::new();
+
+    let mut agent = Agent::new();
+    agent.har(har.clone());
+
+    let req = Request::get(format!("{}/slow", base))
+        .timeout(Duration::from_millis(100))
+        .with_body(())?;
+    assert!(agent.send(req).block().is_err());
+
+    let entries = entries(&har);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["response"]["status"], 0);
+    assert!(entries[0]["_error"].as_str().is_some());
+
+    shut.shutdown().block();
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use hreq::prelude::*;
+use hreq::{Agent, Error};
+
+mod common;
+
+fn server() -> Server<()> {
+    let mut server = Server::new();
+    server
+        .at("/login")
+        .get(|_req: http::Request<Body>| async move {
+            http::Response::builder()
+                .status(302)
+                .header("set-cookie", "session=secret")
+                .header("location", "/whoami")
+                .body(())
+                .unwrap()
+        });
+    server
+        .at("/whoami")
+        .get(|req: http::Request<Body>| async move {
+            req.header("cookie").unwrap_or("nobody").to_string()
+        });
+    server
+        .at("/echo")
+        .post(|mut req: http::Request<Body>| async move {
+            req.body_mut().read_to_vec(10 * 1024 * 1024).await
+        });
+    server
+}
+
+#[test]
+fn in_process_cookies_redirect() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut agent = Agent::new();
+    agent.in_process(&server());
+
+    let req = Request::get("http://my-service/login").with_body(())?;
+    let mut res = agent.send(req).block()?;
+
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.body_mut().read_to_string().block()?, "session=secret");
+
+    // the cookie is kept for the next request.
+    let req = Request::get("http://my-service/whoami").with_body(())?;
+    let mut res = agent.send(req).block()?;
+    assert_eq!(res.body_mut().read_to_string().block()?, "session=secret");
+
+    Ok(())
+}
+
+#[test]
+fn in_process_gzip_body() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut agent = Agent::new();
+    agent.in_process(&server());
+
+    let data: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+
+    let req = Request::post("http://my-service/echo")
+        .header("content-encoding", "gzip")
+        .with_body(data.clone())?;
+    let mut res = agent.send(req).block()?;
+
+    assert_eq!(res.body_mut().read_to_vec(10 * 1024 * 1024).block()?, data);
+
+    Ok(())
+}
+
+#[test]
+fn in_process_http2() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut agent = Agent::new();
+    agent.in_process(&server());
+
+    let req = Request::post("http://my-service/echo")
+        .force_http2(true)
+        .with_body("over http2")?;
+    let mut res = agent.send(req).block()?;
+
+    assert_eq!(res.version(), http::Version::HTTP_2);
+    assert_eq!(res.body_mut().read_to_string().block()?, "over http2");
+
+    Ok(())
+}
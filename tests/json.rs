@@ -1,6 +1,7 @@
 use hreq::prelude::*;
-use hreq::Error;
+use hreq::{Error, Json};
 use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct MyJsonStruct {
@@ -55,3 +56,128 @@ fn json_recv() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[test]
+fn serde_json_round_trip() -> Result<(), Error> {
+    let mut server = Server::new();
+
+    server
+        .at("/path")
+        .all(|mut req: http::Request<Body>| async move {
+            let mut obj: MyJsonStruct = req.body_mut().read_as::<Json, _>().await?;
+            obj.number += 1;
+            http::Response::builder().with_serde::<Json, _>(&obj)
+        });
+
+    let obj = MyJsonStruct { number: 42 };
+    let req = http::Request::post("/path").with_serde::<Json, _>(&obj)?;
+
+    let res = server.handle(req).block()?;
+    assert_eq!(
+        res.header("content-type"),
+        Some("application/json; charset=utf-8")
+    );
+
+    let obj: MyJsonStruct = res.into_body().read_as::<Json, _>().block()?;
+    assert_eq!(obj.number, 43);
+
+    Ok(())
+}
+
+#[test]
+fn serde_wrong_content_type() -> Result<(), Error> {
+    let mut server = Server::new();
+
+    server.at("/path").all(|_: http::Request<Body>| async move {
+        http::Response::builder()
+            .header("content-type", "text/html")
+            .body("{\"number\":42}")
+            .unwrap()
+    });
+
+    let req = http::Request::get("/path").body(())?;
+    let res = server.handle(req).block()?;
+
+    let err = res
+        .into_body()
+        .read_as::<Json, MyJsonStruct>()
+        .block()
+        .unwrap_err();
+    assert!(err.to_string().contains("text/html"));
+
+    Ok(())
+}
+
+#[test]
+fn serde_serialize_error() -> Result<(), Error> {
+    // JSON map keys must be strings.
+    let mut obj = HashMap::new();
+    obj.insert((1, 2), 3);
+
+    assert!(Body::from_serde::<Json, _>(&obj).is_err());
+
+    let err = http::Response::builder()
+        .with_serde::<Json, _>(&obj)
+        .unwrap_err();
+    assert!(matches!(err, Error::Json(_)));
+
+    let err = Request::post("http://127.0.0.1:1/path")
+        .send_serde::<Json, _>(&obj)
+        .block()
+        .unwrap_err();
+    assert!(matches!(err, Error::Json(_)));
+
+    Ok(())
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn msgpack_round_trip() -> Result<(), Error> {
+    use hreq::MsgPack;
+
+    let mut server = Server::new();
+
+    server
+        .at("/path")
+        .all(|mut req: http::Request<Body>| async move {
+            let obj: MyJsonStruct = req.body_mut().read_as::<MsgPack, _>().await?;
+            http::Response::builder().with_serde::<MsgPack, _>(&obj)
+        });
+
+    let obj = MyJsonStruct { number: 42 };
+    let req = http::Request::post("/path").with_serde::<MsgPack, _>(&obj)?;
+
+    let res = server.handle(req).block()?;
+    assert_eq!(res.header("content-type"), Some("application/msgpack"));
+
+    let obj: MyJsonStruct = res.into_body().read_as::<MsgPack, _>().block()?;
+    assert_eq!(obj.number, 42);
+
+    Ok(())
+}
+
+#[cfg(feature = "cbor")]
+#[test]
+fn cbor_round_trip() -> Result<(), Error> {
+    use hreq::Cbor;
+
+    let mut server = Server::new();
+
+    server
+        .at("/path")
+        .all(|mut req: http::Request<Body>| async move {
+            let obj: MyJsonStruct = req.body_mut().read_as::<Cbor, _>().await?;
+            http::Response::builder().with_serde::<Cbor, _>(&obj)
+        });
+
+    let obj = MyJsonStruct { number: 42 };
+    let req = http::Request::post("/path").with_serde::<Cbor, _>(&obj)?;
+
+    let res = server.handle(req).block()?;
+    assert_eq!(res.header("content-type"), Some("application/cbor"));
+
+    let obj: MyJsonStruct = res.into_body().read_as::<Cbor, _>().block()?;
+    assert_eq!(obj.number, 42);
+
+    Ok(())
+}
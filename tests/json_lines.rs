@@ -0,0 +1,100 @@
+use futures_util::stream::{self, StreamExt};
+use hreq::prelude::*;
+use hreq::Error;
+use serde_derive::{Deserialize, Serialize};
+
+mod common;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Record {
+    id: u64,
+}
+
+#[test]
+fn json_lines_upload_and_reply() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server
+        .at("/double")
+        .post(|mut req: http::Request<Body>| async move {
+            assert_eq!(req.header("content-type"), Some("application/x-ndjson"));
+
+            let mut ids = vec![];
+            let mut records = req.body_mut().json_lines::<Record>();
+            while let Some(record) = records.next().await {
+                ids.push(record?.id);
+            }
+
+            let doubled = stream::iter(ids).map(|id| Record { id: id * 2 });
+            Ok::<_, Error>(Body::from_json_lines_stream(doubled))
+        });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/double", addr.port());
+    let records = (0..10_000).map(|id| Record { id });
+    let mut res = Request::post(&uri)
+        .with_body(Body::from_json_lines(records))?
+        .send()
+        .block()?;
+
+    let mut count = 0;
+    let mut lines = res.body_mut().json_lines::<Record>();
+    while let Some(record) = lines.next().block() {
+        assert_eq!(record?, Record { id: count * 2 });
+        count += 1;
+    }
+    assert_eq!(count, 10_000);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+// a received body with the given content.
+fn received(content: &'static str) -> Result<Body, Error> {
+    let mut server = Server::new();
+    server
+        .at("/")
+        .get(move |_: http::Request<Body>| async move { content });
+
+    let req = http::Request::get("/").body(())?;
+    let res = server.handle(req).block()?;
+
+    Ok(res.into_body())
+}
+
+#[test]
+fn json_lines_blank_and_bad_lines() -> Result<(), Error> {
+    let mut body = received("{\"id\":1}\r\n\n  \nnope\n{\"id\":2}")?;
+
+    let items: Vec<_> = body.json_lines::<Record>().collect::<Vec<_>>().block();
+
+    assert_eq!(items.len(), 3);
+    assert_eq!(items[0].as_ref().unwrap(), &Record { id: 1 });
+    assert!(items[1].is_err());
+    assert_eq!(items[2].as_ref().unwrap(), &Record { id: 2 });
+
+    Ok(())
+}
+
+#[test]
+fn json_lines_max_line_size() -> Result<(), Error> {
+    let mut body = received(concat!(
+        "{\"id\":1}\n",
+        "\"a line that is a little longer than fifty bytes in all\"\n",
+        "{\"id\":2}\n"
+    ))?;
+
+    let items: Vec<_> = body
+        .json_lines::<Record>()
+        .max_line_size(50)
+        .collect::<Vec<_>>()
+        .block();
+
+    assert_eq!(items.len(), 2);
+    assert!(items[0].is_ok());
+    assert!(items[1].is_err());
+
+    Ok(())
+}
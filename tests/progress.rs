@@ -0,0 +1,94 @@
+use hreq::prelude::*;
+use hreq::{Agent, Direction, Error, Progress};
+use std::sync::{Arc, Mutex};
+
+mod common;
+
+const SIZE: usize = 100 * 1024;
+
+fn echo_server() -> Result<(hreq::server::ServerHandle, String), Error> {
+    let mut server = Server::new();
+    server
+        .at("/echo")
+        .post(|mut req: http::Request<Body>| async move { req.body_mut().read_to_vec(SIZE).await });
+    let (shut, addr) = server.listen(0).block()?;
+    Ok((shut, format!("http://127.0.0.1:{}/echo", addr.port())))
+}
+
+fn last(all: &[Progress], direction: Direction) -> Progress {
+    let mut seen: Vec<_> = all.iter().filter(|p| p.direction == direction).collect();
+    assert!(!seen.is_empty());
+
+    // transferred bytes only ever grow.
+    for w in seen.windows(2) {
+        assert!(w[0].transferred < w[1].transferred);
+    }
+
+    *seen.pop().unwrap()
+}
+
+#[test]
+fn progress_upload_download() -> Result<(), Error> {
+    common::setup_logger();
+
+    let (shut, uri) = echo_server()?;
+
+    let all = Arc::new(Mutex::new(vec![]));
+    let all_cb = all.clone();
+
+    let mut res = Request::post(&uri)
+        .on_progress(move |p| all_cb.lock().unwrap().push(p))
+        .send(vec![42_u8; SIZE])
+        .block()?;
+
+    assert_eq!(res.body_mut().read_to_vec(SIZE).block()?.len(), SIZE);
+
+    let all = all.lock().unwrap();
+
+    let up = last(&all, Direction::Upload);
+    assert_eq!(up.transferred, SIZE as u64);
+    assert_eq!(up.total, Some(SIZE as u64));
+
+    let down = last(&all, Direction::Download);
+    assert_eq!(down.transferred, SIZE as u64);
+    assert_eq!(down.total, Some(SIZE as u64));
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn progress_agent() -> Result<(), Error> {
+    common::setup_logger();
+
+    let (shut, uri) = echo_server()?;
+
+    let agent_calls = Arc::new(Mutex::new(0));
+    let agent_calls_cb = agent_calls.clone();
+
+    let mut agent = Agent::new();
+    agent.on_progress(move |_| *agent_calls_cb.lock().unwrap() += 1);
+
+    let req = Request::post(&uri).with_body("hello")?;
+    let mut res = agent.send(req).block()?;
+    assert_eq!(res.body_mut().read_to_string().block()?, "hello");
+
+    // one call for each direction
+    assert_eq!(*agent_calls.lock().unwrap(), 2);
+
+    // the request callback takes precedence.
+    let req_calls = Arc::new(Mutex::new(0));
+    let req_calls_cb = req_calls.clone();
+
+    let req = Request::post(&uri)
+        .on_progress(move |_| *req_calls_cb.lock().unwrap() += 1)
+        .with_body("hello")?;
+    let mut res = agent.send(req).block()?;
+    assert_eq!(res.body_mut().read_to_string().block()?, "hello");
+
+    assert_eq!(*agent_calls.lock().unwrap(), 2);
+    assert_eq!(*req_calls.lock().unwrap(), 2);
+
+    shut.shutdown().block();
+    Ok(())
+}
@@ -0,0 +1,113 @@
+use hreq::prelude::*;
+use hreq::{Agent, Error, RateLimit, TimeoutKind};
+use std::time::{Duration, Instant};
+
+mod common;
+
+#[test]
+fn rate_limit_requests_per() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server
+        .at("/path")
+        .get(|_req: http::Request<Body>| async move { "hello" });
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let mut agent = Agent::new();
+    agent.rate_limit(RateLimit::new().requests_per(2, Duration::from_millis(400)));
+
+    let start = Instant::now();
+
+    // two in a burst, then one every 200ms.
+    for _ in 0..4 {
+        let req = Request::get(&uri).with_body(())?;
+        let mut res = agent.send(req).block()?;
+        assert_eq!(res.body_mut().read_to_string().block()?, "hello");
+    }
+
+    assert!(start.elapsed() >= Duration::from_millis(350));
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn rate_limit_queue_timeout() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server
+        .at("/path")
+        .get(|_req: http::Request<Body>| async move { "hello" });
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let mut agent = Agent::new();
+    agent.rate_limit(
+        RateLimit::new()
+            .requests_per(1, Duration::from_secs(10))
+            .queue_timeout(Duration::from_millis(100)),
+    );
+
+    let req = Request::get(&uri).with_body(())?;
+    agent
+        .send(req)
+        .block()?
+        .body_mut()
+        .read_and_discard()
+        .block()?;
+
+    let req = Request::get(&uri).with_body(())?;
+    let err = agent.send(req).block().unwrap_err();
+    assert!(err.is_timeout());
+    assert_eq!(err.timeout_kind(), Some(TimeoutKind::Queue));
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn rate_limit_max_in_flight() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server
+        .at("/path")
+        .get(|_req: http::Request<Body>| async move { "hello" });
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let limit = RateLimit::new().max_in_flight(1);
+
+    let mut agent1 = Agent::new();
+    agent1.rate_limit(limit.clone());
+    let mut agent2 = Agent::new();
+    agent2.rate_limit(limit);
+
+    // in flight until the body is read.
+    let req = Request::get(&uri).with_body(())?;
+    let mut res1 = agent1.send(req).block()?;
+
+    // waiting in the queue counts towards the request timeout.
+    let req = Request::get(&uri)
+        .timeout(Duration::from_millis(100))
+        .with_body(())?;
+    let err = agent2.send(req).block().unwrap_err();
+    assert!(err.is_timeout());
+
+    assert_eq!(res1.body_mut().read_to_string().block()?, "hello");
+
+    let req = Request::get(&uri)
+        .timeout(Duration::from_millis(1000))
+        .with_body(())?;
+    let mut res2 = agent2.send(req).block()?;
+    assert_eq!(res2.body_mut().read_to_string().block()?, "hello");
+
+    shut.shutdown().block();
+    Ok(())
+}
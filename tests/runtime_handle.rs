@@ -0,0 +1,105 @@
+use hreq::prelude::*;
+use hreq::{Agent, Error, TimeoutKind};
+use std::time::Duration;
+use tokio::runtime::{Builder, Handle};
+
+mod common;
+
+async fn exercise(name: &str) -> Result<(), Error> {
+    let mut server = Server::new();
+    server.runtime(Handle::current());
+    server
+        .at("/name")
+        .get(|_: http::Request<Body>| async move { "Ok" });
+    server.at("/slow").get(|_: http::Request<Body>| async move {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        "Ok"
+    });
+
+    let (shut, addr) = server.listen(0).await?;
+
+    let mut agent = Agent::new();
+    agent.runtime(Handle::current());
+
+    for force_http2 in &[false, true] {
+        let req = Request::get(&format!("http://127.0.0.1:{}/name", addr.port()))
+            .force_http2(*force_http2)
+            .with_body(())?;
+        let mut res = agent.send(req).await?;
+        assert_eq!(res.body_mut().read_to_string().await?, "Ok", "{}", name);
+    }
+
+    let req = Request::get(&format!("http://127.0.0.1:{}/slow", addr.port()))
+        .response_header_timeout(Duration::from_millis(100))
+        .with_body(())?;
+    let err = agent.send(req).await.unwrap_err();
+    assert_eq!(err.timeout_kind(), Some(TimeoutKind::ResponseHeaders));
+
+    shut.shutdown().await;
+
+    Ok(())
+}
+
+#[test]
+fn separate_runtimes() -> Result<(), Error> {
+    common::setup_logger();
+
+    // a multi thread runtime next to current thread ones. one worker, since hreq-h1
+    // can miss the end of a body read on another thread than the connection.
+    let main = Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()?;
+
+    let threads: Vec<_> = (0..2)
+        .map(|i| {
+            std::thread::spawn(move || {
+                let rt = Builder::new_current_thread().enable_all().build()?;
+                rt.block_on(exercise(&format!("current thread {}", i)))
+            })
+        })
+        .collect();
+
+    main.block_on(main.spawn(exercise("multi thread")))
+        .unwrap()?;
+
+    for t in threads {
+        t.join().unwrap()?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn body_timers_in_agent_runtime() -> Result<(), Error> {
+    common::setup_logger();
+
+    let rt = Builder::new_multi_thread().enable_all().build()?;
+
+    let mut server = Server::new();
+    server.runtime(rt.handle().clone());
+    server
+        .at("/name")
+        .get(|_: http::Request<Body>| async move { "Ok" });
+
+    let (shut, addr) = rt.block_on(server.listen(0))?;
+
+    let mut agent = Agent::new();
+    agent.runtime(rt.handle().clone());
+
+    // http2, since the body is read on another thread than the connection.
+    let req = Request::get(&format!("http://127.0.0.1:{}/name", addr.port()))
+        .force_http2(true)
+        .timeout(Duration::from_secs(5))
+        .with_body(())?;
+    let mut res = rt.block_on(agent.send(req))?;
+
+    // a runtime without timers, the deadline of the body must use the agent's.
+    let other = Builder::new_current_thread().enable_io().build()?;
+    let text = other.block_on(res.body_mut().read_to_string())?;
+    assert_eq!(text, "Ok");
+
+    rt.block_on(shut.shutdown());
+
+    Ok(())
+}
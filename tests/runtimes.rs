@@ -0,0 +1,110 @@
+#![cfg(all(feature = "server", any(feature = "async-std", feature = "smol")))]
+
+use hreq::prelude::*;
+use hreq::server::Static;
+use hreq::{AsyncRuntime, Error, TimeoutKind};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+mod common;
+
+type Sleep = fn(Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+
+fn exercise(sleep: Sleep) -> Result<(), Error> {
+    let dir = std::env::temp_dir().join(format!("hreq-runtimes-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let file = dir.join("data.bin");
+    let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&file, &data)?;
+
+    let mut server = Server::new();
+    server
+        .at("/echo")
+        .post(|mut req: http::Request<Body>| async move {
+            req.body_mut().read_to_vec(10 * 1024 * 1024).await
+        });
+    server.at("/file").get(Static::file(&file));
+    server
+        .at("/slow")
+        .get(move |_: http::Request<Body>| async move {
+            sleep(Duration::from_secs(1)).await;
+            "Ok"
+        });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    for force_http2 in &[false, true] {
+        // a file body is read by the runtime.
+        let body = Body::from_file(std::fs::File::open(&file)?);
+        let mut res = Request::post(&format!("http://127.0.0.1:{}/echo", addr.port()))
+            .force_http2(*force_http2)
+            .with_body(body)?
+            .send()
+            .block()?;
+        assert_eq!(res.body_mut().read_to_vec(10 * 1024 * 1024).block()?, data);
+
+        let mut res = Request::get(&format!("http://127.0.0.1:{}/file", addr.port()))
+            .force_http2(*force_http2)
+            .call()
+            .block()?;
+        assert_eq!(res.body_mut().read_to_vec(10 * 1024 * 1024).block()?, data);
+    }
+
+    // timers
+    let err = Request::get(&format!("http://127.0.0.1:{}/slow", addr.port()))
+        .response_header_timeout(Duration::from_millis(100))
+        .call()
+        .block()
+        .unwrap_err();
+    assert_eq!(err.timeout_kind(), Some(TimeoutKind::ResponseHeaders));
+
+    shut.shutdown().block();
+
+    #[cfg(unix)]
+    {
+        let path = dir.join("server.sock");
+        let _ = std::fs::remove_file(&path);
+
+        let mut server = Server::new();
+        server.at("/").get(|req: http::Request<Body>| async move {
+            req.header("host").unwrap_or("-").to_string()
+        });
+        let shut = server.listen_unix(&path).block()?;
+
+        let mut res = Request::get("http://my-sidecar/")
+            .unix_socket(&path)
+            .call()
+            .block()?;
+        assert_eq!(res.body_mut().read_to_string().block()?, "my-sidecar");
+
+        shut.shutdown().block();
+    }
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+// The runtime is a global, which is why they're all tried in one test.
+#[test]
+fn alternative_runtimes() -> Result<(), Error> {
+    common::setup_logger();
+
+    #[cfg(feature = "async-std")]
+    {
+        AsyncRuntime::AsyncStd.make_default();
+        exercise(|d| Box::pin(async_std::task::sleep(d)))?;
+    }
+
+    #[cfg(feature = "smol")]
+    {
+        AsyncRuntime::Smol.make_default();
+        exercise(|d| {
+            Box::pin(async move {
+                smol::Timer::after(d).await;
+            })
+        })?;
+    }
+
+    Ok(())
+}
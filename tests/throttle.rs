@@ -0,0 +1,172 @@
+use hreq::prelude::*;
+use hreq::server::Static;
+use hreq::{Agent, Error};
+use std::fs;
+use std::time::{Duration, Instant};
+
+mod common;
+
+// the first second's worth is a burst, the next takes a second.
+const RATE: u64 = 50_000;
+const SIZE: usize = 100_000;
+const MIN_TIME: Duration = Duration::from_millis(900);
+
+fn data_server() -> Result<(hreq::server::ServerHandle, String), Error> {
+    let mut server = Server::new();
+    server
+        .at("/data")
+        .get(|_req: http::Request<Body>| async move { vec![42_u8; SIZE] })
+        .post(|mut req: http::Request<Body>| async move {
+            req.body_mut().read_and_discard().await?;
+            Ok::<_, Error>("ok")
+        });
+    server
+        .at("/throttled")
+        .get(|_req: http::Request<Body>| async move {
+            http::Response::builder()
+                .throttle(RATE)
+                .body(vec![42_u8; SIZE])
+                .unwrap()
+        });
+    let (shut, addr) = server.listen(0).block()?;
+    Ok((shut, format!("http://127.0.0.1:{}", addr.port())))
+}
+
+#[test]
+fn throttle_response_body() -> Result<(), Error> {
+    common::setup_logger();
+
+    let (shut, base) = data_server()?;
+
+    let start = Instant::now();
+
+    let mut res = Request::get(format!("{}/data", base))
+        .throttle(RATE)
+        .call()
+        .block()?;
+    assert_eq!(res.body_mut().read_to_vec(SIZE).block()?.len(), SIZE);
+
+    assert!(start.elapsed() >= MIN_TIME);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn throttle_request_body() -> Result<(), Error> {
+    common::setup_logger();
+
+    let (shut, base) = data_server()?;
+
+    let start = Instant::now();
+
+    let mut res = Request::post(format!("{}/data", base))
+        .throttle(RATE)
+        .send(vec![42_u8; SIZE])
+        .block()?;
+    assert_eq!(res.body_mut().read_to_string().block()?, "ok");
+
+    assert!(start.elapsed() >= MIN_TIME);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn throttle_each_body() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+    server
+        .at("/echo")
+        .post(|mut req: http::Request<Body>| async move {
+            let v = req.body_mut().read_to_vec(SIZE).await?;
+            Ok::<_, Error>(v)
+        });
+    let (shut, addr) = server.listen(0).block()?;
+
+    let start = Instant::now();
+
+    // request and response are each within the burst of their own limit.
+    let uri = format!("http://127.0.0.1:{}/echo", addr.port());
+    let mut res = Request::post(uri)
+        .throttle(RATE)
+        .send(vec![42_u8; RATE as usize])
+        .block()?;
+    assert_eq!(
+        res.body_mut().read_to_vec(SIZE).block()?.len(),
+        RATE as usize
+    );
+
+    assert!(start.elapsed() < MIN_TIME);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn throttle_agent_shared() -> Result<(), Error> {
+    common::setup_logger();
+
+    let (shut, base) = data_server()?;
+
+    let mut agent = Agent::new();
+    agent.throttle(RATE);
+
+    let start = Instant::now();
+
+    // each request alone is within the burst, together they are not.
+    for _ in 0..2 {
+        let req = Request::get(format!("{}/data", base)).with_body(())?;
+        let mut res = agent.send(req).block()?;
+        assert_eq!(res.body_mut().read_to_vec(SIZE).block()?.len(), SIZE);
+    }
+
+    assert!(start.elapsed() >= Duration::from_millis(1900));
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn throttle_server_response() -> Result<(), Error> {
+    common::setup_logger();
+
+    let (shut, base) = data_server()?;
+
+    let start = Instant::now();
+
+    let mut res = Request::get(format!("{}/throttled", base)).call().block()?;
+    assert_eq!(res.body_mut().read_to_vec(SIZE).block()?.len(), SIZE);
+
+    assert!(start.elapsed() >= MIN_TIME);
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn throttle_static() -> Result<(), Error> {
+    common::setup_logger();
+
+    let dir = std::env::temp_dir().join(format!("hreq-throttle-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let file = dir.join("data.bin");
+    fs::write(&file, vec![42_u8; SIZE])?;
+
+    let mut server = Server::new();
+    server.at("/file").get(Static::file(&file).throttle(RATE));
+    let (shut, addr) = server.listen(0).block()?;
+
+    let start = Instant::now();
+
+    let uri = format!("http://127.0.0.1:{}/file", addr.port());
+    let mut res = Request::get(uri).call().block()?;
+    assert_eq!(res.body_mut().read_to_vec(SIZE).block()?.len(), SIZE);
+
+    assert!(start.elapsed() >= MIN_TIME);
+
+    shut.shutdown().block();
+    let _ = fs::remove_dir_all(&dir);
+    Ok(())
+}
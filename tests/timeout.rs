@@ -1,10 +1,11 @@
 use futures_util::io::AsyncRead;
 use hreq::prelude::*;
-use hreq::Error;
+use hreq::{Agent, Error, HostPort, TimeoutKind};
+use std::future::Future;
 use std::io;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 mod common;
 
@@ -20,6 +21,94 @@ impl AsyncRead for NeverRead {
     }
 }
 
+/// Sends a few chunks with a gap before each, then ends or stalls.
+struct Trickle {
+    chunks: usize,
+    gap: Duration,
+    stall: bool,
+    sleep: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl Trickle {
+    fn new(chunks: usize, gap: Duration, stall: bool) -> Self {
+        Trickle {
+            chunks,
+            gap,
+            stall,
+            sleep: None,
+        }
+    }
+}
+
+impl AsyncRead for Trickle {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if this.chunks == 0 {
+            return if this.stall {
+                Poll::Pending
+            } else {
+                Poll::Ready(Ok(0))
+            };
+        }
+
+        let gap = this.gap;
+        let sleep = this
+            .sleep
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep(gap)));
+
+        if sleep.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        this.sleep = None;
+        this.chunks -= 1;
+
+        buf[..6].copy_from_slice(b"chunk\n");
+        Poll::Ready(Ok(6))
+    }
+}
+
+fn trickle_server() -> Result<(hreq::server::ServerHandle, u16), Error> {
+    let mut server = Server::new();
+
+    server
+        .at("/slow-headers")
+        .get(|_: http::Request<Body>| async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            "Ok"
+        });
+
+    server
+        .at("/trickle")
+        .get(|_: http::Request<Body>| async move {
+            Response::builder()
+                .prebuffer_response_body(false)
+                .body(Body::from_async_read(
+                    Trickle::new(6, Duration::from_millis(100), false),
+                    None,
+                ))
+        });
+
+    server
+        .at("/stall")
+        .get(|_: http::Request<Body>| async move {
+            Response::builder()
+                .prebuffer_response_body(false)
+                .body(Body::from_async_read(
+                    Trickle::new(2, Duration::from_millis(10), true),
+                    None,
+                ))
+        });
+
+    let (shut, addr) = server.listen(0).block()?;
+    Ok((shut, addr.port()))
+}
+
 #[test]
 fn request_body_timeout() -> Result<(), Error> {
     common::setup_logger();
@@ -80,9 +169,117 @@ fn response_body_timeout() -> Result<(), Error> {
     assert!(r.is_err());
     let err = r.unwrap_err();
     assert!(err.is_io());
+    assert_eq!(err.timeout_kind(), Some(TimeoutKind::Request));
     assert_eq!(err.into_io().unwrap().kind(), io::ErrorKind::TimedOut);
 
     // deliberately not await this since it will never complete
-    let _ = shut.shutdown();
+    drop(shut.shutdown());
+    Ok(())
+}
+
+#[test]
+fn response_header_timeout() -> Result<(), Error> {
+    common::setup_logger();
+
+    let (shut, port) = trickle_server()?;
+
+    for force_http2 in &[false, true] {
+        let uri = format!("http://127.0.0.1:{}/slow-headers", port);
+
+        let err = Request::get(&uri)
+            .force_http2(*force_http2)
+            .response_header_timeout(Duration::from_millis(100))
+            .call()
+            .block()
+            .unwrap_err();
+
+        assert!(err.is_timeout());
+        assert_eq!(err.timeout_kind(), Some(TimeoutKind::ResponseHeaders));
+        assert_eq!(err.to_string(), "response header timeout");
+    }
+
+    drop(shut.shutdown());
+    Ok(())
+}
+
+#[test]
+fn body_idle_timeout() -> Result<(), Error> {
+    common::setup_logger();
+
+    let (shut, port) = trickle_server()?;
+
+    for force_http2 in &[false, true] {
+        // a slow body that keeps coming is fine, however long it takes in total.
+        let start = Instant::now();
+
+        let mut res = Request::get(&format!("http://127.0.0.1:{}/trickle", port))
+            .force_http2(*force_http2)
+            .body_idle_timeout(Duration::from_millis(400))
+            .call()
+            .block()?;
+
+        assert_eq!(
+            res.body_mut().read_to_string().block()?,
+            "chunk\n".repeat(6)
+        );
+        assert!(start.elapsed() >= Duration::from_millis(600));
+
+        // a stalled one is not.
+        let mut res = Request::get(&format!("http://127.0.0.1:{}/stall", port))
+            .force_http2(*force_http2)
+            .body_idle_timeout(Duration::from_millis(200))
+            .call()
+            .block()?;
+
+        let err = res.body_mut().read_to_vec(1024).block().unwrap_err();
+
+        assert!(err.is_timeout());
+        assert_eq!(err.timeout_kind(), Some(TimeoutKind::BodyIdle));
+    }
+
+    drop(shut.shutdown());
+    Ok(())
+}
+
+#[test]
+fn connect_timeout() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut agent = Agent::new();
+    agent.retries(0);
+    agent.connector(|_: HostPort| async move {
+        type Tcp = tokio_util::compat::Compat<tokio::net::TcpStream>;
+        futures_util::future::pending::<Result<(Tcp, hreq::Protocol), Error>>().await
+    });
+
+    let req = Request::get("http://my-service/")
+        .connect_timeout(Duration::from_millis(100))
+        .with_body(())?;
+
+    let err = agent.send(req).block().unwrap_err();
+
+    assert_eq!(err.timeout_kind(), Some(TimeoutKind::Connect));
+
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn tls_handshake_timeout() -> Result<(), Error> {
+    common::setup_logger();
+
+    // accepts tcp connections (in the backlog) but never says anything.
+    let silent = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let port = silent.local_addr()?.port();
+
+    let err = Request::get(&format!("https://localhost:{}/", port))
+        .connect_timeout(Duration::from_secs(5))
+        .tls_handshake_timeout(Duration::from_millis(100))
+        .call()
+        .block()
+        .unwrap_err();
+
+    assert_eq!(err.timeout_kind(), Some(TimeoutKind::TlsHandshake));
+
     Ok(())
 }
@@ -0,0 +1,144 @@
+use hreq::prelude::*;
+use hreq::Error;
+
+mod common;
+
+fn checksum(value: &str) -> http::HeaderMap {
+    let mut trailers = http::HeaderMap::new();
+    trailers.insert("x-checksum", value.parse().unwrap());
+    trailers
+}
+
+#[test]
+fn trailers_h2() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server
+        .at("/path")
+        .post(|mut req: http::Request<Body>| async move {
+            // trailers are not there until the body is read.
+            assert!(req.body().trailers().is_none());
+
+            let s = req.body_mut().read_to_string().await?;
+            assert_eq!(s, "request body");
+
+            let trailers = req.body().trailers().expect("request trailers");
+            assert_eq!(trailers["x-checksum"], "req123");
+
+            Ok::<_, Error>(Body::from_str("response body").with_trailers(checksum("res123")))
+        });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let req = Request::post(&uri)
+        .force_http2(true)
+        .with_body(Body::from_str("request body").with_trailers(checksum("req123")))?;
+
+    let mut res = req.send().block()?;
+
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.body_mut().read_to_string().block()?, "response body");
+
+    let trailers = res.body().trailers().expect("response trailers");
+    assert_eq!(trailers["x-checksum"], "res123");
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn trailers_h2_empty_body() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server
+        .at("/path")
+        .get(|mut req: http::Request<Body>| async move {
+            req.body_mut().read_and_discard().await?;
+            let value = req.body().trailers().map(|t| t["x-checksum"].clone());
+            assert_eq!(value.unwrap(), "req123");
+            Ok::<_, Error>(Body::empty().with_trailers(checksum("res123")))
+        });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let req = Request::get(&uri)
+        .force_http2(true)
+        .with_body(Body::empty().with_trailers(checksum("req123")))?;
+
+    let mut res = req.send().block()?;
+
+    assert_eq!(res.status(), 200);
+    res.body_mut().read_and_discard().block()?;
+
+    let trailers = res.body().trailers().expect("response trailers");
+    assert_eq!(trailers["x-checksum"], "res123");
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn trailers_h1_dropped() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server
+        .at("/path")
+        .post(|mut req: http::Request<Body>| async move {
+            let s = req.body_mut().read_to_string().await?;
+            assert_eq!(s, "request body");
+            assert!(req.body().trailers().is_none());
+            Ok::<_, Error>(Body::from_str("response body").with_trailers(checksum("res123")))
+        });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let req = Request::post(&uri)
+        .with_body(Body::from_str("request body").with_trailers(checksum("req123")))?;
+
+    let mut res = req.send().block()?;
+
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.body_mut().read_to_string().block()?, "response body");
+    assert!(res.body().trailers().is_none());
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn trailers_handle() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server
+        .at("/path")
+        .post(|mut req: http::Request<Body>| async move {
+            req.body_mut().read_and_discard().await?;
+            assert_eq!(req.body().trailers().unwrap()["x-checksum"], "req123");
+            Ok::<_, Error>(Body::from_str("response body").with_trailers(checksum("res123")))
+        });
+
+    let req = Request::post("/path")
+        .with_body(Body::from_str("request body").with_trailers(checksum("req123")))?;
+
+    let mut res = server.handle(req).block()?;
+
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.body_mut().read_to_string().block()?, "response body");
+    assert_eq!(res.body().trailers().unwrap()["x-checksum"], "res123");
+
+    Ok(())
+}
@@ -0,0 +1,165 @@
+#![cfg(unix)]
+
+use hreq::prelude::*;
+use hreq::{Agent, CurlExt, Error};
+use std::path::PathBuf;
+
+mod common;
+
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("hreq-{}-{}.sock", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn server() -> Server<()> {
+    let mut server = Server::new();
+    server
+        .at("/login")
+        .get(|_req: http::Request<Body>| async move {
+            http::Response::builder()
+                .status(302)
+                .header("set-cookie", "session=secret")
+                .header("location", "/whoami")
+                .body(())
+                .unwrap()
+        });
+    server
+        .at("/whoami")
+        .get(|req: http::Request<Body>| async move {
+            format!(
+                "{} {}",
+                req.header("host").unwrap_or("-"),
+                req.header("cookie").unwrap_or("nobody")
+            )
+        });
+    server
+        .at("/echo")
+        .post(|mut req: http::Request<Body>| async move {
+            req.body_mut().read_to_vec(10 * 1024 * 1024).await
+        });
+    server
+        .at("/peer")
+        .get(|req: http::Request<Body>| async move { format!("{:?}", req.remote_addr()) });
+    server
+}
+
+#[test]
+fn unix_socket_redirect_cookies() -> Result<(), Error> {
+    common::setup_logger();
+
+    let path = socket_path("redirect");
+    let shut = server().listen_unix(&path).block()?;
+
+    let mut agent = Agent::new();
+
+    let req = Request::get("http://my-sidecar/login")
+        .unix_socket(&path)
+        .with_body(())?;
+    let mut res = agent.send(req).block()?;
+
+    assert_eq!(
+        res.body_mut().read_to_string().block()?,
+        "my-sidecar session=secret"
+    );
+
+    shut.shutdown().block();
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn unix_socket_body_http2() -> Result<(), Error> {
+    common::setup_logger();
+
+    let path = socket_path("http2");
+    let shut = server().listen_unix(&path).block()?;
+
+    let data: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+
+    for force_http2 in &[false, true] {
+        let req = Request::post("http://my-sidecar/echo")
+            .unix_socket(&path)
+            .force_http2(*force_http2)
+            .with_body(data.clone())?;
+        let mut res = req.send().block()?;
+
+        let version = if *force_http2 {
+            http::Version::HTTP_2
+        } else {
+            http::Version::HTTP_11
+        };
+        assert_eq!(res.version(), version);
+        assert_eq!(res.body_mut().read_to_vec(10 * 1024 * 1024).block()?, data);
+    }
+
+    shut.shutdown().block();
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn unix_socket_remote_addr() -> Result<(), Error> {
+    common::setup_logger();
+
+    let path = socket_path("peer");
+    let shut = server().listen_unix(&path).block()?;
+
+    let req = Request::get("http://my-sidecar/peer")
+        .unix_socket(&path)
+        .with_body(())?;
+    let mut res = req.send().block()?;
+    assert_eq!(res.body_mut().read_to_string().block()?, "None");
+
+    shut.shutdown().block();
+    std::fs::remove_file(&path)?;
+
+    // over tcp, the client has an address.
+    let (shut, addr) = server().listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/peer", addr.port());
+    let mut res = Request::get(uri).call().block()?;
+    assert!(res
+        .body_mut()
+        .read_to_string()
+        .block()?
+        .starts_with("Some(127.0.0.1:"));
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn unix_socket_curl() -> Result<(), Error> {
+    common::setup_logger();
+
+    let path = socket_path("curl");
+    let shut = server().listen_unix(&path).block()?;
+
+    let cmd = format!(
+        "curl http://localhost/whoami --unix-socket {}",
+        path.display()
+    );
+    let req = Request::from_curl(&cmd)?;
+    assert_eq!(req.to_curl(), cmd);
+
+    let mut res = req.send().block()?;
+    assert_eq!(res.body_mut().read_to_string().block()?, "localhost nobody");
+
+    shut.shutdown().block();
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn unix_socket_already_exists() -> Result<(), Error> {
+    common::setup_logger();
+
+    let path = socket_path("exists");
+    std::fs::write(&path, "")?;
+
+    assert!(server().listen_unix(&path).block().is_err());
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
@@ -0,0 +1,312 @@
+use futures_util::io::{AsyncReadExt, AsyncWriteExt};
+use hreq::prelude::*;
+use hreq::Agent;
+use hreq::AsyncRuntime;
+use hreq::Error;
+
+mod common;
+
+async fn echo(mut req: http::Request<Body>) -> http::Response<()> {
+    let on_upgrade = req.on_upgrade().expect("OnUpgrade in request");
+
+    AsyncRuntime::spawn(async move {
+        let mut upgraded = on_upgrade.await.unwrap();
+        let mut buf = vec![0; 1024];
+        loop {
+            let n = upgraded.read(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            upgraded.write_all(&buf[..n]).await.unwrap();
+        }
+    });
+
+    http::Response::builder()
+        .status(101)
+        .header("connection", "upgrade")
+        .header("upgrade", "echo")
+        .body(())
+        .unwrap()
+}
+
+#[test]
+fn upgrade_echo() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server.at("/echo").get(echo);
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/echo", addr.port());
+
+    let mut res = Request::get(&uri)
+        .header("connection", "upgrade")
+        .header("upgrade", "echo")
+        .call()
+        .block()?;
+
+    assert_eq!(res.status(), 101);
+    assert_eq!(res.header("upgrade"), Some("echo"));
+    assert_eq!(res.header("content-length"), None);
+
+    let mut upgraded = res.on_upgrade().unwrap().block()?;
+
+    upgraded.write_all(b"hello upgrade").block()?;
+
+    let mut buf = vec![0; 13];
+    upgraded.read_exact(&mut buf).block()?;
+
+    assert_eq!(&buf, b"hello upgrade");
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn upgrade_refused() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server
+        .at("/echo")
+        .get(|_req: http::Request<Body>| async move { "No upgrade for you" });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/echo", addr.port());
+
+    let mut res = Request::get(&uri)
+        .header("connection", "upgrade")
+        .header("upgrade", "echo")
+        .call()
+        .block()?;
+
+    assert_eq!(res.status(), 200);
+
+    let on_upgrade = res.on_upgrade().unwrap();
+
+    assert_eq!(
+        res.body_mut().read_to_string().block()?,
+        "No upgrade for you"
+    );
+    assert!(on_upgrade.block().is_err());
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn connect_tunnel() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server
+        .at("/")
+        .connect(|mut req: http::Request<Body>| async move {
+            assert_eq!(req.uri().authority().unwrap(), "example.com:443");
+
+            let on_upgrade = req.on_upgrade().expect("OnUpgrade in request");
+
+            AsyncRuntime::spawn(async move {
+                let mut upgraded = on_upgrade.await.unwrap();
+                upgraded.write_all(b"tunneled").await.unwrap();
+            });
+
+            // any 2xx to CONNECT means the tunnel is established.
+            http::Response::builder().status(200).body(()).unwrap()
+        });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let mut agent = Agent::new();
+
+    let req = Request::connect("example.com:443")
+        .with_override("127.0.0.1", addr.port(), false)
+        .with_body(())?;
+
+    let mut res = agent.send(req).block()?;
+
+    assert_eq!(res.status(), 200);
+
+    let mut upgraded = res.on_upgrade().unwrap().block()?;
+
+    let mut buf = vec![];
+    upgraded.read_to_end(&mut buf).block()?;
+
+    assert_eq!(&buf, b"tunneled");
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn normal_requests_no_upgrade() -> Result<(), Error> {
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server
+        .at("/path")
+        .get(|_req: http::Request<Body>| async move { "ok" });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    let uri = format!("http://127.0.0.1:{}/path", addr.port());
+
+    let mut agent = Agent::new();
+
+    for _ in 0..3 {
+        let mut res = agent.send(Request::get(&uri).with_body(())?).block()?;
+        assert_eq!(res.status(), 200);
+        assert!(res.on_upgrade().is_none());
+        assert_eq!(res.body_mut().read_to_string().block()?, "ok");
+    }
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn upgrade_only_first_request() -> Result<(), Error> {
+    use tokio_util::compat::TokioAsyncReadCompatExt;
+
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server
+        .at("/path")
+        .get(|_req: http::Request<Body>| async move { "ok" });
+    server
+        .at("/echo")
+        .get(|mut req: http::Request<Body>| async move {
+            // only the first request of a connection can take it over.
+            assert!(req.on_upgrade().is_none());
+            "no upgrade"
+        });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    async move {
+        let tcp = tokio::net::TcpStream::connect(("127.0.0.1", addr.port())).await?;
+        let mut tcp = tcp.compat();
+
+        tcp.write_all(b"GET /path HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await?;
+
+        let head = read_head(&mut tcp).await?;
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        let mut body = [0_u8; 2];
+        tcp.read_exact(&mut body).await?;
+        assert_eq!(&body, b"ok");
+
+        tcp.write_all(
+            b"GET /echo HTTP/1.1\r\nhost: localhost\r\nconnection: upgrade\r\nupgrade: echo\r\n\r\n",
+        )
+        .await?;
+
+        let head = read_head(&mut tcp).await?;
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        let mut body = [0_u8; 10];
+        tcp.read_exact(&mut body).await?;
+        assert_eq!(&body, b"no upgrade");
+
+        Ok::<_, Error>(())
+    }
+    .block()?;
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn upgrade_refused_keep_alive() -> Result<(), Error> {
+    use tokio_util::compat::TokioAsyncReadCompatExt;
+
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server
+        .at("/path")
+        .get(|_req: http::Request<Body>| async move { "ok" });
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    async move {
+        let tcp = tokio::net::TcpStream::connect(("127.0.0.1", addr.port())).await?;
+        let mut tcp = tcp.compat();
+
+        // both requests at once, the refused upgrade hands the rest back to http/1.1.
+        tcp.write_all(
+            b"GET /path HTTP/1.1\r\nhost: localhost\r\nconnection: upgrade\r\nupgrade: echo\r\n\r\n\
+              GET /path HTTP/1.1\r\nhost: localhost\r\n\r\n",
+        )
+        .await?;
+
+        for _ in 0..2 {
+            let head = read_head(&mut tcp).await?;
+            assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+            let mut body = [0_u8; 2];
+            tcp.read_exact(&mut body).await?;
+            assert_eq!(&body, b"ok");
+        }
+
+        Ok::<_, Error>(())
+    }
+    .block()?;
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+#[test]
+fn upgrade_early_data() -> Result<(), Error> {
+    use tokio_util::compat::TokioAsyncReadCompatExt;
+
+    common::setup_logger();
+
+    let mut server = Server::new();
+
+    server.at("/echo").get(echo);
+
+    let (shut, addr) = server.listen(0).block()?;
+
+    async move {
+        let tcp = tokio::net::TcpStream::connect(("127.0.0.1", addr.port())).await?;
+        let mut tcp = tcp.compat();
+
+        // bytes right behind the head belong to the upgraded stream.
+        tcp.write_all(
+            b"GET /echo HTTP/1.1\r\nhost: localhost\r\nconnection: upgrade\r\nupgrade: echo\r\n\r\nearly",
+        )
+        .await?;
+
+        let head = read_head(&mut tcp).await?;
+        assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+
+        let mut buf = [0_u8; 5];
+        tcp.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"early");
+
+        Ok::<_, Error>(())
+    }
+    .block()?;
+
+    shut.shutdown().block();
+    Ok(())
+}
+
+async fn read_head(tcp: &mut (impl futures_util::io::AsyncRead + Unpin)) -> Result<String, Error> {
+    let mut head = vec![];
+    let mut byte = [0_u8];
+    while !head.ends_with(b"\r\n\r\n") {
+        tcp.read_exact(&mut byte).await?;
+        head.push(byte[0]);
+    }
+    Ok(String::from_utf8(head).unwrap())
+}